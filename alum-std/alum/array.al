@@ -1,6 +1,13 @@
 $ifndef ALUM_ARRAY
 $define ALUM_ARRAY 1
 
-extern range(int, int): arr<_>
+pub extern range(int, int): arr<_>
+pub extern arr_free(arr<_>): void
+
+pub extern vec_new(): vec
+pub extern push(vec, int): void
+pub extern pop(vec): int
+pub extern len(vec): int
+pub extern vec_free(vec): void
 
 $endif
\ No newline at end of file
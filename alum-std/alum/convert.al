@@ -1,9 +1,9 @@
 $ifndef ALUM_CONVERT
 $define ALUM_CONVERT 1
 
-extern itoa(int): str
-extern atoi(str): int
-extern ftoa(flt): str
-extern atof(str): flt
+pub extern itoa(int): str
+pub extern atoi(str): int
+pub extern ftoa(flt): str
+pub extern atof(str): flt
 
 $endif
\ No newline at end of file
@@ -0,0 +1,8 @@
+$ifndef ALUM_HEAP
+$define ALUM_HEAP 1
+
+pub extern malloc(int): int
+pub extern free(int): void
+pub extern realloc(int, int): int
+
+$endif
\ No newline at end of file
@@ -12,15 +12,16 @@ $define SEEK_SET 0
 $define SEEK_CUR 1
 $define SEEK_END 2
 
-extern write(int, str, int): int
-extern read(int, str, int): int
-extern print(str): int
-extern println(str): int
-extern input(str): str
-extern fopen(str, int, int): int
-extern fclose(int): int
-extern fread(int): str
-extern fwrite(int, str, int): int
-extern lseek(int, int, int): int
+pub extern write(int, str, int): int
+pub extern read(int, str, int): int
+pub extern print(str): int
+pub extern println(str): int
+pub extern printf(str, ...): int
+pub extern input(str): str
+pub extern fopen(str, int, int): int
+pub extern fclose(int): int
+pub extern fread(int): str
+pub extern fwrite(int, str, int): int
+pub extern lseek(int, int, int): int
 
 $endif
\ No newline at end of file
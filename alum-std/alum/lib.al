@@ -9,7 +9,8 @@ $import "string.al"
 $import "convert.al"
 $import "math.al"
 $import "array.al"
-extern syscall(int, int, int, int): int
-extern exit(int): void
+$import "heap.al"
+pub extern syscall(int, int, int, int): int
+pub extern exit(int): void
 
 $endif
\ No newline at end of file
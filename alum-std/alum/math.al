@@ -4,11 +4,11 @@ $define ALUM_MATH 1
 $define PI 3.14
 $define E 2.718
 
-extern abs(int): int
-extern sqrt(int): int
-extern max(int, int): int
-extern min(int, int): int
-extern pow(int, int): int
-extern fact(int): int
+pub extern abs(int): int
+pub extern sqrt(int): int
+pub extern max(int, int): int
+pub extern min(int, int): int
+pub extern pow(int, int): int
+pub extern fact(int): int
 
 $endif
\ No newline at end of file
@@ -1,12 +1,14 @@
 $ifndef ALUM_STRING
 $define ALUM_STRING 1
 
-extern strlen(str): int
-extern strcpy(str, str): str
-extern strcat(str, str): str
-extern memcmp(str, str, int): int
-extern memcpy(str, str, int): str
-extern memset(str, int, int): str
-extern bcmp(str, str, int): int
+pub extern strlen(str): int
+pub extern strcpy(str, str): str
+pub extern strcat(str, str): str
+pub extern memcmp(str, str, int): int
+pub extern memcpy(str, str, int): str
+pub extern memset(str, int, int): str
+pub extern bcmp(str, str, int): int
+
+pub extern string_free(string): void
 
 $endif
\ No newline at end of file
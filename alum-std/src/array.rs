@@ -1,31 +1,133 @@
-const MAX_RANGE_LEN: usize = 1024;
-const POOL_SIZE: usize = 16;
-static mut RANGE_POOL: [[isize; MAX_RANGE_LEN + 1]; POOL_SIZE] =
-    [[0; MAX_RANGE_LEN + 1]; POOL_SIZE];
-static mut POOL_IDX: usize = 0;
+use crate::{
+    exit,
+    heap::{free, malloc, realloc},
+    io::write,
+    string::memcpy,
+};
+
+// Arrays are a length followed by their elements. A `vec` points to its length
+// and to an array whose first word is its capacity rather than its length, so
+// indexing it works the same way.
+#[repr(C)]
+pub struct Vec {
+    len: usize,
+    data: *mut isize,
+}
+
+const VEC_MIN_CAP: usize = 4;
+
+fn alloc_array(len: usize) -> *mut isize {
+    let arr = malloc((len + 1) * 8) as *mut isize;
+    if arr.is_null() {
+        write(2, b"out of memory\n".as_ptr(), 14);
+        exit(1);
+    }
+    unsafe { *arr = len as isize };
+    arr
+}
 
 #[unsafe(no_mangle)]
 pub extern "C" fn range(start: isize, end: isize) -> *mut isize {
+    let len = if end <= start {
+        0
+    } else {
+        (end - start) as usize
+    };
+    let arr = alloc_array(len);
+    unsafe {
+        let data = arr.add(1);
+        for i in 0..len {
+            *data.add(i) = start + i as isize;
+        }
+    }
+    arr
+}
+
+/// # Safety
+///
+/// `arr` must be an array from the heap that is not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn arr_free(arr: *mut isize) {
+    unsafe { free(arr as *mut u8) };
+}
+
+/// Moves an array off the stack of the function returning it.
+///
+/// # Safety
+///
+/// `arr` must point to an array.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn alum_array_copy(arr: *const isize) -> *mut isize {
+    let len = unsafe { *arr } as usize;
+    let copy = alloc_array(len);
+    unsafe { memcpy(copy as *mut u8, arr as *const u8, (len + 1) * 8) };
+    copy
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn vec_new() -> *mut Vec {
+    let v = malloc(size_of::<Vec>()) as *mut Vec;
+    let data = alloc_array(VEC_MIN_CAP);
     unsafe {
-        let len = if end <= start {
-            0
-        } else {
-            (end - start) as usize
-        };
-        let final_len = if len > MAX_RANGE_LEN {
-            MAX_RANGE_LEN
-        } else {
-            len
-        };
-        let buf_idx = POOL_IDX % POOL_SIZE;
-        POOL_IDX += 1;
-        let base_ptr = &mut RANGE_POOL[buf_idx][0] as *mut isize;
-        *(base_ptr as *mut usize) = final_len;
-        let data_ptr = base_ptr.add(1);
-        for i in 0..final_len {
-            *data_ptr.add(i) = start + i as isize;
+        (*v).len = 0;
+        (*v).data = data;
+    }
+    v
+}
+
+/// # Safety
+///
+/// `v` must point to a `vec`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn push(v: *mut Vec, x: isize) {
+    unsafe {
+        let v = &mut *v;
+        let cap = *v.data as usize;
+        if v.len == cap {
+            let data = realloc(v.data as *mut u8, (cap * 2 + 1) * 8) as *mut isize;
+            if data.is_null() {
+                write(2, b"out of memory\n".as_ptr(), 14);
+                exit(1);
+            }
+            *data = (cap * 2) as isize;
+            v.data = data;
+        }
+        *v.data.add(v.len + 1) = x;
+        v.len += 1;
+    }
+}
+
+/// # Safety
+///
+/// `v` must point to a `vec`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pop(v: *mut Vec) -> isize {
+    unsafe {
+        let v = &mut *v;
+        if v.len == 0 {
+            write(2, b"pop from an empty vec\n".as_ptr(), 22);
+            exit(1);
         }
+        v.len -= 1;
+        *v.data.add(v.len + 1)
+    }
+}
 
-        base_ptr
+/// # Safety
+///
+/// `v` must point to a `vec`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn len(v: *const Vec) -> isize {
+    unsafe { (*v).len as isize }
+}
+
+/// # Safety
+///
+/// `v` must point to a `vec` that is not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vec_free(v: *mut Vec) {
+    unsafe {
+        free((*v).data as *mut u8);
+        free(v as *mut u8);
     }
 }
@@ -0,0 +1,149 @@
+use core::arch::asm;
+
+use crate::{string::memcpy, syscall};
+
+// Every block starts with a 16-byte header holding its size, which keeps the
+// payload 16-byte aligned. Small blocks come in power-of-two sizes carved out of
+// the `brk` heap and are recycled through one free list per size; larger ones
+// are mapped on their own and unmapped again by `free`.
+const HEADER: usize = 16;
+const MIN_SHIFT: usize = 5;
+const CLASSES: usize = 12;
+const MAX_SMALL: usize = 1 << (MIN_SHIFT + CLASSES - 1);
+const CHUNK: usize = 1 << 20;
+const PAGE: usize = 4096;
+
+static mut FREE: [usize; CLASSES] = [0; CLASSES];
+static mut HEAP_CUR: usize = 0;
+static mut HEAP_END: usize = 0;
+
+fn mmap(len: usize) -> *mut u8 {
+    let ret: isize;
+    unsafe {
+        asm!("
+        syscall
+        ",
+            inout("rax") 9isize => ret,
+            in("rdi") 0,
+            in("rsi") len,
+            in("rdx") 3,
+            in("r10") 0x22,
+            in("r8") -1isize,
+            in("r9") 0,
+            lateout("rcx") _,
+            lateout("r11") _,
+        );
+    }
+    if ret < 0 {
+        core::ptr::null_mut()
+    } else {
+        ret as *mut u8
+    }
+}
+
+fn sbrk(size: usize) -> *mut u8 {
+    unsafe {
+        if HEAP_CUR == 0 {
+            let base = syscall(12, 0, 0, 0) as usize;
+            HEAP_CUR = (base + 15) & !15;
+            HEAP_END = base;
+        }
+        if HEAP_CUR + size > HEAP_END {
+            let end = (HEAP_CUR + size + CHUNK - 1) & !(CHUNK - 1);
+            if (syscall(12, end as isize, 0, 0) as usize) < end {
+                return core::ptr::null_mut();
+            }
+            HEAP_END = end;
+        }
+        let block = HEAP_CUR as *mut u8;
+        HEAP_CUR += size;
+        block
+    }
+}
+
+// Indexing `FREE` directly would pull in the bounds check panic of `core`.
+fn free_list(class: usize) -> *mut usize {
+    unsafe { (&raw mut FREE as *mut usize).add(class) }
+}
+
+fn class(size: usize) -> usize {
+    let mut class = 0;
+    while (1 << (MIN_SHIFT + class)) < size {
+        class += 1;
+    }
+    class
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn malloc(size: usize) -> *mut u8 {
+    let total = size + HEADER;
+    let block = if total <= MAX_SMALL {
+        let class = class(total);
+        let block_size = 1 << (MIN_SHIFT + class);
+        unsafe {
+            let list = free_list(class);
+            let block = if *list != 0 {
+                let block = *list as *mut usize;
+                *list = *block.add(1);
+                block
+            } else {
+                sbrk(block_size) as *mut usize
+            };
+            if block.is_null() {
+                return core::ptr::null_mut();
+            }
+            *block = block_size;
+            block
+        }
+    } else {
+        let len = (total + PAGE - 1) & !(PAGE - 1);
+        let block = mmap(len) as *mut usize;
+        if block.is_null() {
+            return core::ptr::null_mut();
+        }
+        unsafe { *block = len };
+        block
+    };
+    unsafe { (block as *mut u8).add(HEADER) }
+}
+
+/// # Safety
+///
+/// `ptr` must be null or come from `malloc` or `realloc`, and not be used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let block = ptr.sub(HEADER) as *mut usize;
+        let size = *block;
+        if size <= MAX_SMALL {
+            let list = free_list(class(size));
+            *block.add(1) = *list;
+            *list = block as usize;
+        } else {
+            syscall(11, block as isize, size as isize, 0);
+        }
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be null or come from `malloc` or `realloc`, and not be used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+    let capacity = unsafe { *(ptr.sub(HEADER) as *const usize) } - HEADER;
+    if size <= capacity {
+        return ptr;
+    }
+    let new = malloc(size);
+    if !new.is_null() {
+        unsafe { memcpy(new, ptr, capacity) };
+        unsafe { free(ptr) };
+    }
+    new
+}
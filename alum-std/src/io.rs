@@ -100,3 +100,254 @@ pub extern "C" fn fwrite(fd: isize, buf: *const u8, n: usize) -> isize {
 pub extern "C" fn lseek(fd: isize, off: isize, whence: isize) -> isize {
     syscall(8, fd, off, whence)
 }
+
+/// Output of `printf`, written to stdout whenever the buffer fills up.
+struct Out {
+    buf: [u8; 256],
+    len: usize,
+    total: isize,
+}
+
+impl Out {
+    fn push(&mut self, b: u8) {
+        if self.len == self.buf.len() {
+            self.flush();
+        }
+        self.buf[self.len] = b;
+        self.len += 1;
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.push(b);
+        }
+    }
+
+    fn repeat(&mut self, b: u8, n: usize) {
+        for _ in 0..n {
+            self.push(b);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.len > 0 {
+            self.total += write(1, self.buf.as_ptr(), self.len);
+            self.len = 0;
+        }
+    }
+}
+
+/// The flags, width and precision between `%` and the conversion.
+struct Spec {
+    left: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Writes `sign` and `body` padded to the width, with zeros going between them.
+    fn field(&self, out: &mut Out, sign: &[u8], body: &[u8]) {
+        let pad = self.width.saturating_sub(sign.len() + body.len());
+        if self.left {
+            out.extend(sign);
+            out.extend(body);
+            out.repeat(b' ', pad);
+        } else if self.zero {
+            out.extend(sign);
+            out.repeat(b'0', pad);
+            out.extend(body);
+        } else {
+            out.repeat(b' ', pad);
+            out.extend(sign);
+            out.extend(body);
+        }
+    }
+
+    /// Writes the digits of `n` in `base`, with at least `precision` of them.
+    fn unsigned(&self, out: &mut Out, sign: &[u8], mut n: usize, base: usize) {
+        let mut digits = [0u8; 64];
+        let mut i = digits.len();
+        while n > 0 || i == digits.len() {
+            i -= 1;
+            digits[i] = b"0123456789abcdef"[n % base];
+            n /= base;
+        }
+        let min = self.precision.unwrap_or(0).min(digits.len());
+        while digits.len() - i < min {
+            i -= 1;
+            digits[i] = b'0';
+        }
+        self.field(out, sign, &digits[i..]);
+    }
+
+    fn float(&self, out: &mut Out, n: f64) {
+        if n.is_nan() {
+            return self.field(out, b"", b"nan");
+        }
+        let sign: &[u8] = if n < 0.0 { b"-" } else { b"" };
+        let n = if n < 0.0 { -n } else { n };
+        if n == f64::INFINITY {
+            return self.field(out, sign, b"inf");
+        }
+
+        // Digits past the 17th carry no information, and 10^17 still fits a u64.
+        let precision = self.precision.unwrap_or(6).min(17);
+        let scale = 10u64.pow(precision as u32);
+        let mut int = n as u64;
+        let mut frac = scaled(int, n - int as f64, scale);
+        if frac >= scale {
+            int += 1;
+            frac -= scale;
+        }
+
+        let mut body = [0u8; 64];
+        let mut len = 0;
+        let mut digits = [0u8; 20];
+        let mut i = digits.len();
+        while int > 0 || i == digits.len() {
+            i -= 1;
+            digits[i] = (int % 10) as u8 + b'0';
+            int /= 10;
+        }
+        for &d in &digits[i..] {
+            body[len] = d;
+            len += 1;
+        }
+        if precision > 0 {
+            body[len] = b'.';
+            len += 1;
+            let mut div = scale / 10;
+            while div > 0 {
+                body[len] = (frac / div % 10) as u8 + b'0';
+                len += 1;
+                div /= 10;
+            }
+        }
+        self.field(out, sign, &body[..len]);
+    }
+}
+
+/// `x * scale`, for the fraction `x` of `int + x`, rounded to the nearest integer
+/// with ties going to an even last digit, as C does. The exact binary value of `x`
+/// is rounded, so `0.125` is a tie while the double closest to `0.15` lies just
+/// below it.
+fn scaled(int: u64, x: f64, scale: u64) -> u64 {
+    // `x` is `mantissa / 2^shift`.
+    let bits = x.to_bits();
+    let (mantissa, shift) = match (bits >> 52) as u32 {
+        0 => (bits, 1074),
+        exp => (bits & ((1 << 52) - 1) | 1 << 52, 1075 - exp),
+    };
+    // The product stays below 2^110, so a smaller `x` rounds to 0.
+    if mantissa == 0 || shift >= 120 {
+        return 0;
+    }
+    let product = mantissa as u128 * scale as u128;
+    let (quotient, rem) = (product >> shift, product & ((1 << shift) - 1));
+    let half = 1 << (shift - 1);
+    // With no fraction digits the last digit is the one of `int`.
+    let last = if scale == 1 { int as u128 } else { quotient };
+    let up = rem > half || rem == half && last % 2 == 1;
+    (quotient + up as u128) as u64
+}
+
+/// Prints `fmt` with each conversion replaced by the next argument: `%d` for an
+/// `int`, `%x` for one in hex, `%f` for a `flt`, `%s` for a string and `%c` for a
+/// `char`. A conversion may have the flags `-` and `0`, a width and a precision, as
+/// in C, and `%%` prints a `%`. Returns the number of bytes written.
+///
+/// # Safety
+///
+/// `fmt` must be NUL-terminated, and the arguments must match its conversions.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn printf(fmt: *const u8, mut args: ...) -> isize {
+    let mut out = Out {
+        buf: [0; 256],
+        len: 0,
+        total: 0,
+    };
+    let mut p = fmt;
+    unsafe {
+        while *p != 0 {
+            if *p != b'%' {
+                out.push(*p);
+                p = p.add(1);
+                continue;
+            }
+            let start = p;
+            p = p.add(1);
+
+            let mut spec = Spec {
+                left: false,
+                zero: false,
+                width: 0,
+                precision: None,
+            };
+            loop {
+                match *p {
+                    b'-' => spec.left = true,
+                    b'0' => spec.zero = true,
+                    _ => break,
+                }
+                p = p.add(1);
+            }
+            while (*p).is_ascii_digit() {
+                spec.width = spec.width * 10 + (*p - b'0') as usize;
+                p = p.add(1);
+            }
+            if *p == b'.' {
+                p = p.add(1);
+                let mut precision = 0;
+                while (*p).is_ascii_digit() {
+                    precision = precision * 10 + (*p - b'0') as usize;
+                    p = p.add(1);
+                }
+                spec.precision = Some(precision);
+            }
+            // As in C, zeros only pad numbers without a precision.
+            if spec.precision.is_some() && *p != b'f' {
+                spec.zero = false;
+            }
+
+            match *p {
+                b'd' => {
+                    let n: isize = args.next_arg();
+                    let sign: &[u8] = if n < 0 { b"-" } else { b"" };
+                    spec.unsigned(&mut out, sign, n.unsigned_abs(), 10);
+                }
+                b'x' => spec.unsigned(&mut out, b"", args.next_arg::<usize>(), 16),
+                b'f' => spec.float(&mut out, args.next_arg()),
+                b'c' => {
+                    spec.zero = false;
+                    spec.field(&mut out, b"", &[args.next_arg::<usize>() as u8]);
+                }
+                b's' => {
+                    spec.zero = false;
+                    let s: *const u8 = args.next_arg();
+                    let s = if s.is_null() {
+                        c"(null)".as_ptr().cast()
+                    } else {
+                        s
+                    };
+                    let len = strlen(s).min(spec.precision.unwrap_or(usize::MAX));
+                    spec.field(&mut out, b"", core::slice::from_raw_parts(s, len));
+                }
+                b'%' => out.push(b'%'),
+                // An unknown conversion is printed as it is.
+                _ => {
+                    if *p == 0 {
+                        p = p.sub(1);
+                    }
+                    out.extend(core::slice::from_raw_parts(
+                        start,
+                        p.offset_from(start) as usize + 1,
+                    ));
+                }
+            }
+            p = p.add(1);
+        }
+    }
+    out.flush();
+    out.total
+}
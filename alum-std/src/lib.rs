@@ -3,12 +3,13 @@
 #![no_builtins]
 
 use core::arch::asm;
-use core::panic::PanicInfo;
 
 pub mod array;
 pub mod convert;
+pub mod heap;
 pub mod io;
 pub mod math;
+pub mod panic;
 pub mod string;
 
 #[unsafe(no_mangle)]
@@ -18,13 +19,6 @@ unsafe extern "C" {
     fn main() -> isize;
 }
 
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    unsafe {
-        asm!("ud2", options(noreturn));
-    }
-}
-
 #[inline(always)]
 pub extern "C" fn syscall(nr: usize, a1: isize, a2: isize, a3: isize) -> isize {
     let ret: isize;
@@ -60,8 +54,22 @@ pub extern "C" fn exit(code: isize) {
     }
 }
 
+/// The kernel enters `_start` with a 16-byte aligned stack rather than the one a
+/// `call` leaves, so it is aligned by hand before anything else runs. Variadic
+/// functions such as `printf` spill the XMM registers with aligned stores.
+#[unsafe(naked)]
 #[unsafe(no_mangle)]
-extern "C" fn _start() -> () {
+extern "C" fn _start() -> ! {
+    core::arch::naked_asm!(
+        "xor rbp, rbp",
+        "and rsp, -16",
+        "call {}",
+        sym start,
+    )
+}
+
+extern "C" fn start() -> ! {
     let ret = unsafe { main() };
     exit(ret);
+    unreachable!()
 }
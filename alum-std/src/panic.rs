@@ -0,0 +1,169 @@
+use core::arch::{asm, global_asm};
+use core::panic::PanicInfo;
+
+use crate::{
+    convert::itoa,
+    exit,
+    io::write,
+    string::{bcmp, strlen},
+};
+
+// The compiler puts the start, end and name of every function in `alum_symtab`,
+// which the linker gathers between these two symbols. They stay zero when nothing
+// written in Alum is linked in.
+global_asm!(".weak __start_alum_symtab", ".weak __stop_alum_symtab");
+
+#[repr(C)]
+struct Function {
+    start: usize,
+    end: usize,
+    name: *const u8,
+}
+
+const MAX_FRAMES: usize = 64;
+
+fn eprint(s: *const u8) {
+    write(2, s, strlen(s));
+}
+
+fn eprint_hex(n: usize) {
+    let mut buf = [0u8; 19];
+    let p = buf.as_mut_ptr();
+    let mut len = 0;
+    let mut shift = 60;
+    unsafe {
+        *p = b'0';
+        *p.add(1) = b'x';
+        len += 2;
+        while shift > 0 && (n >> shift) & 0xf == 0 {
+            shift -= 4;
+        }
+        loop {
+            *p.add(len) = b"0123456789abcdef"[(n >> shift) & 0xf];
+            len += 1;
+            if shift == 0 {
+                break;
+            }
+            shift -= 4;
+        }
+    }
+    write(2, p, len);
+}
+
+/// The name of the function a return address points into.
+fn function_at(addr: usize) -> Option<*const u8> {
+    let (mut f, stop): (*const Function, *const Function);
+    unsafe {
+        asm!(
+            "lea {0}, [rip + __start_alum_symtab]",
+            "lea {1}, [rip + __stop_alum_symtab]",
+            out(reg) f,
+            out(reg) stop,
+            options(nomem, nostack, pure),
+        );
+        while f < stop {
+            if (*f).start < addr && addr <= (*f).end {
+                return Some((*f).name);
+            }
+            f = f.add(1);
+        }
+    }
+    None
+}
+
+/// The frame of the function this is inlined into. Alum functions, and `alum-std`
+/// as it is built, all keep `rbp` pointing at the saved `rbp` of their caller,
+/// followed by the return address.
+#[inline(always)]
+fn frame() -> *const usize {
+    let rbp: *const usize;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, pure)) };
+    rbp
+}
+
+/// Prints the return addresses on the chain of frames starting at `fp`, up to
+/// the one in `main`.
+fn backtrace(mut fp: *const usize) {
+    eprint(c"stack backtrace:\n".as_ptr().cast());
+    for i in 0..MAX_FRAMES {
+        if fp.is_null() || !fp.is_aligned() {
+            break;
+        }
+        let (next, ret) = unsafe { (*fp as *const usize, *fp.add(1)) };
+        let name = function_at(ret);
+        eprint(c"  ".as_ptr().cast());
+        eprint(itoa(i as isize));
+        eprint(c": ".as_ptr().cast());
+        eprint_hex(ret);
+        eprint(c" - ".as_ptr().cast());
+        eprint(name.unwrap_or(c"??".as_ptr().cast()));
+        eprint(c"\n".as_ptr().cast());
+        if name.is_some_and(|name| unsafe { bcmp(name, c"main".as_ptr().cast(), 5) } == 0)
+            || next <= fp
+        {
+            break;
+        }
+        fp = next;
+    }
+}
+
+/// Called by `panic` in Alum code.
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_panic(msg: *const u8, file: *const u8, line: isize) {
+    eprint(file);
+    eprint(c":".as_ptr().cast());
+    eprint(itoa(line));
+    eprint(c": panic: ".as_ptr().cast());
+    eprint(msg);
+    eprint(c"\n".as_ptr().cast());
+    backtrace(frame());
+    exit(1);
+}
+
+/// Called by the bounds checks the compiler emits in front of indexing.
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_bounds_panic(file: *const u8, line: isize, index: isize, len: isize) {
+    eprint(file);
+    eprint(c":".as_ptr().cast());
+    eprint(itoa(line));
+    eprint(c": index out of bounds: the index is ".as_ptr().cast());
+    eprint(itoa(index));
+    eprint(c" but the length is ".as_ptr().cast());
+    eprint(itoa(len));
+    eprint(c"\n".as_ptr().cast());
+    backtrace(frame());
+    exit(1);
+}
+
+/// Called when the range of `s[start ~ end]` does not lie within the string. It is
+/// inlined, so that the backtrace starts at the Alum code doing the slicing.
+#[inline(always)]
+pub(crate) fn slice_panic(file: *const u8, line: isize, start: isize, end: isize, len: isize) {
+    eprint(file);
+    eprint(c":".as_ptr().cast());
+    eprint(itoa(line));
+    eprint(c": slice out of bounds: the range is ".as_ptr().cast());
+    eprint(itoa(start));
+    eprint(c" ~ ".as_ptr().cast());
+    eprint(itoa(end));
+    eprint(c" but the length is ".as_ptr().cast());
+    eprint(itoa(len));
+    eprint(c"\n".as_ptr().cast());
+    backtrace(frame());
+    exit(1);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if let Some(location) = info.location() {
+        write(2, location.file().as_ptr(), location.file().len());
+        eprint(c":".as_ptr().cast());
+        eprint(itoa(location.line() as isize));
+        eprint(c": ".as_ptr().cast());
+    }
+    eprint(c"panic in alum-std\n".as_ptr().cast());
+    backtrace(frame());
+    exit(1);
+    // `exit` does not return.
+    unsafe { asm!("ud2", options(noreturn)) }
+}
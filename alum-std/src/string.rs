@@ -1,3 +1,11 @@
+use crate::{
+    convert::{ftoa, itoa},
+    exit,
+    heap::{free, realloc},
+    io::write,
+    panic::slice_panic,
+};
+
 #[unsafe(no_mangle)]
 pub extern "C" fn strlen(ptr: *const u8) -> usize {
     let mut len = 0;
@@ -104,3 +112,99 @@ pub unsafe extern "C" fn memcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
     }
     0
 }
+
+// A `string` points to its bytes on the heap. They end in a NUL, so that a
+// `string` can be passed wherever a `str` is expected, and the word in front of
+// them holds the length.
+const LEN: usize = 8;
+
+fn string_alloc(base: *mut u8, len: usize) -> *mut u8 {
+    let base = unsafe { realloc(base, LEN + len + 1) };
+    if base.is_null() {
+        write(2, b"out of memory\n".as_ptr(), 14);
+        exit(1);
+    }
+    unsafe {
+        *(base as *mut usize) = len;
+        *base.add(LEN + len) = 0;
+        base.add(LEN)
+    }
+}
+
+fn string_len(s: *const u8) -> usize {
+    unsafe { *(s.sub(LEN) as *const usize) }
+}
+
+/// Appends `n` bytes at `src` to `s`, which may be null for an empty string, and
+/// returns where the string now lives.
+fn append(s: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    let (base, len) = if s.is_null() {
+        (core::ptr::null_mut(), 0)
+    } else {
+        (unsafe { s.sub(LEN) }, string_len(s))
+    };
+    let s = string_alloc(base, len + n);
+    unsafe { memcpy(s.add(len), src, n) };
+    s
+}
+
+/// # Safety
+///
+/// `s` must be null or a `string` that is not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn string_free(s: *mut u8) {
+    if !s.is_null() {
+        unsafe { free(s.sub(LEN)) };
+    }
+}
+
+/// `s[start ~ end]`, which must lie within `s`.
+///
+/// # Safety
+///
+/// `s` and `file` must point to NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn alum_string_slice(
+    s: *const u8,
+    start: isize,
+    end: isize,
+    file: *const u8,
+    line: isize,
+) -> *mut u8 {
+    let len = strlen(s) as isize;
+    if start < 0 || end < start || end > len {
+        slice_panic(file, line, start, end, len);
+    }
+    let from = unsafe { s.add(start as usize) };
+    append(core::ptr::null_mut(), from, (end - start) as usize)
+}
+
+// `format` appends each piece of its result with one of these, starting from null.
+
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_format_str(s: *mut u8, x: *const u8) -> *mut u8 {
+    append(s, x, strlen(x))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_format_int(s: *mut u8, x: isize) -> *mut u8 {
+    alum_format_str(s, itoa(x))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_format_flt(s: *mut u8, x: f64) -> *mut u8 {
+    alum_format_str(s, ftoa(x))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_format_bool(s: *mut u8, x: isize) -> *mut u8 {
+    match x {
+        0 => append(s, b"false".as_ptr(), 5),
+        _ => append(s, b"true".as_ptr(), 4),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn alum_format_char(s: *mut u8, x: isize) -> *mut u8 {
+    append(s, &(x as u8), 1)
+}
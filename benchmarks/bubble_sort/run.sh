@@ -1,4 +1,5 @@
 al bubble_sort.al
+al -O2 bubble_sort.al -o bubble_sort_O2
 gcc -O3 bubble_sort.c
-hyperfine -i './bubble_sort' './a.out' 'python bubble_sort.py' --shell=none --warmup 100
-rm bubble_sort a.out
\ No newline at end of file
+hyperfine -i './bubble_sort' './bubble_sort_O2' './a.out' 'python bubble_sort.py' --shell=none --warmup 100
+rm bubble_sort bubble_sort_O2 a.out
\ No newline at end of file
@@ -1,4 +1,5 @@
 al fib1000.al
+al -O2 fib1000.al -o fib1000_O2
 gcc -O3 fib1000.c
-hyperfine -i './fib1000' './a.out' 'python fib1000.py' --shell=none --warmup 100
-rm fib1000 a.out
\ No newline at end of file
+hyperfine -i './fib1000' './fib1000_O2' './a.out' 'python fib1000.py' --shell=none --warmup 100
+rm fib1000 fib1000_O2 a.out
\ No newline at end of file
@@ -1,4 +1,4 @@
-$import "io"
+from io import println
 
 pub fun main(): int {
     println("Hello, Alum!")
@@ -1,5 +1,12 @@
+ALUM_HOME="${ALUM_HOME:-/usr/local}"
+SUDO=sudo
+if [ -w "$ALUM_HOME" ]; then
+    SUDO=
+fi
+
 cargo install --path .
 cd alum-std
 cargo build --release
-sudo cp target/release/libalum_std.a /usr/local/lib/libalum.a
-sudo cp -r alum/ /usr/local
\ No newline at end of file
+$SUDO mkdir -p "$ALUM_HOME/lib"
+$SUDO cp target/release/libalum_std.a "$ALUM_HOME/lib/libalum.a"
+$SUDO cp -r alum/ "$ALUM_HOME"
@@ -0,0 +1,1481 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::elf::{
+    ObjectFile, R_X86_64_32, R_X86_64_64, R_X86_64_PC32, R_X86_64_PLT32, RelocTarget, Relocation,
+    Section, SectionId, Symbol,
+};
+
+#[derive(Debug, Clone)]
+pub enum AssemblerError {
+    SyntaxError { message: String, line: usize },
+    UnsupportedInstruction { message: String, line: usize },
+    UndefinedSymbol { name: String, line: usize },
+}
+
+impl std::error::Error for AssemblerError {}
+
+impl std::fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssemblerError::SyntaxError { message, line } => {
+                write!(f, "Assembler error at line {}: {}", line, message)
+            }
+            AssemblerError::UnsupportedInstruction { message, line } => {
+                write!(
+                    f,
+                    "Assembler error at line {}: unsupported instruction '{}'",
+                    line, message
+                )
+            }
+            AssemblerError::UndefinedSymbol { name, line } => {
+                write!(
+                    f,
+                    "Assembler error at line {}: undefined symbol '{}'",
+                    line, name
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RegKind {
+    Byte,
+    Dword,
+    Qword,
+    Xmm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Reg {
+    num: u8,
+    kind: RegKind,
+}
+
+impl Reg {
+    /// `spl`, `bpl`, `sil` and `dil` only exist with a REX prefix.
+    fn needs_rex(self) -> bool {
+        self.kind == RegKind::Byte && (4..8).contains(&self.num)
+    }
+}
+
+fn parse_reg(name: &str) -> Option<Reg> {
+    const QWORD: [&str; 16] = [
+        "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12",
+        "r13", "r14", "r15",
+    ];
+    const DWORD: [&str; 16] = [
+        "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d",
+        "r12d", "r13d", "r14d", "r15d",
+    ];
+    const BYTE: [&str; 16] = [
+        "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+        "r13b", "r14b", "r15b",
+    ];
+    for (names, kind) in [
+        (QWORD, RegKind::Qword),
+        (DWORD, RegKind::Dword),
+        (BYTE, RegKind::Byte),
+    ] {
+        if let Some(num) = names.iter().position(|n| *n == name) {
+            return Some(Reg {
+                num: num as u8,
+                kind,
+            });
+        }
+    }
+    let num: u8 = name.strip_prefix("xmm")?.parse().ok()?;
+    (num < 16).then_some(Reg {
+        num,
+        kind: RegKind::Xmm,
+    })
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()? as i64
+    } else {
+        digits.parse::<u64>().ok()? as i64
+    };
+    Some(if neg { value.wrapping_neg() } else { value })
+}
+
+/// A memory operand. `symbol` is set for `[rel symbol]`, which is RIP-relative.
+#[derive(Debug, Clone, Default)]
+struct Mem {
+    base: Option<Reg>,
+    index: Option<(Reg, u8)>,
+    disp: i64,
+    symbol: Option<String>,
+    size: Option<RegKind>,
+}
+
+#[derive(Debug, Clone)]
+enum Arg {
+    Reg(Reg),
+    Imm(i64),
+    Mem(Mem),
+    Sym(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FixupKind {
+    Rel32,
+    Call,
+    Abs32,
+    Abs64,
+}
+
+#[derive(Debug, Clone)]
+struct Fixup {
+    pos: usize,
+    symbol: String,
+    kind: FixupKind,
+    addend: i64,
+    /// For `symbol - minus`, which is known once both are laid out in one section.
+    minus: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Code(Vec<u8>, Vec<Fixup>),
+    /// A `jmp` (`cc` is `None`) or conditional jump, which is relaxed to its short
+    /// form whenever the target is close enough.
+    Branch {
+        cc: Option<u8>,
+        target: String,
+        line: usize,
+    },
+    Label(String),
+    Align(usize),
+}
+
+fn condition_code(cc: &str) -> Option<u8> {
+    Some(match cc {
+        "o" => 0x0,
+        "no" => 0x1,
+        "b" | "c" | "nae" => 0x2,
+        "ae" | "nb" | "nc" => 0x3,
+        "e" | "z" => 0x4,
+        "ne" | "nz" => 0x5,
+        "be" | "na" => 0x6,
+        "a" | "nbe" => 0x7,
+        "s" => 0x8,
+        "ns" => 0x9,
+        "p" | "pe" => 0xa,
+        "np" | "po" => 0xb,
+        "l" | "nge" => 0xc,
+        "ge" | "nl" => 0xd,
+        "le" | "ng" => 0xe,
+        "g" | "nle" => 0xf,
+        _ => return None,
+    })
+}
+
+fn fits_i8(n: i64) -> bool {
+    (-128..=127).contains(&n)
+}
+
+fn fits_i32(n: i64) -> bool {
+    (i32::MIN as i64..=i32::MAX as i64).contains(&n)
+}
+
+/// Encodes a single instruction.
+struct Encoder {
+    bytes: Vec<u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    /// Emits `[prefix] [REX] opcode ModRM [SIB] [disp]` with `reg` in the ModRM reg
+    /// field and `rm` as the register or memory operand.
+    fn op_rm(
+        &mut self,
+        prefix: Option<u8>,
+        w: bool,
+        opcode: &[u8],
+        reg: u8,
+        rm: &Arg,
+        byte_rex: bool,
+    ) {
+        if let Some(p) = prefix {
+            self.bytes.push(p);
+        }
+        let (x, b) = match rm {
+            Arg::Reg(r) => (0, r.num >> 3),
+            Arg::Mem(m) => (
+                m.index.map_or(0, |(i, _)| i.num >> 3),
+                m.base.map_or(0, |r| r.num >> 3),
+            ),
+            _ => (0, 0),
+        };
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3) << 2 | x << 1 | b;
+        if rex != 0x40 || byte_rex {
+            self.bytes.push(rex);
+        }
+        self.bytes.extend_from_slice(opcode);
+        let reg = (reg & 7) << 3;
+        match rm {
+            Arg::Reg(r) => self.bytes.push(0xc0 | reg | (r.num & 7)),
+            Arg::Mem(m) => self.mem(reg, m),
+            _ => unreachable!(),
+        }
+    }
+
+    fn mem(&mut self, reg: u8, m: &Mem) {
+        if let Some(symbol) = &m.symbol {
+            self.bytes.push(reg | 0x05);
+            self.fixups.push(Fixup {
+                pos: self.bytes.len(),
+                symbol: symbol.clone(),
+                kind: FixupKind::Rel32,
+                addend: m.disp,
+                minus: None,
+            });
+            self.bytes.extend_from_slice(&[0; 4]);
+            return;
+        }
+        let Some(base) = m.base else {
+            // No base register: [index * scale + disp32], or an absolute address.
+            let (index, scale) = m.index.map_or((4, 0), |(r, s)| (r.num & 7, s));
+            self.bytes.push(reg | 0x04);
+            self.bytes.push(scale << 6 | index << 3 | 0x05);
+            self.bytes.extend_from_slice(&(m.disp as i32).to_le_bytes());
+            return;
+        };
+        // rbp and r13 cannot be encoded without a displacement.
+        let md = if m.disp == 0 && base.num & 7 != 5 {
+            0x00
+        } else if fits_i8(m.disp) {
+            0x40
+        } else {
+            0x80
+        };
+        if m.index.is_some() || base.num & 7 == 4 {
+            let (index, scale) = m.index.map_or((4, 0), |(r, s)| (r.num & 7, s));
+            self.bytes.push(md | reg | 0x04);
+            self.bytes.push(scale << 6 | index << 3 | (base.num & 7));
+        } else {
+            self.bytes.push(md | reg | (base.num & 7));
+        }
+        match md {
+            0x40 => self.bytes.push(m.disp as i8 as u8),
+            0x80 => self.bytes.extend_from_slice(&(m.disp as i32).to_le_bytes()),
+            _ => {}
+        }
+    }
+
+    fn imm(&mut self, value: i64, size: usize) {
+        self.bytes.extend_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    /// RIP-relative fields are relative to the end of the instruction, so their
+    /// addends can only be settled once the whole instruction has been emitted.
+    fn finish(mut self) -> Item {
+        let len = self.bytes.len() as i64;
+        for f in &mut self.fixups {
+            if matches!(f.kind, FixupKind::Rel32 | FixupKind::Call) {
+                f.addend -= len - f.pos as i64;
+            }
+        }
+        Item::Code(self.bytes, self.fixups)
+    }
+}
+
+fn operand_kind(arg: &Arg) -> Option<RegKind> {
+    match arg {
+        Arg::Reg(r) => Some(r.kind),
+        Arg::Mem(m) => m.size,
+        _ => None,
+    }
+}
+
+fn is_gpr(arg: &Arg) -> bool {
+    matches!(arg, Arg::Reg(r) if r.kind != RegKind::Xmm)
+}
+
+fn is_xmm(arg: &Arg) -> bool {
+    matches!(arg, Arg::Reg(r) if r.kind == RegKind::Xmm)
+}
+
+fn is_rm(arg: &Arg) -> bool {
+    is_gpr(arg) || matches!(arg, Arg::Mem(_))
+}
+
+fn is_xmm_rm(arg: &Arg) -> bool {
+    is_xmm(arg) || matches!(arg, Arg::Mem(_))
+}
+
+fn byte_rex(args: &[&Arg]) -> bool {
+    args.iter()
+        .any(|a| matches!(a, Arg::Reg(r) if r.needs_rex()))
+}
+
+fn reg_num(arg: &Arg) -> u8 {
+    match arg {
+        Arg::Reg(r) => r.num,
+        _ => 0,
+    }
+}
+
+/// Encodes an instruction other than a jump, which `Assembler` handles itself.
+fn encode(mnemonic: &str, args: &[Arg]) -> Option<Item> {
+    let mut e = Encoder::new();
+    match (mnemonic, args) {
+        ("ret", []) => e.bytes.push(0xc3),
+        ("leave", []) => e.bytes.push(0xc9),
+        ("cqo", []) => e.bytes.extend_from_slice(&[0x48, 0x99]),
+        ("cdq", []) => e.bytes.push(0x99),
+        ("nop", []) => e.bytes.push(0x90),
+        ("syscall", []) => e.bytes.extend_from_slice(&[0x0f, 0x05]),
+        ("ud2", []) => e.bytes.extend_from_slice(&[0x0f, 0x0b]),
+        ("int3", []) => e.bytes.push(0xcc),
+
+        ("push", [Arg::Reg(r)]) if r.kind == RegKind::Qword => {
+            if r.num >= 8 {
+                e.bytes.push(0x41);
+            }
+            e.bytes.push(0x50 + (r.num & 7));
+        }
+        ("pop", [Arg::Reg(r)]) if r.kind == RegKind::Qword => {
+            if r.num >= 8 {
+                e.bytes.push(0x41);
+            }
+            e.bytes.push(0x58 + (r.num & 7));
+        }
+        ("push", [Arg::Imm(n)]) if fits_i8(*n) => {
+            e.bytes.push(0x6a);
+            e.imm(*n, 1);
+        }
+        ("push", [Arg::Imm(n)]) if fits_i32(*n) => {
+            e.bytes.push(0x68);
+            e.imm(*n, 4);
+        }
+        ("push", [m @ Arg::Mem(_)]) => e.op_rm(None, false, &[0xff], 6, m, false),
+        ("pop", [m @ Arg::Mem(_)]) => e.op_rm(None, false, &[0x8f], 0, m, false),
+
+        ("call", [Arg::Sym(s)]) => {
+            e.bytes.push(0xe8);
+            e.fixups.push(Fixup {
+                pos: 1,
+                symbol: s.clone(),
+                kind: FixupKind::Call,
+                addend: 0,
+                minus: None,
+            });
+            e.bytes.extend_from_slice(&[0; 4]);
+        }
+        ("call", [rm]) if is_rm(rm) => e.op_rm(None, false, &[0xff], 2, rm, false),
+        ("jmp", [rm]) if is_rm(rm) => e.op_rm(None, false, &[0xff], 4, rm, false),
+
+        ("mov", [dst, src @ Arg::Reg(s)]) if is_rm(dst) && is_gpr(src) => {
+            if operand_kind(dst).is_some_and(|k| k != s.kind) {
+                return None;
+            }
+            let op = if s.kind == RegKind::Byte { 0x88 } else { 0x89 };
+            let w = s.kind == RegKind::Qword;
+            e.op_rm(None, w, &[op], s.num, dst, byte_rex(&[dst, src]));
+        }
+        ("mov", [Arg::Reg(d), src @ Arg::Mem(_)]) if d.kind != RegKind::Xmm => {
+            let op = if d.kind == RegKind::Byte { 0x8a } else { 0x8b };
+            e.op_rm(
+                None,
+                d.kind == RegKind::Qword,
+                &[op],
+                d.num,
+                src,
+                d.needs_rex(),
+            );
+        }
+        ("mov", [Arg::Reg(d), Arg::Imm(n)]) if d.kind != RegKind::Xmm => {
+            // Like nasm, use the shortest form that gives the same 64-bit result.
+            match d.kind {
+                RegKind::Byte => {
+                    if d.num >= 8 || d.needs_rex() {
+                        e.bytes.push(0x40 | d.num >> 3);
+                    }
+                    e.bytes.push(0xb0 + (d.num & 7));
+                    e.imm(*n, 1);
+                }
+                RegKind::Qword if !(0..=u32::MAX as i64).contains(n) => {
+                    if fits_i32(*n) {
+                        e.op_rm(None, true, &[0xc7], 0, &Arg::Reg(*d), false);
+                        e.imm(*n, 4);
+                    } else {
+                        e.bytes.push(0x48 | d.num >> 3);
+                        e.bytes.push(0xb8 + (d.num & 7));
+                        e.imm(*n, 8);
+                    }
+                }
+                _ => {
+                    if d.num >= 8 {
+                        e.bytes.push(0x41);
+                    }
+                    e.bytes.push(0xb8 + (d.num & 7));
+                    e.imm(*n, 4);
+                }
+            }
+        }
+        ("mov", [m @ Arg::Mem(mem), Arg::Imm(n)]) => match mem.size? {
+            RegKind::Byte => {
+                e.op_rm(None, false, &[0xc6], 0, m, false);
+                e.imm(*n, 1);
+            }
+            kind if fits_i32(*n) => {
+                e.op_rm(None, kind == RegKind::Qword, &[0xc7], 0, m, false);
+                e.imm(*n, 4);
+            }
+            _ => return None,
+        },
+        ("movzx" | "movsx", [Arg::Reg(d), src]) if is_rm(src) && d.kind != RegKind::Xmm => {
+            if operand_kind(src) != Some(RegKind::Byte) {
+                return None;
+            }
+            let op = if mnemonic == "movzx" { 0xb6 } else { 0xbe };
+            let w = d.kind == RegKind::Qword;
+            e.op_rm(None, w, &[0x0f, op], d.num, src, byte_rex(&[src]));
+        }
+        ("movsxd", [Arg::Reg(d), src]) if is_rm(src) && d.kind == RegKind::Qword => {
+            e.op_rm(None, true, &[0x63], d.num, src, false);
+        }
+        ("lea", [Arg::Reg(d), src @ Arg::Mem(_)]) if d.kind == RegKind::Qword => {
+            e.op_rm(None, true, &[0x8d], d.num, src, false);
+        }
+
+        ("add" | "or" | "and" | "sub" | "xor" | "cmp", [dst, src]) => {
+            let n = match mnemonic {
+                "add" => 0,
+                "or" => 1,
+                "and" => 4,
+                "sub" => 5,
+                "xor" => 6,
+                _ => 7,
+            };
+            let kind = operand_kind(dst).or(operand_kind(src))?;
+            let w = kind == RegKind::Qword;
+            let byte = kind == RegKind::Byte;
+            match src {
+                Arg::Reg(s) if is_rm(dst) && is_gpr(src) => {
+                    if s.kind != kind {
+                        return None;
+                    }
+                    let op = n * 8 + if byte { 0 } else { 1 };
+                    e.op_rm(None, w, &[op], s.num, dst, byte_rex(&[dst, src]));
+                }
+                Arg::Mem(_) if is_gpr(dst) => {
+                    let op = n * 8 + if byte { 2 } else { 3 };
+                    e.op_rm(None, w, &[op], reg_num(dst), src, byte_rex(&[dst]));
+                }
+                Arg::Imm(i) if is_rm(dst) => {
+                    let acc = matches!(dst, Arg::Reg(r) if r.num == 0);
+                    if byte {
+                        if acc {
+                            e.bytes.push(n * 8 + 4);
+                        } else {
+                            e.op_rm(None, false, &[0x80], n, dst, byte_rex(&[dst]));
+                        }
+                        e.imm(*i, 1);
+                    } else if fits_i8(*i) {
+                        e.op_rm(None, w, &[0x83], n, dst, false);
+                        e.imm(*i, 1);
+                    } else if fits_i32(*i) {
+                        if acc {
+                            if w {
+                                e.bytes.push(0x48);
+                            }
+                            e.bytes.push(n * 8 + 5);
+                        } else {
+                            e.op_rm(None, w, &[0x81], n, dst, false);
+                        }
+                        e.imm(*i, 4);
+                    } else {
+                        return None;
+                    }
+                }
+                _ => return None,
+            }
+        }
+        ("test", [dst, Arg::Reg(s)]) if is_rm(dst) && s.kind != RegKind::Xmm => {
+            let op = if s.kind == RegKind::Byte { 0x84 } else { 0x85 };
+            let w = s.kind == RegKind::Qword;
+            e.op_rm(None, w, &[op], s.num, dst, byte_rex(&[dst, &args[1]]));
+        }
+        ("test", [dst, Arg::Imm(i)]) if is_rm(dst) => {
+            let kind = operand_kind(dst)?;
+            if kind == RegKind::Byte {
+                e.op_rm(None, false, &[0xf6], 0, dst, byte_rex(&[dst]));
+                e.imm(*i, 1);
+            } else if fits_i32(*i) {
+                e.op_rm(None, kind == RegKind::Qword, &[0xf7], 0, dst, false);
+                e.imm(*i, 4);
+            } else {
+                return None;
+            }
+        }
+        ("imul", [Arg::Reg(d), src]) if is_rm(src) && d.kind != RegKind::Byte => {
+            e.op_rm(
+                None,
+                d.kind == RegKind::Qword,
+                &[0x0f, 0xaf],
+                d.num,
+                src,
+                false,
+            );
+        }
+        ("imul", [d @ Arg::Reg(r), Arg::Imm(i)]) => {
+            let w = r.kind == RegKind::Qword;
+            if fits_i8(*i) {
+                e.op_rm(None, w, &[0x6b], r.num, d, false);
+                e.imm(*i, 1);
+            } else if fits_i32(*i) {
+                e.op_rm(None, w, &[0x69], r.num, d, false);
+                e.imm(*i, 4);
+            } else {
+                return None;
+            }
+        }
+        ("imul" | "mul" | "idiv" | "div" | "neg" | "not" | "inc" | "dec", [rm]) if is_rm(rm) => {
+            let kind = operand_kind(rm)?;
+            let (op, n) = match mnemonic {
+                "not" => (0xf6, 2),
+                "neg" => (0xf6, 3),
+                "mul" => (0xf6, 4),
+                "imul" => (0xf6, 5),
+                "div" => (0xf6, 6),
+                "idiv" => (0xf6, 7),
+                "inc" => (0xfe, 0),
+                _ => (0xfe, 1),
+            };
+            let op = if kind == RegKind::Byte { op } else { op + 1 };
+            e.op_rm(None, kind == RegKind::Qword, &[op], n, rm, byte_rex(&[rm]));
+        }
+        ("shl" | "sal" | "shr" | "sar" | "rol" | "ror", [rm, count]) if is_rm(rm) => {
+            let kind = operand_kind(rm)?;
+            let n = match mnemonic {
+                "rol" => 0,
+                "ror" => 1,
+                "shl" | "sal" => 4,
+                "shr" => 5,
+                _ => 7,
+            };
+            let byte = kind == RegKind::Byte;
+            let w = kind == RegKind::Qword;
+            let rex = byte_rex(&[rm]);
+            match count {
+                Arg::Imm(1) => e.op_rm(None, w, &[if byte { 0xd0 } else { 0xd1 }], n, rm, rex),
+                Arg::Imm(i) => {
+                    e.op_rm(None, w, &[if byte { 0xc0 } else { 0xc1 }], n, rm, rex);
+                    e.imm(*i, 1);
+                }
+                Arg::Reg(Reg {
+                    num: 1,
+                    kind: RegKind::Byte,
+                }) => e.op_rm(None, w, &[if byte { 0xd2 } else { 0xd3 }], n, rm, rex),
+                _ => return None,
+            }
+        }
+
+        (m, [rm]) if m.starts_with("set") && is_rm(rm) => {
+            let cc = condition_code(&m[3..])?;
+            if operand_kind(rm) != Some(RegKind::Byte) {
+                return None;
+            }
+            e.op_rm(None, false, &[0x0f, 0x90 + cc], 0, rm, byte_rex(&[rm]));
+        }
+        (m, [Arg::Reg(d), src]) if m.starts_with("cmov") && is_rm(src) => {
+            let cc = condition_code(&m[4..])?;
+            e.op_rm(
+                None,
+                d.kind == RegKind::Qword,
+                &[0x0f, 0x40 + cc],
+                d.num,
+                src,
+                false,
+            );
+        }
+
+        ("movsd", [Arg::Reg(d), src]) if d.kind == RegKind::Xmm && is_xmm_rm(src) => {
+            e.op_rm(Some(0xf2), false, &[0x0f, 0x10], d.num, src, false);
+        }
+        ("movsd", [dst @ Arg::Mem(_), Arg::Reg(s)]) if s.kind == RegKind::Xmm => {
+            e.op_rm(Some(0xf2), false, &[0x0f, 0x11], s.num, dst, false);
+        }
+        ("movq", [Arg::Reg(d), src]) if d.kind == RegKind::Xmm && is_gpr(src) => {
+            e.op_rm(Some(0x66), true, &[0x0f, 0x6e], d.num, src, false);
+        }
+        ("movq", [dst, Arg::Reg(s)]) if s.kind == RegKind::Xmm && is_gpr(dst) => {
+            e.op_rm(Some(0x66), true, &[0x0f, 0x7e], s.num, dst, false);
+        }
+        ("movq", [Arg::Reg(d), src]) if d.kind == RegKind::Xmm && is_xmm_rm(src) => {
+            e.op_rm(Some(0xf3), false, &[0x0f, 0x7e], d.num, src, false);
+        }
+        ("movq", [dst @ Arg::Mem(_), Arg::Reg(s)]) if s.kind == RegKind::Xmm => {
+            e.op_rm(Some(0x66), false, &[0x0f, 0xd6], s.num, dst, false);
+        }
+        ("cvtsi2sd", [Arg::Reg(d), src]) if d.kind == RegKind::Xmm && is_rm(src) => {
+            let w = operand_kind(src) != Some(RegKind::Dword);
+            e.op_rm(Some(0xf2), w, &[0x0f, 0x2a], d.num, src, false);
+        }
+        ("cvttsd2si" | "cvtsd2si", [Arg::Reg(d), src]) if is_xmm_rm(src) => {
+            if d.kind == RegKind::Xmm || d.kind == RegKind::Byte {
+                return None;
+            }
+            let op = if mnemonic == "cvttsd2si" { 0x2c } else { 0x2d };
+            e.op_rm(
+                Some(0xf2),
+                d.kind == RegKind::Qword,
+                &[0x0f, op],
+                d.num,
+                src,
+                false,
+            );
+        }
+        (m, [Arg::Reg(d), src]) if d.kind == RegKind::Xmm && is_xmm_rm(src) => {
+            let (prefix, op) = match m {
+                "addsd" => (0xf2, 0x58),
+                "mulsd" => (0xf2, 0x59),
+                "subsd" => (0xf2, 0x5c),
+                "minsd" => (0xf2, 0x5d),
+                "divsd" => (0xf2, 0x5e),
+                "maxsd" => (0xf2, 0x5f),
+                "sqrtsd" => (0xf2, 0x51),
+                "ucomisd" => (0x66, 0x2e),
+                "comisd" => (0x66, 0x2f),
+                "andpd" => (0x66, 0x54),
+                "andnpd" => (0x66, 0x55),
+                "orpd" => (0x66, 0x56),
+                "xorpd" => (0x66, 0x57),
+                "pxor" => (0x66, 0xef),
+                _ => return None,
+            };
+            e.op_rm(Some(prefix), false, &[0x0f, op], d.num, src, false);
+        }
+        _ => return None,
+    }
+    Some(e.finish())
+}
+
+/// Splits operands on the commas that are not inside brackets or quotes.
+fn split_operands(s: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in s.chars() {
+        match (c, quote) {
+            ('"' | '\'' | '`', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('[', None) => depth += 1,
+            (']', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+/// Assembles the NASM subset that `CodeGen` emits into an ELF relocatable object,
+/// so that compiling does not depend on nasm being installed.
+pub struct Assembler<'a> {
+    src: &'a str,
+    line: usize,
+    section: SectionId,
+    sections: HashMap<SectionId, Vec<(Item, usize)>>,
+    globals: HashSet<String>,
+    externs: Vec<String>,
+    symbols: Vec<String>,
+    scope: String,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            line: 0,
+            section: SectionId::Text,
+            sections: HashMap::new(),
+            globals: HashSet::new(),
+            externs: Vec::new(),
+            symbols: Vec::new(),
+            scope: String::new(),
+        }
+    }
+
+    fn syntax_error(&self, message: String) -> AssemblerError {
+        AssemblerError::SyntaxError {
+            message,
+            line: self.line,
+        }
+    }
+
+    fn push(&mut self, item: Item) {
+        let line = self.line;
+        self.sections
+            .entry(self.section)
+            .or_default()
+            .push((item, line));
+    }
+
+    /// nasm attaches labels starting with `.` to the last label that does not.
+    fn full_name(&self, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn define_label(&mut self, name: &str) {
+        if !name.starts_with('.') {
+            self.scope = name.to_string();
+            self.symbols.push(name.to_string());
+        }
+        let name = self.full_name(name);
+        self.push(Item::Label(name));
+    }
+
+    fn parse_mem(&self, inner: &str, size: Option<RegKind>) -> Result<Mem, AssemblerError> {
+        let mut mem = Mem {
+            size,
+            ..Mem::default()
+        };
+        let (inner, rel) = match inner.trim().strip_prefix("rel ") {
+            Some(rest) => (rest, true),
+            None => (inner, false),
+        };
+        let mut terms = Vec::new();
+        let mut term = String::new();
+        let mut sign = 1;
+        for c in inner.chars() {
+            if c == '+' || c == '-' {
+                if !term.trim().is_empty() {
+                    terms.push((sign, term.trim().to_string()));
+                }
+                term.clear();
+                sign = if c == '-' { -1 } else { 1 };
+            } else {
+                term.push(c);
+            }
+        }
+        if !term.trim().is_empty() {
+            terms.push((sign, term.trim().to_string()));
+        }
+
+        for (sign, term) in terms {
+            if let Some((a, b)) = term.split_once('*') {
+                let (a, b) = (a.trim(), b.trim());
+                let (reg, scale) = match (parse_reg(a), parse_reg(b)) {
+                    (Some(r), _) => (r, parse_number(b)),
+                    (_, Some(r)) => (r, parse_number(a)),
+                    _ => (
+                        Reg {
+                            num: 0,
+                            kind: RegKind::Xmm,
+                        },
+                        None,
+                    ),
+                };
+                let scale = match scale {
+                    Some(1) => 0,
+                    Some(2) => 1,
+                    Some(4) => 2,
+                    Some(8) => 3,
+                    _ => return Err(self.syntax_error(format!("invalid index '{}'", term))),
+                };
+                mem.index = Some((reg, scale));
+            } else if let Some(reg) = parse_reg(&term) {
+                if mem.base.is_none() {
+                    mem.base = Some(reg);
+                } else {
+                    mem.index = Some((reg, 0));
+                }
+            } else if let Some(n) = parse_number(&term) {
+                mem.disp += sign * n;
+            } else if rel && mem.symbol.is_none() {
+                mem.symbol = Some(self.full_name(&term));
+            } else {
+                return Err(self.syntax_error(format!("invalid address '{}'", inner)));
+            }
+        }
+        if mem.symbol.is_some() && (mem.base.is_some() || mem.index.is_some()) {
+            return Err(self.syntax_error(format!("invalid address '{}'", inner)));
+        }
+        if mem.index.is_some_and(|(r, _)| r.num == 4) {
+            return Err(self.syntax_error("rsp cannot be an index".to_string()));
+        }
+        Ok(mem)
+    }
+
+    fn parse_arg(&self, s: &str) -> Result<Arg, AssemblerError> {
+        let mut s = s.trim();
+        let mut size = None;
+        for (kw, kind) in [
+            ("byte", Some(RegKind::Byte)),
+            ("dword", Some(RegKind::Dword)),
+            ("qword", Some(RegKind::Qword)),
+            ("oword", None),
+        ] {
+            if let Some(rest) = s.strip_prefix(kw)
+                && rest.starts_with([' ', '['])
+            {
+                s = rest.trim();
+                size = kind;
+            }
+        }
+        if let Some(inner) = s.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            return Ok(Arg::Mem(self.parse_mem(inner, size)?));
+        }
+        if let Some(reg) = parse_reg(s) {
+            return Ok(Arg::Reg(reg));
+        }
+        if let Some(n) = parse_number(s) {
+            return Ok(Arg::Imm(n));
+        }
+        if s.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '$' | '@' | '?'))
+        {
+            return Ok(Arg::Sym(self.full_name(s)));
+        }
+        Err(self.syntax_error(format!("invalid operand '{}'", s)))
+    }
+
+    fn data_directive(&mut self, directive: &str, operands: &str) -> Result<(), AssemblerError> {
+        let size = match directive {
+            "db" => 1,
+            "dw" => 2,
+            "dd" => 4,
+            _ => 8,
+        };
+        let mut bytes = Vec::new();
+        let mut fixups = Vec::new();
+        for value in split_operands(operands) {
+            let quoted = ['"', '\'', '`']
+                .iter()
+                .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)));
+            if let Some(text) = quoted {
+                bytes.extend_from_slice(text.as_bytes());
+                while bytes.len() % size != 0 {
+                    bytes.push(0);
+                }
+            } else if let Some(n) = parse_number(&value) {
+                bytes.extend_from_slice(&n.to_le_bytes()[..size]);
+            } else if size >= 4 {
+                let (symbol, minus) = match value.split_once('-') {
+                    Some((a, b)) => (a.trim(), Some(self.full_name(b.trim()))),
+                    None => (value.as_str(), None),
+                };
+                fixups.push(Fixup {
+                    pos: bytes.len(),
+                    symbol: self.full_name(symbol),
+                    kind: if size == 8 {
+                        FixupKind::Abs64
+                    } else {
+                        FixupKind::Abs32
+                    },
+                    addend: 0,
+                    minus,
+                });
+                bytes.extend_from_slice(&vec![0; size]);
+            } else {
+                return Err(self.syntax_error(format!("invalid data '{}'", value)));
+            }
+        }
+        self.push(Item::Code(bytes, fixups));
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), AssemblerError> {
+        let line = match line.find(';') {
+            Some(i) if !line[..i].contains(['"', '\'', '`']) => &line[..i],
+            _ => line,
+        };
+        let mut line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match word {
+            "section" => {
+                self.section = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(SectionId::from_name)
+                    .ok_or_else(|| self.syntax_error(format!("unknown section '{}'", rest)))?;
+                return Ok(());
+            }
+            "global" => {
+                self.globals.insert(rest.to_string());
+                return Ok(());
+            }
+            "extern" => {
+                self.externs.push(rest.to_string());
+                return Ok(());
+            }
+            "align" => {
+                let n = parse_number(rest)
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| self.syntax_error(format!("invalid alignment '{}'", rest)))?;
+                self.push(Item::Align(n as usize));
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        // `name:` starts a label, and so does a bare name in front of a data directive.
+        if let Some(label) = word.strip_suffix(':') {
+            self.define_label(label);
+            line = rest;
+        } else if let Some((directive, _)) = rest.split_once(char::is_whitespace)
+            && ["db", "dw", "dd", "dq"].contains(&directive)
+        {
+            self.define_label(word);
+            line = rest;
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mnemonic = mnemonic.to_lowercase();
+        if ["db", "dw", "dd", "dq"].contains(&mnemonic.as_str()) {
+            return self.data_directive(&mnemonic, operands);
+        }
+
+        let args = split_operands(operands)
+            .iter()
+            .map(|a| self.parse_arg(a))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let [Arg::Sym(target)] = args.as_slice() {
+            let cc = if mnemonic == "jmp" {
+                Some(None)
+            } else {
+                mnemonic
+                    .strip_prefix('j')
+                    .and_then(condition_code)
+                    .map(Some)
+            };
+            if let Some(cc) = cc {
+                self.push(Item::Branch {
+                    cc,
+                    target: target.clone(),
+                    line: self.line,
+                });
+                return Ok(());
+            }
+        }
+
+        let item =
+            encode(&mnemonic, &args).ok_or_else(|| AssemblerError::UnsupportedInstruction {
+                message: line.to_string(),
+                line: self.line,
+            })?;
+        self.push(item);
+        Ok(())
+    }
+
+    pub fn assemble(&mut self) -> Result<ObjectFile, AssemblerError> {
+        for (i, line) in self.src.lines().enumerate() {
+            self.line = i + 1;
+            self.parse_line(line)?;
+        }
+
+        let sections = std::mem::take(&mut self.sections);
+        let mut labels: HashMap<String, (SectionId, u64)> = HashMap::new();
+        let mut long = HashMap::new();
+        for (id, items) in &sections {
+            let (table, branches) = layout(items);
+            for (name, offset) in table {
+                labels.insert(name, (*id, offset));
+            }
+            long.insert(*id, branches);
+        }
+
+        // Labels starting with `.` only matter inside this object and get no symbol.
+        let mut object = ObjectFile::default();
+        let mut symbol_index: HashMap<String, usize> = HashMap::new();
+        for name in &self.symbols {
+            let (section, offset) = labels[name];
+            symbol_index.insert(name.clone(), object.symbols.len());
+            object.symbols.push(Symbol {
+                name: name.clone(),
+                section: Some(section),
+                value: offset,
+                global: self.globals.contains(name),
+            });
+        }
+        for name in &self.globals {
+            if !labels.contains_key(name) {
+                return Err(AssemblerError::UndefinedSymbol {
+                    name: name.clone(),
+                    line: 0,
+                });
+            }
+        }
+        for name in &self.externs {
+            if !labels.contains_key(name) && !symbol_index.contains_key(name) {
+                symbol_index.insert(name.clone(), object.symbols.len());
+                object.symbols.push(Symbol {
+                    name: name.clone(),
+                    section: None,
+                    value: 0,
+                    global: true,
+                });
+            }
+        }
+
+        let ctx = Emit {
+            labels: &labels,
+            symbol_index: &symbol_index,
+        };
+        for (id, items) in &sections {
+            let (bytes, relocs) = ctx.emit(*id, items, &long[id])?;
+            object.sections.insert(*id, Section { bytes, relocs });
+        }
+        Ok(object)
+    }
+}
+
+/// Assigns offsets to every item of a section, growing branches to their long form
+/// until every short one reaches its target.
+fn layout(items: &[(Item, usize)]) -> (HashMap<String, u64>, Vec<bool>) {
+    let branches = items
+        .iter()
+        .filter(|(i, _)| matches!(i, Item::Branch { .. }))
+        .count();
+    let mut long = vec![false; branches];
+    loop {
+        let mut labels = HashMap::new();
+        let mut offsets = Vec::new();
+        let mut offset = 0u64;
+        let mut b = 0;
+        for (item, _) in items {
+            offsets.push(offset);
+            match item {
+                Item::Code(bytes, _) => offset += bytes.len() as u64,
+                Item::Branch { cc, .. } => {
+                    offset += match (long[b], cc) {
+                        (false, _) => 2,
+                        (true, None) => 5,
+                        (true, Some(_)) => 6,
+                    };
+                    b += 1;
+                }
+                Item::Label(name) => {
+                    labels.insert(name.clone(), offset);
+                }
+                Item::Align(n) => offset = offset.div_ceil(*n as u64) * *n as u64,
+            }
+        }
+
+        let mut changed = false;
+        let mut b = 0;
+        for (i, (item, _)) in items.iter().enumerate() {
+            if let Item::Branch { target, .. } = item {
+                if !long[b] {
+                    let reach = labels
+                        .get(target)
+                        .map(|t| *t as i64 - (offsets[i] as i64 + 2));
+                    if !reach.is_some_and(fits_i8) {
+                        long[b] = true;
+                        changed = true;
+                    }
+                }
+                b += 1;
+            }
+        }
+        if !changed {
+            return (labels, long);
+        }
+    }
+}
+
+struct Emit<'a> {
+    labels: &'a HashMap<String, (SectionId, u64)>,
+    symbol_index: &'a HashMap<String, usize>,
+}
+
+impl Emit<'_> {
+    fn emit(
+        &self,
+        section: SectionId,
+        items: &[(Item, usize)],
+        long: &[bool],
+    ) -> Result<(Vec<u8>, Vec<Relocation>), AssemblerError> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut relocs = Vec::new();
+        let mut b = 0;
+        for (item, line) in items {
+            match item {
+                Item::Code(bytes, fixups) => {
+                    let start = out.len();
+                    out.extend_from_slice(bytes);
+                    for f in fixups {
+                        self.fixup(section, &mut out, &mut relocs, start + f.pos, f, *line)?;
+                    }
+                }
+                Item::Branch { cc, target, line } => {
+                    let start = out.len();
+                    if long[b] {
+                        match cc {
+                            None => out.push(0xe9),
+                            Some(cc) => out.extend_from_slice(&[0x0f, 0x80 + cc]),
+                        }
+                        let pos = out.len();
+                        out.extend_from_slice(&[0; 4]);
+                        let fixup = Fixup {
+                            pos: 0,
+                            symbol: target.clone(),
+                            kind: FixupKind::Call,
+                            addend: -4,
+                            minus: None,
+                        };
+                        self.fixup(section, &mut out, &mut relocs, pos, &fixup, *line)?;
+                    } else {
+                        out.push(match cc {
+                            None => 0xeb,
+                            Some(cc) => 0x70 + cc,
+                        });
+                        let (_, target) = self.labels[target];
+                        out.push((target as i64 - (start as i64 + 2)) as i8 as u8);
+                    }
+                    b += 1;
+                }
+                Item::Label(_) => {}
+                Item::Align(n) => {
+                    let fill = if section == SectionId::Text { 0x90 } else { 0 };
+                    while !out.len().is_multiple_of(*n) {
+                        out.push(fill);
+                    }
+                }
+            }
+        }
+        Ok((out, relocs))
+    }
+
+    fn fixup(
+        &self,
+        section: SectionId,
+        out: &mut [u8],
+        relocs: &mut Vec<Relocation>,
+        pos: usize,
+        f: &Fixup,
+        line: usize,
+    ) -> Result<(), AssemblerError> {
+        let label = self.labels.get(&f.symbol);
+        if let Some(minus) = &f.minus {
+            let value = match (label, self.labels.get(minus)) {
+                (Some((a, x)), Some((b, y))) if a == b => *x as i64 - *y as i64 + f.addend,
+                _ => {
+                    return Err(AssemblerError::SyntaxError {
+                        message: format!(
+                            "'{} - {}' does not subtract labels of one section",
+                            f.symbol, minus
+                        ),
+                        line,
+                    });
+                }
+            };
+            let size = if f.kind == FixupKind::Abs64 { 8 } else { 4 };
+            out[pos..pos + size].copy_from_slice(&value.to_le_bytes()[..size]);
+            return Ok(());
+        }
+        if matches!(f.kind, FixupKind::Rel32 | FixupKind::Call)
+            && let Some((_, target)) = label.filter(|(s, _)| *s == section)
+        {
+            let value = *target as i64 + f.addend - pos as i64;
+            out[pos..pos + 4].copy_from_slice(&(value as i32).to_le_bytes());
+            return Ok(());
+        }
+        let (target, addend) = match label {
+            Some((s, offset)) => (RelocTarget::Section(*s), *offset as i64 + f.addend),
+            None => match self.symbol_index.get(&f.symbol) {
+                Some(i) => (RelocTarget::Symbol(*i), f.addend),
+                None => {
+                    return Err(AssemblerError::UndefinedSymbol {
+                        name: f.symbol.clone(),
+                        line,
+                    });
+                }
+            },
+        };
+        let kind = match (f.kind, label) {
+            (FixupKind::Abs64, _) => R_X86_64_64,
+            (FixupKind::Abs32, _) => R_X86_64_32,
+            (FixupKind::Call, None) => R_X86_64_PLT32,
+            _ => R_X86_64_PC32,
+        };
+        relocs.push(Relocation {
+            offset: pos as u64,
+            target,
+            kind,
+            addend,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bytes `src` assembles to in `.text`.
+    fn text(src: &str) -> Vec<u8> {
+        let obj = Assembler::new(src).assemble().unwrap();
+        obj.sections
+            .get(&SectionId::Text)
+            .cloned()
+            .unwrap_or_default()
+            .bytes
+    }
+
+    /// Checks each instruction against the bytes nasm assembles it to.
+    fn check(cases: &[(&str, &[u8])]) {
+        for (line, bytes) in cases {
+            assert_eq!(text(line), *bytes, "{}", line);
+        }
+    }
+
+    /// Register, immediate and memory moves, including the REX-prefixed registers and
+    /// the `mov r32, imm32` nasm picks for immediates that fit it.
+    #[test]
+    fn moves() {
+        check(&[
+            ("mov rax, rsi", &[0x48, 0x89, 0xf0]),
+            ("mov rbp, rsp", &[0x48, 0x89, 0xe5]),
+            ("mov r8, rbx", &[0x49, 0x89, 0xd8]),
+            ("mov rbx, r15", &[0x4c, 0x89, 0xfb]),
+            ("mov r12, r13", &[0x4d, 0x89, 0xec]),
+            ("mov rcx, r10", &[0x4c, 0x89, 0xd1]),
+            ("mov rax, 5", &[0xb8, 0x05, 0x00, 0x00, 0x00]),
+            ("mov rsi, 577", &[0xbe, 0x41, 0x02, 0x00, 0x00]),
+            ("mov rax, -1", &[0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]),
+            ("mov rax, 2147483647", &[0xb8, 0xff, 0xff, 0xff, 0x7f]),
+            ("mov rax, 4294967295", &[0xb8, 0xff, 0xff, 0xff, 0xff]),
+            (
+                "mov rax, 81985529216486895",
+                &[0x48, 0xb8, 0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01],
+            ),
+            ("mov al, 2", &[0xb0, 0x02]),
+            ("mov rax, [rbp - 8]", &[0x48, 0x8b, 0x45, 0xf8]),
+            ("mov rbx, [rbp - 8]", &[0x48, 0x8b, 0x5d, 0xf8]),
+            (
+                "mov rax, [rbp - 200]",
+                &[0x48, 0x8b, 0x85, 0x38, 0xff, 0xff, 0xff],
+            ),
+            ("mov rcx, qword [rbp - 8]", &[0x48, 0x8b, 0x4d, 0xf8]),
+            (
+                "mov r12, qword [rbp - 136]",
+                &[0x4c, 0x8b, 0xa5, 0x78, 0xff, 0xff, 0xff],
+            ),
+            ("mov rax, [rax]", &[0x48, 0x8b, 0x00]),
+            ("mov rcx, [r10]", &[0x49, 0x8b, 0x0a]),
+            ("mov rax, [r10 + 8]", &[0x49, 0x8b, 0x42, 0x08]),
+            (
+                "mov rax, [r10 + r11 * 8 + 8]",
+                &[0x4b, 0x8b, 0x44, 0xda, 0x08],
+            ),
+            ("mov [rbp - 8], rbx", &[0x48, 0x89, 0x5d, 0xf8]),
+            ("mov qword [rbp - 8], rax", &[0x48, 0x89, 0x45, 0xf8]),
+            (
+                "mov qword [rbp - 300], r15",
+                &[0x4c, 0x89, 0xbd, 0xd4, 0xfe, 0xff, 0xff],
+            ),
+            ("mov [r10], rax", &[0x49, 0x89, 0x02]),
+            ("mov [r10 + 16], rax", &[0x49, 0x89, 0x42, 0x10]),
+            ("mov [r11 + 8], rax", &[0x49, 0x89, 0x43, 0x08]),
+            (
+                "mov [r10 + r11 * 8 + 8], rax",
+                &[0x4b, 0x89, 0x44, 0xda, 0x08],
+            ),
+            ("mov [r10 + r11], al", &[0x43, 0x88, 0x04, 0x1a]),
+            ("mov [rbp + 16], rax", &[0x48, 0x89, 0x45, 0x10]),
+        ]);
+    }
+
+    /// Integer arithmetic, with the short `imm8` forms for small immediates.
+    #[test]
+    fn arithmetic() {
+        check(&[
+            ("add rax, rsi", &[0x48, 0x01, 0xf0]),
+            ("add rax, r12", &[0x4c, 0x01, 0xe0]),
+            ("add r13, rax", &[0x49, 0x01, 0xc5]),
+            ("add rsp, 8", &[0x48, 0x83, 0xc4, 0x08]),
+            ("add rsp, 200", &[0x48, 0x81, 0xc4, 0xc8, 0x00, 0x00, 0x00]),
+            ("add rax, qword [rbp - 16]", &[0x48, 0x03, 0x45, 0xf0]),
+            ("sub rax, rsi", &[0x48, 0x29, 0xf0]),
+            ("sub rsp, 32", &[0x48, 0x83, 0xec, 0x20]),
+            ("sub rsp, 1024", &[0x48, 0x81, 0xec, 0x00, 0x04, 0x00, 0x00]),
+            ("sub rax, qword [rbp - 16]", &[0x48, 0x2b, 0x45, 0xf0]),
+            ("and rax, r11", &[0x4c, 0x21, 0xd8]),
+            ("and rax, 255", &[0x48, 0x25, 0xff, 0x00, 0x00, 0x00]),
+            ("and rsp, -16", &[0x48, 0x83, 0xe4, 0xf0]),
+            ("or rax, r11", &[0x4c, 0x09, 0xd8]),
+            ("or rax, 1", &[0x48, 0x83, 0xc8, 0x01]),
+            ("xor rax, r11", &[0x4c, 0x31, 0xd8]),
+            ("xor rax, 7", &[0x48, 0x83, 0xf0, 0x07]),
+            ("xor al, al", &[0x30, 0xc0]),
+            ("imul rax, rsi", &[0x48, 0x0f, 0xaf, 0xc6]),
+            ("imul rax, r11", &[0x49, 0x0f, 0xaf, 0xc3]),
+            ("imul rax, 3", &[0x48, 0x6b, 0xc0, 0x03]),
+            (
+                "imul rax, 1000",
+                &[0x48, 0x69, 0xc0, 0xe8, 0x03, 0x00, 0x00],
+            ),
+            (
+                "imul rax, qword [rbp - 64]",
+                &[0x48, 0x0f, 0xaf, 0x45, 0xc0],
+            ),
+            ("sal rax, 3", &[0x48, 0xc1, 0xe0, 0x03]),
+            ("sar rax, 63", &[0x48, 0xc1, 0xf8, 0x3f]),
+            ("sal rax, cl", &[0x48, 0xd3, 0xe0]),
+            ("sar rax, cl", &[0x48, 0xd3, 0xf8]),
+            ("neg rax", &[0x48, 0xf7, 0xd8]),
+            ("not rax", &[0x48, 0xf7, 0xd0]),
+            ("cqo", &[0x48, 0x99]),
+            ("idiv r11", &[0x49, 0xf7, 0xfb]),
+        ]);
+    }
+
+    /// Comparisons and the `setcc`/`movzx` pairs that turn them into values.
+    #[test]
+    fn comparisons() {
+        check(&[
+            ("cmp rax, rsi", &[0x48, 0x39, 0xf0]),
+            ("cmp rax, 0", &[0x48, 0x83, 0xf8, 0x00]),
+            ("cmp rax, 100000", &[0x48, 0x3d, 0xa0, 0x86, 0x01, 0x00]),
+            (
+                "cmp rax, qword [rbp - 200]",
+                &[0x48, 0x3b, 0x85, 0x38, 0xff, 0xff, 0xff],
+            ),
+            ("cmp r11, [r10]", &[0x4d, 0x3b, 0x1a]),
+            ("sete al", &[0x0f, 0x94, 0xc0]),
+            ("setne al", &[0x0f, 0x95, 0xc0]),
+            ("setg al", &[0x0f, 0x9f, 0xc0]),
+            ("setge al", &[0x0f, 0x9d, 0xc0]),
+            ("setl al", &[0x0f, 0x9c, 0xc0]),
+            ("setle al", &[0x0f, 0x9e, 0xc0]),
+            ("movzx eax, al", &[0x0f, 0xb6, 0xc0]),
+            (
+                "movzx eax, byte [r10 + r11]",
+                &[0x43, 0x0f, 0xb6, 0x04, 0x1a],
+            ),
+        ]);
+    }
+
+    /// Prologues, epilogues, pushes around calls and address computations.
+    #[test]
+    fn frames() {
+        check(&[
+            ("push rbp", &[0x55]),
+            ("push rax", &[0x50]),
+            ("push r9", &[0x41, 0x51]),
+            ("push r12", &[0x41, 0x54]),
+            ("pop rbp", &[0x5d]),
+            ("pop r9", &[0x41, 0x59]),
+            ("pop r15", &[0x41, 0x5f]),
+            ("lea rax, [rbp - 72]", &[0x48, 0x8d, 0x45, 0xb8]),
+            (
+                "lea rax, [rbp - 400]",
+                &[0x48, 0x8d, 0x85, 0x70, 0xfe, 0xff, 0xff],
+            ),
+            ("lea rax, [r10 + 8]", &[0x49, 0x8d, 0x42, 0x08]),
+            ("leave", &[0xc9]),
+            ("ret", &[0xc3]),
+        ]);
+    }
+
+    /// Scalar double arithmetic and moves between XMM and general registers.
+    #[test]
+    fn floats() {
+        check(&[
+            (
+                "movsd xmm0, qword [rbp - 16]",
+                &[0xf2, 0x0f, 0x10, 0x45, 0xf0],
+            ),
+            (
+                "movsd xmm1, qword [rbp - 200]",
+                &[0xf2, 0x0f, 0x10, 0x8d, 0x38, 0xff, 0xff, 0xff],
+            ),
+            (
+                "movsd qword [rbp - 8], xmm0",
+                &[0xf2, 0x0f, 0x11, 0x45, 0xf8],
+            ),
+            ("movsd [rsp], xmm0", &[0xf2, 0x0f, 0x11, 0x04, 0x24]),
+            ("movsd [rbp + 16], xmm0", &[0xf2, 0x0f, 0x11, 0x45, 0x10]),
+            ("movsd xmm0, xmm1", &[0xf2, 0x0f, 0x10, 0xc1]),
+            ("movsd xmm1, xmm0", &[0xf2, 0x0f, 0x10, 0xc8]),
+            ("movsd xmm7, xmm0", &[0xf2, 0x0f, 0x10, 0xf8]),
+            ("addsd xmm0, xmm1", &[0xf2, 0x0f, 0x58, 0xc1]),
+            ("subsd xmm0, xmm1", &[0xf2, 0x0f, 0x5c, 0xc1]),
+            ("mulsd xmm0, xmm1", &[0xf2, 0x0f, 0x59, 0xc1]),
+            ("divsd xmm0, xmm1", &[0xf2, 0x0f, 0x5e, 0xc1]),
+            (
+                "addsd xmm0, qword [rbp - 128]",
+                &[0xf2, 0x0f, 0x58, 0x45, 0x80],
+            ),
+            (
+                "subsd xmm0, qword [rbp - 64]",
+                &[0xf2, 0x0f, 0x5c, 0x45, 0xc0],
+            ),
+            (
+                "mulsd xmm0, qword [rbp - 32]",
+                &[0xf2, 0x0f, 0x59, 0x45, 0xe0],
+            ),
+            (
+                "divsd xmm0, qword [rbp - 56]",
+                &[0xf2, 0x0f, 0x5e, 0x45, 0xc8],
+            ),
+            ("ucomisd xmm0, xmm1", &[0x66, 0x0f, 0x2e, 0xc1]),
+            ("movq xmm0, rax", &[0x66, 0x48, 0x0f, 0x6e, 0xc0]),
+            ("movq rax, xmm0", &[0x66, 0x48, 0x0f, 0x7e, 0xc0]),
+            ("movq xmm3, r12", &[0x66, 0x49, 0x0f, 0x6e, 0xdc]),
+            ("movq rbx, xmm1", &[0x66, 0x48, 0x0f, 0x7e, 0xcb]),
+        ]);
+    }
+
+    /// Jumps are short when the target is within reach of a byte and long otherwise.
+    #[test]
+    fn branches() {
+        assert_eq!(text("top:\njmp top"), [0xeb, 0xfe]);
+        assert_eq!(text("jmp done\ndone:\nret"), [0xeb, 0x00, 0xc3]);
+        assert_eq!(
+            text("cmp rax, 0\nje skip\nret\nskip:\nret"),
+            [0x48, 0x83, 0xf8, 0x00, 0x74, 0x01, 0xc3, 0xc3]
+        );
+        assert_eq!(
+            text("top:\nret\nret\nret\njb top"),
+            [0xc3, 0xc3, 0xc3, 0x72, 0xfb]
+        );
+        assert_eq!(
+            text("call f\nret\nf:\nret"),
+            [0xe8, 0x01, 0, 0, 0, 0xc3, 0xc3]
+        );
+
+        let far = format!("je far\n{}far:\nret", "ret\n".repeat(200));
+        assert_eq!(text(&far)[..6], [0x0f, 0x84, 0xc8, 0x00, 0x00, 0x00]);
+        let back = format!("top:\n{}jmp top", "ret\n".repeat(200));
+        assert_eq!(text(&back)[200..], [0xe9, 0x33, 0xff, 0xff, 0xff]);
+    }
+}
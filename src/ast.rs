@@ -2,21 +2,54 @@ use crate::token::{Literal, TokenType, VarType};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Program {
+    pub imports: Vec<Import>,
     pub body: Vec<Expr>,
 }
 
+/// `import name` when `names` is `None`, otherwise `from name import a, b`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Import {
+    pub module: String,
+    pub names: Option<Vec<String>>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Stmt {
     pub body: Vec<Expr>,
 }
 
+/// A position in a source file, with rows and columns counted from 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pos {
+    pub row: usize,
+    pub col: usize,
+}
+
+/// The source an expression was parsed from, up to but not including `end`. `file`
+/// indexes the `SourceMap` of the compilation, since `$import` can paste the code of
+/// other files into a module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub file: usize,
+    pub start: Pos,
+    pub end: Pos,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExprKind {
     Stmt(Stmt),
     Val(Val),
     Var(Var),
     ArrayAccess(ArrayAccess),
     ArrayAssign(ArrayAssign),
+    Slice(Slice),
     VarDecl(VarDecl),
     VarMod(VarMod),
     BinOp(BinOp),
@@ -29,7 +62,21 @@ pub enum Expr {
     Return(Return),
     Label(Label),
     Goto(Goto),
+    Break(Break),
+    Continue(Continue),
     Extern(Extern),
+    StructDecl(StructDecl),
+    StructLit(StructLit),
+    FieldAccess(FieldAccess),
+    FieldAssign(FieldAssign),
+    Panic(Panic),
+    Format(Format),
+}
+
+impl ExprKind {
+    pub fn at(self, span: Span) -> Expr {
+        Expr { kind: self, span }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -47,7 +94,8 @@ pub struct Var {
 pub struct VarDecl {
     pub name: String,
     pub value: Box<Expr>,
-    pub typ: VarType,
+    /// `None` until the type checker infers it for a `let` without an annotation.
+    pub typ: Option<VarType>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -96,13 +144,23 @@ pub struct FuncDecl {
     pub body: Box<Expr>,
     pub ret_type: VarType,
     pub is_pub: bool,
+    pub inline: Inline,
+}
+
+/// The `inline` or `noinline` attribute of a function.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Inline {
+    /// Inlined at `-O2` when it is small enough.
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct FuncCall {
     pub name: String,
     pub args: Vec<Expr>,
-    pub ret_type: VarType,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -120,6 +178,16 @@ pub struct Goto {
     pub label: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Break {
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Continue {
+    pub label: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ArrayAccess {
     pub array: String,
@@ -133,9 +201,82 @@ pub struct ArrayAssign {
     pub value: Box<Expr>,
 }
 
+/// `string[start ~ end]`, the bytes from `start` up to but not including `end`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Slice {
+    pub string: String,
+    pub start: Box<Expr>,
+    pub end: Box<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Panic {
+    pub message: Box<Expr>,
+}
+
+/// `format("x = {}", x)`. Each `{}` in the template stands for the next argument,
+/// and `{{` and `}}` for a brace.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Format {
+    pub template: String,
+    pub args: Vec<Expr>,
+}
+
+impl Format {
+    /// The text around the placeholders, one piece more than there are of them.
+    pub fn pieces(&self) -> Result<Vec<String>, String> {
+        let mut pieces = vec![String::new()];
+        let mut chars = self.template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('}')) => {
+                    chars.next();
+                    pieces.push(String::new());
+                }
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    pieces.last_mut().unwrap().push(c);
+                }
+                ('{' | '}', _) => return Err(format!("unmatched '{}' in format string", c)),
+                _ => pieces.last_mut().unwrap().push(c),
+            }
+        }
+        Ok(pieces)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Extern {
     pub name: String,
     pub params: Vec<VarType>,
     pub ret_type: VarType,
+    pub is_pub: bool,
+    /// Declared with a trailing `...`, taking any number of further arguments.
+    pub is_variadic: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<(String, VarType)>,
+    pub is_pub: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StructLit {
+    pub name: String,
+    pub fields: Vec<(String, Expr)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FieldAccess {
+    pub object: Box<Expr>,
+    pub field: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FieldAssign {
+    pub object: Box<Expr>,
+    pub field: String,
+    pub value: Box<Expr>,
 }
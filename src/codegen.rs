@@ -1,13 +1,18 @@
 use ordered_float::OrderedFloat;
 
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::dwarf::{DebugInfo, Variable};
 use crate::ir::{IRConst, IRFunction, IRProgram, IRType, Instruction, Op, Operand};
+use crate::irgen::BOUNDS_PANIC;
+use crate::regalloc::{self, Allocation};
 use std::{collections::HashMap, mem::take};
 
 #[derive(Debug, Clone)]
 pub enum CodeGenError {
-    MissingOperand { message: String },
-    InvalidOperand { message: String },
-    UnsupportedOperation { message: String },
+    MissingOperand { message: String, span: Option<Span> },
+    InvalidOperand { message: String, span: Option<Span> },
+    UnsupportedOperation { message: String, span: Option<Span> },
 }
 
 impl std::error::Error for CodeGenError {}
@@ -15,21 +20,50 @@ impl std::error::Error for CodeGenError {}
 impl std::fmt::Display for CodeGenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CodeGenError::MissingOperand { message } => write!(f, "Missing operand: {}", message),
-            CodeGenError::InvalidOperand { message } => write!(f, "Invalid operand: {}", message),
-            CodeGenError::UnsupportedOperation { message } => {
+            CodeGenError::MissingOperand { message, .. } => {
+                write!(f, "Missing operand: {}", message)
+            }
+            CodeGenError::InvalidOperand { message, .. } => {
+                write!(f, "Invalid operand: {}", message)
+            }
+            CodeGenError::UnsupportedOperation { message, .. } => {
                 write!(f, "Unsupported operation: {}", message)
             }
         }
     }
 }
 
+impl CodeGenError {
+    /// Places the error at the source of the instruction being compiled.
+    fn at(mut self, at: Option<Span>) -> Self {
+        let (CodeGenError::MissingOperand { span, .. }
+        | CodeGenError::InvalidOperand { span, .. }
+        | CodeGenError::UnsupportedOperation { span, .. }) = &mut self;
+        *span = span.or(at);
+        self
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (CodeGenError::MissingOperand { span, .. }
+        | CodeGenError::InvalidOperand { span, .. }
+        | CodeGenError::UnsupportedOperation { span, .. }) = self;
+        let diagnostic = Diagnostic::error(self.to_string());
+        match span {
+            Some(span) => diagnostic.label(*span, ""),
+            None => diagnostic,
+        }
+    }
+}
+
 macro_rules! assemble {
     ($buf:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
         $buf.push_str(&format!(concat!($fmt, "\n") $(, $arg)*))
     };
 }
 
+/// The arguments of a call, each with the `Arg` or `FArg` that passes it.
+type Args = Vec<(Op, Operand)>;
+
 pub struct CodeGen {
     program: IRProgram,
     text: String,
@@ -45,11 +79,20 @@ pub struct CodeGen {
     regs: HashMap<String, Option<Operand>>,
     curr_fn: String,
     loop_label: String,
-    curr_flt_reg: usize,
+    /// Number of the current function's parameters passed on the stack.
+    stack_params: usize,
+    alloc: Allocation,
+    args: Args,
+    /// Functions compiled so far, in the order of the text section.
+    compiled: Vec<String>,
+    /// Debug information, when it is asked for.
+    debug: Option<DebugInfo>,
+    /// Position of the last row of the line table.
+    loc: Option<Span>,
 }
 
 impl CodeGen {
-    pub fn new(program: IRProgram) -> Self {
+    pub fn new(program: IRProgram, debug: bool) -> Self {
         Self {
             program,
             text: String::new(),
@@ -89,7 +132,12 @@ impl CodeGen {
             regs: HashMap::new(),
             curr_fn: String::new(),
             loop_label: String::new(),
-            curr_flt_reg: 0,
+            stack_params: 0,
+            alloc: Allocation::default(),
+            args: Vec::new(),
+            compiled: Vec::new(),
+            debug: debug.then(DebugInfo::new),
+            loc: None,
         }
     }
 
@@ -101,7 +149,24 @@ impl CodeGen {
         for func in take(&mut self.program.functions) {
             self.compile_fn(func)?;
         }
-        Ok(take(&mut self.data) + &self.optim(self.text.clone()))
+        // Backtraces look return addresses up in the table that the linker puts
+        // together from the one of every object.
+        assemble!(
+            self.text,
+            "section alum_symtab progbits alloc noexec nowrite align=8"
+        );
+        for name in take(&mut self.compiled) {
+            let label = self.alloc_str(name.clone());
+            assemble!(self.text, "dq {0}, {0}.L_{0}_end, {1}", name, label);
+        }
+        let debug = match &self.debug {
+            Some(debug) => {
+                let dir = std::env::current_dir().unwrap_or_default();
+                format!("\n{}", debug.emit(&dir.display().to_string()))
+            }
+            None => String::new(),
+        };
+        Ok(take(&mut self.data) + &self.optim(self.text.clone()) + &debug)
     }
 
     fn optim(&mut self, src: String) -> String {
@@ -141,7 +206,7 @@ impl CodeGen {
         if opt == src { opt } else { self.optim(opt) }
     }
 
-    fn compile_code(&mut self, code: Instruction) -> Result<(), CodeGenError> {
+    fn compile_code(&mut self, index: usize, code: Instruction) -> Result<(), CodeGenError> {
         match code.op {
             Op::Move => {
                 let src = code
@@ -149,26 +214,17 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Move operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let dst = code
                     .dst
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Move operation requires dst".to_string(),
+                        span: None,
                     })?;
 
-                self.load(src, "rax")?;
-
-                if match src {
-                    Operand::Var(_) | Operand::Temp(_, _) => {
-                        self.get_offset(src)? != self.get_offset(dst)?
-                    }
-                    _ => true,
-                } {
-                    assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
-                }
-
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.move_to(src, dst)?;
                 Ok(())
             }
             Op::FMove => {
@@ -177,26 +233,20 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "FMove operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let dst = code
                     .dst
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "FMove operation requires dst".to_string(),
+                        span: None,
                     })?;
 
                 self.load(src, "xmm0")?;
-
-                if match src {
-                    Operand::Var(_) | Operand::Temp(_, _) => {
-                        self.get_offset(src)? != self.get_offset(dst)?
-                    }
-                    _ => true,
-                } {
-                    assemble!(self.text, "movsd [rbp - {}], xmm0", self.get_offset(dst)?);
+                if self.home(src).ok() != Some(self.home(dst)?) {
+                    self.store("xmm0", dst)?;
                 }
-
-                self.regs.insert("xmm0".to_string(), Some(dst.clone()));
                 Ok(())
             }
             Op::Load | Op::Store => {
@@ -205,16 +255,16 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Load/Store operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let dst = code
                     .dst
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Load/Store operation requires dst".to_string(),
+                        span: None,
                     })?;
-                self.load(src, "rax")?;
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.move_to(src, dst)?;
                 Ok(())
             }
 
@@ -224,74 +274,105 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "FLoad/FStore operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let dst = code
                     .dst
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "FLoad/FStore operation requires dst".to_string(),
+                        span: None,
                     })?;
                 self.load(src, "xmm0")?;
-                assemble!(self.text, "movsd [rbp - {}], xmm0", self.get_offset(dst)?);
-                self.regs.insert("xmm0".to_string(), Some(dst.clone()));
+                self.store("xmm0", dst)?;
                 Ok(())
             }
-            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::LAnd | Op::LOr | Op::Xor | Op::Not => {
+            Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Mod
+            | Op::Shl
+            | Op::Shr
+            | Op::LAnd
+            | Op::LOr
+            | Op::Xor => {
                 let dst = code
                     .dst
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Binary operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Binary operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Binary operation requires src2".to_string(),
+                        span: None,
                     })?;
                 let asm_op = self.get_asm_op(&code.op).to_string();
                 self.load(src1, "rax");
 
-                match src2 {
-                    Operand::ConstIdx(idx) => {
-                        if let IRConst::Int(v) = &self.program.constants[*idx] {
-                            assemble!(self.text, "{} rax, {}", asm_op, v);
-                        }
+                let divide = matches!(code.op, Op::Div | Op::Mod);
+                let shift = matches!(code.op, Op::Shl | Op::Shr);
+
+                // Immediates are sign-extended from 32 bits, and `idiv` takes none.
+                let imm = match src2 {
+                    Operand::ConstIdx(idx) => match &self.program.constants[*idx] {
+                        IRConst::Int(v) => Some(*v),
+                        _ => None,
+                    },
+                    Operand::Const(IRConst::Int(v)) => Some(*v),
+                    _ => None,
+                }
+                .filter(|v| i32::try_from(*v).is_ok() && !divide);
+
+                match (imm, src2) {
+                    // The count of a shift is taken modulo 64.
+                    (Some(v), _) if shift => {
+                        assemble!(self.text, "{} rax, {}", asm_op, v & 63);
                     }
-                    Operand::Const(IRConst::Int(v)) => {
+                    (Some(v), _) => {
                         assemble!(self.text, "{} rax, {}", asm_op, v);
                     }
-                    Operand::Var(_) | Operand::Temp(_, _) => {
-                        let off = self.get_offset(src2)?;
-                        if matches!(code.op, Op::Div) {
-                            self.load(src2, "rbx")?;
+                    (None, Operand::Var(_) | Operand::Temp(_, _)) if !divide && !shift => {
+                        assemble!(self.text, "{} rax, {}", asm_op, self.home(src2)?);
+                    }
+                    _ => {
+                        self.load(src2, "r11")?;
+                        if divide {
                             assemble!(self.text, "cqo");
-                            assemble!(self.text, "idiv rbx");
+                            assemble!(self.text, "idiv r11");
+                            if matches!(code.op, Op::Mod) {
+                                assemble!(self.text, "mov rax, rdx");
+                            }
+                        } else if shift {
+                            // A variable count has to be in `cl`, and `rcx` may hold a value.
+                            assemble!(self.text, "mov r10, rcx");
+                            assemble!(self.text, "mov rcx, r11");
+                            assemble!(self.text, "{} rax, cl", asm_op);
+                            assemble!(self.text, "mov rcx, r10");
+                            self.regs.remove("r10");
                         } else {
-                            assemble!(self.text, "{} rax, qword [rbp - {}]", asm_op, off);
+                            assemble!(self.text, "{} rax, r11", asm_op);
                         }
                     }
-                    _ => {
-                        self.load(src2, "rbx");
-                        assemble!(self.text, "{} rax, rbx", asm_op);
-                    }
                 }
 
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
-
                 self.regs.remove("rax");
                 self.regs.remove("rdx");
-                if matches!(code.op, Op::Div) {
-                    self.regs.remove("rbx");
+                if divide {
+                    self.regs.remove("r11");
                 }
-
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.store("rax", dst)?;
                 Ok(())
             }
             Op::FAdd | Op::FSub | Op::FMul | Op::FDiv => {
@@ -300,18 +381,21 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Float binary operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Float binary operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Float binary operation requires src2".to_string(),
+                        span: None,
                     })?;
 
                 let fasm_op = self.get_fasm_op(&code.op).to_string();
@@ -327,8 +411,7 @@ impl CodeGen {
                     }
 
                     Operand::Var(_) | Operand::Temp(_, _) => {
-                        let off = self.get_offset(src2)?;
-                        assemble!(self.text, "{} xmm0, qword [rbp - {}]", fasm_op, off);
+                        assemble!(self.text, "{} xmm0, {}", fasm_op, self.home(src2)?);
                     }
                     _ => {
                         self.load(src2, "xmm1")?;
@@ -336,9 +419,7 @@ impl CodeGen {
                     }
                 }
 
-                assemble!(self.text, "movsd [rbp - {}], xmm0", self.get_offset(dst)?);
-
-                self.regs.insert("xmm0".to_string(), Some(dst.clone()));
+                self.store("xmm0", dst)?;
                 Ok(())
             }
             Op::Eq | Op::Ne | Op::Gt | Op::Ge | Op::Lt | Op::Le => {
@@ -347,22 +428,25 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Comparison operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Comparison operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Comparison operation requires src2".to_string(),
+                        span: None,
                     })?;
                 self.load(src1, "rax")?;
-                self.load(src2, "rbx")?;
-                assemble!(self.text, "cmp rax, rbx");
+                let rhs = self.operand(src2, "r11")?;
+                assemble!(self.text, "cmp rax, {}", rhs);
                 let set_op = match code.op {
                     Op::Eq => "sete",
                     Op::Ne => "setne",
@@ -376,9 +460,8 @@ impl CodeGen {
                 };
                 assemble!(self.text, "{} al", set_op);
                 assemble!(self.text, "movzx eax, al");
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
                 self.regs.clear();
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.store("rax", dst)?;
                 Ok(())
             }
             Op::FEq | Op::FNe | Op::FGt | Op::FGe | Op::FLt | Op::FLe => {
@@ -387,18 +470,21 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Float comparison operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Float comparison operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Float comparison operation requires src2".to_string(),
+                        span: None,
                     })?;
                 self.load(src1, "xmm0")?;
                 self.load(src2, "xmm1")?;
@@ -414,9 +500,8 @@ impl CodeGen {
                 };
                 assemble!(self.text, "{} al", set_op);
                 assemble!(self.text, "movzx eax, al");
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
                 self.regs.clear();
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.store("rax", dst)?;
                 Ok(())
             }
             Op::SizeOf => {
@@ -425,18 +510,41 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Unary operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Unary operation requires src1".to_string(),
+                        span: None,
                     })?;
                 self.load(src1, "rax");
                 assemble!(self.text, "mov rax, [rax]");
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
                 self.regs.clear();
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.store("rax", dst)?;
+                Ok(())
+            }
+            Op::Neg | Op::Not => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "Unary operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "Unary operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                let asm_op = self.get_asm_op(&code.op).to_string();
+                self.load(src1, "rax")?;
+                assemble!(self.text, "{} rax", asm_op);
+                self.regs.remove("rax");
+                self.store("rax", dst)?;
                 Ok(())
             }
             Op::FNeg => {
@@ -445,18 +553,19 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "FNeg operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "FNeg operation requires src1".to_string(),
+                        span: None,
                     })?;
                 self.load(src1, "xmm0");
                 assemble!(self.text, "xorpd xmm0, oword [rel neg_mask]");
-                assemble!(self.text, "movsd [rbp - {}], xmm0", self.get_offset(dst)?);
                 self.regs.clear();
-                self.regs.insert("xmm0".to_string(), Some(dst.clone()));
+                self.store("xmm0", dst)?;
                 Ok(())
             }
             Op::Range => {
@@ -465,60 +574,38 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Range operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Range operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Range operation requires src2".to_string(),
+                        span: None,
                     })?;
-                self.load(src1, "rdi");
-                self.load(src2, "rsi");
-                assemble!(self.text, "call range");
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
-                self.regs.clear();
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                let args = vec![(Op::Arg(0), src1.clone()), (Op::Arg(1), src2.clone())];
+                self.call(index, "range", args)?;
+                self.store("rax", dst)?;
                 Ok(())
             }
-            Op::Arg(n) => {
+            // Arguments are only loaded by their call, as computing one may need the
+            // registers of those before it.
+            Op::Arg(_) | Op::FArg(_) => {
                 let op = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Arg operation requires src1".to_string(),
+                        span: None,
                     })?;
-                if n < 6 {
-                    let reg = self.arg_reg[n].clone();
-                    self.load(op, &reg);
-                } else {
-                    self.load(op, "rax")?;
-                    assemble!(self.text, "push rax");
-                }
-                Ok(())
-            }
-            Op::FArg(n) => {
-                let op = code
-                    .src1
-                    .as_ref()
-                    .ok_or_else(|| CodeGenError::MissingOperand {
-                        message: "FArg operation requires src1".to_string(),
-                    })?;
-                if n < 8 {
-                    self.curr_flt_reg = n + 1;
-                    let reg = self.flt_arg_reg[n].clone();
-                    self.load(op, &reg);
-                } else {
-                    self.curr_flt_reg = 8;
-                    self.load(op, "xmm0")?;
-                    assemble!(self.text, "sub rsp, 8");
-                    assemble!(self.text, "movsd [rsp], xmm0");
-                }
+                self.args.push((code.op.clone(), op.clone()));
                 Ok(())
             }
             Op::Call => {
@@ -527,45 +614,23 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Call operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Call operation requires src1".to_string(),
+                        span: None,
                     })?;
                 if let Operand::Function(name) = src1 {
-                    if self.curr_flt_reg > 0 {
-                        assemble!(self.text, "mov al, {}", self.curr_flt_reg);
-                    } else {
-                        assemble!(self.text, "xor al, al");
-                    }
-                    self.curr_flt_reg = 0;
-
-                    assemble!(self.text, "call {}", name);
-
-                    let caller_saved =
-                        ["rax", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11"];
-                    for reg in caller_saved {
-                        self.regs.remove(reg);
-                    }
-                    for i in 0..16 {
-                        self.regs.remove(&format!("xmm{}", i));
-                    }
+                    let args = take(&mut self.args);
+                    self.call(index, name, args)?;
 
-                    let is_float = match dst {
-                        Operand::Temp(_, IRType::Float) => true,
-                        _ => false,
-                    };
-
-                    if is_float {
-                        assemble!(self.text, "movsd [rbp - {}], xmm0", self.get_offset(dst)?);
-
-                        self.regs.insert("xmm0".to_string(), Some(dst.clone()));
+                    if matches!(dst, Operand::Temp(_, IRType::Float)) {
+                        self.store("xmm0", dst)?;
                     } else {
-                        assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
-
-                        self.regs.insert("rax".to_string(), Some(dst.clone()));
+                        self.store("rax", dst)?;
                     }
                 }
                 Ok(())
@@ -581,6 +646,7 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "Jump operation requires src1".to_string(),
+                        span: None,
                     })?;
                 if let Operand::Label(lbl) = src1 {
                     assemble!(self.text, "jmp {}", lbl);
@@ -593,18 +659,21 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "JumpIfFalse operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "JumpIfFalse operation requires src2".to_string(),
+                        span: None,
                     })?;
                 let lbl = match src2 {
                     Operand::Label(s) => s,
                     _ => {
                         return Err(CodeGenError::InvalidOperand {
                             message: "JumpIfFalse src2 must be a Label".to_string(),
+                            span: None,
                         });
                     }
                 };
@@ -619,26 +688,27 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "ArrayAccess operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "ArrayAccess operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "ArrayAccess operation requires src2".to_string(),
+                        span: None,
                     })?;
                 self.load(src1, "r10")?;
-                self.load(src2, "rcx")?;
-                assemble!(self.text, "lea rax, [r10 + rcx * 8 + 8]");
-                assemble!(self.text, "mov rax, [rax]");
-                assemble!(self.text, "mov [rbp - {}], rax", self.get_offset(dst)?);
-                self.regs.clear();
-                self.regs.insert("rax".to_string(), Some(dst.clone()));
+                self.load(src2, "r11")?;
+                assemble!(self.text, "mov rax, [r10 + r11 * 8 + 8]");
+                self.regs.remove("rax");
+                self.store("rax", dst)?;
                 Ok(())
             }
             Op::ArrayAssign => {
@@ -647,24 +717,155 @@ impl CodeGen {
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "ArrayAssign operation requires dst".to_string(),
+                        span: None,
                     })?;
                 let src1 = code
                     .src1
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "ArrayAssign operation requires src1".to_string(),
+                        span: None,
                     })?;
                 let src2 = code
                     .src2
                     .as_ref()
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: "ArrayAssign operation requires src2".to_string(),
+                        span: None,
                     })?;
                 self.load(dst, "r10")?;
-                self.load(src1, "rcx")?;
+                self.load(src1, "r11")?;
                 self.load(src2, "rax")?;
-                assemble!(self.text, "lea rdx, [r10 + rcx * 8 + 8]");
-                assemble!(self.text, "mov [rdx], rax");
+                assemble!(self.text, "mov [r10 + r11 * 8 + 8], rax");
+                Ok(())
+            }
+            Op::ByteLoad => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "ByteLoad operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "ByteLoad operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                let src2 = code
+                    .src2
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "ByteLoad operation requires src2".to_string(),
+                        span: None,
+                    })?;
+                self.load(src1, "r10")?;
+                self.load(src2, "r11")?;
+                assemble!(self.text, "movzx eax, byte [r10 + r11]");
+                self.regs.remove("rax");
+                self.store("rax", dst)?;
+                Ok(())
+            }
+            Op::ByteStore => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "ByteStore operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "ByteStore operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                let src2 = code
+                    .src2
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "ByteStore operation requires src2".to_string(),
+                        span: None,
+                    })?;
+                self.load(dst, "r10")?;
+                self.load(src1, "r11")?;
+                self.load(src2, "rax")?;
+                assemble!(self.text, "mov [r10 + r11], al");
+                Ok(())
+            }
+            Op::BoundsCheck(file, line) => {
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "BoundsCheck operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                let src2 = code
+                    .src2
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "BoundsCheck operation requires src2".to_string(),
+                        span: None,
+                    })?;
+                // A negative index compares as a huge unsigned one. The panic routine
+                // does not return, so nothing needs to be saved around its call.
+                let ok = format!(".L_{}_in_bounds_{}", self.curr_fn, index);
+                let file = self.alloc_str(file.clone());
+                self.load(src1, "r10")?;
+                self.load(src2, "r11")?;
+                assemble!(self.text, "cmp r11, [r10]");
+                assemble!(self.text, "jb {}", ok);
+                assemble!(self.text, "lea rdi, [rel {}]", file);
+                assemble!(self.text, "mov rsi, {}", line);
+                assemble!(self.text, "mov rdx, r11");
+                assemble!(self.text, "mov rcx, [r10]");
+                assemble!(self.text, "and rsp, -16");
+                assemble!(self.text, "call {}", BOUNDS_PANIC);
+                assemble!(self.text, "{}:", ok);
+                Ok(())
+            }
+            Op::LoopBoundsCheck(file, line, first) => {
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "LoopBoundsCheck operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                let src2 = code
+                    .src2
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "LoopBoundsCheck operation requires src2".to_string(),
+                        span: None,
+                    })?;
+                // The first index is not negative, so only the last one is compared.
+                // The index reported is the first to fail, as the loop itself would.
+                let ok = format!(".L_{}_in_bounds_{}", self.curr_fn, index);
+                let file = self.alloc_str(file.clone());
+                self.load(src1, "r10")?;
+                self.load(src2, "r11")?;
+                assemble!(self.text, "test r11, r11");
+                assemble!(self.text, "jle {}", ok);
+                if first != 1 {
+                    assemble!(self.text, "add r11, {}", first - 1);
+                    self.regs.remove("r11");
+                }
+                assemble!(self.text, "cmp r11, [r10]");
+                assemble!(self.text, "jb {}", ok);
+                assemble!(self.text, "lea rdi, [rel {}]", file);
+                assemble!(self.text, "mov rsi, {}", line);
+                assemble!(self.text, "mov rdx, {}", first);
+                assemble!(self.text, "cmp rdx, [r10]");
+                assemble!(self.text, "cmovl rdx, [r10]");
+                assemble!(self.text, "mov rcx, [r10]");
+                assemble!(self.text, "and rsp, -16");
+                assemble!(self.text, "call {}", BOUNDS_PANIC);
+                assemble!(self.text, "{}:", ok);
                 Ok(())
             }
             Op::Return(reg) => {
@@ -674,54 +875,162 @@ impl CodeGen {
                 assemble!(self.text, "jmp {}", self.ret_label);
                 Ok(())
             }
+            Op::StructAlloc(_) => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "StructAlloc operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let block = match dst {
+                    Operand::Temp(id, _) => self
+                        .vars
+                        .get(&format!("_struct_{}", id))
+                        .copied()
+                        .ok_or_else(|| CodeGenError::MissingOperand {
+                            message: format!("struct storage for '_tmp_{}' not found", id),
+                            span: None,
+                        })?,
+                    _ => {
+                        return Err(CodeGenError::InvalidOperand {
+                            message: "StructAlloc dst must be a Temp".to_string(),
+                            span: None,
+                        });
+                    }
+                };
+                assemble!(self.text, "lea rax, [rbp - {}]", block);
+                self.regs.remove("rax");
+                self.store("rax", dst)?;
+                Ok(())
+            }
+            Op::StructCopy(size) => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "StructCopy operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "StructCopy operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                self.load(src1, "r10")?;
+                self.load(dst, "r11")?;
+                for i in (0..size).step_by(8) {
+                    assemble!(self.text, "mov rax, [r10 + {}]", i);
+                    assemble!(self.text, "mov [r11 + {}], rax", i);
+                }
+                self.regs.remove("rax");
+                Ok(())
+            }
+            Op::FieldLoad(off) | Op::FieldAddr(off) => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "Field operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "Field operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                self.load(src1, "r10")?;
+                if matches!(code.op, Op::FieldAddr(_)) {
+                    assemble!(self.text, "lea rax, [r10 + {}]", off);
+                } else {
+                    assemble!(self.text, "mov rax, [r10 + {}]", off);
+                }
+                self.regs.remove("rax");
+                self.store("rax", dst)?;
+                Ok(())
+            }
+            Op::FieldStore(off) => {
+                let dst = code
+                    .dst
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "FieldStore operation requires dst".to_string(),
+                        span: None,
+                    })?;
+                let src1 = code
+                    .src1
+                    .as_ref()
+                    .ok_or_else(|| CodeGenError::MissingOperand {
+                        message: "FieldStore operation requires src1".to_string(),
+                        span: None,
+                    })?;
+                self.load(src1, "rax")?;
+                self.load(dst, "r10")?;
+                assemble!(self.text, "mov [r10 + {}], rax", off);
+                Ok(())
+            }
             _ => Err(CodeGenError::UnsupportedOperation {
                 message: format!("unsupported operation {:?}", code.op),
+                span: None,
             }),
         }
     }
 
     fn compile_fn(&mut self, func: IRFunction) -> Result<(), CodeGenError> {
-        if func.is_external {
+        if func.is_external || func.is_imported {
             assemble!(self.text, "extern {}", func.name);
             return Ok(());
         }
 
         self.vars.clear();
         self.regs.clear();
+        self.alloc = regalloc::allocate(&func, &self.program.constants);
+        if self.debug.is_some() {
+            // A register is shared by values that are not live at the same time, so
+            // the variables stay in their stack slot, where debuggers always find them.
+            let names: Vec<String> = (func.params.iter().filter_map(|(p, _)| regalloc::key(p)))
+                .chain(func.locals.iter().map(|(name, _)| name.clone()))
+                .collect();
+            self.alloc.homes.retain(|key, _| !names.contains(key));
+        }
         let mut offset = 0;
 
-        for (param, _) in &func.params {
-            if let Operand::Var(name) = param {
-                if !self.vars.contains_key(name) {
-                    offset += 8;
-                    self.vars.insert(name.clone(), offset);
-                }
+        let mut register_op = |vars: &mut HashMap<String, usize>, op_opt: &Option<Operand>| {
+            if let Some(key) = op_opt.as_ref().and_then(regalloc::key)
+                && !self.alloc.homes.contains_key(&key)
+                && !vars.contains_key(&key)
+            {
+                offset += 8;
+                vars.insert(key, offset);
             }
+        };
+        for (param, _) in &func.params {
+            register_op(&mut self.vars, &Some(param.clone()));
         }
         for inst in &func.instructions {
-            let mut register_op = |op_opt: &Option<Operand>| {
-                if let Some(op) = op_opt {
-                    match op {
-                        Operand::Var(name) => {
-                            if !self.vars.contains_key(name) {
-                                offset += 8;
-                                self.vars.insert(name.clone(), offset);
-                            }
-                        }
-                        Operand::Temp(id, _) => {
-                            let temp_key = format!("_tmp_{}", id);
-                            if !self.vars.contains_key(&temp_key) {
-                                offset += 8;
-                                self.vars.insert(temp_key, offset);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            };
-            register_op(&inst.dst);
-            register_op(&inst.src1);
-            register_op(&inst.src2);
+            register_op(&mut self.vars, &inst.dst);
+            register_op(&mut self.vars, &inst.src1);
+            register_op(&mut self.vars, &inst.src2);
+        }
+        for inst in &func.instructions {
+            // Struct storage lives in the frame itself, so a literal in a loop reuses
+            // the same block instead of growing the stack on every iteration.
+            if let (Op::StructAlloc(size), Some(Operand::Temp(id, _))) = (&inst.op, &inst.dst) {
+                let key = format!("_struct_{}", id);
+                self.vars.entry(key).or_insert_with(|| {
+                    offset += (size + 7) & !7;
+                    offset
+                });
+            }
+        }
+        let callee_saved = self.alloc.callee_saved();
+        for reg in &callee_saved {
+            offset += 8;
+            self.vars.insert(format!("_save_{}", reg), offset);
         }
 
         let stack_size = (offset + 15) & !15;
@@ -729,46 +1038,116 @@ impl CodeGen {
             assemble!(self.text, "global {}", func.name);
         }
         assemble!(self.text, "{}:", func.name);
+        if let Some(debug) = &mut self.debug {
+            debug.row(
+                func.name.clone(),
+                &self.program.files[func.span.file],
+                func.span,
+            );
+            self.loc = Some(func.span);
+        }
         assemble!(self.text, "push rbp");
         assemble!(self.text, "mov rbp, rsp");
         if stack_size > 0 {
             assemble!(self.text, "sub rsp, {}", stack_size);
         }
+        for reg in &callee_saved {
+            assemble!(
+                self.text,
+                "mov [rbp - {}], {}",
+                self.vars[&format!("_save_{}", reg)],
+                reg
+            );
+        }
 
-        let loop_label = format!(".L_{}_loop", func.name);
-        assemble!(self.text, "{}:", loop_label);
+        self.loop_label = format!(".L_{}_loop", func.name);
+        assemble!(self.text, "{}:", self.loop_label);
         self.curr_fn = func.name.clone();
         self.ret_label = format!(".L_{}_exit", func.name);
 
+        // Parameters past the registers are on the stack, above the return address.
         let mut int_idx = 0;
         let mut flt_idx = 0;
+        let mut moves = Vec::new();
+        let mut stack_params = Vec::new();
         for (param, ty) in &func.params {
-            let off = self.get_offset(param)?;
             if matches!(ty, IRType::Float) {
                 if flt_idx < 8 {
                     let reg = format!("xmm{}", flt_idx);
-                    assemble!(self.text, "movsd [rbp - {}], {}", off, reg);
-                    self.regs.insert(reg, Some(param.clone()));
-
+                    self.store(&reg, param)?;
                     flt_idx += 1;
+                } else {
+                    stack_params.push((param, "xmm0"));
                 }
+            } else if int_idx < 6 {
+                moves.push((self.home(param)?, self.arg_reg[int_idx].clone()));
+                int_idx += 1;
             } else {
-                if int_idx < 6 {
-                    let reg = self.arg_reg[int_idx].clone();
-                    assemble!(self.text, "mov [rbp - {}], {}", off, reg);
-                    self.regs.insert(reg, Some(param.clone()));
-
-                    int_idx += 1;
-                }
+                stack_params.push((param, "rax"));
+            }
+        }
+        self.parallel_move(moves);
+        self.stack_params = stack_params.len();
+        for (i, (param, reg)) in stack_params.into_iter().enumerate() {
+            let src = format!("qword [rbp + {}]", 16 + i * 8);
+            if reg == "xmm0" {
+                assemble!(self.text, "movsd xmm0, {}", src);
+            } else {
+                assemble!(self.text, "mov rax, {}", src);
             }
+            self.regs.remove(reg);
+            self.store(reg, param)?;
         }
 
+        // A tail call cannot reuse the frame while arguments may point into it.
+        let frame_escapes = func.instructions.iter().any(|inst| {
+            matches!(inst.op, Op::StructAlloc(_))
+                || [&inst.src1, &inst.src2]
+                    .into_iter()
+                    .flatten()
+                    .any(|op| match op {
+                        Operand::Const(IRConst::Array(..)) => true,
+                        Operand::ConstIdx(idx) => {
+                            matches!(self.program.constants[*idx], IRConst::Array(..))
+                        }
+                        _ => false,
+                    })
+        });
+
         let insts = &func.instructions;
+        let mut tail_called = false;
         for (i, code) in insts.iter().enumerate() {
+            if let (Some(debug), Some(span)) = (&mut self.debug, code.span)
+                && self.loc != Some(span)
+            {
+                let label = format!(".L_{}_loc_{}", func.name, debug.rows());
+                assemble!(self.text, "{}:", label);
+                debug.row(
+                    format!("{}{}", func.name, label),
+                    &self.program.files[span.file],
+                    span,
+                );
+                self.loc = Some(span);
+            }
             match &code.op {
+                Op::Call if returns_result(insts, i) => {
+                    let done = match &code.src1 {
+                        Some(Operand::Function(name)) => self
+                            .tail_call(name, frame_escapes)
+                            .map_err(|e| e.at(code.span))?,
+                        _ => false,
+                    };
+                    if !done {
+                        self.compile_code(i, code.clone())
+                            .map_err(|e| e.at(code.span))?;
+                    }
+                    tail_called = done && matches!(insts[i + 1].op, Op::Return(_));
+                }
+                // The result of a tail call is returned by the callee.
+                Op::Return(_) if tail_called => tail_called = false,
                 Op::Return(reg_name) => {
                     if let Some(ref val) = code.src1 {
-                        self.load(val, reg_name)?;
+                        self.load(val, reg_name).map_err(|e| e.at(code.span))?;
                     }
                     assemble!(self.text, "jmp {}", self.ret_label);
                 }
@@ -777,18 +1156,60 @@ impl CodeGen {
                     self.regs.clear();
                 }
                 _ => {
-                    self.compile_code(code.clone())?;
+                    self.compile_code(i, code.clone())
+                        .map_err(|e| e.at(code.span))?;
                 }
             }
         }
 
         assemble!(self.text, "{}:", self.ret_label);
+        self.restore_callee_saved();
         assemble!(self.text, "leave");
         assemble!(self.text, "ret");
+        assemble!(self.text, ".L_{}_end:", func.name);
+        if self.debug.is_some() {
+            self.describe_fn(&func);
+        }
+        self.compiled.push(func.name);
         Ok(())
     }
 
+    /// Adds a compiled function to the debug information, with its parameters and
+    /// local variables. Those the optimizer renamed have no slot of their own.
+    fn describe_fn(&mut self, func: &IRFunction) {
+        let variable = |name: &str, ty: &IRType| Variable {
+            name: name.to_string(),
+            ty: ty.clone(),
+            offset: self.vars.get(name).copied(),
+        };
+        let params = func
+            .params
+            .iter()
+            .filter_map(|(param, ty)| match param {
+                Operand::Var(name) if !name.starts_with('.') => Some(variable(name, ty)),
+                _ => None,
+            })
+            .collect();
+        let locals = func
+            .locals
+            .iter()
+            .map(|(name, ty)| variable(name, ty))
+            .collect();
+        if let Some(debug) = &mut self.debug {
+            debug.function(func, &self.program.files[func.span.file], params, locals);
+        }
+    }
+
+    /// Only scratch registers are tracked in `regs`; the others belong to the values
+    /// the allocator put there.
+    fn is_scratch(reg: &str) -> bool {
+        matches!(reg, "rax" | "rdx" | "r10" | "r11") || reg.starts_with("xmm")
+    }
+
     fn load(&mut self, op: &Operand, reg: &str) -> Result<(), CodeGenError> {
+        if !Self::is_scratch(reg) {
+            self.regs.remove(reg);
+        }
         if let Some(Some(cached_op)) = self.regs.get(reg) {
             if cached_op == op {
                 return Ok(());
@@ -840,11 +1261,13 @@ impl CodeGen {
             },
 
             Operand::Var(_) | Operand::Temp(_, _) => {
-                let off = self.get_offset(op)?;
-                if reg.starts_with("xmm") {
-                    assemble!(self.text, "movsd {}, qword [rbp - {}]", reg, off);
-                } else {
-                    assemble!(self.text, "mov {}, [rbp - {}]", reg, off);
+                let home = self.home(op)?;
+                if reg.starts_with("xmm") && !home.starts_with("qword") {
+                    assemble!(self.text, "movq {}, {}", reg, home);
+                } else if reg.starts_with("xmm") {
+                    assemble!(self.text, "movsd {}, {}", reg, home);
+                } else if home != reg {
+                    assemble!(self.text, "mov {}, {}", reg, home);
                 }
             }
 
@@ -855,10 +1278,225 @@ impl CodeGen {
             _ => {}
         }
 
-        self.regs.insert(reg.to_string(), Some(op.clone()));
+        if Self::is_scratch(reg) {
+            self.regs.insert(reg.to_string(), Some(op.clone()));
+        }
+        Ok(())
+    }
+
+    /// Writes `reg` to the home of `dst`. Scratch registers holding the old value of
+    /// `dst` no longer do.
+    fn store(&mut self, reg: &str, dst: &Operand) -> Result<(), CodeGenError> {
+        let home = self.home(dst)?;
+        if reg.starts_with("xmm") && !home.starts_with("qword") {
+            assemble!(self.text, "movq {}, {}", home, reg);
+        } else if reg.starts_with("xmm") {
+            assemble!(self.text, "movsd {}, {}", home, reg);
+        } else if home != reg {
+            assemble!(self.text, "mov {}, {}", home, reg);
+        }
+        self.regs.retain(|_, op| op.as_ref() != Some(dst));
+        if Self::is_scratch(reg) {
+            self.regs.insert(reg.to_string(), Some(dst.clone()));
+        }
+        Ok(())
+    }
+
+    /// Copies an integer value, straight into the register of `dst` if it has one.
+    fn move_to(&mut self, src: &Operand, dst: &Operand) -> Result<(), CodeGenError> {
+        let home = self.home(dst)?;
+        if home.starts_with("qword") {
+            self.load(src, "rax")?;
+            self.store("rax", dst)
+        } else {
+            self.load(src, &home)?;
+            self.regs.retain(|_, op| op.as_ref() != Some(dst));
+            Ok(())
+        }
+    }
+
+    /// Where a variable or temporary lives: its register or its stack slot.
+    fn home(&self, op: &Operand) -> Result<String, CodeGenError> {
+        match regalloc::key(op).and_then(|key| self.alloc.homes.get(&key)) {
+            Some(reg) => Ok(reg.to_string()),
+            None => Ok(format!("qword [rbp - {}]", self.get_offset(op)?)),
+        }
+    }
+
+    /// Returns something `op` can be read from as the second operand of an
+    /// instruction, loading it into `scratch` unless it is a variable or temporary.
+    fn operand(&mut self, op: &Operand, scratch: &str) -> Result<String, CodeGenError> {
+        match op {
+            Operand::Var(_) | Operand::Temp(_, _) => self.home(op),
+            _ => {
+                self.load(op, scratch)?;
+                Ok(scratch.to_string())
+            }
+        }
+    }
+
+    /// Performs register and memory moves as if they happened at once, so that no
+    /// move overwrites a register another one still has to read. Cycles are broken
+    /// through `rax`.
+    fn parallel_move(&mut self, moves: Vec<(String, String)>) {
+        let mut moves: Vec<(String, String)> = moves.into_iter().filter(|(d, s)| d != s).collect();
+        while !moves.is_empty() {
+            let ready = moves
+                .iter()
+                .position(|(dst, _)| !moves.iter().any(|(_, src)| src == dst));
+            match ready {
+                Some(i) => {
+                    let (dst, src) = moves.remove(i);
+                    assemble!(self.text, "mov {}, {}", dst, src);
+                }
+                None => {
+                    let blocked = moves[0].0.clone();
+                    assemble!(self.text, "mov rax, {}", blocked);
+                    self.regs.remove("rax");
+                    for (_, src) in moves.iter_mut().filter(|(_, src)| *src == blocked) {
+                        *src = "rax".to_string();
+                    }
+                }
+            }
+            moves.retain(|(d, s)| d != s);
+        }
+    }
+
+    /// Calls `name` with `args`. Caller-saved registers whose value is still needed
+    /// afterwards are pushed around the call, and the stack stays 16-byte aligned.
+    fn call(&mut self, index: usize, name: &str, args: Args) -> Result<(), CodeGenError> {
+        let saved = self.alloc.saved.get(&index).cloned().unwrap_or_default();
+        for reg in &saved {
+            assemble!(self.text, "push {}", reg);
+        }
+
+        let (stack, regs) = self.split_args(args);
+        let padding = (saved.len() + stack.len()) % 2 == 1;
+        if padding {
+            assemble!(self.text, "sub rsp, 8");
+        }
+        for (op, arg) in stack.iter().rev() {
+            if matches!(op, Op::FArg(_)) {
+                self.load(arg, "xmm0")?;
+                assemble!(self.text, "sub rsp, 8");
+                assemble!(self.text, "movsd [rsp], xmm0");
+            } else {
+                self.load(arg, "rax")?;
+                assemble!(self.text, "push rax");
+            }
+        }
+
+        let stack_floats = stack.iter().any(|(op, _)| matches!(op, Op::FArg(_)));
+        self.load_args(regs, stack_floats)?;
+        assemble!(self.text, "call {}", name);
+
+        let pushed = stack.len() + padding as usize;
+        if pushed > 0 {
+            assemble!(self.text, "add rsp, {}", pushed * 8);
+        }
+        for reg in saved.iter().rev() {
+            assemble!(self.text, "pop {}", reg);
+        }
+        self.regs.clear();
+        Ok(())
+    }
+
+    /// Splits the arguments of a call into those passed on the stack and those
+    /// passed in registers.
+    fn split_args(&self, args: Args) -> (Args, Args) {
+        args.into_iter().partition(|(op, _)| match op {
+            Op::Arg(n) => *n >= self.arg_reg.len(),
+            Op::FArg(n) => *n >= 8,
+            _ => false,
+        })
+    }
+
+    /// Loads the register arguments of a call and sets `al` to the number of XMM
+    /// registers used, as variadic functions expect.
+    fn load_args(&mut self, regs: Args, stack_floats: bool) -> Result<(), CodeGenError> {
+        // Values already in registers are moved first, as loading the others only
+        // writes to argument registers.
+        let mut flt_regs = 0;
+        let mut moves = Vec::new();
+        let mut loads = Vec::new();
+        for (op, arg) in regs {
+            match op {
+                Op::FArg(n) => {
+                    flt_regs = flt_regs.max(n + 1);
+                    let reg = self.flt_arg_reg[n].clone();
+                    self.load(&arg, &reg)?;
+                }
+                Op::Arg(n) => match regalloc::key(&arg).and_then(|k| self.alloc.homes.get(&k)) {
+                    Some(home) => moves.push((self.arg_reg[n].clone(), home.to_string())),
+                    None => loads.push((self.arg_reg[n].clone(), arg)),
+                },
+                _ => {}
+            }
+        }
+        if stack_floats {
+            flt_regs = 8;
+        }
+        self.parallel_move(moves);
+        for (reg, arg) in loads {
+            self.load(&arg, &reg)?;
+        }
+
+        if flt_regs > 0 {
+            assemble!(self.text, "mov al, {}", flt_regs);
+        } else {
+            assemble!(self.text, "xor al, al");
+        }
         Ok(())
     }
 
+    /// Compiles a call whose result is returned right away without growing the
+    /// stack, when nothing points into the frame. A call to the current function
+    /// reloads its parameters and jumps back to the top, while a call to another one
+    /// replaces this function's frame when its stack arguments fit where this
+    /// function's own were passed. Returns false if the call has to stay a normal one.
+    fn tail_call(&mut self, name: &str, frame_escapes: bool) -> Result<bool, CodeGenError> {
+        let args = take(&mut self.args);
+        let (stack, regs) = self.split_args(args);
+        if frame_escapes || stack.len() > self.stack_params {
+            self.args = stack.into_iter().chain(regs).collect();
+            return Ok(false);
+        }
+
+        // Arguments past the registers go where the caller put this function's own,
+        // which the prologue has already copied.
+        for (i, (op, arg)) in stack.iter().enumerate() {
+            let (reg, mov) = match op {
+                Op::FArg(_) => ("xmm0", "movsd"),
+                _ => ("rax", "mov"),
+            };
+            self.load(arg, reg)?;
+            assemble!(self.text, "{} [rbp + {}], {}", mov, 16 + i * 8, reg);
+        }
+        if name == self.curr_fn {
+            self.load_args(regs, false)?;
+            assemble!(self.text, "jmp {}", self.loop_label);
+        } else {
+            let stack_floats = stack.iter().any(|(op, _)| matches!(op, Op::FArg(_)));
+            self.load_args(regs, stack_floats)?;
+            self.restore_callee_saved();
+            assemble!(self.text, "leave");
+            assemble!(self.text, "jmp {}", name);
+        }
+        self.regs.clear();
+        Ok(true)
+    }
+
+    fn restore_callee_saved(&mut self) {
+        for reg in self.alloc.callee_saved() {
+            assemble!(
+                self.text,
+                "mov {}, [rbp - {}]",
+                reg,
+                self.vars[&format!("_save_{}", reg)]
+            );
+        }
+    }
+
     fn alloc_str(&mut self, s: String) -> String {
         if let Some(lbl) = self.str_cache.get(&s) {
             return lbl.clone();
@@ -905,6 +1543,7 @@ impl CodeGen {
                 .get(name)
                 .ok_or_else(|| CodeGenError::MissingOperand {
                     message: format!("variable '{}' not found in stack frame", name),
+                    span: None,
                 })
                 .map(|v| *v),
             Operand::Temp(id, _) => {
@@ -913,11 +1552,13 @@ impl CodeGen {
                     .get(&key)
                     .ok_or_else(|| CodeGenError::MissingOperand {
                         message: format!("temporary '{}' not found in stack frame", key),
+                        span: None,
                     })
                     .map(|v| *v)
             }
             _ => Err(CodeGenError::InvalidOperand {
                 message: "Not a stack operand".to_string(),
+                span: None,
             }),
         }
     }
@@ -942,9 +1583,12 @@ impl CodeGen {
             Op::Add => "add",
             Op::Sub => "sub",
             Op::Mul => "imul",
-            Op::LAnd | Op::Add => "and",
+            Op::Shl => "sal",
+            Op::Shr => "sar",
+            Op::LAnd => "and",
             Op::LOr | Op::Or => "or",
             Op::Xor => "xor",
+            Op::Neg => "neg",
             Op::Not => "not",
             _ => "",
         }
@@ -960,3 +1604,34 @@ impl CodeGen {
         }
     }
 }
+
+/// Whether the result of the call at `i` is returned unchanged. Inlining leaves
+/// copies and a jump to the join label of the inlined body between the two, which
+/// are followed to the return.
+fn returns_result(insts: &[Instruction], i: usize) -> bool {
+    let mut held = vec![insts[i].dst.clone()];
+    let mut j = i + 1;
+    // Bounds the walk, as jumps may loop.
+    for _ in 0..insts.len() {
+        let Some(inst) = insts.get(j) else {
+            return false;
+        };
+        match &inst.op {
+            Op::Return(_) => return inst.src1.is_some() && held.contains(&inst.src1),
+            Op::Move | Op::FMove if held.contains(&inst.src1) => held.push(inst.dst.clone()),
+            Op::Label(_) | Op::Nop => {}
+            Op::Jump => {
+                let Some(Operand::Label(label)) = &inst.src1 else {
+                    return false;
+                };
+                match insts.iter().position(|l| l.op == Op::Label(label.clone())) {
+                    Some(k) => j = k,
+                    None => return false,
+                }
+            }
+            _ => return false,
+        }
+        j += 1;
+    }
+    false
+}
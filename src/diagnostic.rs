@@ -0,0 +1,126 @@
+use crate::ast::Span;
+
+/// The source files of a compilation, which the `file` of a `Span` refers to.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<(String, String)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file unless it is there already, and returns its number.
+    pub fn add(&mut self, path: &str, text: &str) -> usize {
+        match self.files.iter().position(|(p, _)| p == path) {
+            Some(file) => file,
+            None => {
+                self.files.push((path.to_string(), text.to_string()));
+                self.files.len() - 1
+            }
+        }
+    }
+
+    pub fn path(&self, file: usize) -> &str {
+        self.files.get(file).map_or("<unknown>", |(path, _)| path)
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.files.iter().map(|(path, _)| path.clone()).collect()
+    }
+
+    fn line(&self, file: usize, row: usize) -> Option<&str> {
+        let (_, text) = self.files.get(file)?;
+        text.lines().nth(row.checked_sub(1)?)
+    }
+}
+
+/// An error pointing into the source, rendered the way rustc does: the message, the
+/// position of the first label, and each labelled line with its span underlined.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    message: String,
+    labels: Vec<(Span, String)>,
+    notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Points at `span`. The first label is the primary one, which the caret marks.
+    pub fn label(mut self, span: Span, text: impl Into<String>) -> Self {
+        self.labels.push((span, text.into()));
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = format!("error: {}", self.message);
+        let width = self
+            .labels
+            .iter()
+            .map(|(span, _)| span.start.row.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(width);
+
+        let mut file = None;
+        for (i, (span, text)) in self.labels.iter().enumerate() {
+            if file != Some(span.file) {
+                let arrow = if i == 0 { "-->" } else { ":::" };
+                out += &format!(
+                    "\n{}{} {}:{}:{}",
+                    gutter,
+                    arrow,
+                    sources.path(span.file),
+                    span.start.row,
+                    span.start.col
+                );
+                file = Some(span.file);
+            }
+            let Some(line) = sources.line(span.file, span.start.row) else {
+                continue;
+            };
+            // Tabs are kept in front of the underline so that it lines up with the line.
+            let indent: String = line
+                .chars()
+                .take(span.start.col.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let end = if span.end.row == span.start.row {
+                span.end.col
+            } else {
+                line.chars().count() + 1
+            };
+            let mark = if i == 0 { "^" } else { "-" };
+            let underline = mark.repeat(end.saturating_sub(span.start.col).max(1));
+            out += &format!("\n{} |", gutter);
+            out += &format!("\n{:>w$} | {}", span.start.row, line, w = width);
+            out += &format!("\n{} | {}{} {}", gutter, indent, underline, text);
+            out.truncate(out.trim_end().len());
+        }
+
+        if !self.notes.is_empty() && !self.labels.is_empty() {
+            out += &format!("\n{} |", gutter);
+        }
+        for note in &self.notes {
+            out += &format!("\n{} = note: {}", gutter, note);
+        }
+        out
+    }
+}
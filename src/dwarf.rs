@@ -0,0 +1,510 @@
+use crate::ast::Span;
+use crate::ir::{IRFunction, IRType};
+
+// Tags, attributes and forms of DWARF 4 that the debug information uses.
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_TAG_SUBPROGRAM: u8 = 0x2e;
+const DW_TAG_FORMAL_PARAMETER: u8 = 0x05;
+const DW_TAG_VARIABLE: u8 = 0x34;
+const DW_TAG_BASE_TYPE: u8 = 0x24;
+const DW_TAG_POINTER_TYPE: u8 = 0x0f;
+const DW_TAG_TYPEDEF: u8 = 0x16;
+const DW_TAG_STRUCTURE_TYPE: u8 = 0x13;
+
+const DW_AT_LOCATION: u8 = 0x02;
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_BYTE_SIZE: u8 = 0x0b;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+const DW_AT_DECLARATION: u8 = 0x3c;
+const DW_AT_DECL_FILE: u8 = 0x3a;
+const DW_AT_DECL_LINE: u8 = 0x3b;
+const DW_AT_ENCODING: u8 = 0x3e;
+const DW_AT_EXTERNAL: u8 = 0x3f;
+const DW_AT_FRAME_BASE: u8 = 0x40;
+const DW_AT_TYPE: u8 = 0x49;
+
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA1: u8 = 0x0b;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_FORM_FLAG: u8 = 0x0c;
+const DW_FORM_UDATA: u8 = 0x0f;
+const DW_FORM_REF4: u8 = 0x13;
+const DW_FORM_SEC_OFFSET: u8 = 0x17;
+const DW_FORM_EXPRLOC: u8 = 0x18;
+const DW_FORM_FLAG_PRESENT: u8 = 0x19;
+
+const DW_ATE_BOOLEAN: u8 = 0x02;
+const DW_ATE_FLOAT: u8 = 0x04;
+const DW_ATE_SIGNED: u8 = 0x05;
+const DW_ATE_SIGNED_CHAR: u8 = 0x06;
+
+const DW_OP_FBREG: u8 = 0x91;
+const DW_OP_CALL_FRAME_CFA: u8 = 0x9c;
+
+/// Debuggers have no notion of Alum, but evaluate expressions on its values just
+/// fine when told that they are C.
+const DW_LANG_C99: u8 = 0x0c;
+
+// Abbreviation codes, one per kind of entry.
+const ABBREV_COMPILE_UNIT: u8 = 1;
+const ABBREV_SUBPROGRAM: u8 = 2;
+const ABBREV_VOID_SUBPROGRAM: u8 = 3;
+const ABBREV_PARAMETER: u8 = 4;
+const ABBREV_VARIABLE: u8 = 5;
+const ABBREV_BASE_TYPE: u8 = 6;
+const ABBREV_POINTER_TYPE: u8 = 7;
+const ABBREV_TYPEDEF: u8 = 8;
+const ABBREV_STRUCT_DECL: u8 = 9;
+
+const SECTION: &str = "progbits noalloc noexec nowrite align=1";
+
+/// An abbreviation: its code, tag, whether it has children, and the form of each
+/// of its attributes.
+type Abbrev = (u8, u8, bool, Vec<(u8, u8)>);
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub ty: IRType,
+    /// The stack slot `[rbp - N]` of the variable, or `None` once the optimizer has
+    /// split it up, which debuggers show as optimized out.
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    file: usize,
+    line: usize,
+    ret_type: IRType,
+    is_pub: bool,
+    params: Vec<Variable>,
+    locals: Vec<Variable>,
+}
+
+/// A row of the line table: the code from `label` on comes from `span` of `file`.
+#[derive(Debug)]
+struct Row {
+    label: String,
+    file: usize,
+    span: Span,
+}
+
+/// Collects the functions and line table of one object, then writes them out as the
+/// `.debug_*` sections of DWARF 4.
+#[derive(Debug, Default)]
+pub struct DebugInfo {
+    files: Vec<String>,
+    rows: Vec<Row>,
+    functions: Vec<Function>,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of the file in the line table, counting from 1.
+    fn file(&mut self, path: &str) -> usize {
+        match self.files.iter().position(|f| f == path) {
+            Some(i) => i + 1,
+            None => {
+                self.files.push(path.to_string());
+                self.files.len()
+            }
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn row(&mut self, label: String, file: &str, span: Span) {
+        let file = self.file(file);
+        self.rows.push(Row { label, file, span });
+    }
+
+    /// Adds `func` of `file`, which must already be in the text section. It ends at
+    /// the `.L_<name>_end` label.
+    pub fn function(
+        &mut self,
+        func: &IRFunction,
+        file: &str,
+        params: Vec<Variable>,
+        locals: Vec<Variable>,
+    ) {
+        let file = self.file(file);
+        self.functions.push(Function {
+            name: func.name.clone(),
+            file,
+            line: func.span.start.row,
+            ret_type: func.ret_type.clone(),
+            is_pub: func.is_pub,
+            params,
+            locals,
+        });
+    }
+
+    /// The assembly of the debug sections, for files compiled in `comp_dir`. Empty
+    /// if no function was compiled.
+    pub fn emit(&self, comp_dir: &str) -> String {
+        let (Some(first), Some(last)) = (self.functions.first(), self.functions.last()) else {
+            return String::new();
+        };
+        // The modules of an input file come after the ones it imports, so the last
+        // function is in the file itself.
+        let name = &self.files[last.file - 1];
+        let mut out = String::new();
+        self.abbrev(&mut out);
+        self.info(&mut out, name, comp_dir, &first.name, &end(&last.name));
+        self.line(&mut out, &end(&last.name));
+        self.frame(&mut out);
+        out
+    }
+
+    fn abbrev(&self, out: &mut String) {
+        let common = [
+            (DW_AT_NAME, DW_FORM_STRING),
+            (DW_AT_DECL_FILE, DW_FORM_UDATA),
+            (DW_AT_DECL_LINE, DW_FORM_UDATA),
+        ];
+        let code = [
+            (DW_AT_LOW_PC, DW_FORM_ADDR),
+            (DW_AT_HIGH_PC, DW_FORM_ADDR),
+            (DW_AT_FRAME_BASE, DW_FORM_EXPRLOC),
+            (DW_AT_EXTERNAL, DW_FORM_FLAG),
+        ];
+        let typed = [(DW_AT_TYPE, DW_FORM_REF4)];
+        let variable = [
+            (DW_AT_NAME, DW_FORM_STRING),
+            (DW_AT_TYPE, DW_FORM_REF4),
+            (DW_AT_LOCATION, DW_FORM_EXPRLOC),
+        ];
+        let abbrevs: [Abbrev; 9] = [
+            (
+                ABBREV_COMPILE_UNIT,
+                DW_TAG_COMPILE_UNIT,
+                true,
+                vec![
+                    (DW_AT_PRODUCER, DW_FORM_STRING),
+                    (DW_AT_LANGUAGE, DW_FORM_DATA1),
+                    (DW_AT_NAME, DW_FORM_STRING),
+                    (DW_AT_COMP_DIR, DW_FORM_STRING),
+                    (DW_AT_LOW_PC, DW_FORM_ADDR),
+                    (DW_AT_HIGH_PC, DW_FORM_ADDR),
+                    (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+                ],
+            ),
+            (
+                ABBREV_SUBPROGRAM,
+                DW_TAG_SUBPROGRAM,
+                true,
+                [&common[..], &typed, &code].concat(),
+            ),
+            (
+                ABBREV_VOID_SUBPROGRAM,
+                DW_TAG_SUBPROGRAM,
+                true,
+                [&common[..], &code].concat(),
+            ),
+            (
+                ABBREV_PARAMETER,
+                DW_TAG_FORMAL_PARAMETER,
+                false,
+                variable.to_vec(),
+            ),
+            (ABBREV_VARIABLE, DW_TAG_VARIABLE, false, variable.to_vec()),
+            (
+                ABBREV_BASE_TYPE,
+                DW_TAG_BASE_TYPE,
+                false,
+                vec![
+                    (DW_AT_NAME, DW_FORM_STRING),
+                    (DW_AT_ENCODING, DW_FORM_DATA1),
+                    (DW_AT_BYTE_SIZE, DW_FORM_DATA1),
+                ],
+            ),
+            (
+                ABBREV_POINTER_TYPE,
+                DW_TAG_POINTER_TYPE,
+                false,
+                vec![(DW_AT_BYTE_SIZE, DW_FORM_DATA1), (DW_AT_TYPE, DW_FORM_REF4)],
+            ),
+            (
+                ABBREV_TYPEDEF,
+                DW_TAG_TYPEDEF,
+                false,
+                vec![(DW_AT_NAME, DW_FORM_STRING), (DW_AT_TYPE, DW_FORM_REF4)],
+            ),
+            (
+                ABBREV_STRUCT_DECL,
+                DW_TAG_STRUCTURE_TYPE,
+                false,
+                vec![
+                    (DW_AT_NAME, DW_FORM_STRING),
+                    (DW_AT_DECLARATION, DW_FORM_FLAG_PRESENT),
+                ],
+            ),
+        ];
+
+        section(out, ".debug_abbrev");
+        out.push_str("L.debug_abbrev:\n");
+        for (code, tag, children, attrs) in abbrevs {
+            let mut bytes = vec![code, tag, children as u8];
+            for (attr, form) in attrs {
+                bytes.extend([attr, form]);
+            }
+            bytes.extend([0, 0]);
+            db(out, &bytes);
+        }
+        db(out, &[0]);
+    }
+
+    fn info(&self, out: &mut String, name: &str, comp_dir: &str, low: &str, high: &str) {
+        section(out, ".debug_info");
+        out.push_str("L.debug_info:\n");
+        out.push_str("dd L.debug_info_end - L.debug_info_version\n");
+        out.push_str("L.debug_info_version:\n");
+        out.push_str("dw 4\n");
+        out.push_str("dd L.debug_abbrev\n");
+        db(out, &[8]);
+
+        db(out, &[ABBREV_COMPILE_UNIT]);
+        db(out, &string(&format!("al {}", env!("CARGO_PKG_VERSION"))));
+        db(out, &[DW_LANG_C99]);
+        db(out, &string(name));
+        db(out, &string(comp_dir));
+        out.push_str(&format!("dq {}\n", low));
+        out.push_str(&format!("dq {}\n", high));
+        out.push_str("dd L.debug_line\n");
+
+        for (label, name, encoding, size) in [
+            ("int", "int", DW_ATE_SIGNED, 8),
+            ("float", "flt", DW_ATE_FLOAT, 8),
+            ("bool", "bool", DW_ATE_BOOLEAN, 8),
+            ("char", "char", DW_ATE_SIGNED_CHAR, 1),
+        ] {
+            out.push_str(&format!("L.debug_type_{}:\n", label));
+            db(out, &[ABBREV_BASE_TYPE]);
+            db(out, &string(name));
+            db(out, &[encoding, size]);
+        }
+        for target in ["char", "int"] {
+            pointer(out, target, target);
+        }
+        // Strings, arrays and vecs are pointers to their first element.
+        for (name, target) in [("str", "char"), ("arr", "int"), ("vec", "int")] {
+            typedef(out, name, name, &format!("ptr_{}", target));
+        }
+        let mut structs: Vec<&String> = self
+            .functions
+            .iter()
+            .flat_map(|f| {
+                let vars = f.params.iter().chain(&f.locals).map(|v| &v.ty);
+                vars.chain([&f.ret_type])
+            })
+            .filter_map(|ty| match ty {
+                IRType::Struct(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        structs.sort();
+        structs.dedup();
+        // A struct is a pointer to its fields, whose layout is left undescribed.
+        for name in structs {
+            out.push_str(&format!("L.debug_type_decl_{}:\n", name));
+            db(out, &[ABBREV_STRUCT_DECL]);
+            db(out, &string(name));
+            let decl = format!("decl_{}", name);
+            pointer(out, &decl, &decl);
+            let label = format!("struct_{}", name);
+            typedef(out, &label, name, &format!("ptr_{}", decl));
+        }
+
+        for f in &self.functions {
+            let abbrev = match type_label(&f.ret_type) {
+                Some(_) => ABBREV_SUBPROGRAM,
+                None => ABBREV_VOID_SUBPROGRAM,
+            };
+            db(out, &[abbrev]);
+            db(out, &string(&f.name));
+            db(out, &[uleb(f.file as u64), uleb(f.line as u64)].concat());
+            if let Some(ty) = type_label(&f.ret_type) {
+                type_ref(out, &ty);
+            }
+            out.push_str(&format!("dq {}\n", f.name));
+            out.push_str(&format!("dq {}\n", end(&f.name)));
+            db(out, &[1, DW_OP_CALL_FRAME_CFA]);
+            db(out, &[f.is_pub as u8]);
+            for (abbrev, vars) in [(ABBREV_PARAMETER, &f.params), (ABBREV_VARIABLE, &f.locals)] {
+                for var in vars {
+                    let Some(ty) = type_label(&var.ty) else {
+                        continue;
+                    };
+                    db(out, &[abbrev]);
+                    db(out, &string(&var.name));
+                    type_ref(out, &ty);
+                    // Frame slots are relative to the CFA, 16 bytes above `rbp`.
+                    let expr = match var.offset {
+                        Some(offset) => [&[DW_OP_FBREG][..], &sleb(-(offset as i64) - 16)].concat(),
+                        None => Vec::new(),
+                    };
+                    db(out, &[&uleb(expr.len() as u64)[..], &expr].concat());
+                }
+            }
+            db(out, &[0]);
+        }
+        db(out, &[0]);
+        out.push_str("L.debug_info_end:\n");
+    }
+
+    fn line(&self, out: &mut String, high: &str) {
+        section(out, ".debug_line");
+        out.push_str("L.debug_line:\n");
+        out.push_str("dd L.debug_line_end - L.debug_line_version\n");
+        out.push_str("L.debug_line_version:\n");
+        out.push_str("dw 4\n");
+        out.push_str("dd L.debug_line_program - L.debug_line_header\n");
+        out.push_str("L.debug_line_header:\n");
+        // Instruction and operation lengths, is_stmt, line base and range, then the
+        // operand counts of the standard opcodes.
+        db(out, &[1, 1, 1, (-5i8) as u8, 14, 13]);
+        db(out, &[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        // No include directories: the paths are relative to the compile directory.
+        db(out, &[0]);
+        for file in &self.files {
+            db(out, &[&string(file)[..], &[0, 0, 0]].concat());
+        }
+        db(out, &[0]);
+        out.push_str("L.debug_line_program:\n");
+
+        let (mut file, mut line) = (1, 1);
+        for row in &self.rows {
+            set_address(out, &row.label);
+            if row.file != file {
+                db(out, &[&[4][..], &uleb(row.file as u64)].concat());
+                file = row.file;
+            }
+            let delta = row.span.start.row as i64 - line;
+            db(out, &[&[3][..], &sleb(delta)].concat());
+            line = row.span.start.row as i64;
+            db(
+                out,
+                &[&[5][..], &uleb(row.span.start.col as u64), &[1]].concat(),
+            );
+        }
+        set_address(out, high);
+        db(out, &[0, 1, 1]);
+        out.push_str("L.debug_line_end:\n");
+    }
+
+    /// Every function starts with `push rbp` and `mov rbp, rsp`, after which the
+    /// frame is found through `rbp`.
+    fn frame(&self, out: &mut String) {
+        section(out, ".debug_frame");
+        out.push_str("L.debug_frame:\n");
+        out.push_str("dd 20\n");
+        out.push_str("dd 0xffffffff\n");
+        // Version 1, no augmentation, code and data alignment 1 and -8, return
+        // address in register 16. The CFA is rsp + 8, where the return address is.
+        db(
+            out,
+            &[1, 0, 1, 0x78, 16, 0x0c, 7, 8, 0x90, 1, 0, 0, 0, 0, 0, 0],
+        );
+        for f in &self.functions {
+            out.push_str("dd 28\n");
+            out.push_str("dd L.debug_frame\n");
+            out.push_str(&format!("dq {}\n", f.name));
+            out.push_str(&format!("dq {} - {}\n", end(&f.name), f.name));
+            // After the push, the CFA is rsp + 16 and rbp is saved below the return
+            // address; after the move, the CFA is rbp + 16.
+            db(out, &[0x41, 0x0e, 16, 0x86, 2, 0x43, 0x0d, 6]);
+        }
+    }
+}
+
+fn end(name: &str) -> String {
+    format!("{0}.L_{0}_end", name)
+}
+
+fn section(out: &mut String, name: &str) {
+    out.push_str(&format!("section {} {}\n", name, SECTION));
+}
+
+fn db(out: &mut String, bytes: &[u8]) {
+    let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+    out.push_str(&format!("db {}\n", bytes.join(", ")));
+}
+
+fn string(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+fn set_address(out: &mut String, label: &str) {
+    db(out, &[0, 9, 2]);
+    out.push_str(&format!("dq {}\n", label));
+}
+
+fn pointer(out: &mut String, name: &str, target: &str) {
+    out.push_str(&format!("L.debug_type_ptr_{}:\n", name));
+    db(out, &[ABBREV_POINTER_TYPE, 8]);
+    type_ref(out, target);
+}
+
+fn typedef(out: &mut String, label: &str, name: &str, target: &str) {
+    out.push_str(&format!("L.debug_type_{}:\n", label));
+    db(out, &[ABBREV_TYPEDEF]);
+    db(out, &string(name));
+    type_ref(out, target);
+}
+
+fn type_ref(out: &mut String, label: &str) {
+    out.push_str(&format!("dd L.debug_type_{} - L.debug_info\n", label));
+}
+
+fn type_label(ty: &IRType) -> Option<String> {
+    match ty {
+        IRType::Int => Some("int".to_string()),
+        IRType::Float => Some("float".to_string()),
+        IRType::Bool => Some("bool".to_string()),
+        IRType::Char => Some("char".to_string()),
+        IRType::String | IRType::OwnedString => Some("str".to_string()),
+        IRType::Array(_) => Some("arr".to_string()),
+        IRType::Vec => Some("vec".to_string()),
+        IRType::Struct(name) => Some(format!("struct_{}", name)),
+        IRType::Void => None,
+    }
+}
+
+fn uleb(mut n: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(mut n: i64) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,647 @@
+use std::collections::HashMap;
+
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_32: u32 = 10;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionId {
+    Text,
+    Data,
+    /// `alum_symtab`: the start, end and name of every function, for backtraces.
+    Symtab,
+    DebugAbbrev,
+    DebugInfo,
+    DebugLine,
+    DebugFrame,
+}
+
+impl SectionId {
+    pub const ALL: [SectionId; 7] = [
+        SectionId::Text,
+        SectionId::Data,
+        SectionId::Symtab,
+        SectionId::DebugAbbrev,
+        SectionId::DebugInfo,
+        SectionId::DebugLine,
+        SectionId::DebugFrame,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SectionId::Text => ".text",
+            SectionId::Data => ".data",
+            SectionId::Symtab => "alum_symtab",
+            SectionId::DebugAbbrev => ".debug_abbrev",
+            SectionId::DebugInfo => ".debug_info",
+            SectionId::DebugLine => ".debug_line",
+            SectionId::DebugFrame => ".debug_frame",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    fn flags(self) -> u64 {
+        match self {
+            SectionId::Text => SHF_ALLOC | SHF_EXECINSTR,
+            SectionId::Data => SHF_ALLOC | SHF_WRITE,
+            SectionId::Symtab => SHF_ALLOC,
+            _ => 0,
+        }
+    }
+
+    fn align(self) -> u64 {
+        match self {
+            SectionId::Text | SectionId::Data => 16,
+            SectionId::Symtab => 8,
+            _ => 1,
+        }
+    }
+
+    /// Debug sections are only written when there is something in them.
+    fn is_debug(self) -> bool {
+        self.flags() == 0
+    }
+}
+
+/// A symbol of the object. `section` is `None` for symbols defined elsewhere.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<SectionId>,
+    pub value: u64,
+    pub global: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RelocTarget {
+    Section(SectionId),
+    Symbol(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    pub offset: u64,
+    pub target: RelocTarget,
+    pub kind: u32,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Section {
+    pub bytes: Vec<u8>,
+    pub relocs: Vec<Relocation>,
+}
+
+/// An x86-64 ELF relocatable object with a `.text`, a `.data` and an `alum_symtab`
+/// section, and the DWARF sections when compiled with `-g`.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectFile {
+    pub sections: HashMap<SectionId, Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        if s.is_empty() {
+            return 0;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(s.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    typ: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+fn pad_to(buf: &mut Vec<u8>, align: usize) {
+    while !buf.len().is_multiple_of(align) {
+        buf.push(0);
+    }
+}
+
+impl ObjectFile {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let empty = Section::default();
+        let sections: Vec<(SectionId, &Section)> = SectionId::ALL
+            .into_iter()
+            .map(|id| (id, self.sections.get(&id).unwrap_or(&empty)))
+            .filter(|(id, section)| !id.is_debug() || !section.bytes.is_empty())
+            .collect();
+        let index = |id: SectionId| {
+            sections
+                .iter()
+                .position(|(s, _)| *s == id)
+                .map_or(0, |i| i as u16 + 1)
+        };
+        let relocated: Vec<(usize, &Section)> = sections
+            .iter()
+            .enumerate()
+            .filter(|(_, (id, section))| !id.is_debug() || !section.relocs.is_empty())
+            .map(|(i, (_, section))| (i, *section))
+            .collect();
+        let symtab_index = (1 + sections.len() + relocated.len()) as u32;
+
+        // ELF wants every local symbol before the first global one. The section
+        // symbols come first, as relocations against local data use them.
+        let first_local = 1 + sections.len();
+        let mut order: Vec<usize> = (0..self.symbols.len()).collect();
+        order.sort_by_key(|&i| self.symbols[i].global);
+        let mut sym_index = vec![0; self.symbols.len()];
+        for (pos, &i) in order.iter().enumerate() {
+            sym_index[i] = pos + first_local;
+        }
+        let first_global = first_local + self.symbols.iter().filter(|s| !s.global).count();
+
+        let mut strtab = StringTable::new();
+        let mut symtab = Vec::new();
+        let mut push_sym = |name: u32, info: u8, shndx: u16, value: u64| {
+            symtab.extend_from_slice(&name.to_le_bytes());
+            symtab.push(info);
+            symtab.push(0);
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&0u64.to_le_bytes());
+        };
+        push_sym(0, 0, 0, 0);
+        for (id, _) in &sections {
+            push_sym(0, (STB_LOCAL << 4) | STT_SECTION, index(*id), 0);
+        }
+        for &i in &order {
+            let sym = &self.symbols[i];
+            let bind = if sym.global { STB_GLOBAL } else { STB_LOCAL };
+            let shndx = sym.section.map_or(0, index);
+            push_sym(
+                strtab.add(&sym.name),
+                (bind << 4) | STT_NOTYPE,
+                shndx,
+                sym.value,
+            );
+        }
+
+        let rela = |relocs: &[Relocation]| {
+            let mut out = Vec::new();
+            for r in relocs {
+                let sym = match r.target {
+                    RelocTarget::Section(s) => index(s) as u64,
+                    RelocTarget::Symbol(i) => sym_index[i] as u64,
+                };
+                out.extend_from_slice(&r.offset.to_le_bytes());
+                out.extend_from_slice(&((sym << 32) | r.kind as u64).to_le_bytes());
+                out.extend_from_slice(&r.addend.to_le_bytes());
+            }
+            out
+        };
+
+        let mut shstrtab = StringTable::new();
+        let mut headers = vec![SectionHeader {
+            name: 0,
+            typ: 0,
+            flags: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 0,
+            entsize: 0,
+        }];
+        let mut out = vec![0u8; 64];
+        let mut section = |out: &mut Vec<u8>,
+                           name: &str,
+                           typ: u32,
+                           flags: u64,
+                           bytes: &[u8],
+                           (link, info): (u32, u32),
+                           align: u64,
+                           entsize: u64| {
+            pad_to(out, align.max(1) as usize);
+            headers.push(SectionHeader {
+                name: shstrtab.add(name),
+                typ,
+                flags,
+                offset: out.len() as u64,
+                size: bytes.len() as u64,
+                link,
+                info,
+                align,
+                entsize,
+            });
+            out.extend_from_slice(bytes);
+        };
+
+        // The sections, their relocations, then .symtab, .strtab, .note.GNU-stack and
+        // .shstrtab.
+        for (id, s) in &sections {
+            let flags = id.flags();
+            section(
+                &mut out,
+                id.name(),
+                SHT_PROGBITS,
+                flags,
+                &s.bytes,
+                (0, 0),
+                id.align(),
+                0,
+            );
+        }
+        for (i, s) in &relocated {
+            let (id, _) = sections[*i];
+            section(
+                &mut out,
+                &format!(".rela.{}", id.name().trim_start_matches('.')),
+                SHT_RELA,
+                SHF_INFO_LINK,
+                &rela(&s.relocs),
+                (symtab_index, *i as u32 + 1),
+                8,
+                24,
+            );
+        }
+        section(
+            &mut out,
+            ".symtab",
+            SHT_SYMTAB,
+            0,
+            &symtab,
+            (symtab_index + 1, first_global as u32),
+            8,
+            24,
+        );
+        section(
+            &mut out,
+            ".strtab",
+            SHT_STRTAB,
+            0,
+            &strtab.bytes,
+            (0, 0),
+            1,
+            0,
+        );
+        // An empty note tells the linker that the stack need not be executable.
+        section(
+            &mut out,
+            ".note.GNU-stack",
+            SHT_PROGBITS,
+            0,
+            &[],
+            (0, 0),
+            1,
+            0,
+        );
+        let name = shstrtab.add(".shstrtab");
+        pad_to(&mut out, 1);
+        headers.push(SectionHeader {
+            name,
+            typ: SHT_STRTAB,
+            flags: 0,
+            offset: out.len() as u64,
+            size: shstrtab.bytes.len() as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
+        out.extend_from_slice(&shstrtab.bytes);
+
+        pad_to(&mut out, 8);
+        let shoff = out.len() as u64;
+        for h in &headers {
+            out.extend_from_slice(&h.name.to_le_bytes());
+            out.extend_from_slice(&h.typ.to_le_bytes());
+            out.extend_from_slice(&h.flags.to_le_bytes());
+            out.extend_from_slice(&0u64.to_le_bytes());
+            out.extend_from_slice(&h.offset.to_le_bytes());
+            out.extend_from_slice(&h.size.to_le_bytes());
+            out.extend_from_slice(&h.link.to_le_bytes());
+            out.extend_from_slice(&h.info.to_le_bytes());
+            out.extend_from_slice(&h.align.to_le_bytes());
+            out.extend_from_slice(&h.entsize.to_le_bytes());
+        }
+
+        let mut header = Vec::with_capacity(64);
+        header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&1u16.to_le_bytes()); // ET_REL
+        header.extend_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+        header.extend_from_slice(&1u32.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes()); // entry
+        header.extend_from_slice(&0u64.to_le_bytes()); // phoff
+        header.extend_from_slice(&shoff.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // flags
+        header.extend_from_slice(&64u16.to_le_bytes()); // ehsize
+        header.extend_from_slice(&0u16.to_le_bytes()); // phentsize
+        header.extend_from_slice(&0u16.to_le_bytes()); // phnum
+        header.extend_from_slice(&64u16.to_le_bytes()); // shentsize
+        header.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+        header.extend_from_slice(&((headers.len() - 1) as u16).to_le_bytes());
+        out[..64].copy_from_slice(&header);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use std::collections::HashMap;
+
+    /// What `al -S` emits for a function calling into the standard library, cut down.
+    const SRC: &str = "section .data
+align 16
+neg_mask: dq 0x8000000000000000, 0
+L.S.0 db 104, 105, 0
+L.F.0 dq 0x4004000000000000
+section .text
+extern println
+global main
+main:
+push rbp
+mov rbp, rsp
+lea rdi, [rel L.S.0]
+call println
+movsd xmm0, [rel L.F.0]
+xorpd xmm0, oword [rel neg_mask]
+call helper
+jmp .L_main_exit
+.L_main_exit:
+leave
+ret
+.L_main_end:
+helper:
+ret
+section alum_symtab progbits alloc noexec nowrite align=8
+dq main, main.L_main_end, L.S.0
+";
+
+    fn u16_at(b: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(b[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(b: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+    }
+
+    fn c_str(b: &[u8], at: usize) -> String {
+        let end = b[at..].iter().position(|&c| c == 0).unwrap();
+        String::from_utf8(b[at..at + end].to_vec()).unwrap()
+    }
+
+    /// The object read back: its sections by name with their contents, `sh_link` and
+    /// `sh_info`, and its symbols as name, global, section name and value.
+    struct Read {
+        sections: Vec<(String, Vec<u8>, u32, u32)>,
+        symbols: Vec<(String, bool, String, u64)>,
+    }
+
+    impl Read {
+        fn new(b: &[u8]) -> Self {
+            let shoff = u64_at(b, 0x28) as usize;
+            let count = u16_at(b, 0x3c) as usize;
+            let names = shoff + 64 * u16_at(b, 0x3e) as usize;
+            let names = u64_at(b, names + 24) as usize;
+            let sections: Vec<(String, Vec<u8>, u32, u32)> = (0..count)
+                .map(|i| {
+                    let h = shoff + 64 * i;
+                    let (offset, size) = (u64_at(b, h + 24) as usize, u64_at(b, h + 32) as usize);
+                    (
+                        c_str(b, names + u32_at(b, h) as usize),
+                        b[offset..offset + size].to_vec(),
+                        u32_at(b, h + 40),
+                        u32_at(b, h + 44),
+                    )
+                })
+                .collect();
+            let symtab = &sections.iter().find(|s| s.0 == ".symtab").unwrap().1;
+            let strtab = &sections.iter().find(|s| s.0 == ".strtab").unwrap().1;
+            let symbols = symtab
+                .chunks(24)
+                .map(|s| {
+                    let section = sections[u16_at(s, 6) as usize].0.clone();
+                    // Section symbols are named after their section.
+                    let name = match s[4] & 0xf {
+                        3 => section.clone(),
+                        _ => c_str(strtab, u32_at(s, 0) as usize),
+                    };
+                    (name, s[4] >> 4 == 1, section, u64_at(s, 8))
+                })
+                .collect();
+            Self { sections, symbols }
+        }
+
+        fn section(&self, name: &str) -> &(String, Vec<u8>, u32, u32) {
+            self.sections.iter().find(|s| s.0 == name).unwrap()
+        }
+
+        /// The relocations of `.rela<name>` as offset, kind, symbol name and addend.
+        fn relocs(&self, name: &str) -> Vec<(u64, u32, String, i64)> {
+            self.section(&format!(".rela{}", name))
+                .1
+                .chunks(24)
+                .map(|r| {
+                    let info = u64_at(r, 8);
+                    let sym = self.symbols[(info >> 32) as usize].0.clone();
+                    (u64_at(r, 0), info as u32, sym, u64_at(r, 16) as i64)
+                })
+                .collect()
+        }
+    }
+
+    /// The rows of the line program of `.debug_line` as address and line, with the
+    /// addresses resolved through its relocations to offsets into `.text`.
+    fn line_rows(read: &Read) -> Vec<(u64, i64)> {
+        let b = &read.section(".debug_line").1;
+        let targets: HashMap<u64, u64> = read
+            .relocs(".debug_line")
+            .into_iter()
+            .map(|(offset, _, sym, addend)| {
+                let value = read.symbols.iter().find(|s| s.0 == sym).unwrap().3;
+                (offset, value + addend as u64)
+            })
+            .collect();
+        let uleb = |at: &mut usize| {
+            let (mut n, mut shift) = (0u64, 0);
+            loop {
+                let byte = b[*at];
+                *at += 1;
+                n |= ((byte & 0x7f) as u64) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    return (n, shift);
+                }
+            }
+        };
+
+        // Past the unit length, version and header length.
+        let mut at = 10 + u32_at(b, 6) as usize;
+        let (mut address, mut line, mut rows) = (0, 1, Vec::new());
+        while at < b.len() {
+            at += 1;
+            match b[at - 1] {
+                // DW_LNE_set_address, the only extended opcode besides the end of
+                // the sequence.
+                0 => {
+                    let (len, _) = uleb(&mut at);
+                    if b[at] == 2 {
+                        address = targets[&(at as u64 + 1)];
+                    }
+                    at += len as usize;
+                }
+                1 => rows.push((address, line)),
+                3 => {
+                    let (n, shift) = uleb(&mut at);
+                    line += ((n << (64 - shift)) as i64) >> (64 - shift);
+                }
+                4 | 5 => {
+                    uleb(&mut at);
+                }
+                op => panic!("unexpected line opcode {}", op),
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn symbols_and_relocations_round_trip() {
+        let obj = Assembler::new(SRC).assemble().unwrap();
+        let read = Read::new(&obj.to_bytes());
+
+        let named: Vec<(&str, bool, &str, u64)> = read
+            .symbols
+            .iter()
+            .filter(|(name, ..)| {
+                !name.is_empty() && !name.starts_with('.') && name != "alum_symtab"
+            })
+            .map(|(name, global, section, value)| {
+                (name.as_str(), *global, section.as_str(), *value)
+            })
+            .collect();
+        assert_eq!(
+            named,
+            [
+                ("neg_mask", false, ".data", 0),
+                ("L.S.0", false, ".data", 0x10),
+                ("L.F.0", false, ".data", 0x13),
+                ("helper", false, ".text", 0x29),
+                ("main", true, ".text", 0),
+                ("println", true, "", 0),
+            ]
+        );
+        // Every local symbol comes before the first global one, which sh_info names.
+        let first_global = read.symbols.iter().position(|s| s.1).unwrap();
+        assert_eq!(read.section(".symtab").3 as usize, first_global);
+
+        // Local labels are reached through their section symbol, and the displacement
+        // is taken from the end of the instruction.
+        assert_eq!(
+            read.relocs(".text"),
+            [
+                (0x07, super::R_X86_64_PC32, ".data".to_string(), 0x10 - 4),
+                (0x0c, super::R_X86_64_PLT32, "println".to_string(), -4),
+                (0x14, super::R_X86_64_PC32, ".data".to_string(), 0x13 - 4),
+                (0x1c, super::R_X86_64_PC32, ".data".to_string(), -4),
+            ]
+        );
+        assert_eq!(
+            read.relocs(".alum_symtab"),
+            [
+                (0x00, super::R_X86_64_64, ".text".to_string(), 0),
+                (0x08, super::R_X86_64_64, ".text".to_string(), 0x29),
+                (0x10, super::R_X86_64_64, ".data".to_string(), 0x10),
+            ]
+        );
+        // The call to a label of the same section needs no relocation.
+        assert_eq!(
+            read.section(".text").1[0x20..0x25],
+            [0xe8, 0x04, 0x00, 0x00, 0x00]
+        );
+    }
+
+    const DEBUG_SRC: &str = "fun square(x: int): int {
+    return x * x
+}
+
+pub fun main(): int {
+    let n = 7
+    return square(n)
+}
+";
+
+    /// `-g -c` writes the DWARF sections, and the line table starts `main` at the
+    /// line of its declaration and its body at the line of its first statement.
+    #[test]
+    fn debug_sections_map_main_to_its_line() {
+        let dir = std::env::temp_dir().join(format!("alum-elf-debug-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (src, obj) = (dir.join("main.al"), dir.join("main.o"));
+        std::fs::write(&src, DEBUG_SRC).unwrap();
+        crate::compile(
+            &[src.to_str().unwrap()],
+            &crate::SearchPaths::new(Vec::new(), Vec::new()),
+            Some(obj.to_str().unwrap()),
+            "obj",
+            &[],
+            "builtin",
+            crate::Options {
+                opt_level: 0,
+                bounds_check: true,
+                debug: true,
+            },
+        )
+        .unwrap();
+        let read = Read::new(&std::fs::read(&obj).unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+
+        for name in [".debug_line", ".debug_info", ".debug_frame"] {
+            assert!(!read.section(name).1.is_empty(), "{} is empty", name);
+        }
+        let main = read.symbols.iter().find(|s| s.0 == "main").unwrap().3;
+        let rows = line_rows(&read);
+        let first = rows
+            .iter()
+            .position(|&(address, _)| address == main)
+            .unwrap();
+        assert_eq!(rows[first].1, 5);
+        assert_eq!(rows[first + 1].1, 6);
+        assert!(rows[first + 1].0 > main);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::ast::Inline;
+use crate::ir::{IRConst, IRFunction, IRProgram, IRType, Instruction, Op, Operand};
+use crate::ssa::max_temp;
+use std::mem::take;
+
+/// Functions of at most this many instructions are inlined at `-O2` even without
+/// the `inline` attribute.
+pub const INLINE_THRESHOLD: usize = 24;
+
+/// One more than the largest temporary of a function.
+fn temps(func: &IRFunction) -> usize {
+    func.instructions
+        .iter()
+        .flat_map(|inst| [&inst.dst, &inst.src1, &inst.src2])
+        .flatten()
+        .chain(func.params.iter().map(|(param, _)| param))
+        .map(max_temp)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Struct and array literals live on the stack until the function returns, so an
+/// inlined copy in a loop would grow the stack on every iteration.
+fn allocates(inst: &Instruction, constants: &[IRConst]) -> bool {
+    matches!(inst.op, Op::StructAlloc(_))
+        || [&inst.src1, &inst.src2]
+            .into_iter()
+            .flatten()
+            .any(|op| match op {
+                Operand::Const(IRConst::Array(..)) => true,
+                Operand::ConstIdx(idx) => matches!(constants[*idx], IRConst::Array(..)),
+                _ => false,
+            })
+}
+
+fn inlinable(func: &IRFunction, threshold: usize, constants: &[IRConst]) -> bool {
+    let wanted = match func.inline {
+        Inline::Always => true,
+        Inline::Never => false,
+        Inline::Auto => func.instructions.len() <= threshold,
+    };
+    // Structs are passed by reference and returned through a hidden pointer, which
+    // only a real call sets up.
+    wanted
+        && !func.is_external
+        && !func.is_imported
+        && !func.instructions.is_empty()
+        && !matches!(func.ret_type, IRType::Struct(_))
+        && !func
+            .params
+            .iter()
+            .any(|(_, ty)| matches!(ty, IRType::Struct(_)))
+        && !func
+            .instructions
+            .iter()
+            .any(|inst| allocates(inst, constants))
+}
+
+/// Replaces calls to `inline` functions, and at a nonzero `threshold` to functions
+/// of at most that many instructions, with a copy of the callee's body. Extern and
+/// `noinline` functions are always called.
+pub fn inline(program: &mut IRProgram, threshold: usize) {
+    let mut count = 0;
+    for f in 0..program.functions.len() {
+        let func = &mut program.functions[f];
+        if func.is_external || func.is_imported {
+            continue;
+        }
+        let mut next_temp = temps(func);
+        // Taking the body out also keeps a recursive function from inlining itself.
+        let insts = take(&mut func.instructions);
+
+        let mut out = Vec::with_capacity(insts.len());
+        for inst in insts {
+            let callee = match (&inst.op, &inst.src1) {
+                (Op::Call, Some(Operand::Function(name))) => program
+                    .functions
+                    .iter()
+                    .find(|g| g.name == *name)
+                    .filter(|g| inlinable(g, threshold, &program.constants)),
+                _ => None,
+            };
+            match callee {
+                Some(callee) => {
+                    splice(&mut out, inst, callee, next_temp, count);
+                    next_temp += temps(callee);
+                    count += 1;
+                }
+                None => out.push(inst),
+            }
+        }
+        program.functions[f].instructions = out;
+    }
+}
+
+/// Copies the body of `callee` in place of `call` and the arguments before it. Its
+/// temporaries are numbered from `base`, while its variables and labels get the
+/// suffix `.i<n>` and `_i<n>`. The copy keeps the position of the call, since the
+/// callee may well be written in another file.
+fn splice(
+    out: &mut Vec<Instruction>,
+    call: Instruction,
+    callee: &IRFunction,
+    base: usize,
+    n: usize,
+) {
+    let rename = |op: &Operand| match op {
+        Operand::Temp(id, ty) => Operand::Temp(base + id, ty.clone()),
+        Operand::Var(name) => Operand::Var(format!("{}.i{}", name, n)),
+        Operand::Label(name) => Operand::Label(format!("{}_i{}", name, n)),
+        op => op.clone(),
+    };
+
+    let mut ints = Vec::new();
+    let mut floats = Vec::new();
+    while let Some(arg) = out.pop_if(|inst| matches!(inst.op, Op::Arg(_) | Op::FArg(_))) {
+        match arg.op {
+            Op::FArg(i) => floats.push((i, arg.src1)),
+            Op::Arg(i) => ints.push((i, arg.src1)),
+            _ => unreachable!(),
+        }
+    }
+    ints.sort_by_key(|(i, _)| *i);
+    floats.sort_by_key(|(i, _)| *i);
+    let mut ints = ints.into_iter().map(|(_, arg)| arg);
+    let mut floats = floats.into_iter().map(|(_, arg)| arg);
+    for (param, ty) in &callee.params {
+        let (op, arg) = match ty {
+            IRType::Float => (Op::FStore, floats.next()),
+            _ => (Op::Store, ints.next()),
+        };
+        out.push(Instruction {
+            op,
+            dst: Some(rename(param)),
+            src1: arg.flatten(),
+            src2: None,
+            span: call.span,
+        });
+    }
+
+    let end = format!(".inline_{:X}", n);
+    let last = callee.instructions.len() - 1;
+    for (i, inst) in callee.instructions.iter().enumerate() {
+        let src1 = inst.src1.as_ref().map(rename);
+        if let Op::Return(_) = inst.op {
+            if callee.ret_type != IRType::Void {
+                let op = match callee.ret_type {
+                    IRType::Float => Op::FMove,
+                    _ => Op::Move,
+                };
+                out.push(Instruction {
+                    op,
+                    dst: call.dst.clone(),
+                    src1,
+                    src2: None,
+                    span: call.span,
+                });
+            }
+            if i != last {
+                out.push(Instruction {
+                    op: Op::Jump,
+                    dst: None,
+                    src1: Some(Operand::Label(end.clone())),
+                    src2: None,
+                    span: call.span,
+                });
+            }
+            continue;
+        }
+        let op = match &inst.op {
+            Op::Label(name) => Op::Label(format!("{}_i{}", name, n)),
+            op => op.clone(),
+        };
+        out.push(Instruction {
+            op,
+            dst: inst.dst.as_ref().map(rename),
+            src1,
+            src2: inst.src2.as_ref().map(rename),
+            span: call.span,
+        });
+    }
+    out.push(Instruction {
+        op: Op::Label(end),
+        dst: None,
+        src1: None,
+        src2: None,
+        span: call.span,
+    });
+}
@@ -1,12 +1,19 @@
 use ordered_float::OrderedFloat;
 
+use crate::ast::{Inline, Span};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum IRType {
     Int,
     Float,
     String,
+    /// A heap-backed `string`. It points at NUL-terminated bytes like a `String`.
+    OwnedString,
     Bool,
+    Char,
     Array(Option<usize>),
+    Vec,
+    Struct(String),
     Void,
 }
 
@@ -40,6 +47,7 @@ pub enum Op {
     FMul,
     Div,
     FDiv,
+    Mod,
     Eq,
     FEq,
     Ne,
@@ -57,6 +65,8 @@ pub enum Op {
     LAnd,
     LOr,
     Xor,
+    Shl,
+    Shr,
     Not,
     Range,
     Neg,
@@ -76,6 +86,22 @@ pub enum Op {
     JumpIfFalse,
     ArrayAccess,
     ArrayAssign,
+    /// Reads the byte at index `src2` of the string `src1`, zero-extended.
+    ByteLoad,
+    /// Writes the low byte of `src2` at index `src1` of the string `dst`.
+    ByteStore,
+    /// Exits through the std panic routine unless `src2` indexes the array or vec
+    /// `src1`. Carries the file and line of the indexing.
+    BoundsCheck(String, usize),
+    /// Checks at once the indices into the array or vec `src1` of a loop running
+    /// `src2` times, which start at the carried `i64` and grow by one. Does nothing
+    /// if the loop does not run.
+    LoopBoundsCheck(String, usize, i64),
+    StructAlloc(usize),
+    StructCopy(usize),
+    FieldLoad(usize),
+    FieldStore(usize),
+    FieldAddr(usize),
     Label(String),
     Extern(String),
     Nop,
@@ -87,6 +113,8 @@ pub struct Instruction {
     pub dst: Option<Operand>,
     pub src1: Option<Operand>,
     pub src2: Option<Operand>,
+    /// Where in the source the instruction comes from, for debug information.
+    pub span: Option<Span>,
 }
 
 #[derive(Debug, Clone)]
@@ -97,10 +125,19 @@ pub struct IRFunction {
     pub ret_type: IRType,
     pub is_pub: bool,
     pub is_external: bool,
+    /// Defined in the object of another input file rather than in C.
+    pub is_imported: bool,
+    pub inline: Inline,
+    /// Position of the declaration, for debug information.
+    pub span: Span,
+    /// Variables declared in the body, for debug information.
+    pub locals: Vec<(String, IRType)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct IRProgram {
     pub functions: Vec<IRFunction>,
     pub constants: Vec<IRConst>,
+    /// Paths of the source files, indexed by the `file` of spans.
+    pub files: Vec<String>,
 }
@@ -1,19 +1,21 @@
-use std::{collections::HashMap, iter::zip, mem::take};
+use std::{collections::HashMap, mem::take};
 
 use ordered_float::OrderedFloat;
 
 use crate::{
-    ast::{Expr, Extern, FuncDecl, Program, Var},
+    ast::{Expr, ExprKind, Extern, FuncDecl, Inline, Span, StructDecl, Val, Var},
+    diagnostic::{Diagnostic, SourceMap},
     ir::{IRConst, IRFunction, IRProgram, IRType, Instruction, Op, Operand},
+    module::Module,
     token::{Literal, TokenType, VarType},
 };
 
 #[derive(Debug, Clone)]
 pub enum IRGenError {
-    NameError { message: String },
-    TypeError { message: String },
-    ScopeError { message: String },
-    SyntaxError { message: String },
+    NameError { message: String, span: Option<Span> },
+    TypeError { message: String, span: Option<Span> },
+    ScopeError { message: String, span: Option<Span> },
+    SyntaxError { message: String, span: Option<Span> },
 }
 
 impl std::error::Error for IRGenError {}
@@ -21,10 +23,34 @@ impl std::error::Error for IRGenError {}
 impl std::fmt::Display for IRGenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            IRGenError::NameError { message } => write!(f, "Name error: {}", message),
-            IRGenError::TypeError { message } => write!(f, "Type error: {}", message),
-            IRGenError::ScopeError { message } => write!(f, "Scope error: {}", message),
-            IRGenError::SyntaxError { message } => write!(f, "Syntax error: {}", message),
+            IRGenError::NameError { message, .. } => write!(f, "Name error: {}", message),
+            IRGenError::TypeError { message, .. } => write!(f, "Type error: {}", message),
+            IRGenError::ScopeError { message, .. } => write!(f, "Scope error: {}", message),
+            IRGenError::SyntaxError { message, .. } => write!(f, "Syntax error: {}", message),
+        }
+    }
+}
+
+impl IRGenError {
+    /// Places the error at `at` unless a more precise position is known.
+    fn at(mut self, at: Span) -> Self {
+        let (IRGenError::NameError { span, .. }
+        | IRGenError::TypeError { span, .. }
+        | IRGenError::ScopeError { span, .. }
+        | IRGenError::SyntaxError { span, .. }) = &mut self;
+        span.get_or_insert(at);
+        self
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (IRGenError::NameError { span, .. }
+        | IRGenError::TypeError { span, .. }
+        | IRGenError::ScopeError { span, .. }
+        | IRGenError::SyntaxError { span, .. }) = self;
+        let diagnostic = Diagnostic::error(self.to_string());
+        match span {
+            Some(span) => diagnostic.label(*span, ""),
+            None => diagnostic,
         }
     }
 }
@@ -37,11 +63,69 @@ struct Symbol {
 
 type Scope = HashMap<String, Symbol>;
 
+/// Name of the hidden first parameter through which a function returning a struct
+/// receives the caller-owned buffer to fill (the SysV `sret` convention).
+const SRET: &str = ".sret";
+
+/// Index of an argument passed in memory. It lies past every argument register, so
+/// the argument goes on the stack in call order without taking up a register.
+const IN_MEMORY: usize = usize::MAX;
+
+/// Number of registers the SysV ABI passes INTEGER and SSE arguments in.
+const INT_ARG_REGS: usize = 6;
+const FLT_ARG_REGS: usize = 8;
+
+/// Runtime function of `alum-std` that copies an array to the heap.
+const ARRAY_COPY: &str = "alum_array_copy";
+
+/// Runtime function of `alum-std` that reports an out-of-bounds index and exits.
+pub const BOUNDS_PANIC: &str = "alum_bounds_panic";
+
+/// Runtime function of `alum-std` behind `panic`.
+const PANIC: &str = "alum_panic";
+
+/// Runtime function of `alum-std` behind `s[start ~ end]`.
+const STRING_SLICE: &str = "alum_string_slice";
+
+/// Prefix of the runtime functions of `alum-std` that append to a `string`, which
+/// make up `format` and `+` on strings.
+const FORMAT: &str = "alum_format_";
+
+/// User labels are emitted as local labels, so that they do not end the scope of
+/// the compiler's own `.`-prefixed labels in the generated assembly.
+fn user_label(name: &str) -> String {
+    format!(".L.{}", name)
+}
+
+#[derive(Debug, Clone)]
+struct StructLayout {
+    pub fields: Vec<(String, IRType, usize)>,
+    pub size: usize,
+}
+
+impl StructLayout {
+    fn field(&self, name: &str) -> Option<(IRType, usize)> {
+        self.fields
+            .iter()
+            .find(|(f, _, _)| f == name)
+            .map(|(_, t, off)| (t.clone(), *off))
+    }
+}
+
+struct LoopLabels {
+    pub name: Option<String>,
+    pub continue_label: String,
+    pub break_label: String,
+}
+
 struct Context {
     pub instructions: Vec<Instruction>,
     pub tmp_cnt: usize,
     pub scope: Vec<Scope>,
     pub label_cnt: usize,
+    pub loops: Vec<LoopLabels>,
+    pub pending_label: Option<String>,
+    pub locals: Vec<(String, IRType)>,
 }
 
 impl Context {
@@ -51,6 +135,27 @@ impl Context {
             tmp_cnt: 0,
             scope: Vec::new(),
             label_cnt: 0,
+            loops: Vec::new(),
+            pending_label: None,
+            locals: Vec::new(),
+        }
+    }
+
+    pub fn find_loop(&self, label: Option<&str>, keyword: &str) -> Result<&LoopLabels, IRGenError> {
+        match label {
+            None => self.loops.last().ok_or_else(|| IRGenError::SyntaxError {
+                message: format!("'{}' outside of a loop", keyword),
+                span: None,
+            }),
+            Some(name) => self
+                .loops
+                .iter()
+                .rev()
+                .find(|l| l.name.as_deref() == Some(name))
+                .ok_or_else(|| IRGenError::NameError {
+                    message: format!("no enclosing loop labeled '{}'", name),
+                    span: None,
+                }),
         }
     }
 
@@ -71,6 +176,7 @@ impl Context {
     pub fn exit_scope(&mut self) -> Result<(), IRGenError> {
         self.scope.pop().ok_or_else(|| IRGenError::ScopeError {
             message: "Tried to pop the root scope.".to_string(),
+            span: None,
         })?;
         Ok(())
     }
@@ -83,6 +189,7 @@ impl Context {
         }
         Err(IRGenError::NameError {
             message: format!("undefined variable '{}' in current scope.", name),
+            span: None,
         })
     }
 
@@ -91,8 +198,12 @@ impl Context {
             VarType::Int => IRType::Int,
             VarType::Float => IRType::Float,
             VarType::Bool => IRType::Bool,
+            VarType::Char => IRType::Char,
             VarType::Str => IRType::String,
+            VarType::String => IRType::OwnedString,
             VarType::Array(len) => IRType::Array(len.to_owned()),
+            VarType::Vec => IRType::Vec,
+            VarType::Struct(name) => IRType::Struct(name.to_owned()),
             VarType::Void => IRType::Void,
         }
     }
@@ -121,12 +232,18 @@ impl Context {
             .last_mut()
             .ok_or_else(|| IRGenError::ScopeError {
                 message: "No scope available".to_string(),
+                span: None,
             })?;
         if current_scope.contains_key(&name) {
             return Err(IRGenError::NameError {
                 message: format!("variable '{}' already declared in this scope.", name),
+                span: None,
             });
         }
+        // Variables of the compiler's own start with `.` and are left out.
+        if !name.starts_with('.') && !self.locals.iter().any(|(n, _)| *n == name) {
+            self.locals.push((name.clone(), ir_type.clone()));
+        }
         current_scope.insert(name.clone(), Symbol { name, ir_type });
         Ok(())
     }
@@ -136,45 +253,124 @@ pub struct IRGen {
     functions: Vec<IRFunction>,
     constants: Vec<IRConst>,
     constant_pool: HashMap<IRConst, usize>,
+    structs: HashMap<String, StructLayout>,
+    symbols: HashMap<String, String>,
+    /// Whether indexing is checked against the length of the array or vec.
+    bounds_check: bool,
+    /// Paths of the source files that spans refer to, which panics report.
+    files: Vec<String>,
 }
 
 impl IRGen {
-    pub fn new() -> Self {
+    pub fn new(bounds_check: bool) -> Self {
         Self {
             functions: Vec::new(),
             constants: Vec::new(),
             constant_pool: HashMap::new(),
+            structs: HashMap::new(),
+            symbols: HashMap::new(),
+            bounds_check,
+            files: Vec::new(),
         }
     }
 
-    pub fn compile(&mut self, program: Program) -> Result<IRProgram, IRGenError> {
-        for expr in &program.body {
-            match expr {
-                Expr::FuncDecl(decl) => {
-                    self.func_decl(decl.clone())?;
-                }
-                Expr::Extern(ext) => {
-                    self.extern_decl(ext.clone())?;
+    /// Compiles the modules of one input file, given all modules in dependency order
+    /// as returned by `ModuleLoader`. Functions of other units are only declared.
+    pub fn compile(
+        &mut self,
+        modules: &[Module],
+        sources: &SourceMap,
+        unit: usize,
+    ) -> Result<IRProgram, IRGenError> {
+        self.files = sources.paths();
+        // Names each module declares itself, and the subset it makes public.
+        let mut own: Vec<(&Module, HashMap<String, String>)> = Vec::new();
+        let mut exported: HashMap<String, HashMap<String, String>> = HashMap::new();
+        // Other units only matter as far as this one imports from them; the rest are
+        // linked in without it needing to know. Importers come after their imports.
+        let mut needed: Vec<&str> = Vec::new();
+        for module in modules.iter().rev() {
+            if module.unit == unit || needed.contains(&module.name.as_str()) {
+                needed.extend(module.program.imports.iter().map(|i| i.module.as_str()));
+            }
+        }
+
+        for module in modules {
+            let imported = module.unit != unit;
+            if imported && !needed.contains(&module.name.as_str()) {
+                continue;
+            }
+            let mut names = HashMap::new();
+            let mut public = HashMap::new();
+            for expr in &module.program.body {
+                let (name, symbol, is_pub) = match &expr.kind {
+                    ExprKind::FuncDecl(decl) => {
+                        let symbol = module.symbol(&decl.name, decl.is_pub);
+                        self.func_decl(decl.clone(), symbol.clone(), imported)?;
+                        (decl.name.clone(), symbol, decl.is_pub)
+                    }
+                    ExprKind::Extern(ext) => {
+                        self.extern_decl(ext.clone()).map_err(|e| e.at(expr.span))?;
+                        (ext.name.clone(), ext.name.clone(), ext.is_pub)
+                    }
+                    ExprKind::StructDecl(decl) => {
+                        self.struct_decl(decl.clone())?;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if is_pub {
+                    public.insert(name.clone(), symbol.clone());
                 }
-                _ => {}
+                names.insert(name, symbol);
+            }
+            if !imported {
+                own.push((module, names));
             }
+            exported.insert(module.name.clone(), public);
         }
 
-        for expr in program.body {
-            match expr {
-                Expr::FuncDecl(decl) => {
-                    self.compile_fn(decl)?;
+        for (module, names) in own {
+            self.symbols = names;
+            for import in &module.program.imports {
+                let public = &exported[&import.module];
+                match &import.names {
+                    None => {
+                        for (name, symbol) in public {
+                            self.symbols
+                                .insert(format!("{}.{}", import.module, name), symbol.clone());
+                        }
+                    }
+                    Some(names) => {
+                        // Names that are not functions are structs, which the parser
+                        // has already resolved.
+                        for name in names {
+                            if let Some(symbol) = public.get(name) {
+                                self.symbols.insert(name.clone(), symbol.clone());
+                            }
+                        }
+                    }
                 }
-                Expr::Val(val) => {
-                    self.global_constant(val.value)?;
+            }
+
+            for expr in &module.program.body {
+                match &expr.kind {
+                    ExprKind::FuncDecl(decl) => {
+                        let symbol = self.resolve_func(&decl.name)?;
+                        self.compile_fn(decl.clone(), symbol, expr.span)?;
+                    }
+                    ExprKind::Val(val) => {
+                        self.global_constant(val.value.clone())?;
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
         }
 
         Ok(IRProgram {
             functions: take(&mut self.functions),
             constants: take(&mut self.constants),
+            files: take(&mut self.files),
         })
     }
 
@@ -190,12 +386,42 @@ impl IRGen {
     }
 
     fn compile_expr(&mut self, expr: Expr, ctx: &mut Context) -> Result<Operand, IRGenError> {
-        match expr {
-            Expr::Val(val) => {
+        let start = ctx.instructions.len();
+        // The jumps of blocks and branches belong to the line of the code around them.
+        let located = !matches!(
+            expr.kind,
+            ExprKind::Stmt(_) | ExprKind::If(_) | ExprKind::While(_)
+        );
+        let result = self
+            .compile_kind(expr.kind, expr.span, ctx)
+            .map_err(|e| e.at(expr.span))?;
+        if located {
+            // Inner expressions were compiled first and keep their own position.
+            for inst in ctx.instructions.iter_mut().skip(start) {
+                inst.span.get_or_insert(expr.span);
+            }
+        }
+        Ok(result)
+    }
+
+    fn compile_kind(
+        &mut self,
+        kind: ExprKind,
+        span: Span,
+        ctx: &mut Context,
+    ) -> Result<Operand, IRGenError> {
+        // A label written directly in front of a loop names it for `break`/`continue`.
+        let loop_name = match kind {
+            ExprKind::Label(_) => None,
+            _ => ctx.pending_label.take(),
+        };
+        match kind {
+            ExprKind::Val(val) => {
                 let (ir_const, ir_type) = match val.value {
                     Literal::Int(n) => (IRConst::Int(n), IRType::Int),
                     Literal::Float(f) => (IRConst::Float(f), IRType::Float),
-                    Literal::Bool(b) => (IRConst::Int(if b { 1 } else { 0 }), IRType::Int),
+                    Literal::Bool(b) => (IRConst::Int(if b { 1 } else { 0 }), IRType::Bool),
+                    Literal::Char(c) => (IRConst::Int(c as i64), IRType::Char),
                     Literal::Str(s) => (IRConst::Str(s), IRType::String),
                     Literal::Void => return Ok(ctx.new_tmp(IRType::Void)),
                     Literal::Array(len, arr) => {
@@ -223,6 +449,7 @@ impl IRGen {
                                         len,
                                         elements.len()
                                     ),
+                                    span: None,
                                 });
                             }
 
@@ -242,22 +469,32 @@ impl IRGen {
                         dst: Some(res_tmp.clone()),
                         src1: Some(Operand::ConstIdx(const_idx)),
                         src2: None,
+                        span: None,
                     }),
                     _ => ctx.instructions.push(Instruction {
                         op: Op::Move,
                         dst: Some(res_tmp.clone()),
                         src1: Some(Operand::ConstIdx(const_idx)),
                         src2: None,
+                        span: None,
                     }),
                 }
                 Ok(res_tmp)
             }
 
-            Expr::VarDecl(decl) => {
+            ExprKind::VarDecl(decl) => {
+                let fresh = is_fresh_struct(&decl.value);
                 let mut value = self.compile_expr(*decl.value.clone(), ctx)?;
                 let value_type = ctx.get_operand_type(&value)?;
+                // Every type is known once the type checker has run.
+                let Some(typ) = &decl.typ else {
+                    return Err(IRGenError::TypeError {
+                        message: format!("cannot infer the type of '{}'", decl.name),
+                        span: None,
+                    });
+                };
 
-                let var_ir_type = match &decl.typ {
+                let var_ir_type = match typ {
                     VarType::Array(Some(declared_len)) => {
                         if let IRType::Array(Some(actual_len)) = &value_type {
                             if *declared_len > *actual_len && *actual_len == 1 {
@@ -290,16 +527,34 @@ impl IRGen {
                             } else if *declared_len != *actual_len {
                                 return Err(IRGenError::TypeError {
                                     message: "array length mismatch".to_string(),
+                                    span: None,
                                 });
                             }
                             IRType::Array(Some(*declared_len))
                         } else {
                             return Err(IRGenError::TypeError {
                                 message: "expected array".to_string(),
+                                span: None,
+                            });
+                        }
+                    }
+                    VarType::Struct(_) => {
+                        let var_ir_type = ctx.from_var_type(typ);
+                        if value_type != var_ir_type {
+                            return Err(IRGenError::TypeError {
+                                message: format!(
+                                    "cannot initialize '{}' of type {:?} with {:?}",
+                                    decl.name, var_ir_type, value_type
+                                ),
+                                span: None,
                             });
                         }
+                        if !fresh {
+                            value = self.struct_copy(value, ctx)?;
+                        }
+                        var_ir_type
                     }
-                    _ => ctx.from_var_type(&decl.typ),
+                    _ => ctx.from_var_type(typ),
                 };
 
                 ctx.declare_var(decl.name.clone(), var_ir_type.clone())?;
@@ -309,42 +564,44 @@ impl IRGen {
                         dst: Some(Operand::Var(decl.name)),
                         src1: Some(value),
                         src2: None,
+                        span: None,
                     }),
                     _ => ctx.instructions.push(Instruction {
                         op: Op::Store,
                         dst: Some(Operand::Var(decl.name)),
                         src1: Some(value),
                         src2: None,
+                        span: None,
                     }),
                 }
                 Ok(ctx.new_tmp(IRType::Void))
             }
-            Expr::VarMod(modi) => {
-                let value = self.compile_expr(*modi.value, ctx)?;
-                let typ = ctx.get_operand_type(&value)?;
-                let var_typ = ctx.get_var_type(&modi.name)?;
-                if typ != var_typ {
-                    return Err(IRGenError::TypeError {
-                        message: format!("unexpected type: {:?}", typ),
-                    });
+            ExprKind::VarMod(modi) => {
+                let fresh = is_fresh_struct(&modi.value);
+                let mut value = self.compile_expr(*modi.value, ctx)?;
+                let var_type = ctx.get_var_type(&modi.name)?;
+                if matches!(var_type, IRType::Struct(_)) && !fresh {
+                    value = self.struct_copy(value, ctx)?;
                 }
-                match typ {
+                match var_type {
                     IRType::Float => ctx.instructions.push(Instruction {
                         op: Op::FStore,
                         dst: Some(Operand::Var(modi.name)),
                         src1: Some(value),
                         src2: None,
+                        span: None,
                     }),
                     _ => ctx.instructions.push(Instruction {
                         op: Op::Store,
                         dst: Some(Operand::Var(modi.name)),
                         src1: Some(value),
                         src2: None,
+                        span: None,
                     }),
                 }
                 Ok(ctx.new_tmp(IRType::Void))
             }
-            Expr::Var(var) => {
+            ExprKind::Var(var) => {
                 let var_type = ctx.get_var_type(&var.name)?;
                 let res_tmp = ctx.new_tmp(var_type.clone());
                 match var_type {
@@ -353,26 +610,42 @@ impl IRGen {
                         dst: Some(res_tmp.clone()),
                         src1: Some(Operand::Var(var.name)),
                         src2: None,
+                        span: None,
                     }),
                     _ => ctx.instructions.push(Instruction {
                         op: Op::Load,
                         dst: Some(res_tmp.clone()),
                         src1: Some(Operand::Var(var.name)),
                         src2: None,
+                        span: None,
                     }),
                 }
                 Ok(res_tmp)
             }
-            Expr::BinOp(bin) => {
+            ExprKind::BinOp(bin) => {
                 let left = self.compile_expr(*bin.left, ctx)?;
+                let mark = ctx.instructions.len();
                 let right = self.compile_expr(*bin.right, ctx)?;
                 let typ = ctx.get_operand_type(&left)?;
-                let res_tmp: Operand;
-                if bin.operator == TokenType::RANGE {
-                    res_tmp = ctx.new_tmp(IRType::Array(None));
-                } else {
-                    res_tmp = ctx.new_tmp(typ.clone());
+                let is_string = |t: &IRType| matches!(t, IRType::String | IRType::OwnedString);
+                if bin.operator == TokenType::ADD
+                    && is_string(&typ)
+                    && is_string(&ctx.get_operand_type(&right)?)
+                {
+                    return self.concat(left, right, mark, ctx);
                 }
+                let res_tmp = match bin.operator {
+                    TokenType::RANGE => ctx.new_tmp(IRType::Array(None)),
+                    TokenType::COMPEQ
+                    | TokenType::COMPNE
+                    | TokenType::COMPGT
+                    | TokenType::COMPGE
+                    | TokenType::COMPLT
+                    | TokenType::COMPLE
+                    | TokenType::COMPAND
+                    | TokenType::COMPOR => ctx.new_tmp(IRType::Bool),
+                    _ => ctx.new_tmp(typ.clone()),
+                };
 
                 ctx.instructions.push(Instruction {
                     op: match bin.operator {
@@ -380,6 +653,9 @@ impl IRGen {
                         | TokenType::SUB
                         | TokenType::MUL
                         | TokenType::DIV
+                        | TokenType::MOD
+                        | TokenType::SHL
+                        | TokenType::SHR
                         | TokenType::COMPEQ
                         | TokenType::COMPNE
                         | TokenType::COMPGT
@@ -405,6 +681,7 @@ impl IRGen {
                                             "unsupported float operation: {:?}",
                                             bin.operator
                                         ),
+                                        span: None,
                                     });
                                 }
                             },
@@ -413,6 +690,9 @@ impl IRGen {
                                 TokenType::SUB => Op::Sub,
                                 TokenType::MUL => Op::Mul,
                                 TokenType::DIV => Op::Div,
+                                TokenType::MOD => Op::Mod,
+                                TokenType::SHL => Op::Shl,
+                                TokenType::SHR => Op::Shr,
                                 TokenType::COMPEQ => Op::Eq,
                                 TokenType::COMPNE => Op::Ne,
                                 TokenType::COMPGT => Op::Gt,
@@ -427,6 +707,7 @@ impl IRGen {
                                             "unsupported operation: {:?}",
                                             bin.operator
                                         ),
+                                        span: None,
                                     });
                                 }
                             },
@@ -438,19 +719,65 @@ impl IRGen {
                         _ => {
                             return Err(IRGenError::TypeError {
                                 message: format!("unsupported operation: {:?}", bin.operator),
+                                span: None,
                             });
                         }
                     },
                     dst: Some(res_tmp.clone()),
                     src1: Some(left),
                     src2: Some(right),
+                    span: None,
                 });
                 Ok(res_tmp)
             }
-            Expr::UnaryOp(unary) => {
+            ExprKind::UnaryOp(unary) => {
                 let argument = self.compile_expr(*unary.argument, ctx)?;
                 let typ = ctx.get_operand_type(&argument)?;
-                let res_tmp = ctx.new_tmp(typ.clone());
+                if let (TokenType::SIZEOF, IRType::Struct(name)) = (&unary.operator, &typ) {
+                    let size = self.find_struct(name)?.size;
+                    let res_tmp = ctx.new_tmp(IRType::Int);
+                    let idx = self.get_const_index(IRConst::Int(size as i64));
+                    ctx.instructions.push(Instruction {
+                        op: Op::Move,
+                        dst: Some(res_tmp.clone()),
+                        src1: Some(Operand::ConstIdx(idx)),
+                        src2: None,
+                        span: None,
+                    });
+                    return Ok(res_tmp);
+                }
+                let res_tmp = match unary.operator {
+                    TokenType::SIZEOF | TokenType::ORD => ctx.new_tmp(IRType::Int),
+                    TokenType::CHR => ctx.new_tmp(IRType::Char),
+                    TokenType::LOGNOT => ctx.new_tmp(IRType::Bool),
+                    _ => ctx.new_tmp(typ.clone()),
+                };
+                // A char already holds its code, so `ord` only changes the type. `chr`
+                // keeps the low byte, as storing it in a string would.
+                match unary.operator {
+                    TokenType::ORD => {
+                        ctx.instructions.push(Instruction {
+                            op: Op::Move,
+                            dst: Some(res_tmp.clone()),
+                            src1: Some(argument),
+                            src2: None,
+                            span: None,
+                        });
+                        return Ok(res_tmp);
+                    }
+                    TokenType::CHR => {
+                        let mask = self.get_const_index(IRConst::Int(0xff));
+                        ctx.instructions.push(Instruction {
+                            op: Op::LAnd,
+                            dst: Some(res_tmp.clone()),
+                            src1: Some(argument),
+                            src2: Some(Operand::ConstIdx(mask)),
+                            span: None,
+                        });
+                        return Ok(res_tmp);
+                    }
+                    _ => {}
+                }
                 match typ {
                     IRType::Float => match unary.operator {
                         TokenType::NEG => ctx.instructions.push(Instruction {
@@ -458,17 +785,30 @@ impl IRGen {
                             dst: Some(res_tmp.clone()),
                             src1: Some(argument.clone()),
                             src2: None,
+                            span: None,
                         }),
                         _ => {
                             return Err(IRGenError::TypeError {
                                 message: "unsupported float unary operation".to_string(),
+                                span: None,
                             });
                         }
                     },
+                    // `!x` is `x == 0`, leaving `Not` to the bitwise `~`.
+                    _ if unary.operator == TokenType::LOGNOT => {
+                        let zero = self.get_const_index(IRConst::Int(0));
+                        ctx.instructions.push(Instruction {
+                            op: Op::Eq,
+                            dst: Some(res_tmp.clone()),
+                            src1: Some(argument),
+                            src2: Some(Operand::ConstIdx(zero)),
+                            span: None,
+                        })
+                    }
                     _ => ctx.instructions.push(Instruction {
                         op: match unary.operator {
                             TokenType::NEG => Op::Neg,
-                            TokenType::LOGNOT => Op::Not,
+                            TokenType::BITNOT => Op::Not,
                             TokenType::SIZEOF => Op::SizeOf,
                             _ => {
                                 return Err(IRGenError::TypeError {
@@ -476,17 +816,19 @@ impl IRGen {
                                         "unsupported unary operation: {:?}",
                                         unary.operator
                                     ),
+                                    span: None,
                                 });
                             }
                         },
                         dst: Some(res_tmp.clone()),
                         src1: Some(argument),
                         src2: None,
+                        span: None,
                     }),
                 }
                 Ok(res_tmp)
             }
-            Expr::Stmt(stmt) => {
+            ExprKind::Stmt(stmt) => {
                 ctx.enter_scope();
 
                 let body_len = stmt.body.len();
@@ -503,27 +845,31 @@ impl IRGen {
                 ctx.exit_scope()?;
                 Ok(result_operand)
             }
-            Expr::Return(ret_expr) => {
+            ExprKind::Return(ret_expr) => {
                 if let Some(val) = ret_expr.value {
                     let res_op = self.compile_expr(*val, ctx)?;
+                    let res_op = self.struct_return(res_op, ctx)?;
+                    let res_op = self.array_return(res_op, ctx)?;
                     match ctx.get_operand_type(&res_op)? {
                         IRType::Float => ctx.instructions.push(Instruction {
                             op: Op::Return(String::from("xmm0")),
                             dst: None,
                             src1: Some(res_op),
                             src2: None,
+                            span: None,
                         }),
                         _ => ctx.instructions.push(Instruction {
                             op: Op::Return(String::from("rax")),
                             dst: None,
                             src1: Some(res_op),
                             src2: None,
+                            span: None,
                         }),
                     }
                 }
                 Ok(ctx.new_tmp(IRType::Void))
             }
-            Expr::If(i) => {
+            ExprKind::If(i) => {
                 let label_else = ctx.new_label("else");
                 let label_end = ctx.new_label("endif");
 
@@ -534,11 +880,12 @@ impl IRGen {
                     dst: None,
                     src1: Some(cond),
                     src2: Some(Operand::Label(label_else.clone())),
+                    span: None,
                 });
 
                 let res_tmp = ctx.new_tmp(IRType::Void);
 
-                if !matches!(*i.then_branch, Expr::Stmt(_)) {
+                if !matches!(i.then_branch.kind, ExprKind::Stmt(_)) {
                     ctx.enter_scope();
                 }
                 let then_op = self.compile_expr(*i.then_branch.clone(), ctx)?;
@@ -548,8 +895,9 @@ impl IRGen {
                     dst: Some(res_tmp.clone()),
                     src1: Some(then_op),
                     src2: None,
+                    span: None,
                 });
-                if !matches!(*i.then_branch, Expr::Stmt(_)) {
+                if !matches!(i.then_branch.kind, ExprKind::Stmt(_)) {
                     ctx.exit_scope()?;
                 }
 
@@ -558,6 +906,7 @@ impl IRGen {
                     dst: None,
                     src1: Some(Operand::Label(label_end.clone())),
                     src2: None,
+                    span: None,
                 });
 
                 ctx.instructions.push(Instruction {
@@ -565,10 +914,11 @@ impl IRGen {
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
                 });
 
                 if let Some(else_expr) = i.else_branch {
-                    if !matches!(*else_expr, Expr::Stmt(_)) {
+                    if !matches!(else_expr.kind, ExprKind::Stmt(_)) {
                         ctx.enter_scope();
                     }
                     let else_op = self.compile_expr(*else_expr.to_owned(), ctx)?;
@@ -578,8 +928,9 @@ impl IRGen {
                         dst: Some(res_tmp.clone()),
                         src1: Some(else_op),
                         src2: None,
+                        span: None,
                     });
-                    if !matches!(*else_expr.clone(), Expr::Stmt(_)) {
+                    if !matches!(else_expr.kind, ExprKind::Stmt(_)) {
                         ctx.exit_scope()?;
                     }
                 }
@@ -589,11 +940,12 @@ impl IRGen {
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
                 });
 
                 Ok(res_tmp)
             }
-            Expr::While(w) => {
+            ExprKind::While(w) => {
                 let label_start = ctx.new_label("while_start");
                 let label_end = ctx.new_label("while_end");
 
@@ -602,30 +954,48 @@ impl IRGen {
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
                 });
 
-                let cond = self.compile_expr(*w.condition, ctx)?;
+                let infinite = matches!(
+                    w.condition.kind,
+                    ExprKind::Val(Val {
+                        value: Literal::Bool(true),
+                        ..
+                    })
+                );
+                if !infinite {
+                    let cond = self.compile_expr(*w.condition, ctx)?;
 
-                ctx.instructions.push(Instruction {
-                    op: Op::JumpIfFalse,
-                    dst: None,
-                    src1: Some(cond),
-                    src2: Some(Operand::Label(label_end.clone())),
-                });
+                    ctx.instructions.push(Instruction {
+                        op: Op::JumpIfFalse,
+                        dst: None,
+                        src1: Some(cond),
+                        src2: Some(Operand::Label(label_end.clone())),
+                        span: None,
+                    });
+                }
 
-                if !matches!(*w.body, Expr::Stmt(_)) {
+                ctx.loops.push(LoopLabels {
+                    name: loop_name,
+                    continue_label: label_start.clone(),
+                    break_label: label_end.clone(),
+                });
+                if !matches!(w.body.kind, ExprKind::Stmt(_)) {
                     ctx.enter_scope();
                 }
                 self.compile_expr(*w.body.clone(), ctx)?;
-                if !matches!(*w.body, Expr::Stmt(_)) {
+                if !matches!(w.body.kind, ExprKind::Stmt(_)) {
                     ctx.exit_scope()?;
                 }
+                ctx.loops.pop();
 
                 ctx.instructions.push(Instruction {
                     op: Op::Jump,
                     dst: None,
                     src1: Some(Operand::Label(label_start)),
                     src2: None,
+                    span: None,
                 });
 
                 ctx.instructions.push(Instruction {
@@ -633,36 +1003,51 @@ impl IRGen {
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
                 });
 
                 Ok(ctx.new_tmp(IRType::Void))
             }
-            Expr::For(f) => {
-                let array_operand = self.compile_expr(*f.iter, ctx)?;
-                let array_type = ctx.get_operand_type(&array_operand)?;
-
-                let array_len_operand = match array_type {
-                    IRType::Array(Some(l)) => {
-                        let idx = self.get_const_index(IRConst::Int(l as i64));
-                        Operand::ConstIdx(idx)
-                    }
-                    IRType::Array(None) => {
-                        let len_tmp = ctx.new_tmp(IRType::Int);
-                        ctx.instructions.push(Instruction {
-                            op: Op::SizeOf,
-                            dst: Some(len_tmp.clone()),
-                            src1: Some(array_operand.clone()),
-                            src2: None,
-                        });
-                        len_tmp
+            ExprKind::For(f) => {
+                // A range is counted through rather than built, which saves allocating
+                // it and leaves an induction variable for the loop optimizations.
+                let (start, array_len_operand, array_operand) = match f.iter.kind {
+                    ExprKind::BinOp(bin) if bin.operator == TokenType::RANGE => {
+                        let start = self.compile_expr(*bin.left, ctx)?;
+                        let end = self.compile_expr(*bin.right, ctx)?;
+                        (start, end, None)
                     }
                     _ => {
-                        return Err(IRGenError::TypeError {
-                            message: format!(
-                                "can only iterate over arrays, found {:?}",
-                                array_type
-                            ),
-                        });
+                        let array_operand = self.compile_expr(*f.iter, ctx)?;
+                        let array_type = ctx.get_operand_type(&array_operand)?;
+                        let len = match array_type {
+                            IRType::Array(Some(l)) => {
+                                let idx = self.get_const_index(IRConst::Int(l as i64));
+                                Operand::ConstIdx(idx)
+                            }
+                            IRType::Array(None) | IRType::Vec => {
+                                let len_tmp = ctx.new_tmp(IRType::Int);
+                                ctx.instructions.push(Instruction {
+                                    op: Op::SizeOf,
+                                    dst: Some(len_tmp.clone()),
+                                    src1: Some(array_operand.clone()),
+                                    src2: None,
+                                    span: None,
+                                });
+                                len_tmp
+                            }
+                            _ => {
+                                return Err(IRGenError::TypeError {
+                                    message: format!(
+                                        "can only iterate over arrays, found {:?}",
+                                        array_type
+                                    ),
+                                    span: None,
+                                });
+                            }
+                        };
+                        let zero_idx = self.get_const_index(IRConst::Int(0));
+                        (Operand::ConstIdx(zero_idx), len, Some(array_operand))
                     }
                 };
 
@@ -671,21 +1056,23 @@ impl IRGen {
                 let idx_var = Operand::Var(idx_name.clone());
                 ctx.declare_var(idx_name.clone(), IRType::Int)?;
 
-                let zero_idx = self.get_const_index(IRConst::Int(0));
                 ctx.instructions.push(Instruction {
                     op: Op::Store,
                     dst: Some(idx_var.clone()),
-                    src1: Some(Operand::ConstIdx(zero_idx)),
+                    src1: Some(start),
                     src2: None,
+                    span: None,
                 });
 
                 let label_cond = ctx.new_label("for_cond");
+                let label_next = ctx.new_label("for_next");
                 let label_end = ctx.new_label("for_end");
                 ctx.instructions.push(Instruction {
                     op: Op::Label(label_cond.clone()),
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
                 });
 
                 let curr_idx = ctx.new_tmp(IRType::Int);
@@ -694,6 +1081,7 @@ impl IRGen {
                     dst: Some(curr_idx.clone()),
                     src1: Some(idx_var.clone()),
                     src2: None,
+                    span: None,
                 });
 
                 let cond_tmp = ctx.new_tmp(IRType::Bool);
@@ -702,6 +1090,7 @@ impl IRGen {
                     dst: Some(cond_tmp.clone()),
                     src1: Some(curr_idx.clone()),
                     src2: Some(array_len_operand),
+                    span: None,
                 });
 
                 ctx.instructions.push(Instruction {
@@ -709,27 +1098,49 @@ impl IRGen {
                     dst: None,
                     src1: Some(cond_tmp),
                     src2: Some(Operand::Label(label_end.clone())),
+                    span: None,
                 });
 
                 ctx.declare_var(f.init.clone(), IRType::Int)?;
-                let element_tmp = ctx.new_tmp(IRType::Int);
-
-                ctx.instructions.push(Instruction {
-                    op: Op::ArrayAccess,
-                    dst: Some(element_tmp.clone()),
-                    src1: Some(array_operand),
-                    src2: Some(curr_idx.clone()),
-                });
+                let element_tmp = match array_operand {
+                    Some(array_operand) => {
+                        let elements = self.elements(array_operand, ctx)?;
+                        let element_tmp = ctx.new_tmp(IRType::Int);
+                        ctx.instructions.push(Instruction {
+                            op: Op::ArrayAccess,
+                            dst: Some(element_tmp.clone()),
+                            src1: Some(elements),
+                            src2: Some(curr_idx.clone()),
+                            span: None,
+                        });
+                        element_tmp
+                    }
+                    None => curr_idx.clone(),
+                };
 
                 ctx.instructions.push(Instruction {
                     op: Op::Store,
                     dst: Some(Operand::Var(f.init)),
                     src1: Some(element_tmp),
                     src2: None,
+                    span: None,
                 });
 
+                ctx.loops.push(LoopLabels {
+                    name: loop_name,
+                    continue_label: label_next.clone(),
+                    break_label: label_end.clone(),
+                });
                 self.compile_expr(*f.body, ctx)?;
+                ctx.loops.pop();
 
+                ctx.instructions.push(Instruction {
+                    op: Op::Label(label_next),
+                    dst: None,
+                    src1: None,
+                    src2: None,
+                    span: None,
+                });
                 let one_idx = self.get_const_index(IRConst::Int(1));
                 let next_idx = ctx.new_tmp(IRType::Int);
 
@@ -738,141 +1149,451 @@ impl IRGen {
                     dst: Some(next_idx.clone()),
                     src1: Some(curr_idx),
                     src2: Some(Operand::ConstIdx(one_idx)),
+                    span: None,
                 });
                 ctx.instructions.push(Instruction {
                     op: Op::Store,
                     dst: Some(idx_var),
                     src1: Some(next_idx),
                     src2: None,
+                    span: None,
                 });
                 ctx.instructions.push(Instruction {
                     op: Op::Jump,
                     dst: None,
                     src1: Some(Operand::Label(label_cond)),
                     src2: None,
+                    span: None,
                 });
                 ctx.instructions.push(Instruction {
                     op: Op::Label(label_end),
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
                 });
 
                 ctx.exit_scope()?;
                 Ok(ctx.new_tmp(IRType::Void))
             }
-            Expr::FuncDecl(_) => {
+            ExprKind::FuncDecl(_) => {
                 return Err(IRGenError::SyntaxError {
                     message: "cannot declare a function in a function".to_string(),
+                    span: None,
                 });
             }
-            Expr::FuncCall(call) => {
-                let func = self.find_func(&call.name)?;
-                if call.args.len() != func.params.len() {
-                    return Err(IRGenError::TypeError {
-                        message: format!(
-                            "expected {} arguments, got {}",
-                            func.params.len(),
-                            call.args.len()
-                        ),
+            ExprKind::FuncCall(call) => {
+                let symbol = self.resolve_func(&call.name)?;
+                let func = self.find_func(&symbol)?;
+                let sret = matches!(func.ret_type, IRType::Struct(_));
+                let params = if sret {
+                    &func.params[1..]
+                } else {
+                    &func.params[..]
+                };
+                let res_tmp = ctx.new_tmp(func.ret_type.clone());
+
+                // Every argument is evaluated before any register is loaded, so that a
+                // nested call cannot clobber the registers of the outer one.
+                let mut operands = Vec::new();
+                for arg in &call.args {
+                    operands.push(self.compile_expr(arg.clone(), ctx)?);
+                }
+
+                let mut int_idx = 0;
+                let mut flt_idx = 0;
+                if let IRType::Struct(name) = &func.ret_type {
+                    let size = self.find_struct(name)?.size;
+                    let buf = ctx.new_tmp(func.ret_type.clone());
+                    ctx.instructions.push(Instruction {
+                        op: Op::StructAlloc(size),
+                        dst: Some(buf.clone()),
+                        src1: None,
+                        src2: None,
+                        span: None,
+                    });
+                    ctx.instructions.push(Instruction {
+                        op: Op::Arg(int_idx),
+                        dst: None,
+                        src1: Some(buf),
+                        src2: None,
+                        span: None,
                     });
+                    int_idx += 1;
                 }
-                let res_tmp = ctx.new_tmp(ctx.from_var_type(&call.ret_type));
-                let mut n = 0;
-                for (arg, param) in zip(call.args.iter(), func.params.iter()) {
-                    let operand = self.compile_expr(arg.clone(), ctx)?;
-                    let operand_type = ctx.get_operand_type(&operand)?;
-                    if operand_type != param.1 {
-                        return Err(IRGenError::TypeError {
-                            message: format!(
-                                "unexpected type {:?}, expected {:?}",
-                                operand_type, param.1
-                            ),
-                        });
-                    }
-                    match param.1 {
-                        IRType::Float => ctx.instructions.push(Instruction {
-                            op: Op::FArg(n),
-                            dst: None,
-                            src1: Some(operand),
-                            src2: None,
-                        }),
-                        _ => ctx.instructions.push(Instruction {
-                            op: Op::Arg(n),
-                            dst: None,
-                            src1: Some(operand),
-                            src2: None,
-                        }),
+                // The variadic arguments of an extern have no parameter, and are passed
+                // by their own type.
+                for (i, operand) in operands.into_iter().enumerate() {
+                    // Extern functions follow the SysV ABI and take small structs by value,
+                    // one register per eightbyte. Larger ones, and those whose eightbytes
+                    // do not all fit in the registers left, are copied onto the stack.
+                    // Alum functions take them by reference.
+                    let mut in_memory = false;
+                    let parts = match params.get(i).map(|(_, typ)| typ) {
+                        Some(IRType::Struct(name)) if func.is_external => {
+                            let classes = self.eightbytes(name)?;
+                            let flts = classes.iter().filter(|c| **c == IRType::Float).count();
+                            in_memory = self.find_struct(name)?.size > 16
+                                || int_idx + classes.len() - flts > INT_ARG_REGS
+                                || flt_idx + flts > FLT_ARG_REGS;
+                            let mut parts = Vec::new();
+                            for (i, class) in classes.into_iter().enumerate() {
+                                let part = ctx.new_tmp(class);
+                                ctx.instructions.push(Instruction {
+                                    op: Op::FieldLoad(i * 8),
+                                    dst: Some(part.clone()),
+                                    src1: Some(operand.clone()),
+                                    src2: None,
+                                    span: None,
+                                });
+                                parts.push(part);
+                            }
+                            parts
+                        }
+                        _ => vec![operand],
+                    };
+                    for part in parts {
+                        match ctx.get_operand_type(&part)? {
+                            IRType::Float => {
+                                ctx.instructions.push(Instruction {
+                                    op: Op::FArg(if in_memory { IN_MEMORY } else { flt_idx }),
+                                    dst: None,
+                                    src1: Some(part),
+                                    src2: None,
+                                    span: None,
+                                });
+                                flt_idx += !in_memory as usize;
+                            }
+                            _ => {
+                                ctx.instructions.push(Instruction {
+                                    op: Op::Arg(if in_memory { IN_MEMORY } else { int_idx }),
+                                    dst: None,
+                                    src1: Some(part),
+                                    src2: None,
+                                    span: None,
+                                });
+                                int_idx += !in_memory as usize;
+                            }
+                        }
                     }
-                    n += 1;
                 }
                 ctx.instructions.push(Instruction {
                     op: Op::Call,
                     dst: Some(res_tmp.clone()),
-                    src1: Some(Operand::Function(call.name)),
+                    src1: Some(Operand::Function(symbol)),
                     src2: None,
+                    span: None,
                 });
                 Ok(res_tmp)
             }
-            Expr::ArrayAccess(aa) => {
-                let arr = Operand::Var(aa.array.clone());
-                let arr_type = ctx.get_operand_type(&arr)?;
-                if let IRType::Array(_) = arr_type {
-                    let offset = self.compile_expr(*aa.offset, ctx)?;
-                    let res_tmp = ctx.new_tmp(IRType::Int);
-                    ctx.instructions.push(Instruction {
-                        op: Op::ArrayAccess,
-                        dst: Some(res_tmp.clone()),
-                        src1: Some(arr),
-                        src2: Some(offset),
-                    });
-                    Ok(res_tmp)
-                } else {
-                    Err(IRGenError::TypeError {
-                        message: format!("{} is not an array", aa.array),
+            // Strings carry no length, so their bytes are not bounds checked.
+            ExprKind::ArrayAccess(aa)
+                if matches!(
+                    ctx.get_var_type(&aa.array)?,
+                    IRType::String | IRType::OwnedString
+                ) =>
+            {
+                let offset = self.compile_expr(*aa.offset, ctx)?;
+                let res_tmp = ctx.new_tmp(IRType::Char);
+                ctx.instructions.push(Instruction {
+                    op: Op::ByteLoad,
+                    dst: Some(res_tmp.clone()),
+                    src1: Some(Operand::Var(aa.array)),
+                    src2: Some(offset),
+                    span: None,
+                });
+                Ok(res_tmp)
+            }
+            ExprKind::ArrayAssign(aa)
+                if matches!(
+                    ctx.get_var_type(&aa.array)?,
+                    IRType::String | IRType::OwnedString
+                ) =>
+            {
+                let offset = self.compile_expr(*aa.offset, ctx)?;
+                let val = self.compile_expr(*aa.value, ctx)?;
+                ctx.instructions.push(Instruction {
+                    op: Op::ByteStore,
+                    dst: Some(Operand::Var(aa.array)),
+                    src1: Some(offset),
+                    src2: Some(val),
+                    span: None,
+                });
+                Ok(ctx.new_tmp(IRType::Void))
+            }
+            ExprKind::ArrayAccess(aa) => {
+                let arr = self.elements(Operand::Var(aa.array.clone()), ctx)?;
+                let offset = self.compile_expr(*aa.offset, ctx)?;
+                self.bounds_check(Operand::Var(aa.array), offset.clone(), span, ctx)?;
+                let res_tmp = ctx.new_tmp(IRType::Int);
+                ctx.instructions.push(Instruction {
+                    op: Op::ArrayAccess,
+                    dst: Some(res_tmp.clone()),
+                    src1: Some(arr),
+                    src2: Some(offset),
+                    span: None,
+                });
+                Ok(res_tmp)
+            }
+            ExprKind::ArrayAssign(aa) => {
+                let arr = self.elements(Operand::Var(aa.array.clone()), ctx)?;
+                let offset = self.compile_expr(*aa.offset, ctx)?;
+                let val = self.compile_expr(*aa.value, ctx)?;
+                self.bounds_check(Operand::Var(aa.array), offset.clone(), span, ctx)?;
+                let res_tmp = ctx.new_tmp(IRType::Void);
+                ctx.instructions.push(Instruction {
+                    op: Op::ArrayAssign,
+                    dst: Some(arr),
+                    src1: Some(offset),
+                    src2: Some(val),
+                    span: None,
+                });
+                Ok(res_tmp)
+            }
+            ExprKind::Slice(slice) => {
+                let string =
+                    self.compile_expr(ExprKind::Var(Var { name: slice.string }).at(span), ctx)?;
+                let start = self.compile_expr(*slice.start, ctx)?;
+                let end = self.compile_expr(*slice.end, ctx)?;
+                let file = self.compile_expr(
+                    ExprKind::Val(Val {
+                        value: Literal::Str(self.files[span.file].clone()),
+                        typ: VarType::Str,
+                    })
+                    .at(span),
+                    ctx,
+                )?;
+                let line = self.compile_expr(
+                    ExprKind::Val(Val {
+                        value: Literal::Int(span.start.row as i64),
+                        typ: VarType::Int,
                     })
+                    .at(span),
+                    ctx,
+                )?;
+                self.runtime_call(
+                    STRING_SLICE,
+                    vec![
+                        VarType::Str,
+                        VarType::Int,
+                        VarType::Int,
+                        VarType::Str,
+                        VarType::Int,
+                    ],
+                    VarType::String,
+                    vec![string, start, end, file, line],
+                    ctx,
+                )
+            }
+            // Each piece of the result is appended by a runtime function picked by the
+            // type of what is formatted, starting from a null `string`.
+            ExprKind::Format(format) => {
+                let pieces = format.pieces().map_err(|message| IRGenError::TypeError {
+                    message,
+                    span: None,
+                })?;
+                let mut args = Vec::new();
+                for arg in format.args {
+                    args.push(self.compile_expr(arg, ctx)?);
                 }
+                let mut result = ctx.new_tmp(IRType::OwnedString);
+                let null = self.get_const_index(IRConst::Int(0));
+                ctx.instructions.push(Instruction {
+                    op: Op::Move,
+                    dst: Some(result.clone()),
+                    src1: Some(Operand::ConstIdx(null)),
+                    src2: None,
+                    span: None,
+                });
+                for (i, piece) in pieces.into_iter().enumerate() {
+                    // The first piece is always appended, so that the result is never null.
+                    if i == 0 || !piece.is_empty() {
+                        let text = self.compile_expr(
+                            ExprKind::Val(Val {
+                                value: Literal::Str(piece),
+                                typ: VarType::Str,
+                            })
+                            .at(span),
+                            ctx,
+                        )?;
+                        result = self.append(result, text, ctx)?;
+                    }
+                    if let Some(arg) = args.get(i) {
+                        result = self.append(result, arg.clone(), ctx)?;
+                    }
+                }
+                Ok(result)
             }
-            Expr::ArrayAssign(aa) => {
-                let arr = Operand::Var(aa.array.clone());
-                let arr_type = ctx.get_operand_type(&arr)?;
-                if let IRType::Array(_) = arr_type {
-                    let offset = self.compile_expr(*aa.offset, ctx)?;
-                    let val = self.compile_expr(*aa.value, ctx)?;
-                    let res_tmp = ctx.new_tmp(IRType::Void);
-                    ctx.instructions.push(Instruction {
-                        op: Op::ArrayAssign,
-                        dst: Some(arr),
-                        src1: Some(offset),
-                        src2: Some(val),
+            ExprKind::Panic(p) => {
+                let message = self.compile_expr(*p.message, ctx)?;
+                let typ = ctx.get_operand_type(&message)?;
+                if !matches!(typ, IRType::String | IRType::OwnedString) {
+                    return Err(IRGenError::TypeError {
+                        message: format!("panic expects a str message, found {:?}", typ),
+                        span: None,
                     });
-                    Ok(res_tmp)
-                } else {
-                    Err(IRGenError::TypeError {
-                        message: format!("{} is not an array", aa.array),
+                }
+                self.extern_decl(Extern {
+                    name: PANIC.to_string(),
+                    params: vec![VarType::Str, VarType::Str, VarType::Int],
+                    ret_type: VarType::Void,
+                    is_pub: false,
+                    is_variadic: false,
+                })?;
+                let file = self.compile_expr(
+                    ExprKind::Val(Val {
+                        value: Literal::Str(self.files[span.file].clone()),
+                        typ: VarType::Str,
+                    })
+                    .at(span),
+                    ctx,
+                )?;
+                let line = self.compile_expr(
+                    ExprKind::Val(Val {
+                        value: Literal::Int(span.start.row as i64),
+                        typ: VarType::Int,
                     })
+                    .at(span),
+                    ctx,
+                )?;
+                for (i, arg) in [message, file, line].into_iter().enumerate() {
+                    ctx.instructions.push(Instruction {
+                        op: Op::Arg(i),
+                        dst: None,
+                        src1: Some(arg),
+                        src2: None,
+                        span: None,
+                    });
                 }
+                let res_tmp = ctx.new_tmp(IRType::Void);
+                ctx.instructions.push(Instruction {
+                    op: Op::Call,
+                    dst: Some(res_tmp.clone()),
+                    src1: Some(Operand::Function(PANIC.to_string())),
+                    src2: None,
+                    span: None,
+                });
+                Ok(res_tmp)
             }
-            Expr::Extern(_) => {
+            ExprKind::Extern(_) => {
                 return Err(IRGenError::SyntaxError {
                     message: "cannot extern a function in a function".to_string(),
+                    span: None,
+                });
+            }
+            ExprKind::StructDecl(_) => {
+                return Err(IRGenError::SyntaxError {
+                    message: "cannot declare a struct in a function".to_string(),
+                    span: None,
                 });
             }
-            Expr::Goto(goto) => {
+            ExprKind::StructLit(lit) => {
+                let layout = self.find_struct(&lit.name)?;
+                for (i, (field, _)) in lit.fields.iter().enumerate() {
+                    if layout.field(field).is_none() {
+                        return Err(IRGenError::NameError {
+                            message: format!("struct '{}' has no field '{}'", lit.name, field),
+                            span: None,
+                        });
+                    }
+                    if lit.fields[..i].iter().any(|(f, _)| f == field) {
+                        return Err(IRGenError::SyntaxError {
+                            message: format!("field '{}' initialized twice", field),
+                            span: None,
+                        });
+                    }
+                }
+                for (field, _, _) in &layout.fields {
+                    if !lit.fields.iter().any(|(f, _)| f == field) {
+                        return Err(IRGenError::TypeError {
+                            message: format!("missing field '{}' in '{}' literal", field, lit.name),
+                            span: None,
+                        });
+                    }
+                }
+
+                let res_tmp = ctx.new_tmp(IRType::Struct(lit.name.clone()));
+                ctx.instructions.push(Instruction {
+                    op: Op::StructAlloc(layout.size),
+                    dst: Some(res_tmp.clone()),
+                    src1: None,
+                    src2: None,
+                    span: None,
+                });
+                for (field, value) in lit.fields {
+                    let value = self.compile_expr(value, ctx)?;
+                    self.store_field(res_tmp.clone(), &field, value, ctx)?;
+                }
+                Ok(res_tmp)
+            }
+            ExprKind::FieldAccess(fa) => {
+                let object = self.compile_expr(*fa.object, ctx)?;
+                let (field_type, offset) = self.find_field(&object, &fa.field, ctx)?;
+                let res_tmp = ctx.new_tmp(field_type.clone());
+                ctx.instructions.push(Instruction {
+                    op: match field_type {
+                        IRType::Struct(_) => Op::FieldAddr(offset),
+                        _ => Op::FieldLoad(offset),
+                    },
+                    dst: Some(res_tmp.clone()),
+                    src1: Some(object),
+                    src2: None,
+                    span: None,
+                });
+                Ok(res_tmp)
+            }
+            ExprKind::FieldAssign(fa) => {
+                let object = self.compile_expr(*fa.object, ctx)?;
+                let value = self.compile_expr(*fa.value, ctx)?;
+                self.store_field(object, &fa.field, value, ctx)?;
+                Ok(ctx.new_tmp(IRType::Void))
+            }
+            ExprKind::Goto(goto) => {
                 ctx.instructions.push(Instruction {
                     op: Op::Jump,
                     dst: None,
-                    src1: Some(Operand::Label(goto.label)),
+                    src1: Some(Operand::Label(user_label(&goto.label))),
                     src2: None,
+                    span: None,
                 });
                 Ok(ctx.new_tmp(IRType::Void))
             }
-            Expr::Label(label) => {
+            ExprKind::Label(label) => {
+                ctx.pending_label = Some(label.name.clone());
                 ctx.instructions.push(Instruction {
-                    op: Op::Label(label.name),
+                    op: Op::Label(user_label(&label.name)),
                     dst: None,
                     src1: None,
                     src2: None,
+                    span: None,
+                });
+                Ok(ctx.new_tmp(IRType::Void))
+            }
+            ExprKind::Break(b) => {
+                let target = ctx
+                    .find_loop(b.label.as_deref(), "break")?
+                    .break_label
+                    .clone();
+                ctx.instructions.push(Instruction {
+                    op: Op::Jump,
+                    dst: None,
+                    src1: Some(Operand::Label(target)),
+                    src2: None,
+                    span: None,
+                });
+                Ok(ctx.new_tmp(IRType::Void))
+            }
+            ExprKind::Continue(c) => {
+                let target = ctx
+                    .find_loop(c.label.as_deref(), "continue")?
+                    .continue_label
+                    .clone();
+                ctx.instructions.push(Instruction {
+                    op: Op::Jump,
+                    dst: None,
+                    src1: Some(Operand::Label(target)),
+                    src2: None,
+                    span: None,
                 });
                 Ok(ctx.new_tmp(IRType::Void))
             }
@@ -895,13 +1616,26 @@ impl IRGen {
             }
             _ => Err(IRGenError::TypeError {
                 message: "Invalid global constant type.".to_string(),
+                span: None,
             }),
         }
     }
 
-    fn func_decl(&mut self, decl: FuncDecl) -> Result<(), IRGenError> {
+    fn func_decl(
+        &mut self,
+        decl: FuncDecl,
+        symbol: String,
+        imported: bool,
+    ) -> Result<(), IRGenError> {
+        if self.functions.iter().any(|f| f.name == symbol) {
+            return Err(IRGenError::NameError {
+                message: format!("function '{}' is defined more than once", symbol),
+                span: None,
+            });
+        }
+
         let mut temp_ctx = Context::new();
-        let params: Vec<(Operand, IRType)> = decl
+        let mut params: Vec<(Operand, IRType)> = decl
             .params
             .iter()
             .enumerate()
@@ -909,20 +1643,26 @@ impl IRGen {
             .collect();
 
         let ret_type = temp_ctx.from_var_type(&decl.ret_type);
+        if let IRType::Struct(_) = ret_type {
+            params.insert(0, (Operand::Var(SRET.to_string()), ret_type.clone()));
+        }
 
         self.functions.push(IRFunction {
-            name: decl.name.clone(),
+            name: symbol,
             params,
             ret_type,
             instructions: Vec::new(),
             is_pub: decl.is_pub,
             is_external: false,
+            is_imported: imported,
+            inline: decl.inline,
+            span: Span::default(),
+            locals: Vec::new(),
         });
         Ok(())
     }
 
-    fn compile_fn(&mut self, decl: FuncDecl) -> Result<(), IRGenError> {
-        let name = decl.name.clone();
+    fn compile_fn(&mut self, decl: FuncDecl, name: String, span: Span) -> Result<(), IRGenError> {
         let func = self.find_func(&name)?;
 
         let mut ctx = Context::new();
@@ -944,7 +1684,6 @@ impl IRGen {
 
         let body = *decl.body;
         let last_op = self.compile_expr(body, &mut ctx)?;
-        ctx.exit_scope()?;
 
         let last_inst_op = ctx.instructions.last().map(|i| i.op.clone());
 
@@ -956,24 +1695,43 @@ impl IRGen {
             } else {
                 "rax".to_string()
             };
+            let last_op = self.struct_return(last_op, &mut ctx)?;
+            let last_op = match func.ret_type {
+                IRType::Array(_) => self.array_return(last_op, &mut ctx)?,
+                _ => last_op,
+            };
 
             ctx.instructions.push(Instruction {
                 op: Op::Return(reg),
                 dst: None,
                 src1: Some(last_op),
                 src2: None,
+                span: None,
             });
         }
+        ctx.exit_scope()?;
 
         if let Some(f) = self.functions.iter_mut().find(|f| f.name == name) {
             f.instructions = take(&mut ctx.instructions);
+            f.span = span;
+            f.locals = take(&mut ctx.locals);
         }
         Ok(())
     }
 
     fn extern_decl(&mut self, ext: Extern) -> Result<(), IRGenError> {
         let name = ext.name;
-        let params: Vec<(Operand, IRType)> = ext
+        // The same C function may well be declared by several modules.
+        if let Some(f) = self.functions.iter().find(|f| f.name == name) {
+            if f.is_external {
+                return Ok(());
+            }
+            return Err(IRGenError::NameError {
+                message: format!("extern '{}' clashes with a function of the same name", name),
+                span: None,
+            });
+        }
+        let mut params: Vec<(Operand, IRType)> = ext
             .params
             .into_iter()
             .enumerate()
@@ -984,7 +1742,21 @@ impl IRGen {
             })
             .collect();
 
+        // A struct larger than 16 bytes is returned through a caller-owned buffer, as
+        // Alum functions do. Smaller ones would come back in registers.
         let ret_type = Context::new().from_var_type(&ext.ret_type);
+        if let IRType::Struct(s) = &ret_type {
+            if self.find_struct(s)?.size <= 16 {
+                return Err(IRGenError::TypeError {
+                    message: format!(
+                        "extern '{}' cannot return struct '{}' of 16 bytes or less by value",
+                        name, s
+                    ),
+                    span: None,
+                });
+            }
+            params.insert(0, (Operand::Var(SRET.to_string()), ret_type.clone()));
+        }
 
         let signature = IRFunction {
             name: name.clone(),
@@ -993,11 +1765,419 @@ impl IRGen {
             instructions: Vec::new(),
             is_pub: false,
             is_external: true,
+            is_imported: false,
+            inline: Inline::Never,
+            span: Span::default(),
+            locals: Vec::new(),
         };
         self.functions.push(signature);
         Ok(())
     }
 
+    fn struct_decl(&mut self, decl: StructDecl) -> Result<(), IRGenError> {
+        let ctx = Context::new();
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        for (field, typ) in decl.fields {
+            let ir_type = ctx.from_var_type(&typ);
+            let size = match &ir_type {
+                IRType::Struct(name) if *name == decl.name => {
+                    return Err(IRGenError::TypeError {
+                        message: format!("struct '{}' cannot contain itself", name),
+                        span: None,
+                    });
+                }
+                IRType::Struct(name) => self.find_struct(name)?.size,
+                IRType::Void => {
+                    return Err(IRGenError::TypeError {
+                        message: format!("field '{}' cannot be void", field),
+                        span: None,
+                    });
+                }
+                _ => 8,
+            };
+            // Every scalar is eight bytes wide, so the SysV alignment of any member,
+            // nested structs included, is eight as well.
+            offset = (offset + 7) & !7;
+            fields.push((field, ir_type, offset));
+            offset += size;
+        }
+        if self.structs.contains_key(&decl.name) {
+            return Err(IRGenError::NameError {
+                message: format!("struct '{}' is declared more than once", decl.name),
+                span: None,
+            });
+        }
+        self.structs.insert(
+            decl.name,
+            StructLayout {
+                fields,
+                size: (offset + 7) & !7,
+            },
+        );
+        Ok(())
+    }
+
+    fn find_struct(&self, name: &str) -> Result<StructLayout, IRGenError> {
+        self.structs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| IRGenError::NameError {
+                message: format!("undefined struct '{}'", name),
+                span: None,
+            })
+    }
+
+    fn find_field(
+        &self,
+        object: &Operand,
+        field: &str,
+        ctx: &Context,
+    ) -> Result<(IRType, usize), IRGenError> {
+        match ctx.get_operand_type(object)? {
+            IRType::Struct(name) => {
+                self.find_struct(&name)?
+                    .field(field)
+                    .ok_or_else(|| IRGenError::NameError {
+                        message: format!("struct '{}' has no field '{}'", name, field),
+                        span: None,
+                    })
+            }
+            typ => Err(IRGenError::TypeError {
+                message: format!("cannot access field '{}' of {:?}", field, typ),
+                span: None,
+            }),
+        }
+    }
+
+    fn store_field(
+        &mut self,
+        object: Operand,
+        field: &str,
+        value: Operand,
+        ctx: &mut Context,
+    ) -> Result<(), IRGenError> {
+        let (field_type, offset) = self.find_field(&object, field, ctx)?;
+        let value_type = ctx.get_operand_type(&value)?;
+        let compatible = match (&field_type, &value_type) {
+            (IRType::Bool, IRType::Int) => true,
+            (IRType::String, IRType::OwnedString) => true,
+            (IRType::Array(None), IRType::Array(_)) => true,
+            (f, v) => f == v,
+        };
+        if !compatible {
+            return Err(IRGenError::TypeError {
+                message: format!(
+                    "field '{}' has type {:?}, found {:?}",
+                    field, field_type, value_type
+                ),
+                span: None,
+            });
+        }
+        if let IRType::Struct(name) = &field_type {
+            let size = self.find_struct(name)?.size;
+            let addr = ctx.new_tmp(field_type.clone());
+            ctx.instructions.push(Instruction {
+                op: Op::FieldAddr(offset),
+                dst: Some(addr.clone()),
+                src1: Some(object),
+                src2: None,
+                span: None,
+            });
+            ctx.instructions.push(Instruction {
+                op: Op::StructCopy(size),
+                dst: Some(addr),
+                src1: Some(value),
+                src2: None,
+                span: None,
+            });
+        } else {
+            ctx.instructions.push(Instruction {
+                op: Op::FieldStore(offset),
+                dst: Some(object),
+                src1: Some(value),
+                src2: None,
+                span: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Flattens a struct into the SysV classes of its eightbytes: `Float` for SSE,
+    /// anything else for INTEGER.
+    fn eightbytes(&self, name: &str) -> Result<Vec<IRType>, IRGenError> {
+        let mut classes = Vec::new();
+        for (_, typ, _) in self.find_struct(name)?.fields {
+            match typ {
+                IRType::Struct(inner) => classes.extend(self.eightbytes(&inner)?),
+                typ => classes.push(typ),
+            }
+        }
+        Ok(classes)
+    }
+
+    /// The array holding the elements of `arr`. A `vec` keeps its elements in a
+    /// separate block, which `push` may move.
+    fn elements(&mut self, arr: Operand, ctx: &mut Context) -> Result<Operand, IRGenError> {
+        match ctx.get_operand_type(&arr)? {
+            IRType::Array(_) => Ok(arr),
+            IRType::Vec => {
+                let data = ctx.new_tmp(IRType::Array(None));
+                ctx.instructions.push(Instruction {
+                    op: Op::FieldLoad(8),
+                    dst: Some(data.clone()),
+                    src1: Some(arr),
+                    src2: None,
+                    span: None,
+                });
+                Ok(data)
+            }
+            typ => Err(IRGenError::TypeError {
+                message: format!("cannot index {:?}", typ),
+                span: None,
+            }),
+        }
+    }
+
+    /// Appends `value`, formatted according to its type, to the `string` `to`.
+    fn append(
+        &mut self,
+        to: Operand,
+        value: Operand,
+        ctx: &mut Context,
+    ) -> Result<Operand, IRGenError> {
+        let (kind, param) = match ctx.get_operand_type(&value)? {
+            IRType::Float => ("flt", VarType::Float),
+            IRType::Bool => ("bool", VarType::Bool),
+            IRType::Char => ("char", VarType::Char),
+            IRType::String | IRType::OwnedString => ("str", VarType::Str),
+            _ => ("int", VarType::Int),
+        };
+        self.runtime_call(
+            &format!("{}{}", FORMAT, kind),
+            vec![VarType::String, param],
+            VarType::String,
+            vec![to, value],
+            ctx,
+        )
+    }
+
+    /// `left + right` on strings, where the code of `right` starts at instruction
+    /// `mark`. Both may point into the same static buffer, as with `itoa(a) + itoa(b)`,
+    /// so `left` is copied before `right` is evaluated. A `string` just built by the
+    /// runtime is appended to in place instead, since nothing else refers to it.
+    fn concat(
+        &mut self,
+        left: Operand,
+        right: Operand,
+        mark: usize,
+        ctx: &mut Context,
+    ) -> Result<Operand, IRGenError> {
+        let fresh = mark > 0 && {
+            let inst = &ctx.instructions[mark - 1];
+            let runtime = matches!(
+                &inst.src1,
+                Some(Operand::Function(name)) if name.starts_with(FORMAT) || name == STRING_SLICE
+            );
+            inst.op == Op::Call && runtime && inst.dst.as_ref() == Some(&left)
+        };
+        let right_code = ctx.instructions.split_off(mark);
+        let left = if fresh {
+            left
+        } else {
+            let empty = ctx.new_tmp(IRType::OwnedString);
+            let null = self.get_const_index(IRConst::Int(0));
+            ctx.instructions.push(Instruction {
+                op: Op::Move,
+                dst: Some(empty.clone()),
+                src1: Some(Operand::ConstIdx(null)),
+                src2: None,
+                span: None,
+            });
+            self.append(empty, left, ctx)?
+        };
+        ctx.instructions.extend(right_code);
+        self.append(left, right, ctx)
+    }
+
+    /// Calls a runtime function of `alum-std` on operands that are already evaluated.
+    fn runtime_call(
+        &mut self,
+        name: &str,
+        params: Vec<VarType>,
+        ret_type: VarType,
+        args: Vec<Operand>,
+        ctx: &mut Context,
+    ) -> Result<Operand, IRGenError> {
+        let res_tmp = ctx.new_tmp(ctx.from_var_type(&ret_type));
+        self.extern_decl(Extern {
+            name: name.to_string(),
+            params,
+            ret_type,
+            is_pub: false,
+            is_variadic: false,
+        })?;
+        let (mut int_idx, mut flt_idx) = (0, 0);
+        for arg in args {
+            let op = match ctx.get_operand_type(&arg)? {
+                IRType::Float => {
+                    flt_idx += 1;
+                    Op::FArg(flt_idx - 1)
+                }
+                _ => {
+                    int_idx += 1;
+                    Op::Arg(int_idx - 1)
+                }
+            };
+            ctx.instructions.push(Instruction {
+                op,
+                dst: None,
+                src1: Some(arg),
+                src2: None,
+                span: None,
+            });
+        }
+        ctx.instructions.push(Instruction {
+            op: Op::Call,
+            dst: Some(res_tmp.clone()),
+            src1: Some(Operand::Function(name.to_string())),
+            src2: None,
+            span: None,
+        });
+        Ok(res_tmp)
+    }
+
+    /// Arrays and vecs both start with their length, against which the index is
+    /// checked. Loops over an array need no check, so they do not call this.
+    fn bounds_check(
+        &mut self,
+        arr: Operand,
+        index: Operand,
+        span: Span,
+        ctx: &mut Context,
+    ) -> Result<(), IRGenError> {
+        if !self.bounds_check {
+            return Ok(());
+        }
+        self.extern_decl(Extern {
+            name: BOUNDS_PANIC.to_string(),
+            params: vec![VarType::Str, VarType::Int, VarType::Int, VarType::Int],
+            ret_type: VarType::Void,
+            is_pub: false,
+            is_variadic: false,
+        })?;
+        ctx.instructions.push(Instruction {
+            op: Op::BoundsCheck(self.files[span.file].clone(), span.start.row),
+            dst: None,
+            src1: Some(arr),
+            src2: Some(index),
+            span: None,
+        });
+        Ok(())
+    }
+
+    /// Array literals live in the frame of the function, so one that is returned is
+    /// first copied to the heap. Arrays returned by calls are already there.
+    fn array_return(&mut self, value: Operand, ctx: &mut Context) -> Result<Operand, IRGenError> {
+        let IRType::Array(_) = ctx.get_operand_type(&value)? else {
+            return Ok(value);
+        };
+        let fresh = ctx.instructions.last().is_some_and(|inst| {
+            matches!(inst.op, Op::Call | Op::Range) && inst.dst.as_ref() == Some(&value)
+        });
+        if fresh {
+            return Ok(value);
+        }
+        self.extern_decl(Extern {
+            name: ARRAY_COPY.to_string(),
+            params: vec![VarType::Array(None)],
+            ret_type: VarType::Array(None),
+            is_pub: false,
+            is_variadic: false,
+        })?;
+        let copy = ctx.new_tmp(IRType::Array(None));
+        ctx.instructions.push(Instruction {
+            op: Op::Arg(0),
+            dst: None,
+            src1: Some(value),
+            src2: None,
+            span: None,
+        });
+        ctx.instructions.push(Instruction {
+            op: Op::Call,
+            dst: Some(copy.clone()),
+            src1: Some(Operand::Function(ARRAY_COPY.to_string())),
+            src2: None,
+            span: None,
+        });
+        Ok(copy)
+    }
+
+    /// Copies a struct about to be returned into the caller's buffer, if the current
+    /// function has one, and hands back the buffer instead.
+    fn struct_return(&mut self, value: Operand, ctx: &mut Context) -> Result<Operand, IRGenError> {
+        let IRType::Struct(name) = ctx.get_operand_type(&value)? else {
+            return Ok(value);
+        };
+        let Ok(sret_type) = ctx.get_var_type(SRET) else {
+            return Ok(value);
+        };
+        let size = self.find_struct(&name)?.size;
+        let buf = ctx.new_tmp(sret_type);
+        ctx.instructions.push(Instruction {
+            op: Op::Load,
+            dst: Some(buf.clone()),
+            src1: Some(Operand::Var(SRET.to_string())),
+            src2: None,
+            span: None,
+        });
+        ctx.instructions.push(Instruction {
+            op: Op::StructCopy(size),
+            dst: Some(buf.clone()),
+            src1: Some(value),
+            src2: None,
+            span: None,
+        });
+        Ok(buf)
+    }
+
+    /// Copies a struct into a buffer of its own, so that assigning it does not leave two
+    /// variables sharing one.
+    fn struct_copy(&mut self, value: Operand, ctx: &mut Context) -> Result<Operand, IRGenError> {
+        let typ = ctx.get_operand_type(&value)?;
+        let IRType::Struct(name) = &typ else {
+            return Ok(value);
+        };
+        let size = self.find_struct(name)?.size;
+        let buf = ctx.new_tmp(typ.clone());
+        ctx.instructions.push(Instruction {
+            op: Op::StructAlloc(size),
+            dst: Some(buf.clone()),
+            src1: None,
+            src2: None,
+            span: None,
+        });
+        ctx.instructions.push(Instruction {
+            op: Op::StructCopy(size),
+            dst: Some(buf.clone()),
+            src1: Some(value),
+            src2: None,
+            span: None,
+        });
+        Ok(buf)
+    }
+
+    /// Maps a function name as written in the current module to its symbol.
+    fn resolve_func(&self, name: &str) -> Result<String, IRGenError> {
+        self.symbols
+            .get(name)
+            .cloned()
+            .ok_or_else(|| IRGenError::NameError {
+                message: format!("undefined function '{}' in current module", name),
+                span: None,
+            })
+    }
+
     fn find_func(&self, name: &String) -> Result<IRFunction, IRGenError> {
         for func in self.functions.iter().rev() {
             if func.name == *name {
@@ -1006,6 +2186,13 @@ impl IRGen {
         }
         Err(IRGenError::NameError {
             message: format!("undefined function '{}' in current scope", name),
+            span: None,
         })
     }
 }
+
+/// Whether `expr` builds a struct that nothing else refers to yet, which can be
+/// stored without a copy.
+fn is_fresh_struct(expr: &Expr) -> bool {
+    matches!(expr.kind, ExprKind::StructLit(_) | ExprKind::FuncCall(_))
+}
@@ -2,6 +2,7 @@ use std::{iter::Peekable, str::Chars};
 
 use ordered_float::OrderedFloat;
 
+use crate::ast::Pos;
 use crate::token::{Literal, Token, TokenType, VarType};
 
 #[derive(Debug, Clone)]
@@ -25,29 +26,28 @@ pub enum LexerError {
 
 impl std::error::Error for LexerError {}
 
+impl LexerError {
+    pub fn pos(&self) -> (usize, usize) {
+        match self {
+            LexerError::SyntaxError { row, col, .. }
+            | LexerError::InvalidNumber { row, col }
+            | LexerError::UnexpectedChar { row, col, .. } => (*row, *col),
+        }
+    }
+}
+
 impl std::fmt::Display for LexerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LexerError::SyntaxError { message, row, col } => {
-                write!(f, "Syntax error at {}:{}: {}", row, col, message)
-            }
-            LexerError::InvalidNumber { row, col } => {
-                write!(f, "Invalid number at {}:{}", row, col)
-            }
+            LexerError::SyntaxError { message, .. } => write!(f, "Syntax error: {}", message),
+            LexerError::InvalidNumber { .. } => write!(f, "Invalid number"),
             LexerError::UnexpectedChar {
-                expected,
-                found,
-                row,
-                col,
+                expected, found, ..
             } => {
                 if let Some(exp) = expected {
-                    write!(
-                        f,
-                        "Unexpected char at {}:{}: expected '{}', found '{}'",
-                        row, col, exp, found
-                    )
+                    write!(f, "Unexpected char: expected '{}', found '{}'", exp, found)
                 } else {
-                    write!(f, "Unexpected char at {}:{}: '{}'", row, col, found)
+                    write!(f, "Unexpected char: '{}'", found)
                 }
             }
         }
@@ -59,6 +59,12 @@ pub struct Lexer<'a> {
     tok: Token,
     src: Peekable<Chars<'a>>,
     is_flt: bool,
+    /// Position of the next character.
+    row: usize,
+    col: usize,
+    /// Where the current token ends, and where the one before it did.
+    end: Pos,
+    prev_end: Pos,
 }
 
 impl<'a> Lexer<'a> {
@@ -72,6 +78,10 @@ impl<'a> Lexer<'a> {
             },
             src: src.chars().peekable(),
             is_flt: false,
+            row: 1,
+            col: 1,
+            end: Pos { row: 1, col: 1 },
+            prev_end: Pos { row: 1, col: 1 },
         }
     }
 
@@ -83,23 +93,32 @@ impl<'a> Lexer<'a> {
         Ok(*self.src.peek().ok_or_else(|| LexerError::UnexpectedChar {
             expected: None,
             found: '\0',
-            row: self.tok.row,
-            col: self.tok.col,
+            row: self.row,
+            col: self.col,
         })?)
     }
 
     fn bump(&mut self) -> () {
         self.src.next();
-        self.tok.col += 1;
+        self.col += 1;
     }
 
     fn skip_spaces(&mut self) -> () {
-        while self.current() == ' ' || self.current() == '\t' || self.current() == '\n' {
-            if self.current() == '\n' {
-                self.tok.row += 1;
-                self.tok.col = 0;
+        loop {
+            match self.current() {
+                ' ' | '\t' => self.bump(),
+                '\n' => {
+                    self.row += 1;
+                    self.col = 0;
+                    self.bump();
+                }
+                '#' => {
+                    while self.current() != '\n' && self.current() != '\0' {
+                        self.bump();
+                    }
+                }
+                _ => return,
             }
-            self.bump();
         }
     }
 
@@ -115,8 +134,8 @@ impl<'a> Lexer<'a> {
                     .current()
                     .to_digit(10)
                     .ok_or_else(|| LexerError::InvalidNumber {
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     })?;
             self.bump();
         }
@@ -126,8 +145,8 @@ impl<'a> Lexer<'a> {
             self.bump();
             if !self.current().is_numeric() {
                 return Err(LexerError::InvalidNumber {
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 });
             }
             while self.current().is_numeric() {
@@ -137,8 +156,8 @@ impl<'a> Lexer<'a> {
                         .current()
                         .to_digit(10)
                         .ok_or_else(|| LexerError::InvalidNumber {
-                            row: self.tok.row,
-                            col: self.tok.col,
+                            row: self.row,
+                            col: self.col,
                         })?;
                 self.bump();
             }
@@ -171,14 +190,28 @@ impl<'a> Lexer<'a> {
             || prev == '('
     }
 
+    /// Moves on to the next token, recording where it starts and ends.
     pub fn next_token(&mut self) -> Result<(), LexerError> {
+        self.prev_end = self.end;
         self.skip_spaces();
+        let (row, col) = (self.row, self.col);
+        self.scan()?;
+        self.tok.row = row;
+        self.tok.col = col;
+        self.end = Pos {
+            row: self.row,
+            col: self.col,
+        };
+        Ok(())
+    }
+
+    fn scan(&mut self) -> Result<(), LexerError> {
         if self.current() == '\0' {
             self.tok = Token {
                 token: TokenType::EOF,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current().is_numeric() {
@@ -187,15 +220,15 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::LITERAL(VarType::Float),
                     value: Some(Literal::Float(OrderedFloat(val))),
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
             } else {
                 self.tok = Token {
                     token: TokenType::LITERAL(VarType::Int),
                     value: Some(Literal::Int(val as i64)),
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
             }
             return Ok(());
@@ -206,136 +239,200 @@ impl<'a> Lexer<'a> {
                     self.tok = Token {
                         token: TokenType::LITERAL(VarType::Bool),
                         value: Some(Literal::Bool(true)),
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     };
                 }
                 "false" => {
                     self.tok = Token {
                         token: TokenType::LITERAL(VarType::Bool),
                         value: Some(Literal::Bool(false)),
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     };
                 }
                 "null" => {
                     self.tok = Token {
                         token: TokenType::LITERAL(VarType::Void),
                         value: Some(Literal::Void),
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     };
                 }
                 "let" => {
                     self.tok = Token {
                         token: TokenType::VARDECL,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     };
                 }
                 "fun" => {
                     self.tok = Token {
                         token: TokenType::FUNCDECL,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "return" => {
                     self.tok = Token {
                         token: TokenType::RETURN,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "if" => {
                     self.tok = Token {
                         token: TokenType::IF,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "else" => {
                     self.tok = Token {
                         token: TokenType::ELSE,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "while" => {
                     self.tok = Token {
                         token: TokenType::WHILE,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "goto" => {
                     self.tok = Token {
                         token: TokenType::GOTO,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "break" => {
+                    self.tok = Token {
+                        token: TokenType::BREAK,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "continue" => {
+                    self.tok = Token {
+                        token: TokenType::CONTINUE,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "extern" => {
                     self.tok = Token {
                         token: TokenType::EXTERN,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "pub" => {
                     self.tok = Token {
                         token: TokenType::PUB,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "inline" => {
+                    self.tok = Token {
+                        token: TokenType::INLINE,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "noinline" => {
+                    self.tok = Token {
+                        token: TokenType::NOINLINE,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "import" => {
+                    self.tok = Token {
+                        token: TokenType::IMPORT,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "from" => {
+                    self.tok = Token {
+                        token: TokenType::FROM,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "int" => {
                     self.tok = Token {
                         token: TokenType::Type(VarType::Int),
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "flt" => {
                     self.tok = Token {
                         token: TokenType::Type(VarType::Float),
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "str" => {
                     self.tok = Token {
                         token: TokenType::Type(VarType::Str),
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "bool" => {
                     self.tok = Token {
                         token: TokenType::Type(VarType::Bool),
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "char" => {
+                    self.tok = Token {
+                        token: TokenType::Type(VarType::Char),
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "vec" => {
+                    self.tok = Token {
+                        token: TokenType::Type(VarType::Vec),
+                        value: None,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "void" => {
                     self.tok = Token {
                         token: TokenType::Type(VarType::Void),
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "arr" => {
@@ -343,8 +440,8 @@ impl<'a> Lexer<'a> {
                         return Err(LexerError::UnexpectedChar {
                             expected: Some("<".to_string()),
                             found: self.current(),
-                            row: self.tok.row,
-                            col: self.tok.col,
+                            row: self.row,
+                            col: self.col,
                         });
                     }
                     self.bump();
@@ -358,56 +455,96 @@ impl<'a> Lexer<'a> {
                         return Err(LexerError::UnexpectedChar {
                             expected: None,
                             found: self.current(),
-                            row: self.tok.row,
-                            col: self.tok.col,
+                            row: self.row,
+                            col: self.col,
                         });
                     }
                     if self.current() != '>' {
                         return Err(LexerError::UnexpectedChar {
                             expected: Some(">".to_string()),
                             found: self.current(),
-                            row: self.tok.row,
-                            col: self.tok.col,
+                            row: self.row,
+                            col: self.col,
                         });
                     }
                     self.bump();
                     self.tok = Token {
                         token: TokenType::Type(VarType::Array(len)),
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "sizeof" => {
                     self.tok = Token {
                         token: TokenType::SIZEOF,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "ord" => {
+                    self.tok = Token {
+                        token: TokenType::ORD,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "chr" => {
+                    self.tok = Token {
+                        token: TokenType::CHR,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "panic" => {
+                    self.tok = Token {
+                        token: TokenType::PANIC,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "format" => {
+                    self.tok = Token {
+                        token: TokenType::FORMAT,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "for" => {
                     self.tok = Token {
                         token: TokenType::FOR,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 "in" => {
                     self.tok = Token {
                         token: TokenType::IN,
                         value: None,
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
+                    }
+                }
+                "struct" => {
+                    self.tok = Token {
+                        token: TokenType::STRUCT,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
                 _ => {
                     self.tok = Token {
                         token: TokenType::IDENT,
                         value: Some(Literal::Str(ident)),
-                        row: self.tok.row,
-                        col: self.tok.col,
+                        row: self.row,
+                        col: self.col,
                     }
                 }
             }
@@ -457,31 +594,61 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::LITERAL(VarType::Str),
                 value: Some(Literal::Str(s)),
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '\'' {
             self.bump();
-            let mut s = String::new();
-            while self.current() != '\'' {
-                if self.current() == '\0' {
-                    return Err(LexerError::UnexpectedChar {
-                        expected: Some("'".to_string()),
-                        found: self.current(),
-                        row: self.tok.row,
-                        col: self.tok.col,
+            let c = match self.current() {
+                '\\' => {
+                    self.bump();
+                    match self.current() {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '0' => '\0',
+                        c @ ('\\' | '\'' | '"') => c,
+                        c => {
+                            return Err(LexerError::SyntaxError {
+                                message: format!("unknown escape '\\{}'", c),
+                                row: self.row,
+                                col: self.col,
+                            });
+                        }
+                    }
+                }
+                '\'' | '\0' => {
+                    return Err(LexerError::SyntaxError {
+                        message: "empty character literal".to_string(),
+                        row: self.row,
+                        col: self.col,
                     });
                 }
-                s.push(self.current());
-                self.bump();
+                c => c,
+            };
+            // A char is a single byte, so only ASCII fits.
+            if !c.is_ascii() {
+                return Err(LexerError::SyntaxError {
+                    message: format!("'{}' does not fit in a char", c),
+                    row: self.row,
+                    col: self.col,
+                });
+            }
+            self.bump();
+            if self.current() != '\'' {
+                return Err(LexerError::SyntaxError {
+                    message: "character literal holds more than one character".to_string(),
+                    row: self.row,
+                    col: self.col,
+                });
             }
             self.bump();
             self.tok = Token {
-                token: TokenType::LITERAL(VarType::Str),
-                value: Some(Literal::Str(s)),
-                row: self.tok.row,
-                col: self.tok.col,
+                token: TokenType::LITERAL(VarType::Char),
+                value: Some(Literal::Char(c as u8)),
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '+' {
@@ -494,8 +661,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::ADDEQ,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -503,8 +670,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::ADD,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '-' {
@@ -512,8 +679,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::NEG,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -523,8 +690,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::SUBEQ,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -532,8 +699,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::SUB,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '*' {
@@ -542,8 +709,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::MULEQ,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -551,8 +718,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::MUL,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '/' {
@@ -561,8 +728,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::DIVEQ,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -570,16 +737,35 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::DIV,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
+            };
+            return Ok(());
+        } else if self.current() == '%' {
+            self.bump();
+            if self.current() == '=' {
+                self.tok = Token {
+                    token: TokenType::MODEQ,
+                    value: None,
+                    row: self.row,
+                    col: self.col,
+                };
+                self.bump();
+                return Ok(());
+            }
+            self.tok = Token {
+                token: TokenType::MOD,
+                value: None,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '(' {
             self.tok = Token {
                 token: TokenType::LPAREN,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -587,8 +773,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::RPAREN,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -596,8 +782,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::LBRACE,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -605,8 +791,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::RBRACE,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -616,8 +802,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::COMPEQ,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -625,8 +811,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::EQ,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '!' {
@@ -635,8 +821,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::COMPNE,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -644,18 +830,38 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::LOGNOT,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '>' {
             self.bump();
+            if self.current() == '>' {
+                self.bump();
+                if self.current() == '=' {
+                    self.tok = Token {
+                        token: TokenType::SHREQ,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    };
+                    self.bump();
+                    return Ok(());
+                }
+                self.tok = Token {
+                    token: TokenType::SHR,
+                    value: None,
+                    row: self.row,
+                    col: self.col,
+                };
+                return Ok(());
+            }
             if self.current() == '=' {
                 self.tok = Token {
                     token: TokenType::COMPGE,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -663,18 +869,38 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::COMPGT,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '<' {
             self.bump();
+            if self.current() == '<' {
+                self.bump();
+                if self.current() == '=' {
+                    self.tok = Token {
+                        token: TokenType::SHLEQ,
+                        value: None,
+                        row: self.row,
+                        col: self.col,
+                    };
+                    self.bump();
+                    return Ok(());
+                }
+                self.tok = Token {
+                    token: TokenType::SHL,
+                    value: None,
+                    row: self.row,
+                    col: self.col,
+                };
+                return Ok(());
+            }
             if self.current() == '=' {
                 self.tok = Token {
                     token: TokenType::COMPLE,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -682,8 +908,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::COMPLT,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '&' {
@@ -692,8 +918,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::COMPAND,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -701,8 +927,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::LOGAND,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '|' {
@@ -711,8 +937,8 @@ impl<'a> Lexer<'a> {
                 self.tok = Token {
                     token: TokenType::COMPOR,
                     value: None,
-                    row: self.tok.row,
-                    col: self.tok.col,
+                    row: self.row,
+                    col: self.col,
                 };
                 self.bump();
                 return Ok(());
@@ -720,16 +946,16 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::LOGOR,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             return Ok(());
         } else if self.current() == '^' {
             self.tok = Token {
                 token: TokenType::LOGXOR,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -737,17 +963,25 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::COLON,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
         } else if self.current() == '~' {
+            // `a ~ b` is a range, and a `~` that does not follow an operand is a bitwise not.
+            let token = match self.tok.token {
+                TokenType::LITERAL(_)
+                | TokenType::IDENT
+                | TokenType::RPAREN
+                | TokenType::RBRACKET => TokenType::RANGE,
+                _ => TokenType::BITNOT,
+            };
             self.tok = Token {
-                token: TokenType::RANGE,
+                token,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -755,8 +989,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::LBRACKET,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -764,8 +998,8 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::RBRACKET,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
@@ -773,23 +1007,39 @@ impl<'a> Lexer<'a> {
             self.tok = Token {
                 token: TokenType::COMMA,
                 value: None,
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             };
             self.bump();
             return Ok(());
-        } else if self.current() == '#' {
-            while self.current() != '\n' && self.current() != '\0' {
+        } else if self.current() == '.' {
+            self.tok = Token {
+                token: TokenType::DOT,
+                value: None,
+                row: self.row,
+                col: self.col,
+            };
+            self.bump();
+            if self.current() == '.' {
+                self.bump();
+                if self.current() != '.' {
+                    return Err(LexerError::UnexpectedChar {
+                        expected: Some(".".to_string()),
+                        found: self.current(),
+                        row: self.row,
+                        col: self.col,
+                    });
+                }
                 self.bump();
+                self.tok.token = TokenType::ELLIPSIS;
             }
-            self.next_token()?;
             return Ok(());
         } else {
             return Err(LexerError::UnexpectedChar {
                 expected: None,
                 found: self.current(),
-                row: self.tok.row,
-                col: self.tok.col,
+                row: self.row,
+                col: self.col,
             });
         }
     }
@@ -798,6 +1048,11 @@ impl<'a> Lexer<'a> {
         self.tok.clone()
     }
 
+    /// Where the token before the current one ends, just past its last character.
+    pub fn prev_end(&self) -> Pos {
+        self.prev_end
+    }
+
     pub fn curr_ch(&mut self) -> char {
         self.current()
     }
@@ -0,0 +1,1118 @@
+use std::collections::{HashMap, HashSet};
+
+const BASE_ADDR: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+/// Where the executable starts, which the standard library defines.
+const ENTRY: &str = "_start";
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHT_PROGBITS: u32 = 1;
+const SHT_GROUP: u32 = 17;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_TLS: u64 = 0x400;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+const SHN_COMMON: u16 = 0xfff2;
+
+const STB_LOCAL: u8 = 0;
+const STB_WEAK: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_32: u32 = 10;
+const R_X86_64_32S: u32 = 11;
+const R_X86_64_PC64: u32 = 24;
+const R_X86_64_GOTPCRELX: u32 = 41;
+const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+#[derive(Debug, Clone)]
+pub enum LinkerError {
+    InputError { file: String, message: String },
+    UndefinedSymbol { name: String, file: String },
+    DuplicateSymbol { name: String, file: String },
+    RelocationError { file: String, message: String },
+}
+
+impl std::error::Error for LinkerError {}
+
+impl std::fmt::Display for LinkerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkerError::InputError { file, message } => {
+                write!(f, "Linker error in {}: {}", file, message)
+            }
+            LinkerError::UndefinedSymbol { name, file } => {
+                write!(
+                    f,
+                    "Linker error in {}: undefined reference to '{}'",
+                    file, name
+                )
+            }
+            LinkerError::DuplicateSymbol { name, file } => {
+                write!(
+                    f,
+                    "Linker error in {}: multiple definition of '{}'",
+                    file, name
+                )
+            }
+            LinkerError::RelocationError { file, message } => {
+                write!(f, "Linker error in {}: {}", file, message)
+            }
+        }
+    }
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], String> {
+    data.get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| "unexpected end of file".to_string())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, String> {
+    read(data, offset).map(u64::from_le_bytes)
+}
+
+fn c_str(data: &[u8], offset: usize) -> Result<String, String> {
+    let bytes = data.get(offset..).ok_or("string out of range")?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputKind {
+    Text,
+    Rodata,
+    Data,
+    Bss,
+    /// Not loaded, but kept in the file for debuggers.
+    Debug,
+}
+
+#[derive(Debug)]
+struct Rela {
+    offset: u64,
+    sym: usize,
+    kind: u32,
+    addend: i64,
+}
+
+#[derive(Debug)]
+struct InputSection {
+    name: String,
+    kind: OutputKind,
+    data: Vec<u8>,
+    size: u64,
+    align: u64,
+    relocs: Vec<Rela>,
+    /// Address in the executable, known once the layout is done.
+    addr: u64,
+}
+
+#[derive(Debug)]
+struct InputSymbol {
+    name: String,
+    bind: u8,
+    typ: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+/// A relocatable object. Only sections that end up in memory or, when linking with
+/// debug information, in the `.debug_*` sections are kept, indexed by their section
+/// number.
+#[derive(Debug)]
+struct InputObject {
+    name: String,
+    sections: HashMap<usize, InputSection>,
+    symbols: Vec<InputSymbol>,
+}
+
+struct SectionHeader {
+    name: String,
+    typ: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: u64,
+}
+
+/// Whether `ld` would define `__start_` and `__stop_` symbols for a section.
+fn is_c_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn section_headers(data: &[u8]) -> Result<Vec<SectionHeader>, String> {
+    if data.get(..4) != Some(b"\x7fELF") {
+        return Err("not an ELF file".to_string());
+    }
+    if data[4] != 2 || data[5] != 1 || u16_at(data, 18)? != 62 {
+        return Err("not an x86-64 ELF file".to_string());
+    }
+    if u16_at(data, 16)? != 1 {
+        return Err("not a relocatable object".to_string());
+    }
+    let shoff = u64_at(data, 0x28)? as usize;
+    let shnum = u16_at(data, 0x3c)? as usize;
+    let shstrndx = u16_at(data, 0x3e)? as usize;
+    let names = u64_at(data, shoff + shstrndx * 64 + 24)? as usize;
+    (0..shnum)
+        .map(|i| {
+            let h = shoff + i * 64;
+            Ok(SectionHeader {
+                name: c_str(data, names + u32_at(data, h)? as usize)?,
+                typ: u32_at(data, h + 4)?,
+                flags: u64_at(data, h + 8)?,
+                offset: u64_at(data, h + 24)? as usize,
+                size: u64_at(data, h + 32)? as usize,
+                link: u32_at(data, h + 40)?,
+                info: u32_at(data, h + 44)?,
+                align: u64_at(data, h + 48)?,
+            })
+        })
+        .collect()
+}
+
+fn section_data<'a>(data: &'a [u8], h: &SectionHeader) -> Result<&'a [u8], String> {
+    data.get(h.offset..h.offset + h.size)
+        .ok_or_else(|| "section out of range".to_string())
+}
+
+fn parse_symbols(data: &[u8], headers: &[SectionHeader]) -> Result<Vec<InputSymbol>, String> {
+    let Some(symtab) = headers.iter().find(|h| h.typ == SHT_SYMTAB) else {
+        return Ok(Vec::new());
+    };
+    let strtab = headers
+        .get(symtab.link as usize)
+        .ok_or("invalid string table")?;
+    let strtab = section_data(data, strtab)?;
+    let entries = section_data(data, symtab)?;
+    (0..entries.len() / 24)
+        .map(|i| {
+            let e = i * 24;
+            Ok(InputSymbol {
+                name: c_str(strtab, u32_at(entries, e)? as usize)?,
+                bind: entries[e + 4] >> 4,
+                typ: entries[e + 4] & 0xf,
+                shndx: u16_at(entries, e + 6)?,
+                value: u64_at(entries, e + 8)?,
+                size: u64_at(entries, e + 16)?,
+            })
+        })
+        .collect()
+}
+
+/// Global symbols defined by an object, used to index archives without a symbol table.
+fn defined_symbols(data: &[u8]) -> Result<Vec<String>, String> {
+    let headers = section_headers(data)?;
+    Ok(parse_symbols(data, &headers)?
+        .into_iter()
+        .filter(|s| s.bind != STB_LOCAL && s.shndx != SHN_UNDEF)
+        .map(|s| s.name)
+        .collect())
+}
+
+struct Archive {
+    name: String,
+    data: Vec<u8>,
+    /// Name and size of each member, by the offset of its data.
+    members: HashMap<usize, (String, usize)>,
+    /// Offset of the member that defines each symbol.
+    index: HashMap<String, usize>,
+    loaded: HashSet<usize>,
+}
+
+/// A member of an `ar` archive: its name, data offset and size.
+type Member = (String, usize, usize);
+
+/// Returns every member of an `ar` archive, along with its symbol table if it has
+/// one.
+fn archive_members(data: &[u8]) -> Result<(Vec<Member>, Option<&[u8]>), String> {
+    if data.starts_with(b"!<thin>\n") {
+        return Err("thin archives are not supported".to_string());
+    }
+    let mut members = Vec::new();
+    let mut symtab = None;
+    let mut long_names: &[u8] = &[];
+    let mut pos = 8;
+    while pos + 60 <= data.len() {
+        let header = &data[pos..pos + 60];
+        let name = String::from_utf8_lossy(&header[..16])
+            .trim_end()
+            .to_string();
+        let size: usize = String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse()
+            .map_err(|_| "invalid archive member size".to_string())?;
+        let start = pos + 60;
+        let body = data
+            .get(start..start + size)
+            .ok_or("unexpected end of archive")?;
+        match name.as_str() {
+            "/" => symtab = Some(body),
+            "//" => long_names = body,
+            _ => {
+                let name = match name.strip_prefix('/').map(str::parse::<usize>) {
+                    Some(Ok(offset)) => {
+                        let rest = long_names.get(offset..).ok_or("invalid member name")?;
+                        let end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+                        String::from_utf8_lossy(&rest[..end]).to_string()
+                    }
+                    _ => name,
+                };
+                members.push((name.trim_end_matches('/').to_string(), start, size));
+            }
+        }
+        pos = start + size + (size & 1);
+    }
+    Ok((members, symtab))
+}
+
+impl Archive {
+    fn parse(name: &str, data: Vec<u8>) -> Result<Self, String> {
+        let (members, symtab) = archive_members(&data)?;
+        let mut index = HashMap::new();
+        match symtab {
+            // The GNU symbol table: a count, the header offset of the member that
+            // defines each symbol, then their names, all big-endian.
+            Some(symtab) => {
+                let count = u32::from_be_bytes(read(symtab, 0)?) as usize;
+                let mut names = symtab
+                    .get(4 + count * 4..)
+                    .ok_or("invalid archive symbol table")?
+                    .split(|b| *b == 0);
+                for i in 0..count {
+                    let offset = u32::from_be_bytes(read(symtab, 4 + i * 4)?) as usize;
+                    let name = names.next().ok_or("invalid archive symbol table")?;
+                    index
+                        .entry(String::from_utf8_lossy(name).to_string())
+                        .or_insert(offset + 60);
+                }
+            }
+            None => {
+                for (_, start, size) in &members {
+                    if let Ok(symbols) = defined_symbols(&data[*start..*start + *size]) {
+                        for symbol in symbols {
+                            index.entry(symbol).or_insert(*start);
+                        }
+                    }
+                }
+            }
+        }
+        let members = members
+            .into_iter()
+            .map(|(member, start, size)| (start, (member, size)))
+            .collect();
+        Ok(Self {
+            name: name.to_string(),
+            data,
+            members,
+            index,
+            loaded: HashSet::new(),
+        })
+    }
+
+    fn member(&self, start: usize) -> Option<(String, &[u8])> {
+        let (name, size) = self.members.get(&start)?;
+        Some((
+            format!("{}({})", self.name, name),
+            &self.data[start..start + size],
+        ))
+    }
+}
+
+/// Identifies the GOT slot of a symbol: global symbols share one across objects.
+#[derive(PartialEq, Eq, Hash)]
+enum GotKey {
+    Local(usize, usize),
+    Global(String),
+}
+
+fn got_key(obj: &InputObject, object: usize, sym: usize) -> GotKey {
+    match obj.symbols.get(sym) {
+        Some(symbol) if symbol.bind != STB_LOCAL => GotKey::Global(symbol.name.clone()),
+        _ => GotKey::Local(object, sym),
+    }
+}
+
+enum Definition {
+    Section {
+        object: usize,
+        shndx: usize,
+        value: u64,
+    },
+    Absolute(u64),
+}
+
+struct Global {
+    definition: Option<Definition>,
+    weak: bool,
+    typ: u8,
+    size: u64,
+}
+
+/// Links relocatable objects and archives into a static x86-64 executable, the way
+/// `ld` does for the objects `al` produces and the standard library.
+pub struct Linker {
+    objects: Vec<InputObject>,
+    archives: Vec<Archive>,
+    globals: HashMap<String, Global>,
+    comdat_groups: HashSet<String>,
+    /// Whether to keep the debug information of the objects. Unlike `ld`, the
+    /// linker leaves it out unless asked, as that of the standard library is
+    /// larger than most programs.
+    debug: bool,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl Linker {
+    pub fn new(debug: bool) -> Self {
+        Self {
+            objects: Vec::new(),
+            archives: Vec::new(),
+            globals: HashMap::new(),
+            comdat_groups: HashSet::new(),
+            debug,
+        }
+    }
+
+    /// Adds an object or archive, telling them apart by their contents.
+    pub fn add_file(&mut self, name: &str, data: Vec<u8>) -> Result<(), LinkerError> {
+        let input_error = |message| LinkerError::InputError {
+            file: name.to_string(),
+            message,
+        };
+        if data.starts_with(b"!<arch>\n") || data.starts_with(b"!<thin>\n") {
+            let archive = Archive::parse(name, data).map_err(input_error)?;
+            self.archives.push(archive);
+            Ok(())
+        } else {
+            self.add_object(name, &data)
+        }
+    }
+
+    fn add_object(&mut self, name: &str, data: &[u8]) -> Result<(), LinkerError> {
+        let (sections, symbols) =
+            self.parse_object(data)
+                .map_err(|message| LinkerError::InputError {
+                    file: name.to_string(),
+                    message,
+                })?;
+
+        let object = self.objects.len();
+        for symbol in symbols.iter().filter(|s| s.bind != STB_LOCAL) {
+            let weak = symbol.bind == STB_WEAK;
+            let definition = match symbol.shndx {
+                SHN_UNDEF => None,
+                SHN_ABS => Some(Definition::Absolute(symbol.value)),
+                SHN_COMMON => {
+                    return Err(LinkerError::InputError {
+                        file: name.to_string(),
+                        message: format!("common symbol '{}' is not supported", symbol.name),
+                    });
+                }
+                shndx if sections.contains_key(&(shndx as usize)) => Some(Definition::Section {
+                    object,
+                    shndx: shndx as usize,
+                    value: symbol.value,
+                }),
+                // Defined in a section that is not loaded, such as a discarded group.
+                _ => None,
+            };
+            let global = self.globals.entry(symbol.name.clone()).or_insert(Global {
+                definition: None,
+                weak: true,
+                typ: symbol.typ,
+                size: symbol.size,
+            });
+            let Some(definition) = definition else {
+                continue;
+            };
+            match (&global.definition, global.weak, weak) {
+                (Some(_), false, false) => {
+                    return Err(LinkerError::DuplicateSymbol {
+                        name: symbol.name.clone(),
+                        file: name.to_string(),
+                    });
+                }
+                (Some(_), _, true) => {}
+                _ => {
+                    global.definition = Some(definition);
+                    global.weak = weak;
+                    global.typ = symbol.typ;
+                    global.size = symbol.size;
+                }
+            }
+        }
+
+        self.objects.push(InputObject {
+            name: name.to_string(),
+            sections,
+            symbols,
+        });
+        Ok(())
+    }
+
+    /// Reads the sections of an object that get loaded into memory or kept for
+    /// debuggers, with their relocations, and its symbols.
+    fn parse_object(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(HashMap<usize, InputSection>, Vec<InputSymbol>), String> {
+        let headers = section_headers(data)?;
+        let symbols = parse_symbols(data, &headers)?;
+
+        // Only the first copy of a COMDAT group is kept, as `ld` does.
+        let mut discarded = HashSet::new();
+        for h in headers.iter().filter(|h| h.typ == SHT_GROUP) {
+            let signature = symbols
+                .get(h.info as usize)
+                .map(|s| s.name.clone())
+                .unwrap_or_default();
+            let members = section_data(data, h)?;
+            if !self.comdat_groups.insert(signature) {
+                for i in 1..members.len() / 4 {
+                    discarded.insert(u32_at(members, i * 4)? as usize);
+                }
+            }
+        }
+
+        let mut sections = HashMap::new();
+        for (i, h) in headers.iter().enumerate() {
+            let debug = self.debug
+                && h.flags & SHF_ALLOC == 0
+                && h.typ == SHT_PROGBITS
+                && h.name.starts_with(".debug_");
+            if !debug
+                && (h.flags & SHF_ALLOC == 0 || !(h.typ == SHT_PROGBITS || h.typ == SHT_NOBITS))
+                || discarded.contains(&i)
+            {
+                continue;
+            }
+            if h.flags & SHF_TLS != 0 {
+                return Err("thread-local storage is not supported".to_string());
+            }
+            let kind = if debug {
+                OutputKind::Debug
+            } else if h.flags & SHF_EXECINSTR != 0 {
+                OutputKind::Text
+            } else if h.typ == SHT_NOBITS {
+                OutputKind::Bss
+            } else if h.flags & SHF_WRITE != 0 {
+                OutputKind::Data
+            } else {
+                OutputKind::Rodata
+            };
+            let bytes = if h.typ == SHT_NOBITS {
+                Vec::new()
+            } else {
+                section_data(data, h)?.to_vec()
+            };
+            sections.insert(
+                i,
+                InputSection {
+                    name: h.name.clone(),
+                    kind,
+                    data: bytes,
+                    size: h.size as u64,
+                    align: h.align.max(1),
+                    relocs: Vec::new(),
+                    addr: 0,
+                },
+            );
+        }
+        for h in headers.iter().filter(|h| h.typ == SHT_RELA) {
+            let Some(section) = sections.get_mut(&(h.info as usize)) else {
+                continue;
+            };
+            let entries = section_data(data, h)?;
+            for e in (0..entries.len() / 24).map(|i| i * 24) {
+                let info = u64_at(entries, e + 8)?;
+                section.relocs.push(Rela {
+                    offset: u64_at(entries, e)?,
+                    sym: (info >> 32) as usize,
+                    kind: info as u32,
+                    addend: u64_at(entries, e + 16)? as i64,
+                });
+            }
+        }
+        Ok((sections, symbols))
+    }
+
+    /// Pulls in archive members for as long as they define a symbol that is still
+    /// undefined. Archives are searched as one group, so their order does not matter.
+    fn load_archive_members(&mut self) -> Result<(), LinkerError> {
+        loop {
+            let mut wanted = Vec::new();
+            for (name, global) in &self.globals {
+                if global.definition.is_some() {
+                    continue;
+                }
+                for (i, archive) in self.archives.iter().enumerate() {
+                    if let Some(start) = archive.index.get(name)
+                        && !archive.loaded.contains(start)
+                    {
+                        wanted.push((i, *start));
+                        break;
+                    }
+                }
+            }
+            if wanted.is_empty() {
+                return Ok(());
+            }
+            wanted.sort_unstable();
+            wanted.dedup();
+            for (i, start) in wanted {
+                if !self.archives[i].loaded.insert(start) {
+                    continue;
+                }
+                let archive = &self.archives[i];
+                let (name, data) =
+                    archive
+                        .member(start)
+                        .ok_or_else(|| LinkerError::InputError {
+                            file: archive.name.clone(),
+                            message: "invalid archive symbol table".to_string(),
+                        })?;
+                let data = data.to_vec();
+                self.add_object(&name, &data)?;
+            }
+        }
+    }
+
+    /// Address of symbol `sym` of object `object`, or `None` if it is undefined.
+    fn symbol_addr(&self, object: usize, sym: usize) -> Option<u64> {
+        let obj = &self.objects[object];
+        let symbol = obj.symbols.get(sym)?;
+        if symbol.bind == STB_LOCAL {
+            return match symbol.shndx {
+                SHN_ABS => Some(symbol.value),
+                shndx => {
+                    let section = obj.sections.get(&(shndx as usize))?;
+                    let value = if symbol.typ == STT_SECTION {
+                        0
+                    } else {
+                        symbol.value
+                    };
+                    Some(section.addr + value)
+                }
+            };
+        }
+        let global = self.globals.get(&symbol.name)?;
+        match &global.definition {
+            Some(Definition::Section {
+                object,
+                shndx,
+                value,
+            }) => Some(self.objects[*object].sections[shndx].addr + value),
+            Some(Definition::Absolute(value)) => Some(*value),
+            // Undefined weak references resolve to zero.
+            None if symbol.bind == STB_WEAK => Some(0),
+            None => None,
+        }
+    }
+
+    pub fn link(mut self) -> Result<Vec<u8>, LinkerError> {
+        // Like `ld -e`, the entry counts as referenced, so that it is pulled from an
+        // archive even when no object refers to it.
+        self.globals.entry(ENTRY.to_string()).or_insert(Global {
+            definition: None,
+            weak: true,
+            typ: 0,
+            size: 0,
+        });
+        self.load_archive_members()?;
+
+        // Every symbol loaded through the GOT gets a slot holding its address.
+        let mut got: Vec<(usize, usize)> = Vec::new();
+        let mut got_slots: HashMap<GotKey, usize> = HashMap::new();
+        for (o, obj) in self.objects.iter().enumerate() {
+            for section in obj.sections.values() {
+                for r in &section.relocs {
+                    if matches!(
+                        r.kind,
+                        R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX
+                    ) {
+                        got_slots.entry(got_key(obj, o, r.sym)).or_insert_with(|| {
+                            got.push((o, r.sym));
+                            got.len() - 1
+                        });
+                    }
+                }
+            }
+        }
+
+        // Layout: the headers, code and read-only data share the first segment, the
+        // writable data, GOT and zeroed data the second.
+        let headers_size = 64 + 56 * 3;
+        let mut addr = BASE_ADDR + headers_size as u64;
+        let mut order: Vec<(usize, usize)> = Vec::new();
+        let mut bounds = HashMap::new();
+        let mut named: HashMap<String, (u64, u64)> = HashMap::new();
+        for (kind, bound) in [
+            (OutputKind::Text, "text"),
+            (OutputKind::Rodata, "rodata"),
+            (OutputKind::Data, "data"),
+            (OutputKind::Bss, "bss"),
+        ] {
+            if kind == OutputKind::Data {
+                addr = addr.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+            }
+            let start = addr;
+            let mut placed: Vec<(usize, usize)> = Vec::new();
+            for (o, obj) in self.objects.iter().enumerate() {
+                let mut indices: Vec<usize> = obj
+                    .sections
+                    .iter()
+                    .filter(|(_, s)| s.kind == kind)
+                    .map(|(i, _)| *i)
+                    .collect();
+                indices.sort_unstable();
+                placed.extend(indices.into_iter().map(|i| (o, i)));
+            }
+            // Like `ld`, sections named like C identifiers are gathered after the
+            // others, one run per name, so that they can be walked as an array.
+            placed.sort_by_key(|(o, i)| {
+                let name = &self.objects[*o].sections[i].name;
+                is_c_identifier(name).then(|| name.clone())
+            });
+            for (o, i) in placed {
+                let section = self.objects[o].sections.get_mut(&i).unwrap();
+                addr = addr.div_ceil(section.align) * section.align;
+                if is_c_identifier(&section.name) {
+                    named.entry(section.name.clone()).or_insert((addr, addr)).1 =
+                        addr + section.size;
+                }
+                section.addr = addr;
+                addr += section.size;
+                order.push((o, i));
+            }
+            if kind == OutputKind::Data {
+                addr = addr.div_ceil(8) * 8;
+                bounds.insert("got", (addr, addr + got.len() as u64 * 8));
+                addr += got.len() as u64 * 8;
+            }
+            bounds.insert(bound, (start, addr));
+        }
+        for (name, (start, stop)) in named {
+            for (symbol, value) in [("__start_", start), ("__stop_", stop)] {
+                if let Some(global) = self.globals.get_mut(&format!("{}{}", symbol, name)) {
+                    global.definition.get_or_insert(Definition::Absolute(value));
+                }
+            }
+        }
+        // Debug sections are merged by name, and addressed by their offset in the
+        // merged section.
+        let mut debug: Vec<(String, Vec<u8>)> = Vec::new();
+        for (o, obj) in self.objects.iter_mut().enumerate() {
+            let mut indices: Vec<usize> = obj
+                .sections
+                .iter()
+                .filter(|(_, s)| s.kind == OutputKind::Debug)
+                .map(|(i, _)| *i)
+                .collect();
+            indices.sort_unstable();
+            for i in indices {
+                let section = obj.sections.get_mut(&i).unwrap();
+                let bytes = match debug.iter().position(|(name, _)| *name == section.name) {
+                    Some(pos) => &mut debug[pos].1,
+                    None => {
+                        debug.push((section.name.clone(), Vec::new()));
+                        &mut debug.last_mut().unwrap().1
+                    }
+                };
+                while !(bytes.len() as u64).is_multiple_of(section.align) {
+                    bytes.push(0);
+                }
+                section.addr = bytes.len() as u64;
+                bytes.extend_from_slice(&section.data);
+                order.push((o, i));
+            }
+        }
+
+        let got_addr = bounds["got"].0;
+        let file_end = (bounds["got"].1 - BASE_ADDR) as usize;
+        let mut image = vec![0u8; file_end];
+
+        for (o, i) in &order {
+            let obj = &self.objects[*o];
+            let section = &obj.sections[i];
+            let (out, start) = match section.kind {
+                OutputKind::Bss => continue,
+                OutputKind::Debug => {
+                    let pos = debug.iter().position(|(name, _)| *name == section.name);
+                    (&mut debug[pos.unwrap()].1, section.addr as usize)
+                }
+                _ => {
+                    let start = (section.addr - BASE_ADDR) as usize;
+                    image[start..start + section.data.len()].copy_from_slice(&section.data);
+                    (&mut image, start)
+                }
+            };
+
+            for r in &section.relocs {
+                let relocation_error = |message: String| LinkerError::RelocationError {
+                    file: obj.name.clone(),
+                    message,
+                };
+                let symbol = obj
+                    .symbols
+                    .get(r.sym)
+                    .ok_or_else(|| relocation_error("invalid symbol index".to_string()))?;
+                let s = match self.symbol_addr(*o, r.sym) {
+                    Some(s) => s as i64,
+                    // Such as the debug information of a discarded group, as in `ld`.
+                    None if section.kind == OutputKind::Debug => 0,
+                    None => {
+                        return Err(LinkerError::UndefinedSymbol {
+                            name: symbol.name.clone(),
+                            file: obj.name.clone(),
+                        });
+                    }
+                };
+                let p = (section.addr + r.offset) as i64;
+                let a = r.addend;
+                let pos = start + r.offset as usize;
+                let value = match r.kind {
+                    R_X86_64_NONE => continue,
+                    R_X86_64_64 => {
+                        out[pos..pos + 8].copy_from_slice(&(s + a).to_le_bytes());
+                        continue;
+                    }
+                    R_X86_64_PC64 => {
+                        out[pos..pos + 8].copy_from_slice(&(s + a - p).to_le_bytes());
+                        continue;
+                    }
+                    R_X86_64_PC32 | R_X86_64_PLT32 => s + a - p,
+                    R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+                        let slot = got_slots[&got_key(obj, *o, r.sym)];
+                        got_addr as i64 + slot as i64 * 8 + a - p
+                    }
+                    R_X86_64_32 => {
+                        let value = s + a;
+                        if !(0..=u32::MAX as i64).contains(&value) {
+                            return Err(relocation_error(format!(
+                                "relocation against '{}' out of range",
+                                symbol.name
+                            )));
+                        }
+                        out[pos..pos + 4].copy_from_slice(&(value as u32).to_le_bytes());
+                        continue;
+                    }
+                    R_X86_64_32S => s + a,
+                    kind => {
+                        return Err(relocation_error(format!(
+                            "unsupported relocation type {}",
+                            kind
+                        )));
+                    }
+                };
+                let value = i32::try_from(value).map_err(|_| {
+                    relocation_error(format!("relocation against '{}' out of range", symbol.name))
+                })?;
+                out[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        for (slot, (o, sym)) in got.iter().enumerate() {
+            let obj = &self.objects[*o];
+            let value = self
+                .symbol_addr(*o, *sym)
+                .ok_or_else(|| LinkerError::UndefinedSymbol {
+                    name: obj.symbols[*sym].name.clone(),
+                    file: obj.name.clone(),
+                })?;
+            let pos = (got_addr - BASE_ADDR) as usize + slot * 8;
+            image[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let entry = self
+            .symbol_addr_of(ENTRY)
+            .ok_or_else(|| LinkerError::UndefinedSymbol {
+                name: ENTRY.to_string(),
+                file: "the executable".to_string(),
+            })?;
+
+        self.write_executable(image, entry, &bounds, debug)
+    }
+
+    fn symbol_addr_of(&self, name: &str) -> Option<u64> {
+        match &self.globals.get(name)?.definition {
+            Some(Definition::Section {
+                object,
+                shndx,
+                value,
+            }) => Some(self.objects[*object].sections[shndx].addr + value),
+            Some(Definition::Absolute(value)) => Some(*value),
+            None => None,
+        }
+    }
+
+    /// Writes the ELF headers in front of `image`, followed by the `debug` sections,
+    /// a symbol table and section headers so that tools such as `objdump` and `gdb`
+    /// can read the result.
+    fn write_executable(
+        &self,
+        mut out: Vec<u8>,
+        entry: u64,
+        bounds: &HashMap<&str, (u64, u64)>,
+        debug: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<u8>, LinkerError> {
+        let (text_start, _) = bounds["text"];
+        let (_, rodata_end) = bounds["rodata"];
+        let (data_start, _) = bounds["data"];
+        let (_, got_end) = bounds["got"];
+        let (_, bss_end) = bounds["bss"];
+
+        let mut header = Vec::with_capacity(64);
+        header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        header.extend_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+        header.extend_from_slice(&1u32.to_le_bytes());
+        header.extend_from_slice(&entry.to_le_bytes());
+        header.extend_from_slice(&64u64.to_le_bytes()); // phoff
+        let shoff_pos = header.len();
+        header.extend_from_slice(&0u64.to_le_bytes()); // shoff, filled in below
+        header.extend_from_slice(&0u32.to_le_bytes()); // flags
+        header.extend_from_slice(&64u16.to_le_bytes()); // ehsize
+        header.extend_from_slice(&56u16.to_le_bytes()); // phentsize
+        header.extend_from_slice(&3u16.to_le_bytes()); // phnum
+        header.extend_from_slice(&64u16.to_le_bytes()); // shentsize
+        // The null section, five for the image, the debug sections, .symtab, .strtab
+        // and .shstrtab.
+        let shnum = 9 + debug.len() as u16;
+        header.extend_from_slice(&shnum.to_le_bytes());
+        header.extend_from_slice(&(shnum - 1).to_le_bytes()); // shstrndx
+
+        let segment = |typ: u32, flags: u32, start: u64, filesz: u64, memsz: u64| {
+            let mut ph = Vec::with_capacity(56);
+            ph.extend_from_slice(&typ.to_le_bytes());
+            ph.extend_from_slice(&flags.to_le_bytes());
+            ph.extend_from_slice(&(start - BASE_ADDR).to_le_bytes());
+            ph.extend_from_slice(&start.to_le_bytes());
+            ph.extend_from_slice(&start.to_le_bytes());
+            ph.extend_from_slice(&filesz.to_le_bytes());
+            ph.extend_from_slice(&memsz.to_le_bytes());
+            ph.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+            ph
+        };
+        // PT_LOAD with R+X, PT_LOAD with R+W, and PT_GNU_STACK for a stack that is
+        // not executable.
+        header.extend(segment(
+            1,
+            5,
+            BASE_ADDR,
+            rodata_end - BASE_ADDR,
+            rodata_end - BASE_ADDR,
+        ));
+        header.extend(segment(
+            1,
+            6,
+            data_start,
+            got_end - data_start,
+            bss_end - data_start,
+        ));
+        let mut stack = segment(0x6474e551, 6, BASE_ADDR, 0, 0);
+        stack[8..48].fill(0);
+        header.extend(stack);
+        out[..header.len()].copy_from_slice(&header);
+
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 24];
+        let mut names: Vec<&String> = self
+            .globals
+            .iter()
+            .filter(|(_, g)| g.definition.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        let shndx_of = |addr: u64| match addr {
+            a if a < bounds["text"].1 => 1,
+            a if a < bounds["rodata"].1 => 2,
+            a if a < bounds["got"].1 => 3,
+            _ => 5,
+        };
+        for name in names {
+            let global = &self.globals[name];
+            let (value, shndx) = match &global.definition {
+                Some(Definition::Absolute(value)) => (*value, SHN_ABS),
+                _ => {
+                    let addr = self.symbol_addr_of(name).unwrap_or_default();
+                    (addr, shndx_of(addr))
+                }
+            };
+            symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+            let bind = if global.weak { STB_WEAK } else { 1 };
+            symtab.push(bind << 4 | global.typ);
+            symtab.push(0);
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&global.size.to_le_bytes());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+
+        let mut shstrtab =
+            b"\0.text\0.rodata\0.data\0.got\0.bss\0.symtab\0.strtab\0.shstrtab\0".to_vec();
+        for (name, _) in &debug {
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+        let name_of = |name: &str| {
+            shstrtab
+                .windows(name.len() + 1)
+                .position(|w| &w[..name.len()] == name.as_bytes() && w[name.len()] == 0)
+                .unwrap_or(0) as u32
+        };
+
+        let mut debug_offsets = Vec::new();
+        for (_, bytes) in &debug {
+            debug_offsets.push(out.len());
+            out.extend_from_slice(bytes);
+        }
+        let symtab_offset = out.len();
+        out.extend_from_slice(&symtab);
+        let strtab_offset = out.len();
+        out.extend_from_slice(&strtab);
+        let shstrtab_offset = out.len();
+        out.extend_from_slice(&shstrtab);
+        while !out.len().is_multiple_of(8) {
+            out.push(0);
+        }
+        let shoff = out.len() as u64;
+        out[shoff_pos..shoff_pos + 8].copy_from_slice(&shoff.to_le_bytes());
+
+        let mut section = |name: &str,
+                           typ: u32,
+                           flags: u64,
+                           (start, end): (u64, u64),
+                           offset: usize,
+                           link: u32,
+                           entsize: u64| {
+            out.extend_from_slice(&name_of(name).to_le_bytes());
+            out.extend_from_slice(&typ.to_le_bytes());
+            out.extend_from_slice(&flags.to_le_bytes());
+            out.extend_from_slice(&start.to_le_bytes());
+            out.extend_from_slice(&(offset as u64).to_le_bytes());
+            out.extend_from_slice(&(end - start).to_le_bytes());
+            out.extend_from_slice(&link.to_le_bytes());
+            out.extend_from_slice(&(if typ == SHT_SYMTAB { 1u32 } else { 0 }).to_le_bytes());
+            out.extend_from_slice(&(if flags == 0 { 1u64 } else { 16 }).to_le_bytes());
+            out.extend_from_slice(&entsize.to_le_bytes());
+        };
+        let file_offset = |(start, _): (u64, u64)| (start - BASE_ADDR) as usize;
+        section("", 0, 0, (0, 0), 0, 0, 0);
+        let text = (text_start, bounds["text"].1);
+        section(
+            ".text",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            text,
+            file_offset(text),
+            0,
+            0,
+        );
+        let rodata = bounds["rodata"];
+        section(
+            ".rodata",
+            SHT_PROGBITS,
+            SHF_ALLOC,
+            rodata,
+            file_offset(rodata),
+            0,
+            0,
+        );
+        let data = bounds["data"];
+        section(
+            ".data",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE,
+            data,
+            file_offset(data),
+            0,
+            0,
+        );
+        let got = bounds["got"];
+        section(
+            ".got",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE,
+            got,
+            file_offset(got),
+            0,
+            8,
+        );
+        let bss = bounds["bss"];
+        section(
+            ".bss",
+            SHT_NOBITS,
+            SHF_ALLOC | SHF_WRITE,
+            bss,
+            file_offset(bss),
+            0,
+            0,
+        );
+        let size = |offset: usize, len: usize| (offset as u64, (offset + len) as u64);
+        for ((name, bytes), offset) in debug.iter().zip(debug_offsets) {
+            section(name, SHT_PROGBITS, 0, size(0, bytes.len()), offset, 0, 0);
+        }
+        section(
+            ".symtab",
+            SHT_SYMTAB,
+            0,
+            size(0, symtab.len()),
+            symtab_offset,
+            shnum as u32 - 2,
+            24,
+        );
+        section(
+            ".strtab",
+            SHT_STRTAB,
+            0,
+            size(0, strtab.len()),
+            strtab_offset,
+            0,
+            0,
+        );
+        section(
+            ".shstrtab",
+            SHT_STRTAB,
+            0,
+            size(0, shstrtab.len()),
+            shstrtab_offset,
+            0,
+            0,
+        );
+        Ok(out)
+    }
+}
@@ -0,0 +1,724 @@
+use crate::ir::{IRConst, IRFunction, IRType, Instruction, Op, Operand};
+use crate::regalloc::key;
+use crate::ssa::{self, Phi, SsaFunction};
+use std::collections::{HashMap, HashSet};
+use std::mem::take;
+
+/// Loops running at most this many times are unrolled completely...
+const MAX_UNROLL_TRIPS: i64 = 8;
+/// ...as long as the copies of the body add up to at most this many instructions.
+const MAX_UNROLL_SIZE: usize = 64;
+
+/// A natural loop: the header and the blocks from which it can be reached again
+/// without passing through it.
+pub struct Loop {
+    pub header: usize,
+    pub blocks: HashSet<usize>,
+}
+
+impl Loop {
+    /// The only predecessor of the header outside the loop, if it leads nowhere
+    /// else. What is placed at its end runs once before the loop.
+    fn preheader(&self, func: &SsaFunction) -> Option<usize> {
+        let mut outside = func.blocks[self.header]
+            .preds
+            .iter()
+            .filter(|p| !self.blocks.contains(p));
+        let pre = *outside.next()?;
+        (outside.next().is_none() && func.blocks[pre].succs == [self.header]).then_some(pre)
+    }
+}
+
+fn dominates(idom: &[usize], a: usize, mut b: usize) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        if b == 0 || idom[b] == usize::MAX {
+            return false;
+        }
+        b = idom[b];
+    }
+}
+
+/// Finds the loops of a function from the edges back to a block that dominates
+/// their source. Inner loops come before the loops containing them.
+pub fn find_loops(func: &SsaFunction) -> Vec<Loop> {
+    let idom = func.idoms();
+    let mut loops: Vec<Loop> = Vec::new();
+    for (b, block) in func.blocks.iter().enumerate() {
+        for &header in block.succs.iter().filter(|&&h| dominates(&idom, h, b)) {
+            let i = match loops.iter().position(|l| l.header == header) {
+                Some(i) => i,
+                None => {
+                    loops.push(Loop {
+                        header,
+                        blocks: HashSet::from([header]),
+                    });
+                    loops.len() - 1
+                }
+            };
+            let mut work = vec![b];
+            while let Some(x) = work.pop() {
+                if loops[i].blocks.insert(x) {
+                    work.extend(func.blocks[x].preds.iter().copied());
+                }
+            }
+        }
+    }
+    loops.sort_by_key(|l| l.blocks.len());
+    loops
+}
+
+fn constant(op: &Operand) -> Option<i64> {
+    match op {
+        Operand::Const(IRConst::Int(v)) => Some(*v),
+        _ => None,
+    }
+}
+
+/// Whether an instruction can run ahead of the loop, even on a path where the
+/// loop would not have run it: it has no side effects, reads no memory the loop
+/// may write, and cannot trap.
+fn hoistable(inst: &Instruction) -> bool {
+    let literal = [&inst.src1, &inst.src2]
+        .into_iter()
+        .flatten()
+        .any(|op| matches!(op, Operand::Const(IRConst::Array(..))));
+    let safe = match inst.op {
+        Op::Div | Op::Mod => inst
+            .src2
+            .as_ref()
+            .and_then(constant)
+            .is_some_and(|v| v != 0 && v != -1),
+        _ => matches!(
+            inst.op,
+            Op::Add
+                | Op::Sub
+                | Op::Mul
+                | Op::LAnd
+                | Op::LOr
+                | Op::Xor
+                | Op::Shl
+                | Op::Shr
+                | Op::Neg
+                | Op::Not
+                | Op::Eq
+                | Op::Ne
+                | Op::Gt
+                | Op::Ge
+                | Op::Lt
+                | Op::Le
+                | Op::FAdd
+                | Op::FSub
+                | Op::FMul
+                | Op::FDiv
+                | Op::FEq
+                | Op::FNe
+                | Op::FGt
+                | Op::FGe
+                | Op::FLt
+                | Op::FLe
+                | Op::FNeg
+                | Op::Move
+                | Op::FMove
+                | Op::Load
+                | Op::FLoad
+                | Op::Store
+                | Op::FStore
+                | Op::SizeOf
+                | Op::FieldAddr(_)
+        ),
+    };
+    safe && !literal && ssa::def(inst).is_some()
+}
+
+/// Inserts instructions at the end of a block, before its jump if it has one.
+fn append(func: &mut SsaFunction, b: usize, insts: Vec<Instruction>) {
+    let block = &mut func.blocks[b];
+    let at = block.insts.len() - block.terminator().is_some() as usize;
+    block.insts.splice(at..at, insts);
+}
+
+/// Loop-invariant code motion: moves the instructions whose operands do not change
+/// inside a loop to its preheader, so that they run once.
+pub fn hoist_invariants(func: &mut SsaFunction) -> bool {
+    let order = func.reverse_postorder();
+    let mut changed = false;
+    for l in find_loops(func) {
+        let Some(pre) = l.preheader(func) else {
+            continue;
+        };
+        let mut defined: HashSet<String> = HashSet::new();
+        for &b in &l.blocks {
+            let block = &func.blocks[b];
+            defined.extend(block.phis.iter().filter_map(|phi| key(&phi.dst)));
+            defined.extend(
+                block
+                    .insts
+                    .iter()
+                    .filter_map(|inst| ssa::def(inst).and_then(key)),
+            );
+        }
+
+        // Blocks are visited in reverse postorder, so that an instruction is only
+        // looked at once the ones it reads from have been.
+        let mut hoisted = Vec::new();
+        for &b in order.iter().filter(|b| l.blocks.contains(b)) {
+            let insts = take(&mut func.blocks[b].insts);
+            for inst in insts {
+                let invariant = hoistable(&inst)
+                    && ssa::uses(&inst)
+                        .into_iter()
+                        .filter_map(key)
+                        .all(|k| !defined.contains(&k));
+                if invariant {
+                    if let Some(k) = ssa::def(&inst).and_then(key) {
+                        defined.remove(&k);
+                    }
+                    hoisted.push(inst);
+                } else {
+                    func.blocks[b].insts.push(inst);
+                }
+            }
+        }
+        if !hoisted.is_empty() {
+            append(func, pre, hoisted);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// A basic induction variable: a header phi that starts at `init` and grows by
+/// `step` on every trip around the loop, through the instruction defining `next`.
+struct Induction {
+    var: Operand,
+    init: Operand,
+    step: i64,
+    next: String,
+}
+
+fn inductions(func: &SsaFunction, l: &Loop, pre: usize) -> Vec<Induction> {
+    let mut defs: HashMap<String, &Instruction> = HashMap::new();
+    for &b in &l.blocks {
+        for inst in &func.blocks[b].insts {
+            if let Some(k) = ssa::def(inst).and_then(key) {
+                defs.insert(k, inst);
+            }
+        }
+    }
+    let header = &func.blocks[l.header];
+    let outside = header.preds.iter().position(|&p| p == pre).unwrap();
+    let mut ivs = Vec::new();
+    for phi in &header.phis {
+        if !matches!(phi.dst, Operand::Var(_) | Operand::Temp(_, IRType::Int)) {
+            continue;
+        }
+        let mut back = phi
+            .args
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != outside)
+            .map(|(_, arg)| arg);
+        let Some(next) = back.next().and_then(key) else {
+            continue;
+        };
+        if !back.all(|arg| key(arg).as_ref() == Some(&next)) {
+            continue;
+        }
+        let Some(inst) = defs.get(&next).filter(|inst| inst.op == Op::Add) else {
+            continue;
+        };
+        let step = match (inst.src1.as_ref(), inst.src2.as_ref()) {
+            (Some(a), Some(b)) if *a == phi.dst => constant(b),
+            (Some(a), Some(b)) if *b == phi.dst => constant(a),
+            _ => None,
+        };
+        if let Some(step) = step {
+            ivs.push(Induction {
+                var: phi.dst.clone(),
+                init: phi.args[outside].clone(),
+                step,
+                next,
+            });
+        }
+    }
+    ivs
+}
+
+/// Induction variable strength reduction: a product of an induction variable and
+/// a constant becomes a variable of its own, stepped by an addition.
+pub fn reduce_strength(func: &mut SsaFunction) -> bool {
+    let mut changed = false;
+    for l in find_loops(func) {
+        let Some(pre) = l.preheader(func) else {
+            continue;
+        };
+        let ivs = inductions(func, &l, pre);
+        let outside = func.blocks[l.header]
+            .preds
+            .iter()
+            .position(|&p| p == pre)
+            .unwrap();
+
+        let mut products: Vec<(usize, String, usize, i64)> = Vec::new();
+        for &b in &l.blocks {
+            for inst in &func.blocks[b].insts {
+                let (Op::Mul, Some(x), Some(y)) = (&inst.op, &inst.src1, &inst.src2) else {
+                    continue;
+                };
+                let Some(k) = ssa::def(inst).and_then(key) else {
+                    continue;
+                };
+                for (iv, factor) in [(x, y), (y, x)] {
+                    let found = ivs.iter().position(|ind| ind.var == *iv);
+                    if let (Some(found), Some(factor)) = (found, constant(factor)) {
+                        products.push((b, k, found, factor));
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut reduced: HashMap<(usize, i64), Operand> = HashMap::new();
+        for (b, product, iv, factor) in products {
+            let value = match reduced.get(&(iv, factor)) {
+                Some(value) => value.clone(),
+                None => {
+                    let ind = &ivs[iv];
+                    let value = func.new_temp(IRType::Int);
+                    let init = func.new_temp(IRType::Int);
+                    let next = func.new_temp(IRType::Int);
+                    append(
+                        func,
+                        pre,
+                        vec![Instruction {
+                            op: Op::Mul,
+                            dst: Some(init.clone()),
+                            src1: Some(ind.init.clone()),
+                            src2: Some(Operand::Const(IRConst::Int(factor))),
+                            span: None,
+                        }],
+                    );
+                    let header = &mut func.blocks[l.header];
+                    let args = (0..header.preds.len())
+                        .map(|p| {
+                            if p == outside {
+                                init.clone()
+                            } else {
+                                next.clone()
+                            }
+                        })
+                        .collect();
+                    header.phis.push(Phi {
+                        dst: value.clone(),
+                        args,
+                    });
+                    // Step alongside the induction variable itself.
+                    for &s in &l.blocks {
+                        let insts = &mut func.blocks[s].insts;
+                        let at = insts.iter().position(|inst| {
+                            ssa::def(inst).and_then(key).as_ref() == Some(&ind.next)
+                        });
+                        if let Some(at) = at {
+                            insts.insert(
+                                at + 1,
+                                Instruction {
+                                    op: Op::Add,
+                                    dst: Some(next.clone()),
+                                    src1: Some(value.clone()),
+                                    src2: Some(Operand::Const(IRConst::Int(
+                                        ind.step.wrapping_mul(factor),
+                                    ))),
+                                    span: None,
+                                },
+                            );
+                            break;
+                        }
+                    }
+                    reduced.insert((iv, factor), value.clone());
+                    value
+                }
+            };
+            let inst = func.blocks[b]
+                .insts
+                .iter_mut()
+                .find(|inst| ssa::def(inst).and_then(key).as_ref() == Some(&product))
+                .unwrap();
+            inst.op = Op::Move;
+            inst.src1 = Some(value);
+            inst.src2 = None;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Follows `op` through the copies of `defs` back to the value it holds.
+fn origin<'a>(mut op: &'a Operand, defs: &HashMap<String, &'a Instruction>) -> &'a Operand {
+    for _ in 0..=defs.len() {
+        let copy = key(op)
+            .and_then(|k| defs.get(&k))
+            .filter(|inst| matches!(inst.op, Op::Move | Op::Load | Op::Store));
+        match copy.and_then(|inst| inst.src1.as_ref()) {
+            Some(src) => op = src,
+            None => break,
+        }
+    }
+    op
+}
+
+/// The bounds checks of one array and offset in a loop, by block and position,
+/// and whether one of them runs on every trip.
+type CheckGroup = (Vec<(usize, usize)>, bool);
+
+/// Bounds-check hoisting: the checks of an index `i + c` into an array, where `i`
+/// counts up by one from a constant to a bound tested at the top of the loop, are
+/// replaced by a single check of all the indices ahead of the loop.
+///
+/// The loop may only be left through that test and must reach one of the checks on
+/// every trip, so the single check fails exactly when one of the checks would have.
+/// It also may not call anything, as a call could print before the check fails, or
+/// change the length of a vec.
+pub fn hoist_bounds_checks(func: &mut SsaFunction) -> bool {
+    let idom = func.idoms();
+    let mut defs: HashMap<String, &Instruction> = HashMap::new();
+    for inst in func.blocks.iter().flat_map(|b| &b.insts) {
+        if let Some(k) = ssa::def(inst).and_then(key) {
+            defs.insert(k, inst);
+        }
+    }
+
+    let mut hoists = Vec::new();
+    for l in find_loops(func) {
+        let Some(pre) = l.preheader(func) else {
+            continue;
+        };
+        let header = &func.blocks[l.header];
+        let Some(Instruction {
+            op: Op::JumpIfFalse,
+            src1: Some(cond),
+            src2: Some(Operand::Label(exit)),
+            ..
+        }) = header.terminator()
+        else {
+            continue;
+        };
+        let (Some(&body), Some(&out)) = (
+            header.succs.iter().find(|s| l.blocks.contains(s)),
+            header.succs.iter().find(|s| !l.blocks.contains(s)),
+        ) else {
+            continue;
+        };
+        let single_exit = l.blocks.iter().all(|&b| {
+            let block = &func.blocks[b];
+            let returns = block
+                .terminator()
+                .is_some_and(|inst| matches!(inst.op, Op::Return(_)));
+            !returns
+                && block
+                    .succs
+                    .iter()
+                    .all(|s| l.blocks.contains(s) || (b, *s) == (l.header, out))
+        });
+        let calls = l.blocks.iter().any(|&b| {
+            func.blocks[b]
+                .insts
+                .iter()
+                .any(|inst| matches!(inst.op, Op::Call | Op::Range))
+        });
+        if func.blocks[out].label.as_ref() != Some(exit)
+            || func.blocks[body].preds != [l.header]
+            || !single_exit
+            || calls
+        {
+            continue;
+        }
+
+        let mut defined: HashSet<String> = HashSet::new();
+        for &b in &l.blocks {
+            let block = &func.blocks[b];
+            defined.extend(block.phis.iter().filter_map(|phi| key(&phi.dst)));
+            defined.extend(
+                block
+                    .insts
+                    .iter()
+                    .filter_map(|inst| ssa::def(inst).and_then(key)),
+            );
+        }
+        let invariant = |op: &Operand| key(op).is_none_or(|k| !defined.contains(&k));
+
+        // The trips run while `iv < bound`, so `iv` takes the values from its
+        // start up to the bound.
+        let Some(test) = key(cond).and_then(|k| defs.get(&k)) else {
+            continue;
+        };
+        let (Op::Lt, Some(var), Some(bound)) = (&test.op, &test.src1, &test.src2) else {
+            continue;
+        };
+        let var = origin(var, &defs);
+        let Some((iv, start)) = inductions(func, &l, pre).into_iter().find_map(|ind| {
+            let start = constant(origin(&ind.init, &defs))?;
+            (ind.var == *var && ind.step == 1).then_some((ind.var, start))
+        }) else {
+            continue;
+        };
+        if !invariant(bound) {
+            continue;
+        }
+
+        let latches: Vec<usize> = func.blocks[l.header]
+            .preds
+            .iter()
+            .copied()
+            .filter(|p| l.blocks.contains(p))
+            .collect();
+        // Checks are grouped by array and offset. A group is hoisted if one of its
+        // checks runs on every trip.
+        let mut groups: HashMap<(Operand, i64), CheckGroup> = HashMap::new();
+        for &b in l.blocks.iter().filter(|&&b| dominates(&idom, body, b)) {
+            for (i, inst) in func.blocks[b].insts.iter().enumerate() {
+                let (Op::BoundsCheck(..), Some(arr), Some(index)) =
+                    (&inst.op, &inst.src1, &inst.src2)
+                else {
+                    continue;
+                };
+                let index = origin(index, &defs);
+                let offset = if *index == iv {
+                    Some(0)
+                } else {
+                    key(index).and_then(|k| defs.get(&k)).and_then(|def| {
+                        match (&def.op, def.src1.as_ref(), def.src2.as_ref()) {
+                            (Op::Add, Some(x), Some(c)) | (Op::Add, Some(c), Some(x))
+                                if *origin(x, &defs) == iv =>
+                            {
+                                constant(c)
+                            }
+                            (Op::Sub, Some(x), Some(c)) if *origin(x, &defs) == iv => {
+                                constant(c).and_then(i64::checked_neg)
+                            }
+                            _ => None,
+                        }
+                    })
+                };
+                let Some(offset) = offset.filter(|_| invariant(arr)) else {
+                    continue;
+                };
+                let every_trip = latches.iter().all(|&latch| dominates(&idom, b, latch));
+                let group = groups.entry((arr.clone(), offset)).or_default();
+                group.0.push((b, i));
+                group.1 |= every_trip;
+            }
+        }
+
+        // Hoisted in the order of their first check, so that the output is the same
+        // from one run to the next.
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by_key(|(_, (checks, _))| checks[0]);
+        for ((arr, offset), (checks, every_trip)) in groups {
+            // The first index is added to the trip count in a 32-bit immediate.
+            let first = start
+                .checked_add(offset)
+                .filter(|first| (0..i32::MAX as i64).contains(first));
+            if let (Some(first), true) = (first, every_trip) {
+                hoists.push((pre, bound.clone(), start, arr, first, checks));
+            }
+        }
+    }
+
+    let changed = !hoists.is_empty();
+    let mut removed: HashSet<(usize, usize)> = HashSet::new();
+    for (pre, bound, start, arr, first, checks) in hoists {
+        let (b, i) = checks[0];
+        let Op::BoundsCheck(file, line) = func.blocks[b].insts[i].op.clone() else {
+            unreachable!();
+        };
+        let mut insts = Vec::new();
+        let trips = if start == 0 {
+            bound
+        } else {
+            let trips = func.new_temp(IRType::Int);
+            insts.push(Instruction {
+                op: Op::Sub,
+                dst: Some(trips.clone()),
+                src1: Some(bound),
+                src2: Some(Operand::Const(IRConst::Int(start))),
+                span: None,
+            });
+            trips
+        };
+        insts.push(Instruction {
+            op: Op::LoopBoundsCheck(file, line, first),
+            dst: None,
+            src1: Some(arr),
+            src2: Some(trips),
+            span: None,
+        });
+        append(func, pre, insts);
+        removed.extend(checks);
+    }
+    for (b, block) in func.blocks.iter_mut().enumerate() {
+        let mut i = 0;
+        block.insts.retain(|_| {
+            i += 1;
+            !removed.contains(&(b, i - 1))
+        });
+    }
+    changed
+}
+
+/// A `for` loop over an array of known length, in the shape `IRGen` lowers it to.
+struct ForLoop {
+    /// Index of the store of 0 into the index variable, where the loop starts.
+    start: usize,
+    /// Index of the end label, where the loop ends.
+    end: usize,
+    index: Operand,
+    trips: i64,
+    next: String,
+}
+
+fn find_for(insts: &[Instruction], cond: usize, constants: &[IRConst]) -> Option<ForLoop> {
+    let int = |op: &Option<Operand>| match op {
+        Some(Operand::ConstIdx(idx)) => match constants[*idx] {
+            IRConst::Int(v) => Some(v),
+            _ => None,
+        },
+        Some(Operand::Const(IRConst::Int(v))) => Some(*v),
+        _ => None,
+    };
+    let Op::Label(cond_label) = &insts[cond].op else {
+        return None;
+    };
+    let init = insts.get(cond.checked_sub(1)?)?;
+    let [load, test, branch] = insts.get(cond + 1..cond + 4)? else {
+        return None;
+    };
+    let var = init.dst.as_ref()?;
+    let index = load.dst.clone()?;
+    let shape = init.op == Op::Store
+        && int(&init.src1) == Some(0)
+        && load.op == Op::Load
+        && load.src1.as_ref() == Some(var)
+        && test.op == Op::Lt
+        && test.src1.as_ref() == Some(&index)
+        && branch.op == Op::JumpIfFalse
+        && branch.src1 == test.dst;
+    let trips = int(&test.src2).filter(|_| shape)?;
+    let Some(Operand::Label(end_label)) = &branch.src2 else {
+        return None;
+    };
+    let end = cond
+        + insts[cond..]
+            .iter()
+            .position(|inst| inst.op == Op::Label(end_label.clone()))?;
+    let [next, step, store, jump] = insts.get(end.checked_sub(4)?..end)? else {
+        return None;
+    };
+    let Op::Label(next_label) = &next.op else {
+        return None;
+    };
+    let closes = end >= cond + 8
+        && step.op == Op::Add
+        && step.src1.as_ref() == Some(&index)
+        && int(&step.src2) == Some(1)
+        && store.op == Op::Store
+        && store.dst.as_ref() == Some(var)
+        && store.src1 == step.dst
+        && jump.op == Op::Jump
+        && jump.src1 == Some(Operand::Label(cond_label.clone()));
+    closes.then(|| ForLoop {
+        start: cond - 1,
+        end,
+        index,
+        trips,
+        next: next_label.clone(),
+    })
+}
+
+/// Unrolls completely the `for` loops over short arrays of known length, dropping
+/// the index checks and letting later passes see a constant index in each copy.
+/// This runs before SSA construction, on the instructions `IRGen` produced.
+pub fn unroll(func: &mut IRFunction, constants: &[IRConst]) -> bool {
+    let mut changed = false;
+    // Later loops are tried first, so that inner loops are unrolled before the
+    // loops around them.
+    let mut cond = func.instructions.len();
+    while cond > 0 {
+        cond -= 1;
+        let insts = &func.instructions;
+        let Some(l) = find_for(insts, cond, constants) else {
+            continue;
+        };
+        let body = cond + 4..l.end - 4;
+        if !(0..=MAX_UNROLL_TRIPS).contains(&l.trips)
+            || body.len() * l.trips as usize > MAX_UNROLL_SIZE
+        {
+            continue;
+        }
+
+        // Labels of the body get a copy each, so no jump from outside may go to one.
+        let mut labels: HashSet<String> = insts[body.clone()]
+            .iter()
+            .filter_map(|inst| match &inst.op {
+                Op::Label(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        labels.insert(l.next.clone());
+        let targets = |inst: &Instruction| match (&inst.src1, &inst.src2) {
+            (Some(Operand::Label(name)), _) | (_, Some(Operand::Label(name))) => Some(name.clone()),
+            _ => None,
+        };
+        let outside = insts[..body.start].iter().chain(&insts[body.end..]);
+        if outside
+            .filter_map(targets)
+            .any(|name| labels.contains(&name))
+            || insts[body.clone()]
+                .iter()
+                .filter_map(targets)
+                .any(|name| Op::Label(name) == insts[cond].op)
+        {
+            continue;
+        }
+
+        let rename = |name: &String, k: i64| match labels.contains(name) {
+            true => format!("{}_u{}", name, k),
+            false => name.clone(),
+        };
+        let mut unrolled = Vec::new();
+        for k in 0..l.trips {
+            unrolled.push(Instruction {
+                op: Op::Move,
+                dst: Some(l.index.clone()),
+                src1: Some(Operand::Const(IRConst::Int(k))),
+                src2: None,
+                span: None,
+            });
+            for inst in &insts[body.clone()] {
+                let mut inst = inst.clone();
+                if let Op::Label(name) = &inst.op {
+                    inst.op = Op::Label(rename(name, k));
+                }
+                for op in [&mut inst.src1, &mut inst.src2].into_iter().flatten() {
+                    if let Operand::Label(name) = op {
+                        *name = rename(name, k);
+                    }
+                }
+                unrolled.push(inst);
+            }
+            unrolled.push(Instruction {
+                op: Op::Label(rename(&l.next, k)),
+                dst: None,
+                src1: None,
+                src2: None,
+                span: None,
+            });
+        }
+        func.instructions.splice(l.start..l.end, unrolled);
+        cond = func.instructions.len();
+        changed = true;
+    }
+    changed
+}
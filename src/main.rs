@@ -1,156 +1,294 @@
 #![allow(warnings)]
+use crate::assembler::Assembler;
 use crate::codegen::CodeGen;
+use crate::diagnostic::SourceMap;
 use crate::irgen::IRGen;
-use crate::{lexer::Lexer, parser::Parser, preprocessor::Preprocessor};
+use crate::linker::Linker;
+use crate::module::{Module, ModuleLoader};
+use crate::optimizer::PassManager;
+use crate::preprocessor::Preprocessor;
+use crate::typeck::TypeChecker;
 use clap::{Arg, ArgAction, Command};
-use std::{fs, path::Path};
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
 
+pub mod assembler;
 pub mod ast;
 pub mod codegen;
+pub mod diagnostic;
+pub mod dwarf;
+pub mod elf;
+pub mod inliner;
 pub mod ir;
 pub mod irgen;
 pub mod lexer;
+pub mod linker;
+pub mod loops;
+pub mod module;
+pub mod optimizer;
 pub mod parser;
 pub mod preprocessor;
+pub mod regalloc;
+pub mod ssa;
 pub mod token;
+pub mod typeck;
 
-fn print_ast(file: &String) -> Result<(), Box<dyn std::error::Error>> {
-    let src = fs::read_to_string(file)?;
-    let path = Path::new(&file.clone())
-        .parent()
-        .ok_or("Invalid file path")?
-        .to_str()
-        .ok_or("Invalid path encoding")?
-        .to_string();
-    let mut preprocessor = Preprocessor::new(src.as_str(), path);
-    let code = preprocessor.preprocess()?;
-    let lexer = Lexer::new(code.as_str());
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse()?;
-    println!("{:#?}", ast);
+/// Where `install.sh` puts the standard library unless `ALUM_HOME` says otherwise:
+/// its `.al` files go to `alum/` and `libalum.a` to `lib/`.
+const DEFAULT_HOME: &str = "/usr/local";
+
+/// Directories searched for imports and libraries. Those given with `-I` and `-L`
+/// come first, in order, then the ones of the standard library.
+struct SearchPaths {
+    include: Vec<String>,
+    library: Vec<String>,
+}
+
+impl SearchPaths {
+    fn new(mut include: Vec<String>, mut library: Vec<String>) -> Self {
+        let home = std::env::var("ALUM_HOME")
+            .ok()
+            .filter(|home| !home.is_empty())
+            .unwrap_or_else(|| DEFAULT_HOME.to_string());
+        include.push(format!("{}/alum", home));
+        library.push(format!("{}/lib", home));
+        Self { include, library }
+    }
+
+    /// Finds `lib<name>.a`, the way `-l<name>` does for `ld`.
+    fn find_library(&self, name: &str) -> Result<String, String> {
+        let file = format!("lib{}.a", name);
+        let tried: Vec<String> = self
+            .library
+            .iter()
+            .map(|dir| Path::new(dir).join(&file).display().to_string())
+            .collect();
+        match tried.iter().find(|path| Path::new(path).is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(format!(
+                "cannot find library '{}'; tried:\n  {}",
+                name,
+                tried.join("\n  ")
+            )),
+        }
+    }
+}
+
+/// How every unit is compiled, from `-O`, `--no-bounds-check` and `-g`.
+#[derive(Debug, Clone, Copy)]
+struct Options {
+    opt_level: u8,
+    bounds_check: bool,
+    debug: bool,
+}
+
+/// Loads and type checks the modules of `files`, together with the source files
+/// their spans refer to. Errors are rendered against those sources.
+fn load_modules(
+    files: &[&str],
+    paths: &SearchPaths,
+) -> Result<(Vec<Module>, SourceMap), Box<dyn std::error::Error>> {
+    let mut loader = ModuleLoader::new(paths.include.clone());
+    let mut modules = loader
+        .load(files)
+        .map_err(|e| e.diagnostic().render(&loader.sources))?;
+    TypeChecker::new().check(&mut modules).map_err(|errors| {
+        let mut report: Vec<String> = errors.iter().map(|e| e.render(&loader.sources)).collect();
+        if errors.len() > 1 {
+            report.push(format!(
+                "error: aborting due to {} previous errors",
+                errors.len()
+            ));
+        }
+        report.join("\n\n")
+    })?;
+    Ok((modules, loader.sources))
+}
+
+fn print_ast(files: &[&str], paths: &SearchPaths) -> Result<(), Box<dyn std::error::Error>> {
+    for module in load_modules(files, paths)?.0 {
+        println!("{:#?}", module);
+    }
     Ok(())
 }
 
-fn print_ir(file: &String) -> Result<(), Box<dyn std::error::Error>> {
-    let src = fs::read_to_string(file)?;
-    let path = Path::new(&file.clone())
-        .parent()
-        .ok_or("Invalid file path")?
-        .to_str()
-        .ok_or("Invalid path encoding")?
-        .to_string();
-    let mut preprocessor = Preprocessor::new(src.as_str(), path);
-    let code = preprocessor.preprocess()?;
-    let lexer = Lexer::new(code.as_str());
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse()?;
-    let mut irgen = IRGen::new();
-    let ir = irgen.compile(ast)?;
-    println!("{:#?}", ir);
+fn print_ir(
+    files: &[&str],
+    paths: &SearchPaths,
+    opt_level: u8,
+    bounds_check: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (modules, source_map) = load_modules(files, paths)?;
+    for unit in 0..files.len() {
+        let mut irgen = IRGen::new(bounds_check);
+        let ir = irgen
+            .compile(&modules, &source_map, unit)
+            .map_err(|e| e.diagnostic().render(&source_map))?;
+        let ir = PassManager::new(opt_level).run(ir);
+        println!("{:#?}", ir);
+    }
     Ok(())
 }
 
-fn print_pred(file: &String) -> Result<(), Box<dyn std::error::Error>> {
-    let src = fs::read_to_string(file)?;
-    let path = Path::new(&file.clone())
-        .parent()
-        .ok_or("Invalid file path")?
-        .to_str()
-        .ok_or("Invalid path encoding")?
-        .to_string();
-    let mut preprocessor = Preprocessor::new(src.as_str(), path);
-    let code = preprocessor.preprocess()?;
-    println!("{}", code);
+fn print_pred(files: &[&str], paths: &SearchPaths) -> Result<(), Box<dyn std::error::Error>> {
+    for file in files {
+        let src = fs::read_to_string(file)?;
+        let path = Path::new(file)
+            .parent()
+            .ok_or("Invalid file path")?
+            .to_str()
+            .ok_or("Invalid path encoding")?
+            .to_string();
+        let mut preprocessor =
+            Preprocessor::new(src.as_str(), file.to_string(), path, paths.include.clone());
+        let code = preprocessor.preprocess().map_err(|e| {
+            let mut sources = SourceMap::new();
+            for (path, text) in preprocessor.files() {
+                sources.add(path, text);
+            }
+            e.diagnostic(preprocessor.error_file()).render(&sources)
+        })?;
+        println!("{}", code);
+    }
     Ok(())
 }
 
+fn assemble(assembly: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let object = Assembler::new(assembly).assemble()?;
+    Ok(object.to_bytes())
+}
+
+fn link_builtin(
+    units: &[(&str, Vec<u8>)],
+    objects: &[&str],
+    output: &str,
+    debug: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut linker = Linker::new(debug);
+    for (stem, object) in units {
+        linker.add_file(&format!("{}.o", stem), object.clone())?;
+    }
+    for file in objects {
+        let data = fs::read(file).map_err(|e| format!("{}: {}", file, e))?;
+        linker.add_file(file, data)?;
+    }
+    let executable = linker.link()?;
+
+    fs::write(output, executable)?;
+    fs::set_permissions(output, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+fn link_system(
+    units: &[(&str, Vec<u8>)],
+    objects: &[&str],
+    output: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut obj_files = Vec::new();
+    let mut result = Ok(());
+    for (stem, object) in units {
+        let obj_file = format!("{}.{}.o", output, stem);
+        if let Err(e) = fs::write(&obj_file, object) {
+            result = Err(e.into());
+            break;
+        }
+        obj_files.push(obj_file);
+    }
+
+    if result.is_ok() {
+        let mut ld_args = vec!["-o", output];
+        ld_args.extend(obj_files.iter().map(String::as_str));
+        ld_args.extend(objects);
+
+        let ld_status = std::process::Command::new("ld").args(&ld_args).status()?;
+        if !ld_status.success() {
+            result = Err("ld failed".into());
+        }
+    }
+
+    for obj_file in &obj_files {
+        let _ = fs::remove_file(obj_file);
+    }
+    result
+}
+
+/// Compiles every `.al` file in `input_files` to its own object. Any other input,
+/// such as a `.o` or `.a` file, is passed on to the linker as it is, followed by
+/// the `libraries` found in the library search paths.
 fn compile(
-    input_file: &str,
+    input_files: &[&str],
+    paths: &SearchPaths,
     output_file: Option<&str>,
     emit_type: &str,
-    no_std: bool,
+    libraries: &[String],
+    linker: &str,
+    options: Options,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let src = fs::read_to_string(input_file)?;
-    let path = Path::new(&input_file)
-        .parent()
-        .ok_or("Invalid file path")?
-        .to_str()
-        .ok_or("Invalid path encoding")?
-        .to_string();
-    let mut preprocessor = Preprocessor::new(&src, path);
-    let code = preprocessor.preprocess()?;
-    let lexer = Lexer::new(&code);
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse()?;
-    let mut irgen = IRGen::new();
-    let ir = irgen.compile(ast)?;
-    let mut codegen = CodeGen::new(ir);
-    let assembly = codegen.compile()?;
-
-    let input_path = Path::new(input_file);
-    let stem = input_path
-        .file_stem()
-        .ok_or("Invalid input filename")?
-        .to_str()
-        .ok_or("Invalid filename encoding")?;
-
-    let output = if let Some(output_path) = output_file {
-        output_path.to_string()
-    } else {
-        match emit_type {
-            "asm" => format!("{}.s", stem),
-            "obj" => format!("{}.o", stem),
-            "bin" => stem.to_string(),
-            _ => stem.to_string(),
-        }
-    };
+    let (sources, objects): (Vec<&str>, Vec<&str>) = input_files
+        .iter()
+        .partition(|f| Path::new(f).extension().is_some_and(|e| e == "al"));
+
+    if sources.is_empty() && emit_type != "bin" {
+        return Err("no Alum source files given".into());
+    }
+    if output_file.is_some() && sources.len() > 1 && emit_type != "bin" {
+        return Err("cannot specify -o with -c or -S and multiple files".into());
+    }
+
+    let (modules, source_map) = load_modules(&sources, paths)?;
+    let mut units = Vec::new();
+    for (unit, source) in sources.iter().enumerate() {
+        let mut irgen = IRGen::new(options.bounds_check);
+        let ir = irgen
+            .compile(&modules, &source_map, unit)
+            .map_err(|e| e.diagnostic().render(&source_map))?;
+        let ir = PassManager::new(options.opt_level).run(ir);
+        let mut codegen = CodeGen::new(ir, options.debug);
+        let assembly = codegen
+            .compile()
+            .map_err(|e| e.diagnostic().render(&source_map))?;
+
+        let stem = Path::new(source)
+            .file_stem()
+            .ok_or("Invalid input filename")?
+            .to_str()
+            .ok_or("Invalid filename encoding")?;
+        units.push((stem, assembly));
+    }
 
     match emit_type {
         "asm" => {
-            fs::write(&output, &assembly)?;
+            for (stem, assembly) in &units {
+                let output = output_file.map_or(format!("{}.s", stem), str::to_string);
+                fs::write(&output, assembly)?;
+            }
         }
         "obj" => {
-            let asm_file = format!("{}.s", output);
-            fs::write(&asm_file, &assembly)?;
-
-            let nasm_status = std::process::Command::new("nasm")
-                .args(&["-f", "elf64", "-o", &output, &asm_file])
-                .status()?;
-
-            if !nasm_status.success() {
-                let _ = fs::remove_file(&asm_file);
-                return Err("nasm failed".into());
+            for (stem, assembly) in &units {
+                let output = output_file.map_or(format!("{}.o", stem), str::to_string);
+                fs::write(&output, assemble(assembly)?)?;
             }
-
-            let _ = fs::remove_file(&asm_file);
         }
         "bin" => {
-            let asm_file = format!("{}.asm", output);
-            let obj_file = format!("{}.o", output);
+            let output = match (output_file, units.first()) {
+                (Some(output_path), _) => output_path.to_string(),
+                (None, Some((stem, _))) => stem.to_string(),
+                (None, None) => "a.out".to_string(),
+            };
 
-            fs::write(&asm_file, &assembly)?;
-
-            let nasm_status = std::process::Command::new("nasm")
-                .args(&["-f", "elf64", "-o", &obj_file, &asm_file])
-                .status()?;
-
-            if !nasm_status.success() {
-                let _ = fs::remove_file(&asm_file);
-                return Err("nasm failed".into());
+            let mut assembled = Vec::new();
+            for (stem, assembly) in &units {
+                assembled.push((*stem, assemble(assembly)?));
             }
-
-            let mut ld_args = vec!["-o", &output, &obj_file];
-            if !no_std {
-                ld_args.push("/usr/local/lib/libalum.a");
-            }
-
-            let ld_status = std::process::Command::new("ld").args(&ld_args).status()?;
-
-            let _ = fs::remove_file(&asm_file);
-            let _ = fs::remove_file(&obj_file);
-
-            if !ld_status.success() {
-                return Err("ld failed".into());
+            let libraries = libraries
+                .iter()
+                .map(|name| paths.find_library(name))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut objects = objects;
+            objects.extend(libraries.iter().map(String::as_str));
+            if linker == "system" {
+                link_system(&assembled, &objects, &output)?;
+            } else {
+                link_builtin(&assembled, &objects, &output, options.debug)?;
             }
         }
         _ => {}
@@ -165,7 +303,7 @@ fn main() {
         .arg_required_else_help(true)
         .arg(
             Arg::new("input_files")
-                .help("Input source files")
+                .help("Input source, object or archive files")
                 .required(true)
                 .num_args(1..),
         )
@@ -206,12 +344,61 @@ fn main() {
                 .help("Dump IR representation")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("opt_level")
+                .short('O')
+                .help("Optimization level")
+                .value_name("level")
+                .value_parser(["0", "1", "2"])
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("no_bounds_check")
+                .long("no-bounds-check")
+                .help("Do not check array and vec indices against their length")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("debug")
+                .short('g')
+                .help("Generate DWARF debug information")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include_dirs")
+                .short('I')
+                .help("Add <dir> to the import search paths")
+                .value_name("dir")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("library_dirs")
+                .short('L')
+                .help("Add <dir> to the library search paths")
+                .value_name("dir")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("libraries")
+                .short('l')
+                .help("Link with lib<name>.a")
+                .value_name("name")
+                .action(ArgAction::Append),
+        )
         .arg(
             Arg::new("nostdlib")
                 .long("nostdlib")
                 .help("Do not link with standard library")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("linker")
+                .long("linker")
+                .help("Link with the built-in linker or the system `ld`")
+                .value_name("linker")
+                .value_parser(["builtin", "system"])
+                .default_value("builtin"),
+        )
         .arg(
             Arg::new("verbose")
                 .short('v')
@@ -222,34 +409,85 @@ fn main() {
 
     let matches = cmd.get_matches();
 
-    let input_files: Vec<&String> = matches.get_many("input_files").unwrap().collect();
+    let input_files: Vec<&str> = matches
+        .get_many::<String>("input_files")
+        .unwrap()
+        .map(String::as_str)
+        .collect();
 
-    let input_file = input_files[0];
     let output_file = matches.get_one::<String>("output").map(|s| s.as_str());
 
     let verbose = matches.get_flag("verbose");
     let no_std = matches.get_flag("nostdlib");
+    let linker = matches.get_one::<String>("linker").unwrap();
+    let opt_level: u8 = matches
+        .get_one::<String>("opt_level")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let bounds_check = !matches.get_flag("no_bounds_check");
+    let options = Options {
+        opt_level,
+        bounds_check,
+        debug: matches.get_flag("debug"),
+    };
+
+    let values = |id: &str| -> Vec<String> {
+        matches
+            .get_many::<String>(id)
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default()
+    };
+    let paths = SearchPaths::new(values("include_dirs"), values("library_dirs"));
+    let mut libraries = values("libraries");
+    if !no_std {
+        libraries.push("alum".to_string());
+    }
 
     if verbose {
         eprintln!("Alum compiler v0.5.2");
-        eprintln!("Input: {}", input_file);
+        eprintln!("Input: {}", input_files.join(" "));
         if let Some(out) = output_file {
             eprintln!("Output: {}", out);
         }
     }
 
     let result = if matches.get_flag("dump_ast") {
-        print_ast(input_file)
+        print_ast(&input_files, &paths)
     } else if matches.get_flag("dump_ir") {
-        print_ir(input_file)
+        print_ir(&input_files, &paths, opt_level, bounds_check)
     } else if matches.get_flag("preprocess") {
-        print_pred(input_file)
+        print_pred(&input_files, &paths)
     } else if matches.get_flag("assemble") {
-        compile(input_file, output_file, "asm", no_std)
+        compile(
+            &input_files,
+            &paths,
+            output_file,
+            "asm",
+            &libraries,
+            linker,
+            options,
+        )
     } else if matches.get_flag("compile") {
-        compile(input_file, output_file, "obj", no_std)
+        compile(
+            &input_files,
+            &paths,
+            output_file,
+            "obj",
+            &libraries,
+            linker,
+            options,
+        )
     } else {
-        compile(input_file, output_file, "bin", no_std)
+        compile(
+            &input_files,
+            &paths,
+            output_file,
+            "bin",
+            &libraries,
+            linker,
+            options,
+        )
     };
 
     if let Err(e) = result {
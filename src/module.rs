@@ -0,0 +1,327 @@
+use std::{
+    fs,
+    mem::take,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{ExprKind, Import, Program, Span},
+    diagnostic::{Diagnostic, SourceMap},
+    lexer::Lexer,
+    parser::{Parser, ParserError},
+    preprocessor::Preprocessor,
+};
+
+#[derive(Debug, Clone)]
+pub enum ModuleError {
+    NotFound {
+        module: String,
+        tried: Vec<String>,
+        span: Span,
+    },
+    CircularImport {
+        chain: String,
+        span: Span,
+    },
+    ImportError {
+        message: String,
+        span: Span,
+    },
+    /// An error of the preprocessor, lexer or parser, already turned into a diagnostic.
+    SourceError(Diagnostic),
+    IoError {
+        path: String,
+        message: String,
+    },
+    Conflict {
+        module: String,
+        paths: (String, String),
+    },
+}
+
+impl std::error::Error for ModuleError {}
+
+impl std::fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleError::NotFound { module, .. } => {
+                write!(f, "Import error: cannot find module '{}'", module)
+            }
+            ModuleError::CircularImport { chain, .. } => {
+                write!(f, "Import error: circular import {}", chain)
+            }
+            ModuleError::ImportError { message, .. } => write!(f, "Import error: {}", message),
+            ModuleError::SourceError(diagnostic) => write!(f, "{}", diagnostic.message()),
+            ModuleError::IoError { path, message } => write!(f, "{}: {}", path, message),
+            ModuleError::Conflict { module, paths } => write!(
+                f,
+                "Import error: module '{}' refers to both {} and {}",
+                module, paths.0, paths.1
+            ),
+        }
+    }
+}
+
+impl ModuleError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            ModuleError::NotFound { tried, span, .. } => {
+                let mut diagnostic = Diagnostic::error(self.to_string()).label(*span, "");
+                for path in tried {
+                    diagnostic = diagnostic.note(format!("tried {}", path));
+                }
+                diagnostic
+            }
+            ModuleError::CircularImport { span, .. } | ModuleError::ImportError { span, .. } => {
+                Diagnostic::error(self.to_string()).label(*span, "")
+            }
+            ModuleError::SourceError(diagnostic) => diagnostic.clone(),
+            ModuleError::IoError { .. } | ModuleError::Conflict { .. } => {
+                Diagnostic::error(self.to_string())
+            }
+        }
+    }
+}
+
+/// A single source file. Its functions are private to it unless declared `pub`.
+/// `unit` is the input file whose object the module is compiled into.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: String,
+    pub path: String,
+    pub is_root: bool,
+    pub unit: usize,
+    pub program: Program,
+}
+
+impl Module {
+    /// Looks up a top-level `pub` function, extern or struct by name.
+    pub fn export(&self, name: &str) -> Option<&ExprKind> {
+        self.program
+            .body
+            .iter()
+            .map(|expr| &expr.kind)
+            .find(|kind| match kind {
+                ExprKind::FuncDecl(decl) => decl.is_pub && decl.name == name,
+                ExprKind::Extern(ext) => ext.is_pub && ext.name == name,
+                ExprKind::StructDecl(decl) => decl.is_pub && decl.name == name,
+                _ => false,
+            })
+    }
+
+    /// Name under which a function of this module is emitted. Public functions keep
+    /// their name so that other objects can link against them, and so do those of
+    /// the root module; the rest are prefixed with the module name.
+    pub fn symbol(&self, name: &str, is_pub: bool) -> String {
+        if is_pub || self.is_root {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.name, name)
+        }
+    }
+}
+
+/// Loads input files together with every module they import, directly or not.
+pub struct ModuleLoader {
+    search_paths: Vec<String>,
+    modules: Vec<Module>,
+    loading: Vec<String>,
+    unit: usize,
+    /// Every file read so far, which the spans of the modules and errors refer to.
+    pub sources: SourceMap,
+}
+
+impl ModuleLoader {
+    pub fn new(search_paths: Vec<String>) -> Self {
+        Self {
+            search_paths,
+            modules: Vec::new(),
+            loading: Vec::new(),
+            unit: 0,
+            sources: SourceMap::new(),
+        }
+    }
+
+    /// Returns the modules in dependency order, so that every module comes after the
+    /// ones it imports. A module imported by several inputs is loaded once and goes
+    /// into the object of the first, unless it is an input file itself.
+    pub fn load(&mut self, files: &[&str]) -> Result<Vec<Module>, ModuleError> {
+        for (unit, file) in files.iter().enumerate() {
+            self.unit = unit;
+            let path = Path::new(file);
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            match self.modules.iter_mut().find(|m| m.name == name) {
+                Some(m) if Path::new(&m.path).canonicalize().ok() == path.canonicalize().ok() => {
+                    m.unit = unit;
+                }
+                Some(m) => {
+                    return Err(ModuleError::Conflict {
+                        module: name.to_string(),
+                        paths: (m.path.clone(), file.to_string()),
+                    });
+                }
+                None => self.load_file(path, true)?,
+            }
+        }
+        Ok(take(&mut self.modules))
+    }
+
+    /// Looks for `name.al` next to the importing file, then in every search path in
+    /// order. On failure, returns the paths that were tried.
+    fn find(&self, name: &str, dir: &Path) -> Result<PathBuf, Vec<String>> {
+        let file = format!("{}.al", name);
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let candidates: Vec<PathBuf> = std::iter::once(dir.to_path_buf())
+            .chain(self.search_paths.iter().map(PathBuf::from))
+            .map(|p| p.join(&file))
+            .collect();
+        match candidates.iter().find(|p| p.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(candidates.iter().map(|p| p.display().to_string()).collect()),
+        }
+    }
+
+    fn load_file(&mut self, path: &Path, is_root: bool) -> Result<(), ModuleError> {
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let src = fs::read_to_string(path).map_err(|e| ModuleError::IoError {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+
+        let mut preprocessor = Preprocessor::new(
+            &src,
+            path.display().to_string(),
+            dir.to_str().unwrap_or(".").to_string(),
+            self.search_paths.clone(),
+        );
+        let code = preprocessor.preprocess();
+        let files: Vec<usize> = preprocessor
+            .files()
+            .iter()
+            .map(|(path, text)| self.sources.add(path, text))
+            .collect();
+        let code = code.map_err(|e| {
+            ModuleError::SourceError(e.diagnostic(files[preprocessor.error_file()]))
+        })?;
+        let mut parser = Parser::new(Lexer::new(&code));
+        parser.set_lines(
+            preprocessor
+                .lines()
+                .iter()
+                .map(|&(file, row)| (files[file], row))
+                .collect(),
+        );
+        let parser_error = |parser: &Parser, e: ParserError| {
+            let (row, col) = e.pos();
+            let diagnostic = Diagnostic::error(e.to_string());
+            ModuleError::SourceError(match row {
+                0 => diagnostic,
+                _ => diagnostic.label(parser.locate(row, col), ""),
+            })
+        };
+        let imports = parser
+            .parse_imports()
+            .map_err(|e| parser_error(&parser, e))?;
+
+        self.loading.push(name.clone());
+        for import in &imports {
+            let module = self.import(import, &dir)?;
+            self.declare(&mut parser, import, module)?;
+        }
+        self.loading.pop();
+
+        let program = parser.parse().map_err(|e| parser_error(&parser, e))?;
+        self.modules.push(Module {
+            name,
+            path: path.display().to_string(),
+            is_root,
+            unit: self.unit,
+            program,
+        });
+        Ok(())
+    }
+
+    /// Loads the module named by `import` unless it was loaded already, and returns
+    /// its index in `modules`.
+    fn import(&mut self, import: &Import, dir: &Path) -> Result<usize, ModuleError> {
+        let path = self
+            .find(&import.module, dir)
+            .map_err(|tried| ModuleError::NotFound {
+                module: import.module.clone(),
+                tried,
+                span: import.span,
+            })?;
+
+        if self.loading.contains(&import.module) {
+            let mut chain = self.loading.clone();
+            chain.push(import.module.clone());
+            return Err(ModuleError::CircularImport {
+                chain: chain.join(" -> "),
+                span: import.span,
+            });
+        }
+
+        let path_str = path.display().to_string();
+        if let Some(i) = self.modules.iter().position(|m| m.name == import.module) {
+            if Path::new(&self.modules[i].path).canonicalize().ok() != path.canonicalize().ok() {
+                return Err(ModuleError::ImportError {
+                    message: format!(
+                        "module '{}' refers to both {} and {}",
+                        import.module, self.modules[i].path, path_str
+                    ),
+                    span: import.span,
+                });
+            }
+            return Ok(i);
+        }
+
+        self.load_file(&path, false)?;
+        Ok(self.modules.len() - 1)
+    }
+
+    /// Tells the parser about the structs brought in by `from m import a, b`, and
+    /// checks that every name imported that way is public in `m`. The types of
+    /// functions are left to the type checker.
+    fn declare(
+        &self,
+        parser: &mut Parser,
+        import: &Import,
+        module: usize,
+    ) -> Result<(), ModuleError> {
+        let module = &self.modules[module];
+        let Some(names) = &import.names else {
+            return Ok(());
+        };
+
+        for name in names {
+            match module.export(name) {
+                Some(ExprKind::FuncDecl(_) | ExprKind::Extern(_)) => {}
+                Some(ExprKind::StructDecl(decl)) => {
+                    parser.declare_struct(name.clone(), decl.fields.clone())
+                }
+                _ => {
+                    return Err(ModuleError::ImportError {
+                        message: format!(
+                            "module '{}' has no public function or struct '{}'",
+                            module.name, name
+                        ),
+                        span: import.span,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,514 @@
+use crate::inliner::{self, INLINE_THRESHOLD};
+use crate::ir::{IRConst, IRProgram, Instruction, Op, Operand};
+use crate::loops;
+use crate::regalloc::key;
+use crate::ssa::{self, SsaFunction};
+use std::collections::{HashMap, HashSet};
+
+/// Passes stop once a round changes nothing, or after this many rounds.
+const MAX_ROUNDS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    ConstProp,
+    CopyProp,
+    Cse,
+    Licm,
+    StrengthReduce,
+    HoistBounds,
+    Bounds,
+    Dce,
+}
+
+impl Pass {
+    /// Runs the pass over a function and returns whether it changed anything.
+    fn run(self, func: &mut SsaFunction) -> bool {
+        match self {
+            Pass::ConstProp => propagate_constants(func),
+            Pass::CopyProp => propagate_copies(func),
+            Pass::Cse => eliminate_common_subexpressions(func),
+            Pass::Licm => loops::hoist_invariants(func),
+            Pass::StrengthReduce => loops::reduce_strength(func),
+            Pass::HoistBounds => loops::hoist_bounds_checks(func),
+            Pass::Bounds => eliminate_bounds_checks(func),
+            Pass::Dce => eliminate_dead_code(func),
+        }
+    }
+}
+
+/// Runs the optimization passes of an `-O` level over every function of a program.
+/// At `-O0` the IR goes to codegen as `IRGen` produced it.
+pub struct PassManager {
+    passes: Vec<Pass>,
+    /// Size up to which functions are inlined without the `inline` attribute.
+    inline_threshold: usize,
+    unroll: bool,
+}
+
+impl PassManager {
+    pub fn new(level: u8) -> Self {
+        match level {
+            0 => Self {
+                passes: vec![],
+                inline_threshold: 0,
+                unroll: false,
+            },
+            1 => Self {
+                passes: vec![
+                    Pass::ConstProp,
+                    Pass::CopyProp,
+                    Pass::Licm,
+                    Pass::HoistBounds,
+                    Pass::Bounds,
+                    Pass::Dce,
+                ],
+                inline_threshold: 0,
+                unroll: false,
+            },
+            _ => Self {
+                passes: vec![
+                    Pass::ConstProp,
+                    Pass::CopyProp,
+                    Pass::Cse,
+                    Pass::Licm,
+                    Pass::StrengthReduce,
+                    Pass::HoistBounds,
+                    Pass::Bounds,
+                    Pass::Dce,
+                ],
+                inline_threshold: INLINE_THRESHOLD,
+                unroll: true,
+            },
+        }
+    }
+
+    pub fn run(&self, mut program: IRProgram) -> IRProgram {
+        if self.passes.is_empty() {
+            return program;
+        }
+        inliner::inline(&mut program, self.inline_threshold);
+        for func in &mut program.functions {
+            if func.is_external || func.is_imported {
+                continue;
+            }
+            if self.unroll {
+                loops::unroll(func, &program.constants);
+            }
+            let mut ssa = SsaFunction::build(func, &program.constants);
+            for _ in 0..MAX_ROUNDS {
+                let mut changed = false;
+                for pass in &self.passes {
+                    changed |= pass.run(&mut ssa);
+                }
+                if !changed {
+                    break;
+                }
+            }
+            func.instructions = ssa.lower();
+        }
+        program
+    }
+}
+
+fn constant(op: &Operand) -> Option<i64> {
+    match op {
+        Operand::Const(IRConst::Int(v)) => Some(*v),
+        _ => None,
+    }
+}
+
+fn is_copy(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Move | Op::Load | Op::Store | Op::FMove | Op::FLoad | Op::FStore
+    )
+}
+
+/// Evaluates an integer operation the way the generated code would. A division
+/// that traps, by zero or of `i64::MIN` by -1, is left for the program to run.
+fn fold(op: &Op, a: i64, b: i64) -> Option<i64> {
+    Some(match op {
+        Op::Add => a.wrapping_add(b),
+        Op::Sub => a.wrapping_sub(b),
+        Op::Mul => a.wrapping_mul(b),
+        Op::Div => a.checked_div(b)?,
+        Op::Mod => a.checked_rem(b)?,
+        Op::Shl => a.wrapping_shl(b as u32),
+        Op::Shr => a.wrapping_shr(b as u32),
+        Op::LAnd => a & b,
+        Op::LOr => a | b,
+        Op::Xor => a ^ b,
+        Op::Eq => (a == b) as i64,
+        Op::Ne => (a != b) as i64,
+        Op::Gt => (a > b) as i64,
+        Op::Ge => (a >= b) as i64,
+        Op::Lt => (a < b) as i64,
+        Op::Le => (a <= b) as i64,
+        _ => return None,
+    })
+}
+
+fn evaluate(inst: &Instruction) -> Option<i64> {
+    let src1 = inst.src1.as_ref().and_then(constant);
+    match &inst.op {
+        Op::Move | Op::Load | Op::Store => src1,
+        Op::Neg => src1.map(i64::wrapping_neg),
+        Op::Not => src1.map(|v| !v),
+        op => fold(op, src1?, inst.src2.as_ref().and_then(constant)?),
+    }
+}
+
+/// Replaces every use of a value in `values` by what it maps to.
+fn substitute(func: &mut SsaFunction, values: &HashMap<String, Operand>) -> bool {
+    let mut changed = false;
+    let mut replace = |op: &mut Operand| {
+        if let Some(value) = key(op).and_then(|k| values.get(&k)) {
+            *op = value.clone();
+            changed = true;
+        }
+    };
+    for block in &mut func.blocks {
+        for phi in &mut block.phis {
+            phi.args.iter_mut().for_each(&mut replace);
+        }
+        for inst in &mut block.insts {
+            ssa::uses_mut(inst).into_iter().for_each(&mut replace);
+        }
+    }
+    changed
+}
+
+/// Finds the values that are integer constants, replaces their uses with the
+/// constant, and resolves the branches on them.
+fn propagate_constants(func: &mut SsaFunction) -> bool {
+    let order = func.reverse_postorder();
+    let mut values: HashMap<String, Operand> = HashMap::new();
+    loop {
+        let mut grew = false;
+        for &b in &order {
+            for phi in &func.blocks[b].phis {
+                let Some(k) = key(&phi.dst).filter(|k| !values.contains_key(k)) else {
+                    continue;
+                };
+                let mut args = phi
+                    .args
+                    .iter()
+                    .filter(|arg| **arg != phi.dst)
+                    .map(|arg| key(arg).and_then(|k| values.get(&k)).unwrap_or(arg).clone());
+                if let Some(first) = args.next().filter(|first| constant(first).is_some())
+                    && args.all(|arg| arg == first)
+                {
+                    values.insert(k, first);
+                    grew = true;
+                }
+            }
+            for inst in &func.blocks[b].insts {
+                let Some(k) = ssa::def(inst).and_then(key) else {
+                    continue;
+                };
+                if values.contains_key(&k) {
+                    continue;
+                }
+                let mut inst = inst.clone();
+                for op in ssa::uses_mut(&mut inst) {
+                    if let Some(value) = key(op).and_then(|k| values.get(&k)) {
+                        *op = value.clone();
+                    }
+                }
+                if let Some(v) = evaluate(&inst) {
+                    values.insert(k, Operand::Const(IRConst::Int(v)));
+                    grew = true;
+                }
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+    let mut changed = substitute(func, &values);
+
+    for b in 0..func.blocks.len() {
+        let Some(inst) = func.blocks[b].terminator() else {
+            continue;
+        };
+        let (Op::JumpIfFalse, Some(v)) = (&inst.op, inst.src1.as_ref().and_then(constant)) else {
+            continue;
+        };
+        let Some(Operand::Label(label)) = inst.src2.clone() else {
+            continue;
+        };
+        let target = func
+            .blocks
+            .iter()
+            .position(|block| block.label.as_ref() == Some(&label));
+        let next = func.blocks[b]
+            .succs
+            .iter()
+            .copied()
+            .find(|&s| Some(s) != target);
+        let block = &mut func.blocks[b];
+        if v == 0 {
+            let inst = block.insts.last_mut().unwrap();
+            inst.op = Op::Jump;
+            inst.src1 = inst.src2.take();
+            if let Some(next) = next {
+                func.remove_edge(b, next);
+            }
+        } else {
+            block.insts.pop();
+            if let Some(target) = target.filter(|_| next.is_some()) {
+                func.remove_edge(b, target);
+            }
+        }
+        changed = true;
+    }
+    changed | func.remove_unreachable()
+}
+
+/// Replaces the uses of a copy by its source, and of a phi whose arguments are all
+/// the same value by that value.
+fn propagate_copies(func: &mut SsaFunction) -> bool {
+    let mut copies: HashMap<String, Operand> = HashMap::new();
+    for block in &func.blocks {
+        for phi in &block.phis {
+            let mut args = phi.args.iter().filter(|arg| **arg != phi.dst);
+            if let (Some(k), Some(first)) = (key(&phi.dst), args.next())
+                && args.all(|arg| arg == first)
+            {
+                copies.insert(k, first.clone());
+            }
+        }
+        for inst in &block.insts {
+            let (Some(k), Some(src)) = (ssa::def(inst).and_then(key), inst.src1.as_ref()) else {
+                continue;
+            };
+            if is_copy(&inst.op) && (key(src).is_some() || constant(src).is_some()) {
+                copies.insert(k, src.clone());
+            }
+        }
+    }
+
+    // Follow chains of copies to their end.
+    let resolved: HashMap<String, Operand> = copies
+        .keys()
+        .map(|k| {
+            let mut value = &copies[k];
+            for _ in 0..copies.len() {
+                match key(value).and_then(|k| copies.get(&k)) {
+                    Some(next) => value = next,
+                    None => break,
+                }
+            }
+            (k.clone(), value.clone())
+        })
+        .filter(|(k, value)| key(value).as_ref() != Some(k))
+        .collect();
+    substitute(func, &resolved)
+}
+
+fn is_pure(op: &Op) -> bool {
+    !matches!(
+        op,
+        Op::Call
+            | Op::Range
+            | Op::Arg(_)
+            | Op::FArg(_)
+            | Op::Return(_)
+            | Op::Jump
+            | Op::JumpIfFalse
+            | Op::ArrayAssign
+            | Op::ByteStore
+            | Op::BoundsCheck(..)
+            | Op::LoopBoundsCheck(..)
+            | Op::FieldStore(_)
+            | Op::StructCopy(_)
+            | Op::Label(_)
+            | Op::Extern(_)
+    )
+}
+
+/// Removes the instructions and phis without side effects whose value is never
+/// needed, starting from what the side effects read.
+fn eliminate_dead_code(func: &mut SsaFunction) -> bool {
+    let mut defs: HashMap<String, Vec<&Operand>> = HashMap::new();
+    let mut live: HashSet<String> = HashSet::new();
+    let mut work: Vec<String> = Vec::new();
+    for block in &func.blocks {
+        for phi in &block.phis {
+            if let Some(k) = key(&phi.dst) {
+                defs.entry(k).or_default().extend(phi.args.iter());
+            }
+        }
+        for inst in &block.insts {
+            match ssa::def(inst).and_then(key) {
+                Some(k) if is_pure(&inst.op) => defs.entry(k).or_default().extend(ssa::uses(inst)),
+                _ => work.extend(ssa::uses(inst).into_iter().filter_map(key)),
+            }
+        }
+    }
+    while let Some(k) = work.pop() {
+        if live.insert(k.clone()) {
+            for op in defs.get(&k).into_iter().flatten() {
+                work.extend(key(op));
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let before = block.phis.len() + block.insts.len();
+        block
+            .phis
+            .retain(|phi| key(&phi.dst).is_some_and(|k| live.contains(&k)));
+        block.insts.retain(|inst| {
+            !is_pure(&inst.op)
+                || ssa::def(inst)
+                    .and_then(key)
+                    .is_some_and(|k| live.contains(&k))
+        });
+        changed |= before != block.phis.len() + block.insts.len();
+    }
+    changed
+}
+
+/// The length of the array literal `op` is a copy of, if it is one.
+fn array_length<'a>(mut op: &'a Operand, copies: &'a HashMap<String, Operand>) -> Option<usize> {
+    for _ in 0..=copies.len() {
+        match op {
+            Operand::Const(IRConst::Array(len, _)) => return Some(*len),
+            _ => op = copies.get(&key(op)?)?,
+        }
+    }
+    None
+}
+
+/// Removes the bounds checks of constant indices into array literals, and those
+/// repeated within a block. Only calls can change the length of a vec in between.
+fn eliminate_bounds_checks(func: &mut SsaFunction) -> bool {
+    let mut copies: HashMap<String, Operand> = HashMap::new();
+    for inst in func.blocks.iter().flat_map(|b| &b.insts) {
+        if !is_copy(&inst.op) {
+            continue;
+        }
+        if let (Some(k), Some(src)) = (ssa::def(inst).and_then(key), &inst.src1) {
+            copies.insert(k, src.clone());
+        }
+    }
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let mut checked: HashSet<(Operand, Operand)> = HashSet::new();
+        block
+            .insts
+            .retain(|inst| match (&inst.op, &inst.src1, &inst.src2) {
+                (Op::BoundsCheck(..), Some(arr), Some(index)) => {
+                    let safe = matches!(
+                        (array_length(arr, &copies), constant(index)),
+                        (Some(len), Some(i)) if i >= 0 && (i as usize) < len
+                    );
+                    let redundant = !checked.insert((arr.clone(), index.clone()));
+                    changed |= safe || redundant;
+                    !(safe || redundant)
+                }
+                (Op::Call, ..) => {
+                    checked.clear();
+                    true
+                }
+                _ => true,
+            });
+    }
+    changed
+}
+
+fn is_commutative(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Add | Op::Mul | Op::LAnd | Op::LOr | Op::Xor | Op::Eq | Op::Ne | Op::FAdd | Op::FMul
+    )
+}
+
+/// Replaces an arithmetic operation by a copy of an identical one that dominates it.
+fn eliminate_common_subexpressions(func: &mut SsaFunction) -> bool {
+    let children = func.dominator_tree();
+    let mut available: HashMap<(String, Vec<Operand>), Operand> = HashMap::new();
+    cse_block(func, 0, &children, &mut available)
+}
+
+fn cse_block(
+    func: &mut SsaFunction,
+    b: usize,
+    children: &[Vec<usize>],
+    available: &mut HashMap<(String, Vec<Operand>), Operand>,
+) -> bool {
+    let mut changed = false;
+    let mut added = Vec::new();
+    for inst in &mut func.blocks[b].insts {
+        let cse = matches!(
+            inst.op,
+            Op::Add
+                | Op::Sub
+                | Op::Mul
+                | Op::Div
+                | Op::Mod
+                | Op::Shl
+                | Op::Shr
+                | Op::Neg
+                | Op::Not
+                | Op::LAnd
+                | Op::LOr
+                | Op::Xor
+                | Op::Eq
+                | Op::Ne
+                | Op::Gt
+                | Op::Ge
+                | Op::Lt
+                | Op::Le
+                | Op::FAdd
+                | Op::FSub
+                | Op::FMul
+                | Op::FDiv
+                | Op::FEq
+                | Op::FNe
+                | Op::FGt
+                | Op::FGe
+                | Op::FLt
+                | Op::FLe
+                | Op::FNeg
+                | Op::FieldAddr(_)
+        );
+        let Some(dst) = ssa::def(inst).cloned().filter(|_| cse) else {
+            continue;
+        };
+        let mut operands: Vec<Operand> = [&inst.src1, &inst.src2]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        if is_commutative(&inst.op) {
+            operands.sort_by_key(|op| format!("{:?}", op));
+        }
+        let expr = (format!("{:?}", inst.op), operands);
+        match available.get(&expr) {
+            Some(value) => {
+                let float = matches!(
+                    inst.op,
+                    Op::FAdd | Op::FSub | Op::FMul | Op::FDiv | Op::FNeg
+                );
+                inst.op = if float { Op::FMove } else { Op::Move };
+                inst.src1 = Some(value.clone());
+                inst.src2 = None;
+                changed = true;
+            }
+            None => {
+                available.insert(expr.clone(), dst);
+                added.push(expr);
+            }
+        }
+    }
+    for &child in &children[b] {
+        changed |= cse_block(func, child, children, available);
+    }
+    for expr in added {
+        available.remove(&expr);
+    }
+    changed
+}
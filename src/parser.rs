@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     ast::{
-        ArrayAccess, ArrayAssign, BinOp, Expr, Extern, For, FuncCall, FuncDecl, Goto, If, Label,
-        Program, Return, Stmt, UnaryOp, Val, Var, VarDecl, VarMod, While,
+        ArrayAccess, ArrayAssign, BinOp, Break, Continue, Expr, ExprKind, Extern, FieldAccess,
+        FieldAssign, For, Format, FuncCall, FuncDecl, Goto, If, Import, Inline, Label, Panic, Pos,
+        Program, Return, Slice, Span, Stmt, StructDecl, StructLit, UnaryOp, Val, Var, VarDecl,
+        VarMod, While,
     },
     lexer::{Lexer, LexerError},
     token::{Literal, Token, TokenType, VarType},
@@ -42,35 +44,35 @@ impl From<LexerError> for ParserError {
 
 impl std::error::Error for ParserError {}
 
+impl ParserError {
+    /// Where the error is in the preprocessed source, or row 0 if it is unknown.
+    pub fn pos(&self) -> (usize, usize) {
+        match self {
+            ParserError::LexerError(e) => e.pos(),
+            ParserError::SyntaxError { row, col, .. }
+            | ParserError::UnexpectedChar { row, col, .. }
+            | ParserError::UnknownType { row, col }
+            | ParserError::TypeError { row, col, .. } => (*row, *col),
+        }
+    }
+}
+
 impl std::fmt::Display for ParserError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ParserError::LexerError(e) => write!(f, "{}", e),
-            ParserError::SyntaxError { message, row, col } => {
-                write!(f, "Syntax error at {}:{}: {}", row, col, message)
-            }
+            ParserError::SyntaxError { message, .. } => write!(f, "Syntax error: {}", message),
             ParserError::UnexpectedChar {
-                expected,
-                found,
-                row,
-                col,
+                expected, found, ..
             } => {
                 if let Some(exp) = expected {
-                    write!(
-                        f,
-                        "Unexpected char at {}:{}: expected '{}', found '{}'",
-                        row, col, exp, found
-                    )
+                    write!(f, "Unexpected char: expected '{}', found '{}'", exp, found)
                 } else {
-                    write!(f, "Unexpected char at {}:{}: '{}'", row, col, found)
+                    write!(f, "Unexpected char: '{}'", found)
                 }
             }
-            ParserError::UnknownType { row, col } => {
-                write!(f, "Unknown type at {}:{}", row, col)
-            }
-            ParserError::TypeError { message, row, col } => {
-                write!(f, "Type error at {}:{}: {}", row, col, message)
-            }
+            ParserError::UnknownType { .. } => write!(f, "Unknown type"),
+            ParserError::TypeError { message, .. } => write!(f, "Type error: {}", message),
         }
     }
 }
@@ -78,26 +80,134 @@ impl std::fmt::Display for ParserError {
 #[derive(Debug)]
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
-    functions: HashMap<String, VarType>,
+    structs: HashMap<String, Vec<(String, VarType)>>,
+    imports: Option<Vec<Import>>,
+    modules: HashSet<String>,
+    lines: Vec<(usize, usize)>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
         Self {
             lexer,
-            functions: HashMap::new(),
+            structs: HashMap::new(),
+            imports: None,
+            modules: HashSet::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Gives the file and row each row of the preprocessed source comes from, so
+    /// that spans point into the original files. Without it, rows are kept as they
+    /// are and belong to file 0.
+    pub fn set_lines(&mut self, lines: Vec<(usize, usize)>) {
+        self.lines = lines;
+    }
+
+    /// The span of a single position of the preprocessed source.
+    pub fn locate(&self, row: usize, col: usize) -> Span {
+        let (file, row) = match row.checked_sub(1).and_then(|i| self.lines.get(i)) {
+            Some(&(file, row)) => (file, row),
+            None => (0, row),
+        };
+        let pos = Pos { row, col };
+        Span {
+            file,
+            start: pos,
+            end: pos,
         }
     }
 
     pub fn parse(&mut self) -> Result<Program, ParserError> {
-        self.lexer.next_token()?;
+        if self.imports.is_none() {
+            self.parse_imports()?;
+        }
         let mut exprs: Vec<Expr> = Vec::new();
         while self.lexer.curr_tok().token != TokenType::EOF {
             exprs.push(self.ctrl()?);
         }
-        Ok(Program { body: exprs })
+        Ok(Program {
+            imports: self.imports.clone().unwrap_or_default(),
+            body: exprs,
+        })
+    }
+
+    /// Parses the `import` and `from ... import` lines at the top of a file. They are
+    /// read before the rest, so that the imported modules can be loaded and their
+    /// names declared before the body refers to them.
+    pub fn parse_imports(&mut self) -> Result<Vec<Import>, ParserError> {
+        self.lexer.next_token()?;
+        let mut imports = Vec::new();
+        loop {
+            let tok = self.lexer.curr_tok();
+            match tok.token {
+                TokenType::IMPORT => {
+                    self.lexer.next_token()?;
+                    let module = self.get_ident()?;
+                    self.lexer.next_token()?;
+                    self.modules.insert(module.clone());
+                    imports.push(Import {
+                        module,
+                        names: None,
+                        span: self.span_from(self.locate(tok.row, tok.col)),
+                    });
+                }
+                TokenType::FROM => {
+                    self.lexer.next_token()?;
+                    let module = self.get_ident()?;
+                    self.lexer.next_token()?;
+                    if self.lexer.curr_tok().token != TokenType::IMPORT {
+                        return Err(ParserError::UnexpectedChar {
+                            expected: Some("import".to_string()),
+                            found: self.lexer.curr_ch(),
+                            row: self.lexer.curr_tok().row,
+                            col: self.lexer.curr_tok().col,
+                        });
+                    }
+                    let mut names = Vec::new();
+                    loop {
+                        self.lexer.next_token()?;
+                        names.push(self.get_ident()?);
+                        self.lexer.next_token()?;
+                        if self.lexer.curr_tok().token != TokenType::COMMA {
+                            break;
+                        }
+                    }
+                    imports.push(Import {
+                        module,
+                        names: Some(names),
+                        span: self.span_from(self.locate(tok.row, tok.col)),
+                    });
+                }
+                _ => break,
+            }
+        }
+        self.imports = Some(imports.clone());
+        Ok(imports)
+    }
+
+    /// Makes a struct of another module usable under its own name.
+    pub fn declare_struct(&mut self, name: String, fields: Vec<(String, VarType)>) {
+        self.structs.insert(name, fields);
+    }
+
+    /// Where the current token starts.
+    fn span(&self) -> Span {
+        let tok = self.lexer.curr_tok();
+        self.locate(tok.row, tok.col)
+    }
+
+    /// Extends `start` up to the end of the last token read.
+    fn span_from(&self, start: Span) -> Span {
+        let end = self.lexer.prev_end();
+        Span {
+            end: self.locate(end.row, end.col).start,
+            ..start
+        }
     }
+
     fn ctrl(&mut self) -> Result<Expr, ParserError> {
+        let span = self.span();
         match self.lexer.curr_tok().token {
             TokenType::IF => {
                 self.lexer.next_token()?;
@@ -106,8 +216,8 @@ impl<'a> Parser<'a> {
                 if self.lexer.curr_tok().token == TokenType::ELSE {
                     self.lexer.next_token()?;
                     let else_body = self.stmt()?;
-                    match cond.clone() {
-                        Expr::Val(val) => match val.value {
+                    match cond.kind.clone() {
+                        ExprKind::Val(val) => match val.value {
                             Literal::Bool(b) => {
                                 if b {
                                     return Ok(body);
@@ -119,59 +229,55 @@ impl<'a> Parser<'a> {
                         },
                         _ => {}
                     }
-                    return Ok(Expr::If(If {
+                    return Ok(ExprKind::If(If {
                         condition: Box::new(cond),
                         then_branch: Box::new(body),
                         else_branch: Some(Box::new(else_body)),
-                    }));
+                    })
+                    .at(self.span_from(span)));
                 }
-                match cond.clone() {
-                    Expr::Val(val) => match val.value {
+                match cond.kind.clone() {
+                    ExprKind::Val(val) => match val.value {
                         Literal::Bool(b) => {
                             if b {
                                 return Ok(body);
                             } else {
-                                return Ok(Expr::Stmt(Stmt { body: vec![] }));
+                                return Ok(
+                                    ExprKind::Stmt(Stmt { body: vec![] }).at(self.span_from(span))
+                                );
                             }
                         }
                         _ => {}
                     },
                     _ => {}
                 }
-                Ok(Expr::If(If {
+                Ok(ExprKind::If(If {
                     condition: Box::new(cond),
                     then_branch: Box::new(body),
                     else_branch: None,
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::WHILE => {
                 self.lexer.next_token()?;
                 let cond = self.expr()?;
                 let body = self.stmt()?;
-                match cond.clone() {
-                    Expr::Val(val) => match val.value {
-                        Literal::Bool(b) => {
-                            if b {
-                                let lbl = format!("loop{:p}", &body);
-                                return Ok(Expr::Stmt(Stmt {
-                                    body: vec![
-                                        Expr::Label(Label { name: lbl.clone() }),
-                                        body,
-                                        Expr::Goto(Goto { label: lbl }),
-                                    ],
-                                }));
-                            } else {
-                                return Ok(Expr::Stmt(Stmt { body: vec![] }));
-                            }
+                match cond.kind.clone() {
+                    ExprKind::Val(val) => match val.value {
+                        Literal::Bool(false) => {
+                            return Ok(
+                                ExprKind::Stmt(Stmt { body: vec![] }).at(self.span_from(span))
+                            );
                         }
                         _ => {}
                     },
                     _ => {}
                 }
-                Ok(Expr::While(While {
+                Ok(ExprKind::While(While {
                     condition: Box::new(cond),
                     body: Box::new(body),
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::FOR => {
                 self.lexer.next_token()?;
@@ -188,29 +294,51 @@ impl<'a> Parser<'a> {
                 self.lexer.next_token()?;
                 let iter = self.expr()?;
                 let body = self.stmt()?;
-                Ok(Expr::For(For {
+                Ok(ExprKind::For(For {
                     init,
                     iter: Box::new(iter),
                     body: Box::new(body),
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::PUB => {
                 self.lexer.next_token()?;
-                if self.lexer.curr_tok().token != TokenType::FUNCDECL {
-                    return Err(ParserError::UnexpectedChar {
-                        expected: Some("fun".to_string()),
+                match self.lexer.curr_tok().token {
+                    TokenType::FUNCDECL | TokenType::INLINE | TokenType::NOINLINE => {
+                        let inline = self.inline_attr()?;
+                        self.func_decl(true, inline)
+                    }
+                    TokenType::STRUCT => self.struct_decl(true),
+                    TokenType::EXTERN => {
+                        let mut ext = self.expr()?;
+                        if let ExprKind::Extern(e) = &mut ext.kind {
+                            e.is_pub = true;
+                        }
+                        Ok(ext)
+                    }
+                    _ => Err(ParserError::UnexpectedChar {
+                        expected: Some("fun, struct or extern".to_string()),
                         found: self.lexer.curr_ch(),
                         row: self.lexer.curr_tok().row,
                         col: self.lexer.curr_tok().col,
-                    });
+                    }),
                 }
-                self.func_decl(true)
             }
-            TokenType::FUNCDECL => self.func_decl(false),
+            TokenType::IMPORT | TokenType::FROM => Err(ParserError::SyntaxError {
+                message: "imports must come before any other declaration".to_string(),
+                row: self.lexer.curr_tok().row,
+                col: self.lexer.curr_tok().col,
+            }),
+            TokenType::FUNCDECL | TokenType::INLINE | TokenType::NOINLINE => {
+                let inline = self.inline_attr()?;
+                self.func_decl(false, inline)
+            }
+            TokenType::STRUCT => self.struct_decl(false),
             _ => self.stmt(),
         }
     }
     fn stmt(&mut self) -> Result<Expr, ParserError> {
+        let span = self.span();
         if self.lexer.curr_tok().token == TokenType::LBRACE {
             let mut exprs: Vec<Expr> = Vec::new();
             self.lexer.next_token()?;
@@ -228,51 +356,75 @@ impl<'a> Parser<'a> {
             }
 
             self.lexer.next_token()?;
-            return Ok(Expr::Stmt(Stmt { body: exprs }));
+            return Ok(ExprKind::Stmt(Stmt { body: exprs }).at(self.span_from(span)));
         }
         if self.lexer.curr_tok().token == TokenType::IF
             || self.lexer.curr_tok().token == TokenType::WHILE
             || self.lexer.curr_tok().token == TokenType::FUNCDECL
+            || self.lexer.curr_tok().token == TokenType::INLINE
+            || self.lexer.curr_tok().token == TokenType::NOINLINE
         {
             return self.ctrl();
         }
         self.expr()
     }
     fn expr(&mut self) -> Result<Expr, ParserError> {
+        let span = self.span();
         match self.lexer.curr_tok().token {
             TokenType::GOTO => {
                 self.lexer.next_token()?;
                 let name = self.get_ident()?;
                 self.lexer.next_token()?;
-                Ok(Expr::Goto(Goto { label: name }))
+                Ok(ExprKind::Goto(Goto { label: name }).at(self.span_from(span)))
+            }
+            TokenType::BREAK | TokenType::CONTINUE => {
+                let keyword = self.lexer.curr_tok();
+                self.lexer.next_token()?;
+                // The next statement may just as well start with an identifier, so a
+                // loop label only counts when it is on the same line as the keyword.
+                let label = if self.lexer.curr_tok().token == TokenType::IDENT
+                    && self.lexer.curr_tok().row == keyword.row
+                {
+                    let name = self.get_ident()?;
+                    self.lexer.next_token()?;
+                    Some(name)
+                } else {
+                    None
+                };
+                if keyword.token == TokenType::BREAK {
+                    Ok(ExprKind::Break(Break { label }).at(self.span_from(span)))
+                } else {
+                    Ok(ExprKind::Continue(Continue { label }).at(self.span_from(span)))
+                }
             }
             TokenType::VARDECL => {
                 self.lexer.next_token()?;
                 let name = self.get_ident()?;
                 self.lexer.next_token()?;
-                if self.lexer.curr_tok().token != TokenType::COLON {
-                    return Err(ParserError::UnexpectedChar {
-                        expected: Some(":".to_string()),
-                        found: self.lexer.curr_ch(),
-                        row: self.lexer.curr_tok().row,
-                        col: self.lexer.curr_tok().col,
-                    });
-                }
-                self.lexer.next_token()?;
-                let typ = match &self.lexer.curr_tok().token {
-                    TokenType::Type(VarType::Int) => VarType::Int,
-                    TokenType::Type(VarType::Float) => VarType::Float,
-                    TokenType::Type(VarType::Bool) => VarType::Bool,
-                    TokenType::Type(VarType::Str) => VarType::Str,
-                    TokenType::Type(VarType::Array(n)) => VarType::Array(*n),
-                    _ => {
-                        return Err(ParserError::UnknownType {
-                            row: self.lexer.curr_tok().row,
-                            col: self.lexer.curr_tok().col,
-                        });
-                    }
+                // Without an annotation the type checker infers the type.
+                let typ = if self.lexer.curr_tok().token == TokenType::COLON {
+                    self.lexer.next_token()?;
+                    let typ = match &self.lexer.curr_tok().token {
+                        TokenType::Type(VarType::Int) => VarType::Int,
+                        TokenType::Type(VarType::Float) => VarType::Float,
+                        TokenType::Type(VarType::Bool) => VarType::Bool,
+                        TokenType::Type(VarType::Char) => VarType::Char,
+                        TokenType::Type(VarType::Str) => VarType::Str,
+                        TokenType::Type(VarType::Array(n)) => VarType::Array(*n),
+                        TokenType::Type(VarType::Vec) => VarType::Vec,
+                        TokenType::IDENT => self.get_type()?,
+                        _ => {
+                            return Err(ParserError::UnknownType {
+                                row: self.lexer.curr_tok().row,
+                                col: self.lexer.curr_tok().col,
+                            });
+                        }
+                    };
+                    self.lexer.next_token()?;
+                    Some(typ)
+                } else {
+                    None
                 };
-                self.lexer.next_token()?;
                 if self.lexer.curr_tok().token != TokenType::EQ {
                     return Err(ParserError::UnexpectedChar {
                         expected: Some("=".to_string()),
@@ -283,18 +435,20 @@ impl<'a> Parser<'a> {
                 }
                 self.lexer.next_token()?;
                 let value = self.expr()?;
-                Ok(Expr::VarDecl(VarDecl {
+                Ok(ExprKind::VarDecl(VarDecl {
                     name,
                     value: Box::new(value),
                     typ,
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::RETURN => {
                 self.lexer.next_token()?;
                 let value = self.expr()?;
-                Ok(Expr::Return(Return {
+                Ok(ExprKind::Return(Return {
                     value: Some(Box::new(value)),
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::EXTERN => {
                 self.lexer.next_token()?;
@@ -310,32 +464,35 @@ impl<'a> Parser<'a> {
                 }
                 self.lexer.next_token()?;
                 let mut params: Vec<VarType> = Vec::new();
+                let mut is_variadic = false;
                 while self.lexer.curr_tok().token != TokenType::RPAREN {
-                    match self.lexer.curr_tok().token {
-                        TokenType::Type(typ) => {
-                            params.push(typ);
-                            self.lexer.next_token()?;
-                            if self.lexer.curr_tok().token == TokenType::COMMA {
-                                self.lexer.next_token()?;
-                            } else if self.lexer.curr_tok().token == TokenType::RPAREN {
-                                break;
-                            } else {
-                                Err(ParserError::UnexpectedChar {
-                                    expected: Some(") or ,".to_string()),
-                                    found: self.lexer.curr_ch(),
-                                    row: self.lexer.curr_tok().row,
-                                    col: self.lexer.curr_tok().col,
-                                })?;
-                            }
-                        }
-                        _ => {
+                    // `...` ends the parameters, as in C.
+                    if self.lexer.curr_tok().token == TokenType::ELLIPSIS {
+                        is_variadic = true;
+                        self.lexer.next_token()?;
+                        if self.lexer.curr_tok().token != TokenType::RPAREN {
                             return Err(ParserError::UnexpectedChar {
-                                expected: Some("TYPE".to_string()),
+                                expected: Some(")".to_string()),
                                 found: self.lexer.curr_ch(),
                                 row: self.lexer.curr_tok().row,
                                 col: self.lexer.curr_tok().col,
                             });
                         }
+                        break;
+                    }
+                    params.push(self.get_type()?);
+                    self.lexer.next_token()?;
+                    if self.lexer.curr_tok().token == TokenType::COMMA {
+                        self.lexer.next_token()?;
+                    } else if self.lexer.curr_tok().token == TokenType::RPAREN {
+                        break;
+                    } else {
+                        Err(ParserError::UnexpectedChar {
+                            expected: Some(") or ,".to_string()),
+                            found: self.lexer.curr_ch(),
+                            row: self.lexer.curr_tok().row,
+                            col: self.lexer.curr_tok().col,
+                        })?;
                     }
                 }
                 self.lexer.next_token()?;
@@ -348,25 +505,16 @@ impl<'a> Parser<'a> {
                     });
                 }
                 self.lexer.next_token()?;
-                let ret_type: VarType;
-                match self.lexer.curr_tok().token {
-                    TokenType::Type(typ) => ret_type = typ,
-                    _ => {
-                        return Err(ParserError::UnexpectedChar {
-                            expected: Some("TYPE".to_string()),
-                            found: self.lexer.curr_ch(),
-                            row: self.lexer.curr_tok().row,
-                            col: self.lexer.curr_tok().col,
-                        });
-                    }
-                }
+                let ret_type = self.get_type()?;
                 self.lexer.next_token()?;
-                self.functions.insert(func.clone(), ret_type.clone());
-                Ok(Expr::Extern(Extern {
+                Ok(ExprKind::Extern(Extern {
                     name: func,
                     params,
                     ret_type,
-                }))
+                    is_pub: false,
+                    is_variadic,
+                })
+                .at(self.span_from(span)))
             }
             TokenType::IF | TokenType::WHILE | TokenType::LBRACE => self.ctrl(),
             _ => self.logical(),
@@ -381,64 +529,12 @@ impl<'a> Parser<'a> {
             let op = self.lexer.curr_tok().token;
             self.lexer.next_token()?;
             let right = self.comparison()?;
-            match (left.clone(), right.clone()) {
-                (Expr::Val(l), Expr::Val(r)) => match (l.value, r.value) {
-                    (Literal::Int(n), Literal::Int(m)) => match op.clone() {
-                        TokenType::LOGAND => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n & m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        TokenType::LOGOR => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n | m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        TokenType::LOGXOR => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n ^ m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        _ => {}
-                    },
-                    (Literal::Bool(n), Literal::Bool(m)) => match op.clone() {
-                        TokenType::LOGAND => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n & m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::LOGOR => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n | m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::LOGXOR => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n ^ m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        _ => {}
-                    },
-                    (_, _) => {}
-                },
-                (_, _) => {}
-            }
-            left = Expr::BinOp(BinOp {
-                left: Box::new(left),
-                right: Box::new(right),
-                operator: op,
-            })
+            left = binop(left, right, op);
         }
         Ok(left)
     }
     fn comparison(&mut self) -> Result<Expr, ParserError> {
-        let mut left = self.additive()?;
+        let mut left = self.shift()?;
         while self.lexer.curr_tok().token == TokenType::COMPEQ
             || self.lexer.curr_tok().token == TokenType::COMPNE
             || self.lexer.curr_tok().token == TokenType::COMPLT
@@ -448,75 +544,23 @@ impl<'a> Parser<'a> {
             || self.lexer.curr_tok().token == TokenType::COMPAND
             || self.lexer.curr_tok().token == TokenType::COMPOR
             || self.lexer.curr_tok().token == TokenType::RANGE
+        {
+            let op = self.lexer.curr_tok().token;
+            self.lexer.next_token()?;
+            let right = self.shift()?;
+            left = binop(left, right, op);
+        }
+        Ok(left)
+    }
+    fn shift(&mut self) -> Result<Expr, ParserError> {
+        let mut left = self.additive()?;
+        while self.lexer.curr_tok().token == TokenType::SHL
+            || self.lexer.curr_tok().token == TokenType::SHR
         {
             let op = self.lexer.curr_tok().token;
             self.lexer.next_token()?;
             let right = self.additive()?;
-            match (left.clone(), right.clone()) {
-                (Expr::Val(l), Expr::Val(r)) => match (l.value, r.value) {
-                    (Literal::Int(n), Literal::Int(m)) => match op.clone() {
-                        TokenType::COMPEQ => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n == m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::COMPNE => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n != m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::COMPGT => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n > m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::COMPGE => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n >= m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::COMPLT => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n < m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::COMPLE => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n <= m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        _ => {}
-                    },
-                    (Literal::Bool(n), Literal::Bool(m)) => match op.clone() {
-                        TokenType::COMPAND => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n && m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        TokenType::COMPOR => {
-                            left = Expr::Val(Val {
-                                value: Literal::Bool(n || m),
-                                typ: VarType::Bool,
-                            });
-                        }
-                        _ => {}
-                    },
-                    (_, _) => {}
-                },
-                (_, _) => {}
-            }
-            left = Expr::BinOp(BinOp {
-                left: Box::new(left),
-                right: Box::new(right),
-                operator: op,
-            });
+            left = binop(left, right, op);
         }
         Ok(left)
     }
@@ -528,32 +572,7 @@ impl<'a> Parser<'a> {
             let op = self.lexer.curr_tok().token;
             self.lexer.next_token()?;
             let right = self.term()?;
-            match (left.clone(), right.clone()) {
-                (Expr::Val(l), Expr::Val(r)) => match (l.value, r.value) {
-                    (Literal::Int(n), Literal::Int(m)) => match op.clone() {
-                        TokenType::ADD => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n + m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        TokenType::SUB => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n - m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        _ => {}
-                    },
-                    (_, _) => {}
-                },
-                (_, _) => {}
-            }
-            left = Expr::BinOp(BinOp {
-                left: Box::new(left),
-                right: Box::new(right),
-                operator: op,
-            });
+            left = binop(left, right, op);
         }
         Ok(left)
     }
@@ -561,48 +580,28 @@ impl<'a> Parser<'a> {
         let mut left = self.factor()?;
         while self.lexer.curr_tok().token == TokenType::MUL
             || self.lexer.curr_tok().token == TokenType::DIV
+            || self.lexer.curr_tok().token == TokenType::MOD
         {
             let op = self.lexer.curr_tok().token;
+            let (row, col) = (self.lexer.curr_tok().row, self.lexer.curr_tok().col);
             self.lexer.next_token()?;
             let right = self.factor()?;
-            match (left.clone(), right.clone()) {
-                (Expr::Val(l), Expr::Val(r)) => match (l.value, r.value) {
-                    (Literal::Int(n), Literal::Int(m)) => match op.clone() {
-                        TokenType::MUL => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n * m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        TokenType::DIV => {
-                            left = Expr::Val(Val {
-                                value: Literal::Int(n / m),
-                                typ: VarType::Int,
-                            });
-                        }
-                        _ => {}
-                    },
-                    (_, _) => {}
-                },
-                (_, _) => {}
-            }
-            left = Expr::BinOp(BinOp {
-                left: Box::new(left),
-                right: Box::new(right),
-                operator: op,
-            });
+            check_divisor(&op, &right, row, col)?;
+            left = binop(left, right, op);
         }
         Ok(left)
     }
     fn factor(&mut self) -> Result<Expr, ParserError> {
+        let span = self.span();
         match self.lexer.curr_tok().token {
             TokenType::LITERAL(typ) => {
                 if let Some(val) = self.lexer.curr_tok().value.clone() {
                     self.lexer.next_token()?;
-                    Ok(Expr::Val(Val {
+                    Ok(ExprKind::Val(Val {
                         value: val,
                         typ: typ,
-                    }))
+                    })
+                    .at(self.span_from(span)))
                 } else {
                     Err(ParserError::SyntaxError {
                         message: "expected literal value".to_string(),
@@ -645,71 +644,270 @@ impl<'a> Parser<'a> {
                 }
 
                 self.lexer.next_token()?;
-                Ok(Expr::Val(Val {
+                Ok(ExprKind::Val(Val {
                     value: Literal::Array(array.len(), array.clone()),
                     typ: VarType::Array(Some(array.len())),
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::NEG => {
                 self.lexer.next_token()?;
                 let argument = self.expr()?;
-                match argument.clone() {
-                    Expr::Val(val) => match val.value {
+                match argument.kind.clone() {
+                    ExprKind::Val(val) => match val.value {
                         Literal::Int(n) => {
-                            return Ok(Expr::Val(Val {
-                                value: Literal::Int(-n),
+                            return Ok(ExprKind::Val(Val {
+                                value: Literal::Int(n.wrapping_neg()),
                                 typ: VarType::Int,
-                            }));
+                            })
+                            .at(self.span_from(span)));
                         }
                         _ => {}
                     },
                     _ => {}
                 }
-                Ok(Expr::UnaryOp(UnaryOp {
+                Ok(ExprKind::UnaryOp(UnaryOp {
                     argument: Box::new(argument),
                     operator: TokenType::NEG,
-                }))
+                })
+                .at(self.span_from(span)))
             }
             TokenType::LOGNOT => {
                 self.lexer.next_token()?;
                 let argument = self.expr()?;
-                match argument.clone() {
-                    Expr::Val(val) => match val.value {
+                match argument.kind.clone() {
+                    ExprKind::Val(val) => match val.value {
                         Literal::Bool(n) => {
-                            return Ok(Expr::Val(Val {
+                            return Ok(ExprKind::Val(Val {
                                 value: Literal::Bool(!n),
                                 typ: VarType::Bool,
-                            }));
+                            })
+                            .at(self.span_from(span)));
                         }
                         _ => {}
                     },
                     _ => {}
                 }
-                Ok(Expr::UnaryOp(UnaryOp {
+                Ok(ExprKind::UnaryOp(UnaryOp {
                     argument: Box::new(argument),
                     operator: TokenType::LOGNOT,
-                }))
+                })
+                .at(self.span_from(span)))
+            }
+            TokenType::BITNOT => {
+                self.lexer.next_token()?;
+                let argument = self.factor()?;
+                if let ExprKind::Val(Val {
+                    value: Literal::Int(n),
+                    ..
+                }) = argument.kind
+                {
+                    return Ok(ExprKind::Val(Val {
+                        value: Literal::Int(!n),
+                        typ: VarType::Int,
+                    })
+                    .at(self.span_from(span)));
+                }
+                Ok(ExprKind::UnaryOp(UnaryOp {
+                    argument: Box::new(argument),
+                    operator: TokenType::BITNOT,
+                })
+                .at(self.span_from(span)))
+            }
+            TokenType::ORD | TokenType::CHR => {
+                let operator = self.lexer.curr_tok().token;
+                self.lexer.next_token()?;
+                let argument = self.factor()?;
+                let folded = match (&operator, &argument.kind) {
+                    (
+                        TokenType::ORD,
+                        ExprKind::Val(Val {
+                            value: Literal::Char(c),
+                            ..
+                        }),
+                    ) => Some((Literal::Int(*c as i64), VarType::Int)),
+                    (
+                        TokenType::CHR,
+                        ExprKind::Val(Val {
+                            value: Literal::Int(n @ 0..=255),
+                            ..
+                        }),
+                    ) => Some((Literal::Char(*n as u8), VarType::Char)),
+                    _ => None,
+                };
+                if let Some((value, typ)) = folded {
+                    return Ok(ExprKind::Val(Val { value, typ }).at(self.span_from(span)));
+                }
+                Ok(ExprKind::UnaryOp(UnaryOp {
+                    argument: Box::new(argument),
+                    operator,
+                })
+                .at(self.span_from(span)))
             }
             TokenType::SIZEOF => {
                 self.lexer.next_token()?;
                 let argument = self.expr()?;
-                Ok(Expr::UnaryOp(UnaryOp {
+                Ok(ExprKind::UnaryOp(UnaryOp {
                     argument: Box::new(argument),
                     operator: TokenType::SIZEOF,
-                }))
+                })
+                .at(self.span_from(span)))
+            }
+            TokenType::PANIC => {
+                self.lexer.next_token()?;
+                if self.lexer.curr_tok().token != TokenType::LPAREN {
+                    return Err(ParserError::UnexpectedChar {
+                        expected: Some("(".to_string()),
+                        found: self.lexer.curr_ch(),
+                        row: self.lexer.curr_tok().row,
+                        col: self.lexer.curr_tok().col,
+                    });
+                }
+                self.lexer.next_token()?;
+                let message = self.expr()?;
+                if self.lexer.curr_tok().token != TokenType::RPAREN {
+                    return Err(ParserError::UnexpectedChar {
+                        expected: Some(")".to_string()),
+                        found: self.lexer.curr_ch(),
+                        row: self.lexer.curr_tok().row,
+                        col: self.lexer.curr_tok().col,
+                    });
+                }
+                self.lexer.next_token()?;
+                Ok(ExprKind::Panic(Panic {
+                    message: Box::new(message),
+                })
+                .at(self.span_from(span)))
+            }
+            TokenType::FORMAT => {
+                self.lexer.next_token()?;
+                if self.lexer.curr_tok().token != TokenType::LPAREN {
+                    return Err(ParserError::UnexpectedChar {
+                        expected: Some("(".to_string()),
+                        found: self.lexer.curr_ch(),
+                        row: self.lexer.curr_tok().row,
+                        col: self.lexer.curr_tok().col,
+                    });
+                }
+                self.lexer.next_token()?;
+                // The template is split up at compile time, so it has to be a literal.
+                let template = match self.lexer.curr_tok().value {
+                    Some(Literal::Str(s))
+                        if self.lexer.curr_tok().token == TokenType::LITERAL(VarType::Str) =>
+                    {
+                        s
+                    }
+                    _ => {
+                        return Err(ParserError::SyntaxError {
+                            message: "format expects a string literal".to_string(),
+                            row: self.lexer.curr_tok().row,
+                            col: self.lexer.curr_tok().col,
+                        });
+                    }
+                };
+                self.lexer.next_token()?;
+                let mut args = Vec::new();
+                while self.lexer.curr_tok().token == TokenType::COMMA {
+                    self.lexer.next_token()?;
+                    args.push(self.expr()?);
+                }
+                if self.lexer.curr_tok().token != TokenType::RPAREN {
+                    return Err(ParserError::UnexpectedChar {
+                        expected: Some(")".to_string()),
+                        found: self.lexer.curr_ch(),
+                        row: self.lexer.curr_tok().row,
+                        col: self.lexer.curr_tok().col,
+                    });
+                }
+                self.lexer.next_token()?;
+                Ok(ExprKind::Format(Format { template, args }).at(self.span_from(span)))
             }
             TokenType::IDENT => {
-                let name = self.get_ident()?;
+                let mut name = self.get_ident()?;
                 self.lexer.next_token()?;
+                // `module.name` refers to a function of a module brought in by `import`.
+                if self.modules.contains(&name) && self.lexer.curr_tok().token == TokenType::DOT {
+                    self.lexer.next_token()?;
+                    name = format!("{}.{}", name, self.get_ident()?);
+                    self.lexer.next_token()?;
+                }
                 match self.lexer.curr_tok().token {
                     TokenType::COLON => {
                         self.lexer.next_token()?;
-                        Ok(Expr::Label(Label { name: name }))
+                        Ok(ExprKind::Label(Label { name: name }).at(self.span_from(span)))
+                    }
+                    TokenType::LBRACE if self.structs.contains_key(&name) => {
+                        self.lexer.next_token()?;
+                        let mut fields: Vec<(String, Expr)> = Vec::new();
+                        while self.lexer.curr_tok().token != TokenType::RBRACE {
+                            let field = self.get_ident()?;
+                            self.lexer.next_token()?;
+                            if self.lexer.curr_tok().token != TokenType::COLON {
+                                return Err(ParserError::UnexpectedChar {
+                                    expected: Some(":".to_string()),
+                                    found: self.lexer.curr_ch(),
+                                    row: self.lexer.curr_tok().row,
+                                    col: self.lexer.curr_tok().col,
+                                });
+                            }
+                            self.lexer.next_token()?;
+                            fields.push((field, self.expr()?));
+                            if self.lexer.curr_tok().token == TokenType::COMMA {
+                                self.lexer.next_token()?;
+                            } else if self.lexer.curr_tok().token == TokenType::RBRACE {
+                                break;
+                            } else {
+                                Err(ParserError::UnexpectedChar {
+                                    expected: Some("} or ,".to_string()),
+                                    found: self.lexer.curr_ch(),
+                                    row: self.lexer.curr_tok().row,
+                                    col: self.lexer.curr_tok().col,
+                                })?;
+                            }
+                        }
+                        self.lexer.next_token()?;
+                        Ok(
+                            ExprKind::StructLit(StructLit { name, fields })
+                                .at(self.span_from(span)),
+                        )
+                    }
+                    TokenType::DOT => {
+                        let mut object = ExprKind::Var(Var { name }).at(self.span_from(span));
+                        loop {
+                            self.lexer.next_token()?;
+                            let field = self.get_ident()?;
+                            self.lexer.next_token()?;
+                            match self.lexer.curr_tok().token {
+                                TokenType::DOT => {
+                                    object = ExprKind::FieldAccess(FieldAccess {
+                                        object: Box::new(object),
+                                        field,
+                                    })
+                                    .at(self.span_from(span));
+                                }
+                                TokenType::EQ => {
+                                    self.lexer.next_token()?;
+                                    let value = self.expr()?;
+                                    return Ok(ExprKind::FieldAssign(FieldAssign {
+                                        object: Box::new(object),
+                                        field,
+                                        value: Box::new(value),
+                                    })
+                                    .at(self.span_from(span)));
+                                }
+                                _ => {
+                                    return Ok(ExprKind::FieldAccess(FieldAccess {
+                                        object: Box::new(object),
+                                        field,
+                                    })
+                                    .at(self.span_from(span)));
+                                }
+                            }
+                        }
                     }
                     TokenType::LPAREN => {
                         self.lexer.next_token()?;
                         let mut args: Vec<Expr> = Vec::new();
-                        let ret_type = self.find_func_ret_type(&name)?;
                         while self.lexer.curr_tok().token != TokenType::RPAREN {
                             args.push(self.expr()?);
                             if self.lexer.curr_tok().token == TokenType::COMMA {
@@ -726,67 +924,146 @@ impl<'a> Parser<'a> {
                             }
                         }
                         self.lexer.next_token()?;
-                        Ok(Expr::FuncCall(FuncCall {
-                            name,
-                            args,
-                            ret_type,
-                        }))
+                        Ok(ExprKind::FuncCall(FuncCall { name, args }).at(self.span_from(span)))
                     }
                     TokenType::EQ => {
                         self.lexer.next_token()?;
                         let val = self.expr()?;
-                        Ok(Expr::VarMod(VarMod {
+                        Ok(ExprKind::VarMod(VarMod {
                             name,
                             value: Box::new(val),
-                        }))
+                        })
+                        .at(self.span_from(span)))
                     }
                     TokenType::ADDEQ => {
                         self.lexer.next_token()?;
                         let val = self.expr()?;
-                        Ok(Expr::VarMod(VarMod {
+                        Ok(ExprKind::VarMod(VarMod {
                             name: name.clone(),
-                            value: Box::new(Expr::BinOp(BinOp {
-                                left: Box::new(Expr::Var(Var { name })),
-                                right: Box::new(val),
-                                operator: TokenType::ADD,
-                            })),
-                        }))
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::ADD,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
                     }
                     TokenType::SUBEQ => {
                         self.lexer.next_token()?;
                         let val = self.expr()?;
-                        Ok(Expr::VarMod(VarMod {
+                        Ok(ExprKind::VarMod(VarMod {
                             name: name.clone(),
-                            value: Box::new(Expr::BinOp(BinOp {
-                                left: Box::new(Expr::Var(Var { name })),
-                                right: Box::new(val),
-                                operator: TokenType::SUB,
-                            })),
-                        }))
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::SUB,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
                     }
                     TokenType::MULEQ => {
                         self.lexer.next_token()?;
                         let val = self.expr()?;
-                        Ok(Expr::VarMod(VarMod {
+                        Ok(ExprKind::VarMod(VarMod {
                             name: name.clone(),
-                            value: Box::new(Expr::BinOp(BinOp {
-                                left: Box::new(Expr::Var(Var { name })),
-                                right: Box::new(val),
-                                operator: TokenType::MUL,
-                            })),
-                        }))
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::MUL,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
                     }
                     TokenType::DIVEQ => {
+                        let (row, col) = (self.lexer.curr_tok().row, self.lexer.curr_tok().col);
+                        self.lexer.next_token()?;
+                        let val = self.expr()?;
+                        check_divisor(&TokenType::DIVEQ, &val, row, col)?;
+                        Ok(ExprKind::VarMod(VarMod {
+                            name: name.clone(),
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::DIV,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
+                    }
+                    TokenType::MODEQ => {
+                        let (row, col) = (self.lexer.curr_tok().row, self.lexer.curr_tok().col);
+                        self.lexer.next_token()?;
+                        let val = self.expr()?;
+                        check_divisor(&TokenType::MODEQ, &val, row, col)?;
+                        Ok(ExprKind::VarMod(VarMod {
+                            name: name.clone(),
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::MOD,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
+                    }
+                    TokenType::SHLEQ => {
+                        self.lexer.next_token()?;
+                        let val = self.expr()?;
+                        Ok(ExprKind::VarMod(VarMod {
+                            name: name.clone(),
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::SHL,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
+                    }
+                    TokenType::SHREQ => {
                         self.lexer.next_token()?;
                         let val = self.expr()?;
-                        Ok(Expr::VarMod(VarMod {
+                        Ok(ExprKind::VarMod(VarMod {
                             name: name.clone(),
-                            value: Box::new(Expr::BinOp(BinOp {
-                                left: Box::new(Expr::Var(Var { name })),
-                                right: Box::new(val),
-                                operator: TokenType::DIV,
-                            })),
-                        }))
+                            value: Box::new(
+                                ExprKind::BinOp(BinOp {
+                                    left: Box::new(
+                                        ExprKind::Var(Var { name }).at(self.span_from(span)),
+                                    ),
+                                    right: Box::new(val),
+                                    operator: TokenType::SHR,
+                                })
+                                .at(self.span_from(span)),
+                            ),
+                        })
+                        .at(self.span_from(span)))
                     }
                     TokenType::LBRACKET => {
                         self.lexer.next_token()?;
@@ -803,19 +1080,33 @@ impl<'a> Parser<'a> {
                         if self.lexer.curr_tok().token == TokenType::EQ {
                             self.lexer.next_token()?;
                             let value = self.expr()?;
-                            Ok(Expr::ArrayAssign(ArrayAssign {
+                            Ok(ExprKind::ArrayAssign(ArrayAssign {
                                 array: name,
                                 offset: Box::new(offset),
                                 value: Box::new(value),
-                            }))
+                            })
+                            .at(self.span_from(span)))
+                        } else if let ExprKind::BinOp(BinOp {
+                            left,
+                            right,
+                            operator: TokenType::RANGE,
+                        }) = offset.kind
+                        {
+                            Ok(ExprKind::Slice(Slice {
+                                string: name,
+                                start: left,
+                                end: right,
+                            })
+                            .at(self.span_from(span)))
                         } else {
-                            Ok(Expr::ArrayAccess(ArrayAccess {
+                            Ok(ExprKind::ArrayAccess(ArrayAccess {
                                 array: name,
                                 offset: Box::new(offset),
-                            }))
+                            })
+                            .at(self.span_from(span)))
                         }
                     }
-                    _ => Ok(Expr::Var(Var { name })),
+                    _ => Ok(ExprKind::Var(Var { name }).at(self.span_from(span))),
                 }
             }
             _ => Err(ParserError::SyntaxError {
@@ -842,7 +1133,115 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn func_decl(&mut self, is_pub: bool) -> Result<Expr, ParserError> {
+    fn get_type(&mut self) -> Result<VarType, ParserError> {
+        match self.lexer.curr_tok().token {
+            TokenType::Type(vt) => Ok(vt),
+            TokenType::IDENT => {
+                let name = self.get_ident()?;
+                // `string` is no keyword, as it also names the module of `alum-std`.
+                if name == "string" {
+                    Ok(VarType::String)
+                } else if self.structs.contains_key(&name) {
+                    Ok(VarType::Struct(name))
+                } else {
+                    Err(ParserError::UnknownType {
+                        row: self.lexer.curr_tok().row,
+                        col: self.lexer.curr_tok().col,
+                    })
+                }
+            }
+            _ => Err(ParserError::UnexpectedChar {
+                expected: Some("TYPE".to_string()),
+                found: self.lexer.curr_ch(),
+                row: self.lexer.curr_tok().row,
+                col: self.lexer.curr_tok().col,
+            }),
+        }
+    }
+
+    fn struct_decl(&mut self, is_pub: bool) -> Result<Expr, ParserError> {
+        let span = self.span();
+        self.lexer.next_token()?;
+        let name = self.get_ident()?;
+        self.lexer.next_token()?;
+        if self.lexer.curr_tok().token != TokenType::LBRACE {
+            return Err(ParserError::UnexpectedChar {
+                expected: Some("{".to_string()),
+                found: self.lexer.curr_ch(),
+                row: self.lexer.curr_tok().row,
+                col: self.lexer.curr_tok().col,
+            });
+        }
+        // Registered up front so a self-referencing field still parses; irgen rejects
+        // such layouts since a struct cannot contain itself by value.
+        self.structs.insert(name.clone(), Vec::new());
+        self.lexer.next_token()?;
+        let mut fields: Vec<(String, VarType)> = Vec::new();
+        while self.lexer.curr_tok().token != TokenType::RBRACE {
+            let field = self.get_ident()?;
+            if fields.iter().any(|(f, _)| *f == field) {
+                return Err(ParserError::SyntaxError {
+                    message: format!("duplicate field '{}' in struct '{}'", field, name),
+                    row: self.lexer.curr_tok().row,
+                    col: self.lexer.curr_tok().col,
+                });
+            }
+            self.lexer.next_token()?;
+            if self.lexer.curr_tok().token != TokenType::COLON {
+                return Err(ParserError::UnexpectedChar {
+                    expected: Some(":".to_string()),
+                    found: self.lexer.curr_ch(),
+                    row: self.lexer.curr_tok().row,
+                    col: self.lexer.curr_tok().col,
+                });
+            }
+            self.lexer.next_token()?;
+            fields.push((field, self.get_type()?));
+            self.lexer.next_token()?;
+            if self.lexer.curr_tok().token == TokenType::COMMA {
+                self.lexer.next_token()?;
+            } else if self.lexer.curr_tok().token == TokenType::RBRACE {
+                break;
+            } else {
+                Err(ParserError::UnexpectedChar {
+                    expected: Some("} or ,".to_string()),
+                    found: self.lexer.curr_ch(),
+                    row: self.lexer.curr_tok().row,
+                    col: self.lexer.curr_tok().col,
+                })?;
+            }
+        }
+        self.lexer.next_token()?;
+        self.structs.insert(name.clone(), fields.clone());
+        Ok(ExprKind::StructDecl(StructDecl {
+            name,
+            fields,
+            is_pub,
+        })
+        .at(self.span_from(span)))
+    }
+
+    /// Reads the `inline` or `noinline` attribute in front of `fun`, if there is one.
+    fn inline_attr(&mut self) -> Result<Inline, ParserError> {
+        let inline = match self.lexer.curr_tok().token {
+            TokenType::INLINE => Inline::Always,
+            TokenType::NOINLINE => Inline::Never,
+            _ => return Ok(Inline::Auto),
+        };
+        self.lexer.next_token()?;
+        if self.lexer.curr_tok().token != TokenType::FUNCDECL {
+            return Err(ParserError::UnexpectedChar {
+                expected: Some("fun".to_string()),
+                found: self.lexer.curr_ch(),
+                row: self.lexer.curr_tok().row,
+                col: self.lexer.curr_tok().col,
+            });
+        }
+        Ok(inline)
+    }
+
+    fn func_decl(&mut self, is_pub: bool, inline: Inline) -> Result<Expr, ParserError> {
+        let span = self.span();
         self.lexer.next_token()?;
         let name = self.get_ident()?;
         let mut params: Vec<(String, VarType)> = Vec::new();
@@ -884,19 +1283,7 @@ impl<'a> Parser<'a> {
             self.lexer.next_token()?;
             if self.lexer.curr_tok().token == TokenType::COLON {
                 self.lexer.next_token()?;
-                match self.lexer.curr_tok().token {
-                    TokenType::Type(vt) => {
-                        typ = vt;
-                    }
-                    _ => {
-                        return Err(ParserError::UnexpectedChar {
-                            expected: Some("TYPE".to_string()),
-                            found: self.lexer.curr_ch(),
-                            row: self.lexer.curr_tok().row,
-                            col: self.lexer.curr_tok().col,
-                        });
-                    }
-                }
+                typ = self.get_type()?;
             } else {
                 return Err(ParserError::UnexpectedChar {
                     expected: Some(":".to_string()),
@@ -924,19 +1311,7 @@ impl<'a> Parser<'a> {
         let ret_type: VarType;
         if self.lexer.curr_tok().token == TokenType::COLON {
             self.lexer.next_token()?;
-            match self.lexer.curr_tok().token {
-                TokenType::Type(vt) => {
-                    ret_type = vt;
-                }
-                _ => {
-                    return Err(ParserError::UnexpectedChar {
-                        expected: Some("TYPE".to_string()),
-                        found: self.lexer.curr_ch(),
-                        row: self.lexer.curr_tok().row,
-                        col: self.lexer.curr_tok().col,
-                    });
-                }
-            }
+            ret_type = self.get_type()?;
         } else {
             return Err(ParserError::UnexpectedChar {
                 expected: Some(":".to_string()),
@@ -945,26 +1320,101 @@ impl<'a> Parser<'a> {
                 col: self.lexer.curr_tok().col,
             });
         }
-        self.functions.insert(name.clone(), ret_type.clone());
         self.lexer.next_token()?;
         let body = self.expr()?;
-        Ok(Expr::FuncDecl(FuncDecl {
+        Ok(ExprKind::FuncDecl(FuncDecl {
             name,
             params,
             body: Box::new(body),
             ret_type,
             is_pub,
-        }))
+            inline,
+        })
+        .at(self.span_from(span)))
     }
+}
 
-    fn find_func_ret_type(&self, name: &String) -> Result<VarType, ParserError> {
-        self.functions
-            .get(name)
-            .ok_or_else(|| ParserError::SyntaxError {
-                message: format!("undefined function: '{}'", name),
-                row: 0,
-                col: 0,
+/// Builds `left op right`, or its value when both sides are literals.
+fn binop(left: Expr, right: Expr, operator: TokenType) -> Expr {
+    let span = Span {
+        end: right.span.end,
+        ..left.span
+    };
+    fold(&operator, &left, &right)
+        .unwrap_or_else(|| {
+            ExprKind::BinOp(BinOp {
+                left: Box::new(left),
+                right: Box::new(right),
+                operator,
             })
-            .map(|t| t.to_owned())
+        })
+        .at(span)
+}
+
+/// Evaluates an operation on two literals the way the generated code would.
+fn fold(op: &TokenType, left: &Expr, right: &Expr) -> Option<ExprKind> {
+    let (ExprKind::Val(l), ExprKind::Val(r)) = (&left.kind, &right.kind) else {
+        return None;
+    };
+    let value = match (&l.value, &r.value) {
+        (&Literal::Int(n), &Literal::Int(m)) => match op {
+            TokenType::ADD => Literal::Int(n.wrapping_add(m)),
+            TokenType::SUB => Literal::Int(n.wrapping_sub(m)),
+            TokenType::MUL => Literal::Int(n.wrapping_mul(m)),
+            TokenType::DIV => Literal::Int(n.checked_div(m)?),
+            TokenType::MOD => Literal::Int(n.checked_rem(m)?),
+            // Like `sal` and `sar`, only the low six bits of the count are used.
+            TokenType::SHL => Literal::Int(n.wrapping_shl(m as u32)),
+            TokenType::SHR => Literal::Int(n.wrapping_shr(m as u32)),
+            TokenType::LOGAND => Literal::Int(n & m),
+            TokenType::LOGOR => Literal::Int(n | m),
+            TokenType::LOGXOR => Literal::Int(n ^ m),
+            TokenType::COMPEQ => Literal::Bool(n == m),
+            TokenType::COMPNE => Literal::Bool(n != m),
+            TokenType::COMPGT => Literal::Bool(n > m),
+            TokenType::COMPGE => Literal::Bool(n >= m),
+            TokenType::COMPLT => Literal::Bool(n < m),
+            TokenType::COMPLE => Literal::Bool(n <= m),
+            _ => return None,
+        },
+        (&Literal::Bool(n), &Literal::Bool(m)) => match op {
+            TokenType::LOGAND => Literal::Bool(n & m),
+            TokenType::LOGOR => Literal::Bool(n | m),
+            TokenType::LOGXOR => Literal::Bool(n ^ m),
+            TokenType::COMPAND => Literal::Bool(n && m),
+            TokenType::COMPOR => Literal::Bool(n || m),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let typ = match value {
+        Literal::Bool(_) => VarType::Bool,
+        _ => VarType::Int,
+    };
+    Some(ExprKind::Val(Val { value, typ }))
+}
+
+/// Dividing by a literal zero would only trap at run time.
+fn check_divisor(
+    op: &TokenType,
+    divisor: &Expr,
+    row: usize,
+    col: usize,
+) -> Result<(), ParserError> {
+    let name = match op {
+        TokenType::DIV | TokenType::DIVEQ => "division",
+        TokenType::MOD | TokenType::MODEQ => "remainder",
+        _ => return Ok(()),
+    };
+    match divisor.kind {
+        ExprKind::Val(Val {
+            value: Literal::Int(0),
+            ..
+        }) => Err(ParserError::SyntaxError {
+            message: format!("{} by zero", name),
+            row,
+            col,
+        }),
+        _ => Ok(()),
     }
 }
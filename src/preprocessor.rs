@@ -1,9 +1,15 @@
-use std::{collections::HashMap, fs, iter::Peekable, str::Chars};
+use std::{collections::HashMap, fs, iter::Peekable, mem::take, str::Chars};
+
+use crate::{
+    ast::{Pos, Span},
+    diagnostic::Diagnostic,
+};
 
 #[derive(Debug, Clone)]
 pub enum PreprocessorError {
     ImportError {
         file: String,
+        tried: Vec<String>,
         row: usize,
         col: usize,
     },
@@ -22,58 +28,510 @@ pub enum PreprocessorError {
         row: usize,
         col: usize,
     },
+    MacroError {
+        message: String,
+        row: usize,
+        col: usize,
+    },
 }
 
 impl std::error::Error for PreprocessorError {}
 
+impl PreprocessorError {
+    pub fn pos(&self) -> (usize, usize) {
+        match self {
+            PreprocessorError::ImportError { row, col, .. }
+            | PreprocessorError::IoError { row, col, .. }
+            | PreprocessorError::UnexpectedEndOfFile { row, col, .. }
+            | PreprocessorError::ConditionError { row, col, .. }
+            | PreprocessorError::MacroError { row, col, .. } => (*row, *col),
+        }
+    }
+
+    /// Points at the error in `file`, the number of `error_file()` in a `SourceMap`.
+    pub fn diagnostic(&self, file: usize) -> Diagnostic {
+        // Columns count from 0 here.
+        let (row, col) = self.pos();
+        let pos = Pos { row, col: col + 1 };
+        let span = Span {
+            file,
+            start: pos,
+            end: pos,
+        };
+        let mut diagnostic = Diagnostic::error(self.to_string()).label(span, "");
+        if let PreprocessorError::ImportError { tried, .. } = self {
+            for path in tried {
+                diagnostic = diagnostic.note(format!("tried {}", path));
+            }
+        }
+        diagnostic
+    }
+}
+
 impl std::fmt::Display for PreprocessorError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            PreprocessorError::ImportError { file, row, col } => {
-                write!(
-                    f,
-                    "Import error at {}:{}: cannot import '{}'",
-                    row, col, file
-                )
+            PreprocessorError::ImportError { file, .. } => {
+                write!(f, "Import error: cannot import '{}'", file)
+            }
+            PreprocessorError::IoError { message, .. } => write!(f, "IO error: {}", message),
+            PreprocessorError::UnexpectedEndOfFile { expected, .. } => {
+                write!(f, "Unexpected end of file: expected {}", expected)
+            }
+            PreprocessorError::ConditionError { message, .. } => {
+                write!(f, "Condition error: {}", message)
+            }
+            PreprocessorError::MacroError { message, .. } => write!(f, "Macro error: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Option<Vec<String>>,
+    body: String,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the index just past the string or char literal opening at `start`.
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() && chars[i] != quote {
+        if chars[i] == '\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    (i + 1).min(chars.len())
+}
+
+/// Returns the index of the first character after the identifier starting at `start`.
+fn skip_ident(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() && is_ident_char(chars[i]) {
+        i += 1;
+    }
+    i
+}
+
+/// Splits the argument list whose `(` is at `open`. Commas only separate arguments
+/// at the outermost level, outside of any brackets or literals. Returns the raw
+/// arguments and the index just past the closing `)`.
+fn split_args(chars: &[char], open: usize) -> Option<(Vec<String>, usize)> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut depth = 0;
+    let mut i = open + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' | '\'' => {
+                let end = skip_quoted(chars, i);
+                arg.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' if depth == 0 => {
+                args.push(arg);
+                return Some((args, i + 1));
+            }
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(take(&mut arg));
+                i += 1;
+                continue;
             }
-            PreprocessorError::IoError { message, row, col } => {
-                write!(f, "IO error at {}:{}: {}", row, col, message)
+            _ => {}
+        }
+        arg.push(chars[i]);
+        i += 1;
+    }
+    None
+}
+
+/// Drops a trailing `#` comment from a macro body. In a function-like macro, `#`
+/// directly followed by a parameter name is the stringification operator instead.
+fn strip_comment(body: &str, params: &[String]) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '"' | '\'' => {
+                i = skip_quoted(&chars, i);
+                continue;
             }
-            PreprocessorError::UnexpectedEndOfFile { expected, row, col } => {
-                write!(
-                    f,
-                    "Unexpected end of file at {}:{}: expected {}",
-                    row, col, expected
-                )
+            '#' => {
+                let end = skip_ident(&chars, i + 1);
+                let name: String = chars[i + 1..end].iter().collect();
+                if !params.contains(&name) {
+                    return chars[..i].iter().collect::<String>().trim().to_string();
+                }
+                i = end;
+                continue;
             }
-            PreprocessorError::ConditionError { message, row, col } => {
-                write!(f, "Condition error at {}:{}: {}", row, col, message)
+            _ => {}
+        }
+        i += 1;
+    }
+    body.trim().to_string()
+}
+
+/// Replaces the parameters of a function-like macro in its body: `x` and `$x`
+/// become the expanded argument, `#x` becomes the raw argument as a string literal.
+fn substitute(body: &str, params: &[String], raw: &[String], expanded: &[String]) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' || c == '\'' {
+            let end = skip_quoted(&chars, i);
+            out.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+        let sigil = (c == '#' || c == '$') && i + 1 < chars.len() && is_ident_start(chars[i + 1]);
+        if sigil || is_ident_start(c) {
+            let start = if sigil { i + 1 } else { i };
+            let end = skip_ident(&chars, start);
+            let name: String = chars[start..end].iter().collect();
+            match params.iter().position(|p| *p == name) {
+                Some(n) if c == '#' => {
+                    out.push('"');
+                    out.push_str(&raw[n].replace('\\', "\\\\").replace('"', "\\\""));
+                    out.push('"');
+                }
+                Some(n) => out.push_str(&expanded[n]),
+                None => out.extend(&chars[i..end]),
             }
+            i = end;
+            continue;
         }
+        out.push(c);
+        i += 1;
     }
+    out
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CondValue {
+    Int(i64),
+    Float(f64),
+}
+
+impl CondValue {
+    fn as_float(self) -> f64 {
+        match self {
+            CondValue::Int(n) => n as f64,
+            CondValue::Float(f) => f,
+        }
+    }
+
+    fn truthy(self) -> bool {
+        match self {
+            CondValue::Int(n) => n != 0,
+            CondValue::Float(f) => f != 0.0,
+        }
+    }
+}
+
+impl From<bool> for CondValue {
+    fn from(b: bool) -> Self {
+        CondValue::Int(b as i64)
+    }
+}
+
+/// Evaluates the expression of an `$if` or `$elif` once macros have been expanded.
+/// Identifiers that are still left at this point are not defined and count as 0.
+struct CondEval {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl CondEval {
+    fn eval(text: &str) -> Result<bool, String> {
+        let mut eval = CondEval {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        if eval.peek().is_none() {
+            return Err("expected an expression".to_string());
+        }
+        let value = eval.logical_or()?;
+        match eval.peek() {
+            None => Ok(value.truthy()),
+            Some(c) => Err(format!("unexpected '{}' in expression", c)),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        self.peek();
+        let end = self.pos + op.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(op.chars()) {
+            self.pos = end;
+            return true;
+        }
+        false
+    }
+
+    fn logical_or(&mut self) -> Result<CondValue, String> {
+        let mut left = self.logical_and()?;
+        while self.eat("||") {
+            let right = self.logical_and()?;
+            left = (left.truthy() || right.truthy()).into();
+        }
+        Ok(left)
+    }
+
+    fn logical_and(&mut self) -> Result<CondValue, String> {
+        let mut left = self.equality()?;
+        while self.eat("&&") {
+            let right = self.equality()?;
+            left = (left.truthy() && right.truthy()).into();
+        }
+        Ok(left)
+    }
+
+    fn equality(&mut self) -> Result<CondValue, String> {
+        let mut left = self.comparison()?;
+        loop {
+            let eq = if self.eat("==") {
+                true
+            } else if self.eat("!=") {
+                false
+            } else {
+                return Ok(left);
+            };
+            let right = self.comparison()?;
+            let same = match (left, right) {
+                (CondValue::Int(a), CondValue::Int(b)) => a == b,
+                _ => left.as_float() == right.as_float(),
+            };
+            left = (same == eq).into();
+        }
+    }
+
+    fn comparison(&mut self) -> Result<CondValue, String> {
+        let mut left = self.additive()?;
+        loop {
+            let op = ["<=", ">=", "<", ">"].into_iter().find(|op| self.eat(op));
+            let Some(op) = op else {
+                return Ok(left);
+            };
+            let right = self.additive()?;
+            let ord = match (left, right) {
+                (CondValue::Int(a), CondValue::Int(b)) => a.partial_cmp(&b),
+                _ => left.as_float().partial_cmp(&right.as_float()),
+            };
+            let result = match (op, ord) {
+                (_, None) => false,
+                ("<=", Some(o)) => o.is_le(),
+                (">=", Some(o)) => o.is_ge(),
+                ("<", Some(o)) => o.is_lt(),
+                (_, Some(o)) => o.is_gt(),
+            };
+            left = result.into();
+        }
+    }
+
+    fn additive(&mut self) -> Result<CondValue, String> {
+        let mut left = self.term()?;
+        loop {
+            let add = match self.peek() {
+                Some('+') => true,
+                Some('-') => false,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.term()?;
+            left = match (left, right) {
+                (CondValue::Int(a), CondValue::Int(b)) if add => CondValue::Int(a.wrapping_add(b)),
+                (CondValue::Int(a), CondValue::Int(b)) => CondValue::Int(a.wrapping_sub(b)),
+                _ if add => CondValue::Float(left.as_float() + right.as_float()),
+                _ => CondValue::Float(left.as_float() - right.as_float()),
+            };
+        }
+    }
+
+    fn term(&mut self) -> Result<CondValue, String> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(c @ ('*' | '/' | '%')) => c,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.unary()?;
+            left = match (left, right) {
+                (CondValue::Int(_), CondValue::Int(0)) if op != '*' => {
+                    return Err("division by zero in expression".to_string());
+                }
+                (CondValue::Int(a), CondValue::Int(b)) => CondValue::Int(match op {
+                    '*' => a.wrapping_mul(b),
+                    '/' => a.wrapping_div(b),
+                    _ => a.wrapping_rem(b),
+                }),
+                _ => {
+                    let (a, b) = (left.as_float(), right.as_float());
+                    CondValue::Float(match op {
+                        '*' => a * b,
+                        '/' => a / b,
+                        _ => a % b,
+                    })
+                }
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<CondValue, String> {
+        if self.eat("!") {
+            return Ok((!self.unary()?.truthy()).into());
+        }
+        if self.eat("-") {
+            return Ok(match self.unary()? {
+                CondValue::Int(n) => CondValue::Int(n.wrapping_neg()),
+                CondValue::Float(f) => CondValue::Float(-f),
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<CondValue, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.logical_or()?;
+                if !self.eat(")") {
+                    return Err("expected ')' in expression".to_string());
+                }
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.pos < self.chars.len()
+                    && (self.chars[self.pos].is_ascii_digit() || self.chars[self.pos] == '.')
+                {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                let value = if text.contains('.') {
+                    text.parse().ok().map(CondValue::Float)
+                } else {
+                    text.parse().ok().map(CondValue::Int)
+                };
+                value.ok_or_else(|| format!("invalid number '{}' in expression", text))
+            }
+            Some(c) if is_ident_start(c) => {
+                self.pos = skip_ident(&self.chars, self.pos);
+                Ok(CondValue::Int(0))
+            }
+            Some(c) => Err(format!("unexpected '{}' in expression", c)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// One level of `$if`/`$ifdef`/`$ifndef` nesting. `taken` is set once any branch of
+/// the chain was included, so that later `$elif`/`$else` branches are skipped.
+struct Conditional {
+    active: bool,
+    taken: bool,
+    has_else: bool,
 }
 
 pub struct Preprocessor<'a> {
     src: Peekable<Chars<'a>>,
     path: String,
+    search_paths: Vec<String>,
     row: usize,
     col: usize,
-    defines: HashMap<String, String>,
-    condition_stack: Vec<bool>,
+    defines: HashMap<String, Macro>,
+    condition_stack: Vec<Conditional>,
     skipping: bool,
+    /// The path and text of every file the output comes from, this one first.
+    files: Vec<(String, String)>,
+    /// For each line of the output, the file and row it was taken from.
+    lines: Vec<(usize, usize)>,
+    /// How much of the output `lines` covers.
+    synced: usize,
+    error_file: usize,
 }
 
 impl<'a> Preprocessor<'a> {
-    pub fn new(src: &'a str, path: String) -> Self {
+    /// `file` is the path of the source, for the line map, and `path` its directory;
+    /// `$import` looks there first, then in `search_paths` in order.
+    pub fn new(src: &'a str, file: String, path: String, search_paths: Vec<String>) -> Self {
         Self {
             src: src.chars().peekable(),
             path,
+            search_paths,
             row: 1,
             col: 0,
             defines: HashMap::new(),
             condition_stack: Vec::new(),
             skipping: false,
+            files: vec![(file, src.to_string())],
+            lines: vec![(0, 1)],
+            synced: 0,
+            error_file: 0,
+        }
+    }
+
+    /// The files the output was pasted together from, as path and text.
+    pub fn files(&self) -> &[(String, String)] {
+        &self.files
+    }
+
+    /// Maps each row of the output, counting from 1, to a file and row in `files`.
+    pub fn lines(&self) -> &[(usize, usize)] {
+        &self.lines
+    }
+
+    /// The file in `files` that the error returned by `preprocess` is about.
+    pub fn error_file(&self) -> usize {
+        self.error_file
+    }
+
+    /// Notes where the lines started since the last call come from. A line break
+    /// is written before the input moves past it, so the next line begins at the
+    /// current row.
+    fn sync(&mut self, output: &str) {
+        for _ in output[self.synced..].matches('\n') {
+            self.lines.push((0, self.row));
         }
+        self.synced = output.len();
+    }
+
+    /// Takes over the files of an imported file, returning their numbers here.
+    fn merge_files(&mut self, child: &Preprocessor) -> Vec<usize> {
+        child
+            .files
+            .iter()
+            .map(|(path, text)| {
+                self.files
+                    .iter()
+                    .position(|(p, _)| p == path)
+                    .unwrap_or_else(|| {
+                        self.files.push((path.clone(), text.clone()));
+                        self.files.len() - 1
+                    })
+            })
+            .collect()
     }
 
     fn current(&mut self) -> char {
@@ -134,24 +592,164 @@ impl<'a> Preprocessor<'a> {
         None
     }
 
-    fn expand_macros(&self, value: &str) -> String {
-        let mut result = value.to_string();
-        let mut changed = true;
+    fn macro_error(&self, message: String) -> PreprocessorError {
+        PreprocessorError::MacroError {
+            message,
+            row: self.row,
+            col: self.col,
+        }
+    }
 
-        while changed {
-            changed = false;
-            for (name, val) in &self.defines {
-                let pattern = format!("${}", name);
-                if result.contains(&pattern) {
-                    result = result.replace(&pattern, val);
-                    changed = true;
-                }
+    /// Expands every macro use in `text`, rescanning the results. `active` holds the
+    /// macros currently being expanded, so that a macro reaching itself again is
+    /// reported instead of expanding forever.
+    fn expand(&self, text: &str, active: &mut Vec<String>) -> Result<String, PreprocessorError> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '"' || c == '\'' {
+                let end = skip_quoted(&chars, i);
+                out.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            let sigil = c == '$' && i + 1 < chars.len() && is_ident_start(chars[i + 1]);
+            if !sigil && !is_ident_start(c) {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+            let start = if sigil { i + 1 } else { i };
+            let end = skip_ident(&chars, start);
+            let name: String = chars[start..end].iter().collect();
+            let Some(mac) = self.defines.get(&name) else {
+                out.extend(&chars[i..end]);
+                i = end;
+                continue;
+            };
+            if mac.params.is_none() {
+                out.push_str(&self.expand_macro(&name, Vec::new(), active)?);
+                i = end;
+                continue;
+            }
+            let mut open = end;
+            while open < chars.len() && (chars[open] == ' ' || chars[open] == '\t') {
+                open += 1;
+            }
+            if open < chars.len() && chars[open] == '(' {
+                let (args, close) = split_args(&chars, open).ok_or_else(|| {
+                    self.macro_error(format!("unterminated arguments to macro '{}'", name))
+                })?;
+                out.push_str(&self.expand_macro(&name, args, active)?);
+                i = close;
+            } else {
+                out.extend(&chars[i..end]);
+                i = end;
             }
         }
+        Ok(out)
+    }
 
+    fn expand_macro(
+        &self,
+        name: &str,
+        args: Vec<String>,
+        active: &mut Vec<String>,
+    ) -> Result<String, PreprocessorError> {
+        if active.iter().any(|a| a == name) {
+            return Err(self.macro_error(format!("recursive expansion of macro '{}'", name)));
+        }
+        let mac = self.defines[name].clone();
+        let body = match mac.params {
+            None => mac.body,
+            Some(params) => {
+                let raw: Vec<String> = args.iter().map(|a| a.trim().to_string()).collect();
+                let raw = if params.is_empty() && raw.len() == 1 && raw[0].is_empty() {
+                    Vec::new()
+                } else {
+                    raw
+                };
+                if raw.len() != params.len() {
+                    return Err(self.macro_error(format!(
+                        "macro '{}' expects {} arguments, got {}",
+                        name,
+                        params.len(),
+                        raw.len()
+                    )));
+                }
+                let mut expanded = Vec::new();
+                for arg in &raw {
+                    expanded.push(self.expand(arg, active)?);
+                }
+                substitute(&mac.body, &params, &raw, &expanded)
+            }
+        };
+        active.push(name.to_string());
+        let result = self.expand(&body, active);
+        active.pop();
         result
     }
 
+    /// Expands a macro used directly in the source, reading the arguments of a
+    /// function-like macro from the input. `text` is what was consumed for the name,
+    /// and is given back unchanged if a function-like macro is not being called.
+    fn expand_source_macro(&mut self, name: &str, text: &str) -> Result<String, PreprocessorError> {
+        let mut args = Vec::new();
+        if self.defines[name].params.is_some() {
+            let mut gap = String::new();
+            while self.current() == ' ' || self.current() == '\t' {
+                gap.push(self.current());
+                self.bump();
+            }
+            if self.current() != '(' {
+                return Ok(format!("{}{}", text, gap));
+            }
+            let mut call = String::new();
+            let mut depth = 0;
+            loop {
+                let c = self.current();
+                match c {
+                    '\0' => {
+                        return Err(PreprocessorError::UnexpectedEndOfFile {
+                            expected: format!("')' to close arguments to macro '{}'", name),
+                            row: self.row,
+                            col: self.col,
+                        });
+                    }
+                    '"' | '\'' => {
+                        call.push(c);
+                        self.bump();
+                        while self.current() != c && self.current() != '\0' {
+                            if self.current() == '\\' {
+                                call.push(self.current());
+                                self.bump();
+                            }
+                            call.push(self.current());
+                            self.bump();
+                        }
+                    }
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth -= 1,
+                    _ => {}
+                }
+                call.push(self.current());
+                self.bump();
+                if depth == 0 {
+                    break;
+                }
+            }
+            let chars: Vec<char> = call.chars().collect();
+            args = split_args(&chars, 0)
+                .ok_or_else(|| {
+                    self.macro_error(format!("unterminated arguments to macro '{}'", name))
+                })?
+                .0;
+        }
+        self.expand_macro(name, args, &mut Vec::new())
+    }
+
     fn check_condition(&mut self, negated: bool) -> bool {
         self.skip_spaces();
         let ident = self.parse_ident();
@@ -160,11 +758,138 @@ impl<'a> Preprocessor<'a> {
         if negated { !defined } else { defined }
     }
 
+    fn condition_error(&self, message: String, row: usize, col: usize) -> PreprocessorError {
+        PreprocessorError::ConditionError { message, row, col }
+    }
+
+    /// Evaluates the rest of the line as the expression of an `$if` or `$elif`.
+    /// `defined(NAME)` and `defined NAME` are resolved before macros are expanded.
+    fn eval_condition(&mut self) -> Result<bool, PreprocessorError> {
+        let (row, col) = (self.row, self.col);
+        let mut line = String::new();
+        while self.current() != '\n' && self.current() != '\0' {
+            line.push(self.current());
+            self.bump();
+        }
+
+        let chars: Vec<char> = strip_comment(&line, &[]).chars().collect();
+        let mut text = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if !is_ident_start(chars[i]) {
+                text.push(chars[i]);
+                i += 1;
+                continue;
+            }
+            let end = skip_ident(&chars, i);
+            let ident: String = chars[i..end].iter().collect();
+            i = end;
+            if ident != "defined" {
+                text.push_str(&ident);
+                continue;
+            }
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            let parens = i < chars.len() && chars[i] == '(';
+            if parens {
+                i += 1;
+                while i < chars.len() && chars[i].is_whitespace() {
+                    i += 1;
+                }
+            }
+            let end = skip_ident(&chars, i);
+            let name: String = chars[i..end].iter().collect();
+            i = end;
+            if parens {
+                while i < chars.len() && chars[i].is_whitespace() {
+                    i += 1;
+                }
+                if i >= chars.len() || chars[i] != ')' {
+                    return Err(self.condition_error(
+                        "expected ')' after 'defined'".to_string(),
+                        row,
+                        col,
+                    ));
+                }
+                i += 1;
+            }
+            if name.is_empty() {
+                return Err(self.condition_error(
+                    "expected macro name after 'defined'".to_string(),
+                    row,
+                    col,
+                ));
+            }
+            text.push_str(if self.defines.contains_key(&name) {
+                " 1 "
+            } else {
+                " 0 "
+            });
+        }
+
+        let expanded = self.expand(&text, &mut Vec::new())?;
+        CondEval::eval(&expanded).map_err(|message| self.condition_error(message, row, col))
+    }
+
+    /// Handles the conditional directives. These are also seen while skipping, so
+    /// that nesting is tracked, but nothing is evaluated inside a skipped block.
+    fn conditional(&mut self, cmd: &str) -> Result<(), PreprocessorError> {
+        let (row, col) = (self.row, self.col);
+        let outer = self.condition_stack.iter().all(|c| c.active);
+        match cmd {
+            "ifdef" | "ifndef" | "if" => {
+                let active = if !outer {
+                    self.skip_until_newline();
+                    false
+                } else if cmd == "if" {
+                    self.eval_condition()?
+                } else {
+                    self.check_condition(cmd == "ifndef")
+                };
+                self.condition_stack.push(Conditional {
+                    active,
+                    taken: active || !outer,
+                    has_else: false,
+                });
+            }
+            "elif" | "else" => {
+                let Some(top) = self.condition_stack.last() else {
+                    return Err(self.condition_error(format!("Unexpected ${}", cmd), row, col));
+                };
+                if top.has_else {
+                    return Err(self.condition_error(format!("${} after $else", cmd), row, col));
+                }
+                let taken = top.taken;
+                let active = if taken {
+                    self.skip_until_newline();
+                    false
+                } else if cmd == "elif" {
+                    self.eval_condition()?
+                } else {
+                    true
+                };
+                let top = self.condition_stack.last_mut().unwrap();
+                top.active = active;
+                top.taken |= active;
+                top.has_else = cmd == "else";
+            }
+            _ => {
+                if self.condition_stack.pop().is_none() {
+                    return Err(self.condition_error("Unexpected $endif".to_string(), row, col));
+                }
+            }
+        }
+        self.skipping = !self.condition_stack.iter().all(|c| c.active);
+        Ok(())
+    }
+
     pub fn preprocess(&mut self) -> Result<String, PreprocessorError> {
         let mut output = String::new();
         let mut in_comment = false;
 
         while self.current() != '\0' {
+            self.sync(&output);
             if self.current() == '#' {
                 while self.current() != '\n' && self.current() != '\0' {
                     self.bump();
@@ -178,23 +903,8 @@ impl<'a> Preprocessor<'a> {
                     let cmd = self.parse_ident();
 
                     match cmd.as_str() {
-                        "ifdef" | "ifndef" => {
-                            self.skip_spaces();
-                            self.parse_ident();
-
-                            self.condition_stack.push(false);
-                        }
-                        "endif" => {
-                            if let Some(_) = self.condition_stack.pop() {
-                                self.skipping = !self.condition_stack.is_empty()
-                                    && self.condition_stack.last().map(|&s| !s).unwrap_or(false);
-                            } else {
-                                return Err(PreprocessorError::ConditionError {
-                                    message: "Unexpected $endif".to_string(),
-                                    row: self.row,
-                                    col: self.col,
-                                });
-                            }
+                        "ifdef" | "ifndef" | "if" | "elif" | "else" | "endif" => {
+                            self.conditional(&cmd)?;
                         }
                         _ => {
                             self.skip_until_newline();
@@ -214,6 +924,43 @@ impl<'a> Preprocessor<'a> {
                     "define" => {
                         self.skip_spaces();
                         let name = self.parse_ident();
+                        if name.is_empty() {
+                            return Err(self.macro_error("expected macro name".to_string()));
+                        }
+
+                        // Only a `(` directly after the name makes a function-like macro.
+                        let mut params = None;
+                        if self.current() == '(' {
+                            self.bump();
+                            let mut names = Vec::new();
+                            loop {
+                                self.skip_spaces();
+                                if self.current() == ')' && names.is_empty() {
+                                    break;
+                                }
+                                let param = self.parse_ident();
+                                if param.is_empty() || names.contains(&param) {
+                                    return Err(self.macro_error(format!(
+                                        "invalid parameter list for macro '{}'",
+                                        name
+                                    )));
+                                }
+                                names.push(param);
+                                self.skip_spaces();
+                                match self.current() {
+                                    ',' => self.bump(),
+                                    ')' => break,
+                                    _ => {
+                                        return Err(self.macro_error(format!(
+                                            "expected ',' or ')' in parameters of macro '{}'",
+                                            name
+                                        )));
+                                    }
+                                }
+                            }
+                            self.bump();
+                            params = Some(names);
+                        }
                         self.skip_spaces();
 
                         let mut value = String::new();
@@ -222,32 +969,16 @@ impl<'a> Preprocessor<'a> {
                             self.bump();
                         }
 
-                        let value = value.trim().to_string();
-
-                        let expanded_value = self.expand_macros(&value);
-                        self.defines.insert(name, expanded_value);
+                        let body = strip_comment(&value, params.as_deref().unwrap_or(&[]));
+                        self.defines.insert(name, Macro { params, body });
                     }
-                    "ifdef" => {
-                        let condition_met = self.check_condition(false);
-                        self.condition_stack.push(condition_met);
-                        self.skipping = !condition_met;
+                    "undef" => {
+                        self.skip_spaces();
+                        let name = self.parse_ident();
+                        self.defines.remove(&name);
                     }
-                    "ifndef" => {
-                        let condition_met = self.check_condition(true);
-                        self.condition_stack.push(condition_met);
-                        self.skipping = !condition_met;
-                    }
-                    "endif" => {
-                        if let Some(_) = self.condition_stack.pop() {
-                            self.skipping = !self.condition_stack.is_empty()
-                                && self.condition_stack.last().map(|&s| !s).unwrap_or(false);
-                        } else {
-                            return Err(PreprocessorError::ConditionError {
-                                message: "Unexpected $endif".to_string(),
-                                row: self.row,
-                                col: self.col,
-                            });
-                        }
+                    "ifdef" | "ifndef" | "if" | "elif" | "else" | "endif" => {
+                        self.conditional(&cmd)?;
                     }
                     "import" => {
                         let file_name =
@@ -257,54 +988,68 @@ impl<'a> Preprocessor<'a> {
                                 col: self.col,
                             })?;
 
-                        let paths_to_try = [
-                            format!(
-                                "{}/{}",
-                                if self.path.is_empty() {
-                                    "."
-                                } else {
-                                    &self.path
-                                },
-                                file_name
-                            ),
-                            format!(
-                                "{}/{}.al",
-                                if self.path.is_empty() {
-                                    "."
-                                } else {
-                                    &self.path
-                                },
-                                file_name
-                            ),
-                            format!("/usr/local/alum/{}", file_name),
-                            format!("/usr/local/alum/{}.al", file_name),
-                        ];
+                        let dir = if self.path.is_empty() {
+                            "."
+                        } else {
+                            &self.path
+                        };
+                        let paths_to_try: Vec<String> = std::iter::once(dir)
+                            .chain(self.search_paths.iter().map(String::as_str))
+                            .flat_map(|dir| {
+                                [
+                                    format!("{}/{}", dir, file_name),
+                                    format!("{}/{}.al", dir, file_name),
+                                ]
+                            })
+                            .collect();
 
                         let mut raw_content = None;
                         for p in &paths_to_try {
                             if let Ok(c) = fs::read_to_string(p) {
-                                raw_content = Some(c);
+                                raw_content = Some((p.clone(), c));
                                 break;
                             }
                         }
 
-                        if let Some(content) = raw_content {
-                            let mut child_pp = Preprocessor::new(&content, self.path.clone());
+                        if let Some((file, content)) = raw_content {
+                            let mut child_pp = Preprocessor::new(
+                                &content,
+                                file,
+                                self.path.clone(),
+                                self.search_paths.clone(),
+                            );
                             child_pp.defines = self.defines.clone();
-                            let processed_sub = child_pp.preprocess()?;
+                            let processed = child_pp.preprocess();
+                            let files = self.merge_files(&child_pp);
+                            let processed_sub = processed.inspect_err(|_| {
+                                self.error_file = files[child_pp.error_file];
+                            })?;
+                            // The imported text goes on the line of the `$import`,
+                            // which then comes from the imported file if it was empty.
+                            let mut lines = child_pp.lines.iter();
+                            if output.is_empty() || output.ends_with('\n') {
+                                self.lines.pop();
+                            } else {
+                                lines.next();
+                            }
+                            self.lines
+                                .extend(lines.map(|&(file, row)| (files[file], row)));
                             output.push_str(&processed_sub);
+                            self.synced = output.len();
                             self.defines = child_pp.defines;
                         } else {
                             return Err(PreprocessorError::ImportError {
                                 file: file_name,
+                                tried: paths_to_try,
                                 row: self.row,
                                 col: self.col,
                             });
                         }
                     }
                     _ => {
-                        if let Some(val) = self.defines.get(&cmd) {
-                            output.push_str(val);
+                        if self.defines.contains_key(&cmd) {
+                            let expanded = self.expand_source_macro(&cmd, &format!("${}", cmd))?;
+                            output.push_str(&expanded);
                         } else {
                             output.push('$');
                             output.push_str(&cmd);
@@ -312,12 +1057,29 @@ impl<'a> Preprocessor<'a> {
                     }
                 }
             } else {
-                if self.current().is_ascii_alphabetic() || self.current() == '_' {
+                if self.current() == '"' || self.current() == '\'' {
+                    let quote = self.current();
+                    output.push(quote);
+                    self.bump();
+                    while self.current() != quote && self.current() != '\0' {
+                        if self.current() == '\\' {
+                            output.push(self.current());
+                            self.bump();
+                        }
+                        output.push(self.current());
+                        self.bump();
+                    }
+                    if self.current() == quote {
+                        output.push(quote);
+                        self.bump();
+                    }
+                } else if self.current().is_ascii_alphabetic() || self.current() == '_' {
                     let start_col = self.col;
                     let ident = self.parse_ident();
 
-                    if let Some(val) = self.defines.get(&ident) {
-                        output.push_str(val);
+                    if self.defines.contains_key(&ident) {
+                        let expanded = self.expand_source_macro(&ident, &ident)?;
+                        output.push_str(&expanded);
                     } else {
                         output.push_str(&ident);
                     }
@@ -327,10 +1089,11 @@ impl<'a> Preprocessor<'a> {
                 }
             }
         }
+        self.sync(&output);
 
         if !self.condition_stack.is_empty() {
             return Err(PreprocessorError::ConditionError {
-                message: "Unclosed $ifdef or $ifndef".to_string(),
+                message: "Unclosed $if, $ifdef or $ifndef".to_string(),
                 row: self.row,
                 col: self.col,
             });
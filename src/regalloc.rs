@@ -0,0 +1,333 @@
+use crate::ir::{IRConst, IRFunction, IRType, Instruction, Op, Operand};
+use std::collections::{HashMap, HashSet};
+
+/// Registers that keep their value across a call. A function saves the ones it uses
+/// in its prologue, so they suit values that are live across calls.
+pub const CALLEE_SAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+/// Registers a call may clobber. They cost nothing to use, but values live across a
+/// call have to be pushed around it. `rax`, `rdx`, `r10` and `r11` are left out, as
+/// codegen uses them as scratch registers.
+pub const CALLER_SAVED: [&str; 5] = ["rcx", "rsi", "rdi", "r8", "r9"];
+
+/// Name under which a variable or temporary is allocated, the same for every use.
+pub fn key(op: &Operand) -> Option<String> {
+    match op {
+        Operand::Var(name) => Some(name.clone()),
+        Operand::Temp(id, _) => Some(format!("_tmp_{}", id)),
+        _ => None,
+    }
+}
+
+fn is_call(op: &Op) -> bool {
+    matches!(op, Op::Call | Op::Range)
+}
+
+/// Where each variable and temporary of a function lives. Those missing from `homes`
+/// stay in their stack slot.
+#[derive(Debug, Default)]
+pub struct Allocation {
+    pub homes: HashMap<String, &'static str>,
+    /// Caller-saved registers holding a value that is still needed after the call at
+    /// the given instruction.
+    pub saved: HashMap<usize, Vec<&'static str>>,
+}
+
+impl Allocation {
+    /// Callee-saved registers the function uses, which it has to preserve.
+    pub fn callee_saved(&self) -> Vec<&'static str> {
+        CALLEE_SAVED
+            .into_iter()
+            .filter(|reg| self.homes.values().any(|home| home == reg))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Interval {
+    key: String,
+    start: usize,
+    end: usize,
+    crosses_call: bool,
+}
+
+/// Returns the operands read and the operand written by an instruction. Array,
+/// field and struct copy stores write through their `dst` rather than to it.
+fn uses_defs<'a>(
+    inst: &'a Instruction,
+    constants: &'a [IRConst],
+) -> (Vec<&'a Operand>, Option<&'a Operand>) {
+    let mut uses = Vec::new();
+    let push = |op: &'a Operand, uses: &mut Vec<&'a Operand>| {
+        uses.push(op);
+        let elements = match op {
+            Operand::ConstIdx(idx) => match &constants[*idx] {
+                IRConst::Array(_, elements) => Some(elements),
+                _ => None,
+            },
+            Operand::Const(IRConst::Array(_, elements)) => Some(elements),
+            _ => None,
+        };
+        uses.extend(elements.into_iter().flatten());
+    };
+    for op in [&inst.src1, &inst.src2].into_iter().flatten() {
+        push(op, &mut uses);
+    }
+    match (&inst.op, &inst.dst) {
+        (Op::ArrayAssign | Op::ByteStore | Op::FieldStore(_) | Op::StructCopy(_), Some(dst)) => {
+            push(dst, &mut uses);
+            (uses, None)
+        }
+        (_, dst) => (uses, dst.as_ref()),
+    }
+}
+
+/// Variables and temporaries that hold floats. They go through the XMM registers
+/// and always stay on the stack.
+fn floats(func: &IRFunction) -> HashSet<String> {
+    let mut floats: HashSet<String> = func
+        .params
+        .iter()
+        .filter(|(_, ty)| *ty == IRType::Float)
+        .filter_map(|(param, _)| key(param))
+        .collect();
+    for inst in &func.instructions {
+        let is_float = match &inst.op {
+            Op::FAdd
+            | Op::FSub
+            | Op::FMul
+            | Op::FDiv
+            | Op::FNeg
+            | Op::FMove
+            | Op::FLoad
+            | Op::FStore
+            | Op::FArg(_) => true,
+            Op::Return(reg) => reg == "xmm0",
+            _ => false,
+        };
+        let is_compare = matches!(
+            inst.op,
+            Op::FEq | Op::FNe | Op::FGt | Op::FGe | Op::FLt | Op::FLe
+        );
+        for (i, op) in [&inst.dst, &inst.src1, &inst.src2].into_iter().enumerate() {
+            let Some(op) = op else { continue };
+            // Float comparisons produce an integer.
+            if is_float || (is_compare && i > 0) || matches!(op, Operand::Temp(_, IRType::Float)) {
+                floats.extend(key(op));
+            }
+        }
+    }
+    floats
+}
+
+/// Position at which instruction `i` reads its operands. It writes its result just
+/// after, at `use_pos(i) + 1`, and parameters are written at 0.
+fn use_pos(i: usize) -> usize {
+    2 * i + 1
+}
+
+/// Computes a live interval for every variable and temporary: the range of
+/// positions, in program order, over which its value may still be needed.
+fn intervals(func: &IRFunction, constants: &[IRConst]) -> Vec<Interval> {
+    let insts = &func.instructions;
+    if insts.is_empty() {
+        return Vec::new();
+    }
+
+    // Arguments are loaded into their registers by the call they belong to.
+    let mut use_at: Vec<usize> = (0..insts.len()).collect();
+    let mut call = insts.len();
+    for (i, inst) in insts.iter().enumerate().rev() {
+        match inst.op {
+            Op::Call => call = i,
+            Op::Arg(_) | Op::FArg(_) if call < insts.len() => use_at[i] = call,
+            _ => {}
+        }
+    }
+
+    let mut starts = vec![0];
+    for (i, inst) in insts.iter().enumerate() {
+        match inst.op {
+            Op::Label(_) if i > 0 => starts.push(i),
+            Op::Jump | Op::JumpIfFalse | Op::Return(_) if i + 1 < insts.len() => starts.push(i + 1),
+            _ => {}
+        }
+    }
+    starts.dedup();
+    let blocks: Vec<(usize, usize)> = starts
+        .iter()
+        .enumerate()
+        .map(|(b, &start)| (start, starts.get(b + 1).map_or(insts.len(), |&s| s) - 1))
+        .collect();
+    let block_of: HashMap<&str, usize> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(b, &(start, _))| match &insts[start].op {
+            Op::Label(name) => Some((name.as_str(), b)),
+            _ => None,
+        })
+        .collect();
+    let target = |op: &Option<Operand>| match op {
+        Some(Operand::Label(name)) => block_of.get(name.as_str()).copied(),
+        _ => None,
+    };
+    let succs: Vec<Vec<usize>> = blocks
+        .iter()
+        .enumerate()
+        .map(|(b, &(_, end))| {
+            let next = (b + 1 < blocks.len()).then_some(b + 1);
+            let last = &insts[end];
+            match last.op {
+                Op::Jump => target(&last.src1).into_iter().collect(),
+                Op::JumpIfFalse => target(&last.src2).into_iter().chain(next).collect(),
+                Op::Return(_) => Vec::new(),
+                _ => next.into_iter().collect(),
+            }
+        })
+        .collect();
+
+    let mut used = vec![HashSet::new(); blocks.len()];
+    let mut defined = vec![HashSet::new(); blocks.len()];
+    for (b, &(start, end)) in blocks.iter().enumerate() {
+        for inst in &insts[start..=end] {
+            let (uses, def) = uses_defs(inst, constants);
+            for op in uses.into_iter().filter_map(key) {
+                if !defined[b].contains(&op) {
+                    used[b].insert(op);
+                }
+            }
+            defined[b].extend(def.and_then(key));
+        }
+    }
+    let mut live_in: Vec<HashSet<String>> = used.clone();
+    let mut live_out: Vec<HashSet<String>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..blocks.len()).rev() {
+            let out: HashSet<String> = succs[b]
+                .iter()
+                .flat_map(|&s| live_in[s].iter().cloned())
+                .collect();
+            let mut ins = used[b].clone();
+            ins.extend(out.difference(&defined[b]).cloned());
+            if ins.len() != live_in[b].len() || out.len() != live_out[b].len() {
+                changed = true;
+            }
+            live_in[b] = ins;
+            live_out[b] = out;
+        }
+    }
+
+    let mut ranges: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut extend = |key: String, at: usize| {
+        let range = ranges.entry(key).or_insert((at, at));
+        range.0 = range.0.min(at);
+        range.1 = range.1.max(at);
+    };
+    for key in func.params.iter().filter_map(|(param, _)| key(param)) {
+        extend(key, 0);
+    }
+    for (b, &(start, end)) in blocks.iter().enumerate() {
+        for key in &live_in[b] {
+            extend(key.clone(), use_pos(start));
+        }
+        for key in &live_out[b] {
+            extend(key.clone(), use_pos(end) + 1);
+        }
+        for i in start..=end {
+            let (uses, def) = uses_defs(&insts[i], constants);
+            for key in uses.into_iter().filter_map(key) {
+                extend(key, use_pos(use_at[i]));
+            }
+            if let Some(key) = def.and_then(key) {
+                extend(key, use_pos(i) + 1);
+            }
+        }
+    }
+
+    let calls: Vec<usize> = (0..insts.len())
+        .filter(|&i| is_call(&insts[i].op))
+        .map(use_pos)
+        .collect();
+    let mut intervals: Vec<Interval> = ranges
+        .into_iter()
+        .map(|(key, (start, end))| Interval {
+            key,
+            start,
+            end,
+            crosses_call: calls.iter().any(|&c| start < c && c < end),
+        })
+        .collect();
+    intervals.sort_by(|a, b| (a.start, a.end, &a.key).cmp(&(b.start, b.end, &b.key)));
+    intervals
+}
+
+/// Assigns registers to the integer variables and temporaries of a function with a
+/// linear scan over their live intervals. When every register is taken, the value
+/// needed furthest ahead stays on the stack.
+pub fn allocate(func: &IRFunction, constants: &[IRConst]) -> Allocation {
+    let floats = floats(func);
+    let intervals: Vec<Interval> = intervals(func, constants)
+        .into_iter()
+        .filter(|interval| !floats.contains(&interval.key))
+        .collect();
+
+    let mut homes: HashMap<String, &'static str> = HashMap::new();
+    let mut active: Vec<usize> = Vec::new();
+    for (i, interval) in intervals.iter().enumerate() {
+        active.retain(|&a| intervals[a].end >= interval.start);
+        let taken: Vec<&str> = active
+            .iter()
+            .filter_map(|&a| homes.get(&intervals[a].key).copied())
+            .collect();
+        let (first, second) = if interval.crosses_call {
+            (CALLEE_SAVED, CALLER_SAVED)
+        } else {
+            (CALLER_SAVED, CALLEE_SAVED)
+        };
+        let free = first
+            .into_iter()
+            .chain(second)
+            .find(|reg| !taken.contains(reg));
+        match free {
+            Some(reg) => {
+                homes.insert(interval.key.clone(), reg);
+                active.push(i);
+            }
+            None => {
+                let furthest = active
+                    .iter()
+                    .copied()
+                    .filter(|&a| homes.contains_key(&intervals[a].key))
+                    .max_by_key(|&a| intervals[a].end);
+                if let Some(a) = furthest.filter(|&a| intervals[a].end > interval.end) {
+                    let reg = homes.remove(&intervals[a].key).unwrap();
+                    homes.insert(interval.key.clone(), reg);
+                    active.push(i);
+                }
+            }
+        }
+    }
+
+    let mut saved: HashMap<usize, Vec<&'static str>> = HashMap::new();
+    for (c, _) in func
+        .instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, inst)| is_call(&inst.op))
+    {
+        let at = use_pos(c);
+        let mut regs: Vec<&'static str> = intervals
+            .iter()
+            .filter(|interval| interval.start < at && at < interval.end)
+            .filter_map(|interval| homes.get(&interval.key).copied())
+            .filter(|reg| CALLER_SAVED.contains(reg))
+            .collect();
+        if !regs.is_empty() {
+            regs.sort_by_key(|reg| CALLER_SAVED.iter().position(|r| r == reg));
+            saved.insert(c, regs);
+        }
+    }
+    Allocation { homes, saved }
+}
@@ -0,0 +1,544 @@
+use crate::ir::{IRConst, IRFunction, IRType, Instruction, Op, Operand};
+use crate::regalloc::key;
+use std::collections::{HashMap, HashSet};
+
+/// `dst` takes the value of `args[i]` when control comes from the `i`th predecessor
+/// of its block.
+#[derive(Debug, Clone)]
+pub struct Phi {
+    pub dst: Operand,
+    pub args: Vec<Operand>,
+}
+
+/// A basic block. Only its last instruction may be a jump or a return; without
+/// one, control falls through to the next block.
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub label: Option<String>,
+    pub phis: Vec<Phi>,
+    pub insts: Vec<Instruction>,
+    pub preds: Vec<usize>,
+    pub succs: Vec<usize>,
+}
+
+impl Block {
+    pub fn terminator(&self) -> Option<&Instruction> {
+        self.insts.last().filter(|inst| is_terminator(&inst.op))
+    }
+}
+
+/// A function in SSA form, where every variable and temporary is written once.
+/// Block 0 is an empty entry block in which the parameters are defined.
+#[derive(Debug, Clone)]
+pub struct SsaFunction {
+    pub blocks: Vec<Block>,
+    next_temp: usize,
+}
+
+pub fn is_terminator(op: &Op) -> bool {
+    matches!(op, Op::Jump | Op::JumpIfFalse | Op::Return(_))
+}
+
+/// Array, byte, field and struct copy stores write through their `dst` rather than
+/// to it.
+fn writes_through(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::ArrayAssign | Op::ByteStore | Op::FieldStore(_) | Op::StructCopy(_)
+    )
+}
+
+/// Operands an instruction reads, including the elements of an array literal.
+pub fn uses(inst: &Instruction) -> Vec<&Operand> {
+    let dst = inst.dst.as_ref().filter(|_| writes_through(&inst.op));
+    let mut uses = Vec::new();
+    for op in [inst.src1.as_ref(), inst.src2.as_ref(), dst]
+        .into_iter()
+        .flatten()
+    {
+        match op {
+            Operand::Const(IRConst::Array(_, elements)) => uses.extend(elements.iter()),
+            op => uses.push(op),
+        }
+    }
+    uses
+}
+
+pub fn uses_mut(inst: &mut Instruction) -> Vec<&mut Operand> {
+    let through = writes_through(&inst.op);
+    let dst = inst.dst.as_mut().filter(|_| through);
+    let mut uses = Vec::new();
+    for op in [inst.src1.as_mut(), inst.src2.as_mut(), dst]
+        .into_iter()
+        .flatten()
+    {
+        match op {
+            Operand::Const(IRConst::Array(_, elements)) => uses.extend(elements.iter_mut()),
+            op => uses.push(op),
+        }
+    }
+    uses
+}
+
+/// The variable or temporary an instruction writes, if any.
+pub fn def(inst: &Instruction) -> Option<&Operand> {
+    inst.dst
+        .as_ref()
+        .filter(|dst| !writes_through(&inst.op) && key(dst).is_some())
+}
+
+fn def_mut(inst: &mut Instruction) -> Option<&mut Operand> {
+    let through = writes_through(&inst.op);
+    inst.dst
+        .as_mut()
+        .filter(|dst| !through && key(dst).is_some())
+}
+
+/// One more than the largest temporary `op` mentions, or 0 if there is none.
+pub fn max_temp(op: &Operand) -> usize {
+    match op {
+        Operand::Temp(id, _) => id + 1,
+        Operand::Const(IRConst::Array(_, elements)) => {
+            elements.iter().map(max_temp).max().unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+struct Renamer {
+    next_temp: usize,
+    versions: HashMap<String, usize>,
+    stacks: HashMap<String, Vec<Operand>>,
+}
+
+impl Renamer {
+    /// Gives a definition of `op` a new name, which later uses see until the
+    /// walk leaves the dominator subtree.
+    fn define(&mut self, op: &Operand, pushed: &mut Vec<String>) -> Operand {
+        let key = key(op).unwrap_or_default();
+        let new = match op {
+            Operand::Var(name) => {
+                let version = self.versions.entry(name.clone()).or_insert(0);
+                *version += 1;
+                Operand::Var(format!("{}.{}", name, version))
+            }
+            Operand::Temp(_, ty) => {
+                self.next_temp += 1;
+                Operand::Temp(self.next_temp - 1, ty.clone())
+            }
+            op => op.clone(),
+        };
+        self.stacks
+            .entry(key.clone())
+            .or_default()
+            .push(new.clone());
+        pushed.push(key);
+        new
+    }
+
+    /// The reaching definition of `op`. Parameters, and values that are read
+    /// before any write, keep their original name.
+    fn current(&self, op: &Operand) -> Operand {
+        key(op)
+            .and_then(|key| self.stacks.get(&key))
+            .and_then(|stack| stack.last())
+            .cloned()
+            .unwrap_or_else(|| op.clone())
+    }
+}
+
+impl SsaFunction {
+    /// Splits a function into basic blocks and converts it to SSA form, with phis
+    /// placed on the iterated dominance frontier of the blocks writing a value.
+    pub fn build(func: &IRFunction, constants: &[IRConst]) -> Self {
+        let mut blocks = vec![Block::default()];
+        let mut current = Block::default();
+        let mut next_temp = 0;
+        for inst in &func.instructions {
+            let mut inst = inst.clone();
+            // Integer and array constants are inlined so that passes can look at them
+            // and rename the temporaries in array literals.
+            for op in [&mut inst.src1, &mut inst.src2].into_iter().flatten() {
+                if let Operand::ConstIdx(idx) = op
+                    && let c @ (IRConst::Int(_) | IRConst::Array(..)) = &constants[*idx]
+                {
+                    *op = Operand::Const(c.clone());
+                }
+            }
+            for op in [&inst.dst, &inst.src1, &inst.src2].into_iter().flatten() {
+                next_temp = next_temp.max(max_temp(op));
+            }
+            match &inst.op {
+                Op::Label(name) => {
+                    if current.label.is_some() || !current.insts.is_empty() {
+                        blocks.push(current);
+                    }
+                    current = Block {
+                        label: Some(name.clone()),
+                        ..Block::default()
+                    };
+                }
+                op => {
+                    let ends = is_terminator(op);
+                    current.insts.push(inst);
+                    if ends {
+                        blocks.push(current);
+                        current = Block::default();
+                    }
+                }
+            }
+        }
+        if current.label.is_some() || !current.insts.is_empty() {
+            blocks.push(current);
+        }
+        for (param, _) in &func.params {
+            next_temp = next_temp.max(max_temp(param));
+        }
+
+        let mut ssa = SsaFunction { blocks, next_temp };
+        ssa.link();
+        ssa.remove_unreachable();
+        ssa.rename(func);
+        ssa
+    }
+
+    /// A temporary that no instruction uses yet.
+    pub fn new_temp(&mut self, ty: IRType) -> Operand {
+        self.next_temp += 1;
+        Operand::Temp(self.next_temp - 1, ty)
+    }
+
+    /// Computes the edges of the CFG from the jumps and fall-throughs.
+    fn link(&mut self) {
+        let labels: HashMap<String, usize> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(b, block)| block.label.clone().map(|label| (label, b)))
+            .collect();
+        let target = |op: &Option<Operand>| match op {
+            Some(Operand::Label(name)) => labels.get(name).copied(),
+            _ => None,
+        };
+        let count = self.blocks.len();
+        for b in 0..count {
+            let next = (b + 1 < count).then_some(b + 1);
+            let mut succs: Vec<usize> = match self.blocks[b].terminator() {
+                Some(inst) if inst.op == Op::Jump => target(&inst.src1).into_iter().collect(),
+                Some(inst) if inst.op == Op::JumpIfFalse => {
+                    target(&inst.src2).into_iter().chain(next).collect()
+                }
+                Some(_) => Vec::new(),
+                None => next.into_iter().collect(),
+            };
+            succs.dedup();
+            self.blocks[b].succs = succs;
+            self.blocks[b].preds.clear();
+        }
+        for b in 0..count {
+            for s in self.blocks[b].succs.clone() {
+                self.blocks[s].preds.push(b);
+            }
+        }
+    }
+
+    /// Removes the edge from `from` to `to`, along with the phi arguments for it.
+    pub fn remove_edge(&mut self, from: usize, to: usize) {
+        self.blocks[from].succs.retain(|&s| s != to);
+        let block = &mut self.blocks[to];
+        if let Some(i) = block.preds.iter().position(|&p| p == from) {
+            block.preds.remove(i);
+            for phi in &mut block.phis {
+                phi.args.remove(i);
+            }
+        }
+    }
+
+    /// Drops the blocks that cannot be reached from the entry.
+    pub fn remove_unreachable(&mut self) -> bool {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut work = vec![0];
+        while let Some(b) = work.pop() {
+            if !reachable[b] {
+                reachable[b] = true;
+                work.extend(self.blocks[b].succs.iter().copied());
+            }
+        }
+        if reachable.iter().all(|&r| r) {
+            return false;
+        }
+
+        for (b, &r) in reachable.iter().enumerate() {
+            if !r {
+                for s in self.blocks[b].succs.clone() {
+                    self.remove_edge(b, s);
+                }
+            }
+        }
+        let mut index = vec![0; self.blocks.len()];
+        let mut count = 0;
+        for (b, &r) in reachable.iter().enumerate() {
+            index[b] = count;
+            count += r as usize;
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        self.blocks = blocks
+            .into_iter()
+            .zip(&reachable)
+            .filter(|(_, r)| **r)
+            .map(|(mut block, _)| {
+                block.preds.iter_mut().for_each(|p| *p = index[*p]);
+                block.succs.iter_mut().for_each(|s| *s = index[*s]);
+                block
+            })
+            .collect();
+        true
+    }
+
+    /// Blocks in reverse postorder from the entry.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, i)) = stack.pop() {
+            match self.blocks[b].succs.get(i) {
+                Some(&s) => {
+                    stack.push((b, i + 1));
+                    if !visited[s] {
+                        visited[s] = true;
+                        stack.push((s, 0));
+                    }
+                }
+                None => order.push(b),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Immediate dominator of every block, with the algorithm of Cooper, Harvey and
+    /// Kennedy. The entry is its own.
+    pub fn idoms(&self) -> Vec<usize> {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, &b) in order.iter().enumerate() {
+            rank[b] = i;
+        }
+        let mut idom = vec![usize::MAX; self.blocks.len()];
+        idom[0] = 0;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in order.iter().skip(1) {
+                let mut new = usize::MAX;
+                for &p in &self.blocks[b].preds {
+                    if idom[p] == usize::MAX {
+                        continue;
+                    }
+                    if new == usize::MAX {
+                        new = p;
+                        continue;
+                    }
+                    let mut x = p;
+                    while x != new {
+                        while rank[x] > rank[new] {
+                            x = idom[x];
+                        }
+                        while rank[new] > rank[x] {
+                            new = idom[new];
+                        }
+                    }
+                }
+                if idom[b] != new {
+                    idom[b] = new;
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    /// Children of every block in the dominator tree.
+    pub fn dominator_tree(&self) -> Vec<Vec<usize>> {
+        let mut children = vec![Vec::new(); self.blocks.len()];
+        for (b, &d) in self.idoms().iter().enumerate().skip(1) {
+            if d != usize::MAX {
+                children[d].push(b);
+            }
+        }
+        children
+    }
+
+    fn rename(&mut self, func: &IRFunction) {
+        let idom = self.idoms();
+        let mut frontier: Vec<HashSet<usize>> = vec![HashSet::new(); self.blocks.len()];
+        for b in 0..self.blocks.len() {
+            if self.blocks[b].preds.len() < 2 {
+                continue;
+            }
+            for &p in &self.blocks[b].preds {
+                let mut runner = p;
+                while runner != idom[b] {
+                    frontier[runner].insert(b);
+                    runner = idom[runner];
+                }
+            }
+        }
+
+        // Only values read in some block before being written there can need a phi.
+        let mut defsites: HashMap<String, (Operand, HashSet<usize>)> = HashMap::new();
+        let mut global = HashSet::new();
+        for (param, _) in &func.params {
+            if let Some(k) = key(param) {
+                defsites
+                    .entry(k)
+                    .or_insert((param.clone(), HashSet::new()))
+                    .1
+                    .insert(0);
+            }
+        }
+        for (b, block) in self.blocks.iter().enumerate() {
+            let mut written = HashSet::new();
+            for inst in &block.insts {
+                for k in uses(inst).into_iter().filter_map(key) {
+                    if !written.contains(&k) {
+                        global.insert(k);
+                    }
+                }
+                if let Some(dst) = def(inst) {
+                    let k = key(dst).unwrap_or_default();
+                    defsites
+                        .entry(k.clone())
+                        .or_insert((dst.clone(), HashSet::new()))
+                        .1
+                        .insert(b);
+                    written.insert(k);
+                }
+            }
+        }
+        let mut phi_keys: Vec<Vec<String>> = vec![Vec::new(); self.blocks.len()];
+        let mut keys: Vec<&String> = defsites.keys().filter(|k| global.contains(*k)).collect();
+        keys.sort();
+        for k in keys {
+            let (op, sites) = &defsites[k];
+            let mut has_phi = HashSet::new();
+            let mut work: Vec<usize> = sites.iter().copied().collect();
+            while let Some(b) = work.pop() {
+                for &f in &frontier[b] {
+                    if has_phi.insert(f) {
+                        let args = vec![op.clone(); self.blocks[f].preds.len()];
+                        self.blocks[f].phis.push(Phi {
+                            dst: op.clone(),
+                            args,
+                        });
+                        phi_keys[f].push(k.clone());
+                        if !sites.contains(&f) {
+                            work.push(f);
+                        }
+                    }
+                }
+            }
+        }
+
+        let children = self.dominator_tree();
+        let mut renamer = Renamer {
+            next_temp: self.next_temp,
+            versions: HashMap::new(),
+            stacks: HashMap::new(),
+        };
+        self.rename_block(0, &children, &phi_keys, &mut renamer);
+        self.next_temp = renamer.next_temp;
+    }
+
+    fn rename_block(
+        &mut self,
+        b: usize,
+        children: &[Vec<usize>],
+        phi_keys: &[Vec<String>],
+        renamer: &mut Renamer,
+    ) {
+        let mut pushed = Vec::new();
+        let block = &mut self.blocks[b];
+        for phi in &mut block.phis {
+            phi.dst = renamer.define(&phi.dst, &mut pushed);
+        }
+        for inst in &mut block.insts {
+            for op in uses_mut(inst) {
+                *op = renamer.current(op);
+            }
+            if let Some(dst) = def_mut(inst) {
+                *dst = renamer.define(dst, &mut pushed);
+            }
+        }
+        for s in self.blocks[b].succs.clone() {
+            let i = self.blocks[s].preds.iter().position(|&p| p == b).unwrap();
+            for (phi, k) in self.blocks[s].phis.iter_mut().zip(&phi_keys[s]) {
+                if let Some(value) = renamer.stacks.get(k).and_then(|stack| stack.last()) {
+                    phi.args[i] = value.clone();
+                }
+            }
+        }
+        for &child in &children[b] {
+            self.rename_block(child, children, phi_keys, renamer);
+        }
+        for k in pushed {
+            renamer.stacks.get_mut(&k).map(Vec::pop);
+        }
+    }
+
+    /// Converts back to a flat instruction list. Each phi gets a temporary that every
+    /// predecessor writes before leaving, and that the phi's block copies into the
+    /// phi's value on entry, so that phis of the same block never see each other's
+    /// writes.
+    pub fn lower(mut self) -> Vec<Instruction> {
+        let mut at_end: Vec<Vec<Instruction>> = vec![Vec::new(); self.blocks.len()];
+        let mut at_start: Vec<Vec<Instruction>> = vec![Vec::new(); self.blocks.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for phi in &block.phis {
+                let ty = match &phi.dst {
+                    Operand::Temp(_, ty) => ty.clone(),
+                    _ => IRType::Int,
+                };
+                let tmp = Operand::Temp(self.next_temp, ty);
+                self.next_temp += 1;
+                for (&p, arg) in block.preds.iter().zip(&phi.args) {
+                    at_end[p].push(Instruction {
+                        op: Op::Move,
+                        dst: Some(tmp.clone()),
+                        src1: Some(arg.clone()),
+                        src2: None,
+                        span: None,
+                    });
+                }
+                at_start[b].push(Instruction {
+                    op: Op::Move,
+                    dst: Some(phi.dst.clone()),
+                    src1: Some(tmp),
+                    src2: None,
+                    span: None,
+                });
+            }
+        }
+
+        let mut insts = Vec::new();
+        for (b, block) in self.blocks.into_iter().enumerate() {
+            if let Some(label) = block.label {
+                insts.push(Instruction {
+                    op: Op::Label(label),
+                    dst: None,
+                    src1: None,
+                    src2: None,
+                    span: None,
+                });
+            }
+            insts.append(&mut at_start[b]);
+            let mut body = block.insts;
+            let terminator = body.pop_if(|inst| is_terminator(&inst.op));
+            insts.extend(body);
+            insts.append(&mut at_end[b]);
+            insts.extend(terminator);
+        }
+        insts
+    }
+}
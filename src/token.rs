@@ -8,12 +8,18 @@ pub enum TokenType {
     SUB,
     MUL,
     DIV,
+    MOD,
+    SHL,
+    SHR,
     NEG,
     EQ,
     ADDEQ,
     SUBEQ,
     MULEQ,
     DIVEQ,
+    MODEQ,
+    SHLEQ,
+    SHREQ,
     COMPEQ,
     COMPNE,
     COMPGT,
@@ -26,6 +32,7 @@ pub enum TokenType {
     LOGAND,
     LOGOR,
     LOGXOR,
+    BITNOT,
     LITERAL(VarType),
     LPAREN,
     RPAREN,
@@ -44,16 +51,29 @@ pub enum TokenType {
     IN,
     LABEL,
     GOTO,
+    BREAK,
+    CONTINUE,
     FUNCDECL,
     CALL,
     RETURN,
     IDENT,
     EXTERN,
     PUB,
+    INLINE,
+    NOINLINE,
+    IMPORT,
+    FROM,
     Type(VarType),
     SIZEOF,
+    ORD,
+    CHR,
+    PANIC,
+    FORMAT,
     RANGE,
     COMMA,
+    STRUCT,
+    DOT,
+    ELLIPSIS,
     EOF,
 }
 
@@ -62,6 +82,7 @@ pub enum Literal {
     Int(i64),
     Float(OrderedFloat<f64>),
     Bool(bool),
+    Char(u8),
     Str(String),
     Array(usize, Vec<Expr>),
     Void,
@@ -72,11 +93,34 @@ pub enum VarType {
     Int,
     Float,
     Bool,
+    Char,
     Str,
+    String,
     Array(Option<usize>),
+    Vec,
+    Struct(String),
     Void,
 }
 
+/// Types are shown the way they are written in Alum.
+impl std::fmt::Display for VarType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VarType::Int => write!(f, "int"),
+            VarType::Float => write!(f, "flt"),
+            VarType::Bool => write!(f, "bool"),
+            VarType::Char => write!(f, "char"),
+            VarType::Str => write!(f, "str"),
+            VarType::String => write!(f, "string"),
+            VarType::Array(Some(len)) => write!(f, "arr<{}>", len),
+            VarType::Array(None) => write!(f, "arr<_>"),
+            VarType::Vec => write!(f, "vec"),
+            VarType::Struct(name) => write!(f, "{}", name),
+            VarType::Void => write!(f, "void"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token: TokenType,
@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expr, ExprKind, Span},
+    diagnostic::Diagnostic,
+    module::Module,
+    token::{Literal, TokenType, VarType},
+};
+
+/// The names under which `range`, the function of `array` that builds `a ~ b`, can
+/// be imported.
+const RANGE: [&str; 2] = ["range", "array.range"];
+
+/// The parameter and return types of a function or extern.
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<VarType>,
+    ret_type: VarType,
+    /// Takes further arguments of any type after `params`.
+    is_variadic: bool,
+}
+
+/// Checks the types of every module before any code is generated. All declarations
+/// are collected first, so that a function can be called before it is declared, and
+/// checking goes on after an error so that all of them are reported at once.
+pub struct TypeChecker {
+    structs: HashMap<String, Vec<(String, VarType)>>,
+    /// The functions of each module under their own names.
+    exports: HashMap<String, HashMap<String, (Signature, bool)>>,
+    /// The functions callable from the module being checked, as written there.
+    functions: HashMap<String, Signature>,
+    /// The variables in scope, with no type for those whose initializer had an error.
+    scopes: Vec<HashMap<String, Option<VarType>>>,
+    ret_type: VarType,
+    errors: Vec<Diagnostic>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            structs: HashMap::new(),
+            exports: HashMap::new(),
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+            ret_type: VarType::Void,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Checks the modules, given in dependency order as returned by `ModuleLoader`,
+    /// and fills in the types it infers for unannotated `let`s.
+    pub fn check(mut self, modules: &mut [Module]) -> Result<(), Vec<Diagnostic>> {
+        for module in modules.iter() {
+            self.declare(module);
+        }
+        for module in modules.iter_mut() {
+            self.resolve(module);
+            for expr in &mut module.program.body {
+                if let ExprKind::FuncDecl(decl) = &mut expr.kind {
+                    self.ret_type = decl.ret_type.clone();
+                    self.scopes = vec![
+                        decl.params
+                            .iter()
+                            .map(|(name, typ)| (name.clone(), Some(typ.clone())))
+                            .collect(),
+                    ];
+                    self.expr(&mut decl.body);
+                }
+            }
+        }
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self.errors),
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span, label: String) {
+        self.errors
+            .push(Diagnostic::error(message).label(span, label));
+    }
+
+    /// Collects the structs and function signatures of a module.
+    fn declare(&mut self, module: &Module) {
+        let mut functions: HashMap<String, (Signature, bool)> = HashMap::new();
+        for expr in &module.program.body {
+            let (name, signature, is_pub) = match &expr.kind {
+                ExprKind::FuncDecl(decl) => (
+                    &decl.name,
+                    Signature {
+                        params: decl.params.iter().map(|(_, t)| t.clone()).collect(),
+                        ret_type: decl.ret_type.clone(),
+                        is_variadic: false,
+                    },
+                    decl.is_pub,
+                ),
+                ExprKind::Extern(ext) => (
+                    &ext.name,
+                    Signature {
+                        params: ext.params.clone(),
+                        ret_type: ext.ret_type.clone(),
+                        is_variadic: ext.is_variadic,
+                    },
+                    ext.is_pub,
+                ),
+                ExprKind::StructDecl(decl) => {
+                    self.structs.insert(decl.name.clone(), decl.fields.clone());
+                    continue;
+                }
+                _ => continue,
+            };
+            // The same C function may well be declared more than once.
+            if functions.contains_key(name) && !matches!(expr.kind, ExprKind::Extern(_)) {
+                self.error(
+                    format!("Name error: function '{}' is defined more than once", name),
+                    expr.span,
+                    "redefined here".to_string(),
+                );
+            }
+            functions.insert(name.clone(), (signature, is_pub));
+        }
+        self.exports.insert(module.name.clone(), functions);
+    }
+
+    /// Makes the functions of a module and those it imports callable by the names
+    /// they have there.
+    fn resolve(&mut self, module: &Module) {
+        self.functions = self.exports[&module.name]
+            .iter()
+            .map(|(name, (signature, _))| (name.clone(), signature.clone()))
+            .collect();
+        for import in &module.program.imports {
+            let Some(exported) = self.exports.get(&import.module) else {
+                continue;
+            };
+            for (name, (signature, is_pub)) in exported {
+                let visible = match &import.names {
+                    None => Some(format!("{}.{}", import.module, name)),
+                    Some(names) if names.contains(name) => Some(name.clone()),
+                    Some(_) => None,
+                };
+                if let (Some(visible), true) = (visible, is_pub) {
+                    self.functions.insert(visible, signature.clone());
+                }
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Option<VarType>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn declare_var(&mut self, name: &str, typ: Option<VarType>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), typ);
+        }
+    }
+
+    /// Checks `expr` in a scope of its own, as the body of a branch or loop.
+    fn scoped(&mut self, expr: &mut Expr) -> Option<VarType> {
+        self.scopes.push(HashMap::new());
+        let typ = self.expr(expr);
+        self.scopes.pop();
+        typ
+    }
+
+    /// Reports `found` where `expected` is needed. Unknown types come from an error
+    /// that was already reported.
+    fn expect(&mut self, expected: &VarType, found: Option<VarType>, span: Span, what: &str) {
+        if let Some(found) = found
+            && !compatible(expected, &found)
+        {
+            self.error(
+                format!("Type error: mismatched types in {}", what),
+                span,
+                format!("expected {}, found {}", expected, found),
+            );
+        }
+    }
+
+    /// The type of `expr`, or `None` if it cannot be known because of an error.
+    fn expr(&mut self, expr: &mut Expr) -> Option<VarType> {
+        let span = expr.span;
+        match &mut expr.kind {
+            ExprKind::Val(val) => Some(match &mut val.value {
+                Literal::Int(_) => VarType::Int,
+                Literal::Float(_) => VarType::Float,
+                Literal::Bool(_) => VarType::Bool,
+                Literal::Char(_) => VarType::Char,
+                Literal::Str(_) => VarType::Str,
+                Literal::Void => VarType::Void,
+                Literal::Array(len, elems) => {
+                    for elem in elems.iter_mut() {
+                        self.expr(elem);
+                    }
+                    let fill = *len > 1 && elems.len() == 1;
+                    VarType::Array(Some(if fill { *len } else { elems.len() }))
+                }
+            }),
+            ExprKind::Var(var) => match self.lookup(&var.name) {
+                Some(typ) => typ.clone(),
+                None => {
+                    self.error(
+                        format!("Name error: undefined variable '{}'", var.name),
+                        span,
+                        "not found in this scope".to_string(),
+                    );
+                    None
+                }
+            },
+            ExprKind::VarDecl(decl) => {
+                let found = self.expr(&mut decl.value);
+                match (&decl.typ, &found) {
+                    // A single element fills the whole array.
+                    (Some(VarType::Array(Some(_))), Some(VarType::Array(Some(1)))) => {}
+                    (Some(typ), _) => self.expect(
+                        &typ.clone(),
+                        found,
+                        decl.value.span,
+                        &format!("declaration of '{}'", decl.name),
+                    ),
+                    (None, Some(VarType::Void)) => {
+                        self.error(
+                            format!("Type error: cannot infer the type of '{}'", decl.name),
+                            decl.value.span,
+                            "this expression has no value".to_string(),
+                        );
+                    }
+                    (None, _) => decl.typ = found,
+                }
+                self.declare_var(&decl.name, decl.typ.clone());
+                Some(VarType::Void)
+            }
+            ExprKind::VarMod(modi) => {
+                let found = self.expr(&mut modi.value);
+                match self.lookup(&modi.name).cloned() {
+                    Some(None) => {}
+                    Some(Some(typ)) => self.expect(
+                        &typ,
+                        found,
+                        modi.value.span,
+                        &format!("assignment to '{}'", modi.name),
+                    ),
+                    None => self.error(
+                        format!("Name error: undefined variable '{}'", modi.name),
+                        span,
+                        "not found in this scope".to_string(),
+                    ),
+                }
+                Some(VarType::Void)
+            }
+            ExprKind::BinOp(bin) => {
+                // Outside a `for` loop, a range is built by a call to `range`.
+                if bin.operator == TokenType::RANGE
+                    && !RANGE.iter().any(|name| self.functions.contains_key(*name))
+                {
+                    self.error(
+                        "Name error: undefined function 'range'".to_string(),
+                        span,
+                        "a range is built by `range`, imported from `array`".to_string(),
+                    );
+                }
+                let left = self.expr(&mut bin.left);
+                let right = self.expr(&mut bin.right);
+                let (left, right) = (left?, right?);
+                let typ = binop_type(&bin.operator, &left, &right);
+                if typ.is_none() {
+                    self.error(
+                        format!(
+                            "Type error: cannot apply '{}' to {} and {}",
+                            op_symbol(&bin.operator),
+                            left,
+                            right
+                        ),
+                        span,
+                        String::new(),
+                    );
+                }
+                typ
+            }
+            ExprKind::UnaryOp(unary) => {
+                let argument = self.expr(&mut unary.argument)?;
+                let typ = match (&unary.operator, &argument) {
+                    (TokenType::SIZEOF, _) => Some(VarType::Int),
+                    (TokenType::ORD, VarType::Char) => Some(VarType::Int),
+                    (TokenType::CHR, VarType::Int) => Some(VarType::Char),
+                    (TokenType::NEG, VarType::Int | VarType::Float) => Some(argument.clone()),
+                    (TokenType::BITNOT, VarType::Int) => Some(VarType::Int),
+                    (TokenType::LOGNOT, VarType::Int | VarType::Bool) => Some(VarType::Bool),
+                    _ => None,
+                };
+                if typ.is_none() {
+                    self.error(
+                        format!(
+                            "Type error: cannot apply '{}' to {}",
+                            op_symbol(&unary.operator),
+                            argument
+                        ),
+                        span,
+                        String::new(),
+                    );
+                }
+                typ
+            }
+            ExprKind::Stmt(stmt) => {
+                self.scopes.push(HashMap::new());
+                let mut typ = Some(VarType::Void);
+                for expr in &mut stmt.body {
+                    typ = self.expr(expr);
+                }
+                self.scopes.pop();
+                typ
+            }
+            ExprKind::Return(ret) => {
+                let ret_type = self.ret_type.clone();
+                match &mut ret.value {
+                    Some(value) => {
+                        let found = self.expr(value);
+                        self.expect(&ret_type, found, value.span, "return value");
+                    }
+                    None if ret_type != VarType::Void => self.error(
+                        "Type error: missing return value".to_string(),
+                        span,
+                        format!("expected {}", ret_type),
+                    ),
+                    None => {}
+                }
+                Some(VarType::Void)
+            }
+            ExprKind::If(i) => {
+                self.expr(&mut i.condition);
+                let then_type = self.scoped(&mut i.then_branch);
+                let else_type = i.else_branch.as_mut().map(|e| self.scoped(e));
+                match else_type {
+                    Some(else_type) if else_type == then_type => then_type,
+                    _ => Some(VarType::Void),
+                }
+            }
+            ExprKind::While(w) => {
+                self.expr(&mut w.condition);
+                self.scoped(&mut w.body);
+                Some(VarType::Void)
+            }
+            ExprKind::For(f) => {
+                // A `for` loop counts through a range without building it.
+                if let ExprKind::BinOp(bin) = &mut f.iter.kind {
+                    if bin.operator == TokenType::RANGE {
+                        for bound in [&mut bin.left, &mut bin.right] {
+                            let found = self.expr(bound);
+                            self.expect(&VarType::Int, found, bound.span, "range bound");
+                        }
+                    }
+                } else if let Some(typ) = self.expr(&mut f.iter)
+                    && !matches!(typ, VarType::Array(_) | VarType::Vec)
+                {
+                    self.error(
+                        format!("Type error: cannot iterate over {}", typ),
+                        f.iter.span,
+                        "expected an array or vec".to_string(),
+                    );
+                }
+                self.scopes.push(HashMap::new());
+                self.declare_var(&f.init, Some(VarType::Int));
+                self.expr(&mut f.body);
+                self.scopes.pop();
+                Some(VarType::Void)
+            }
+            ExprKind::FuncCall(call) => {
+                let Some(signature) = self.functions.get(&call.name).cloned() else {
+                    for arg in &mut call.args {
+                        self.expr(arg);
+                    }
+                    self.error(
+                        format!("Name error: undefined function '{}'", call.name),
+                        span,
+                        "not found in this module".to_string(),
+                    );
+                    return None;
+                };
+                let arity = signature.params.len();
+                if call.args.len() != arity && !(signature.is_variadic && call.args.len() > arity) {
+                    let expected = match arity {
+                        1 => "1 argument".to_string(),
+                        n => format!("{} arguments", n),
+                    };
+                    let expected = if signature.is_variadic {
+                        format!("at least {}", expected)
+                    } else {
+                        expected
+                    };
+                    self.error(
+                        format!(
+                            "Type error: '{}' takes {} but {} {} given",
+                            call.name,
+                            expected,
+                            call.args.len(),
+                            if call.args.len() == 1 { "was" } else { "were" }
+                        ),
+                        span,
+                        format!("expected {}", expected),
+                    );
+                }
+                for (i, arg) in call.args.iter_mut().enumerate() {
+                    let found = self.expr(arg);
+                    if let Some(param) = signature.params.get(i) {
+                        self.expect(
+                            param,
+                            found,
+                            arg.span,
+                            &format!("argument {} of '{}'", i + 1, call.name),
+                        );
+                    } else if let Some(typ @ (VarType::Void | VarType::Struct(_))) = found {
+                        // Further arguments go in registers as they are, like those of C.
+                        self.error(
+                            format!(
+                                "Type error: cannot pass {} to the variadic arguments of '{}'",
+                                typ, call.name
+                            ),
+                            arg.span,
+                            format!("found {}", typ),
+                        );
+                    }
+                }
+                Some(signature.ret_type)
+            }
+            ExprKind::ArrayAccess(aa) => {
+                let element = self.indexed(&aa.array, span);
+                let index = self.expr(&mut aa.offset);
+                self.expect(&VarType::Int, index, aa.offset.span, "index");
+                element
+            }
+            ExprKind::ArrayAssign(aa) => {
+                let element = self.indexed(&aa.array, span);
+                let index = self.expr(&mut aa.offset);
+                self.expect(&VarType::Int, index, aa.offset.span, "index");
+                let found = self.expr(&mut aa.value);
+                if element == Some(VarType::Char) {
+                    self.expect(&VarType::Char, found, aa.value.span, "string element");
+                }
+                Some(VarType::Void)
+            }
+            ExprKind::Slice(slice) => {
+                match self.lookup(&slice.string).cloned() {
+                    Some(Some(VarType::Str | VarType::String)) | Some(None) => {}
+                    Some(Some(typ)) => self.error(
+                        format!("Type error: cannot slice {}", typ),
+                        span,
+                        format!("'{}' is not a str or string", slice.string),
+                    ),
+                    None => self.error(
+                        format!("Name error: undefined variable '{}'", slice.string),
+                        span,
+                        "not found in this scope".to_string(),
+                    ),
+                }
+                for bound in [&mut slice.start, &mut slice.end] {
+                    let found = self.expr(bound);
+                    self.expect(&VarType::Int, found, bound.span, "slice bound");
+                }
+                Some(VarType::String)
+            }
+            ExprKind::Format(format) => {
+                match format.pieces() {
+                    Ok(pieces) if pieces.len() - 1 != format.args.len() => self.error(
+                        format!(
+                            "Type error: format string has {} placeholders but {} arguments were given",
+                            pieces.len() - 1,
+                            format.args.len()
+                        ),
+                        span,
+                        String::new(),
+                    ),
+                    Ok(_) => {}
+                    Err(message) => self.error(format!("Syntax error: {}", message), span, String::new()),
+                }
+                for arg in &mut format.args {
+                    let Some(typ) = self.expr(arg) else {
+                        continue;
+                    };
+                    if !matches!(
+                        typ,
+                        VarType::Int
+                            | VarType::Float
+                            | VarType::Bool
+                            | VarType::Char
+                            | VarType::Str
+                            | VarType::String
+                    ) {
+                        self.error(
+                            format!("Type error: cannot format {}", typ),
+                            arg.span,
+                            "expected int, flt, bool, char, str or string".to_string(),
+                        );
+                    }
+                }
+                Some(VarType::String)
+            }
+            ExprKind::Panic(p) => {
+                let message = self.expr(&mut p.message);
+                self.expect(&VarType::Str, message, p.message.span, "panic message");
+                Some(VarType::Void)
+            }
+            ExprKind::StructLit(lit) => {
+                let fields = self.structs.get(&lit.name).cloned().unwrap_or_default();
+                for (field, value) in &mut lit.fields {
+                    let found = self.expr(value);
+                    match fields.iter().find(|(f, _)| f == field) {
+                        Some((_, typ)) => {
+                            self.expect(typ, found, value.span, &format!("field '{}'", field))
+                        }
+                        None => self.error(
+                            format!("Name error: struct '{}' has no field '{}'", lit.name, field),
+                            value.span,
+                            String::new(),
+                        ),
+                    }
+                }
+                Some(VarType::Struct(lit.name.clone()))
+            }
+            ExprKind::FieldAccess(fa) => {
+                let object = self.expr(&mut fa.object)?;
+                self.field(&object, &fa.field, span)
+            }
+            ExprKind::FieldAssign(fa) => {
+                let object = self.expr(&mut fa.object);
+                let found = self.expr(&mut fa.value);
+                if let Some(typ) = object.and_then(|o| self.field(&o, &fa.field, span)) {
+                    self.expect(
+                        &typ,
+                        found,
+                        fa.value.span,
+                        &format!("assignment to field '{}'", fa.field),
+                    );
+                }
+                Some(VarType::Void)
+            }
+            ExprKind::Label(_)
+            | ExprKind::Goto(_)
+            | ExprKind::Break(_)
+            | ExprKind::Continue(_)
+            | ExprKind::FuncDecl(_)
+            | ExprKind::Extern(_)
+            | ExprKind::StructDecl(_) => Some(VarType::Void),
+        }
+    }
+
+    /// The type of the elements of `name`, which must be an array, vec or str.
+    fn indexed(&mut self, name: &str, span: Span) -> Option<VarType> {
+        match self.lookup(name).cloned() {
+            Some(Some(VarType::Array(_) | VarType::Vec)) => Some(VarType::Int),
+            Some(Some(VarType::Str | VarType::String)) => Some(VarType::Char),
+            Some(None) => None,
+            Some(Some(typ)) => {
+                self.error(
+                    format!("Type error: cannot index {}", typ),
+                    span,
+                    format!("'{}' is not an array, vec, str or string", name),
+                );
+                None
+            }
+            None => {
+                self.error(
+                    format!("Name error: undefined variable '{}'", name),
+                    span,
+                    "not found in this scope".to_string(),
+                );
+                None
+            }
+        }
+    }
+
+    fn field(&mut self, object: &VarType, field: &str, span: Span) -> Option<VarType> {
+        let VarType::Struct(name) = object else {
+            self.error(
+                format!("Type error: cannot access field '{}' of {}", field, object),
+                span,
+                String::new(),
+            );
+            return None;
+        };
+        let typ = self
+            .structs
+            .get(name)
+            .and_then(|fields| fields.iter().find(|(f, _)| f == field))
+            .map(|(_, typ)| typ.clone());
+        if typ.is_none() {
+            self.error(
+                format!("Name error: struct '{}' has no field '{}'", name, field),
+                span,
+                String::new(),
+            );
+        }
+        typ
+    }
+}
+
+/// Whether a value of type `found` can be used where `expected` is needed. Integers
+/// serve as truth values, an array of any length fits an `arr<_>` and a `string`
+/// can be read as a `str`.
+fn compatible(expected: &VarType, found: &VarType) -> bool {
+    match (expected, found) {
+        (VarType::Bool, VarType::Int) => true,
+        (VarType::Str, VarType::String) => true,
+        (VarType::Array(None), VarType::Array(_)) => true,
+        (expected, found) => expected == found,
+    }
+}
+
+/// The type of `left op right`, or `None` if the operator does not apply to them.
+/// A `str` can be offset by an integer, as a pointer would, while adding two strings
+/// concatenates them into a new `string`.
+fn binop_type(op: &TokenType, left: &VarType, right: &VarType) -> Option<VarType> {
+    use VarType::*;
+    let integer = |t: &VarType| matches!(t, Int | Bool);
+    match op {
+        TokenType::RANGE if *left == Int && *right == Int => Some(Array(None)),
+        TokenType::COMPAND | TokenType::COMPOR if integer(left) && integer(right) => Some(Bool),
+        TokenType::COMPEQ
+        | TokenType::COMPNE
+        | TokenType::COMPGT
+        | TokenType::COMPGE
+        | TokenType::COMPLT
+        | TokenType::COMPLE
+            if left == right || integer(left) && integer(right) =>
+        {
+            Some(Bool)
+        }
+        TokenType::LOGAND | TokenType::LOGOR | TokenType::LOGXOR
+            if integer(left) && integer(right) =>
+        {
+            Some(if left == right { left.clone() } else { Int })
+        }
+        TokenType::ADD | TokenType::SUB | TokenType::MUL | TokenType::DIV => match (left, right) {
+            (Float, Float) => Some(Float),
+            (Str | String, Str | String) if *op == TokenType::ADD => Some(String),
+            (Str, Int) if matches!(op, TokenType::ADD | TokenType::SUB) => Some(Str),
+            (l, r) if integer(l) && integer(r) => Some(Int),
+            _ => None,
+        },
+        TokenType::MOD | TokenType::SHL | TokenType::SHR if integer(left) && integer(right) => {
+            Some(Int)
+        }
+        _ => None,
+    }
+}
+
+fn op_symbol(op: &TokenType) -> &'static str {
+    match op {
+        TokenType::ADD => "+",
+        TokenType::SUB | TokenType::NEG => "-",
+        TokenType::MUL => "*",
+        TokenType::DIV => "/",
+        TokenType::MOD => "%",
+        TokenType::SHL => "<<",
+        TokenType::SHR => ">>",
+        TokenType::COMPEQ => "==",
+        TokenType::COMPNE => "!=",
+        TokenType::COMPGT => ">",
+        TokenType::COMPGE => ">=",
+        TokenType::COMPLT => "<",
+        TokenType::COMPLE => "<=",
+        TokenType::COMPAND => "&&",
+        TokenType::COMPOR => "||",
+        TokenType::LOGAND => "&",
+        TokenType::LOGOR => "|",
+        TokenType::LOGXOR => "^",
+        TokenType::LOGNOT => "!",
+        TokenType::BITNOT => "~",
+        TokenType::RANGE => "~",
+        TokenType::SIZEOF => "sizeof",
+        TokenType::ORD => "ord",
+        TokenType::CHR => "chr",
+        _ => "?",
+    }
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use std::fs;
+
+const SUM: &str = "
+from io import printf
+
+fun sum(a: arr<_>, n: int): int {
+    let s = 0
+    for i in 1 ~ n {
+        s = s + a[i] - a[i - 1]
+    }
+    return s
+}
+
+pub fun main(): int {
+    let a = [1, 2, 4, 8]
+    printf(\"%d %d\\n\", sum(a, 4), sum(a, 0 - 1))
+    printf(\"%d\\n\", sum(a, 5))
+    return 0
+}
+";
+
+/// The checks of an index counting through a range are made once, before the loop.
+#[test]
+fn hoisted_out_of_loop() {
+    let dir = common::scratch("bounds_hoist");
+    let built = common::al(&dir, SUM, "main.s", &["-O1", "-S"]);
+    assert!(built.status.success());
+    let asm = fs::read_to_string(dir.join("main.s")).unwrap();
+    let sum = &asm[asm.find("\nsum:").unwrap()..];
+    let (before, rest) = sum.split_at(sum.find(".for_cond").unwrap());
+    let body = &rest[..rest.find(".for_end").unwrap()];
+    assert_eq!(before.matches("call alum_bounds_panic").count(), 2);
+    assert!(!body.contains("alum_bounds_panic"));
+}
+
+/// A hoisted check lets through the loops that stay in bounds, and stops the
+/// others at the line of the indexing.
+#[test]
+fn hoisted_check_fails_like_the_loop() {
+    if !common::has_std() {
+        return;
+    }
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("bounds_run", SUM, &[opt]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "7 0\n");
+        assert_eq!(out.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            stderr.contains("main.al:7: index out of bounds: the index is 4 but the length is 4"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+const PAST_END: &str = "
+from io import printf
+
+fun sum(a: arr<_>, n: int): int {
+    let s = 0
+    for i in 0 ~ n {
+        s = s + a[i]
+    }
+    return s
+}
+
+pub fun main(): int {
+    let a = [1, 2, 3, 4, 5, 6, 7]
+    printf(\"%d\\n\", sum(a, 10))
+    return 0
+}
+";
+
+/// The hoisted check reports the first index that fails, whatever the level.
+#[test]
+fn hoisted_check_reports_first_failure() {
+    if !common::has_std() {
+        return;
+    }
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("bounds_first", PAST_END, &[opt]);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            stderr.contains("main.al:7: index out of bounds: the index is 7 but the length is 7"),
+            "at {}: {}",
+            opt,
+            stderr
+        );
+    }
+}
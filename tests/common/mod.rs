@@ -0,0 +1,51 @@
+//! Compiles Alum programs with the `al` binary and runs them.
+
+// Every test crate includes this module but uses only part of it.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A scratch directory for one test, emptied when it is created.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("alum-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Whether the standard library is installed, which every executable links against.
+pub fn has_std() -> bool {
+    let home = std::env::var("ALUM_HOME").unwrap_or_else(|_| "/usr/local".to_string());
+    let found = Path::new(&home).join("lib/libalum.a").exists();
+    if !found {
+        eprintln!("skipped: alum-std is not installed in {}", home);
+    }
+    found
+}
+
+/// Runs `al` on `src` with `args`, writing the output to `out` in `dir`.
+pub fn al(dir: &Path, src: &str, out: &str, args: &[&str]) -> Output {
+    let file = dir.join("main.al");
+    fs::write(&file, src).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_al"))
+        .args(args)
+        .arg(&file)
+        .arg("-o")
+        .arg(dir.join(out))
+        .output()
+        .unwrap()
+}
+
+/// Compiles `src` into an executable and runs it.
+pub fn run(name: &str, src: &str, args: &[&str]) -> Output {
+    let dir = scratch(name);
+    let built = al(&dir, src, "main", args);
+    assert!(
+        built.status.success(),
+        "al failed:\n{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+    Command::new(dir.join("main")).output().unwrap()
+}
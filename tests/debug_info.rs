@@ -0,0 +1,61 @@
+mod common;
+
+use std::process::Command;
+
+const SRC: &str = "from io import println
+from convert import itoa
+
+fun square(x: int): int {
+    let y = x * x
+    return y
+}
+
+pub fun main(): int {
+    let n = 7
+    let r = square(n)
+    println(itoa(r))
+    return 0
+}
+";
+
+/// Stops in `square` by line with gdb, and reads its locals and the backtrace back
+/// from the DWARF that `-g` writes.
+#[test]
+fn gdb_breaks_by_line_and_reads_locals() {
+    if Command::new("gdb").arg("--version").output().is_err() {
+        eprintln!("skipped: gdb is not installed");
+        return;
+    }
+    if !common::has_std() {
+        return;
+    }
+    let dir = common::scratch("debug_info");
+    let built = common::al(&dir, SRC, "app", &["-g"]);
+    assert!(
+        built.status.success(),
+        "al failed:\n{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+
+    let out = Command::new("gdb")
+        .args(["-batch", "-nx"])
+        .args([
+            "-ex",
+            "break main.al:6",
+            "-ex",
+            "run",
+            "-ex",
+            "info locals",
+            "-ex",
+            "bt",
+        ])
+        .arg(dir.join("app"))
+        .output()
+        .unwrap();
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(out.contains("Breakpoint 1, square (x=7)"), "{}", out);
+    assert!(out.contains("y = 49"), "{}", out);
+    assert!(out.contains("#0  square (x=7) at"), "{}", out);
+    assert!(out.contains("in main () at"), "{}", out);
+    assert!(out.contains("main.al:11"), "{}", out);
+}
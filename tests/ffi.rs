@@ -0,0 +1,136 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BIG_C: &str = "
+struct Big { long a; double b; long c; };
+
+double sum(long x, struct Big p, double y, long z) {
+    return x + p.a + p.b + p.c + y + z;
+}
+
+double sum8(long a, long b, long c, long d, long e, long f, struct Big p, long g) {
+    return a + b + c + d + e + f + p.a + p.b + p.c + g;
+}
+
+struct Big make(long a, double b) {
+    struct Big r = {a, b, a * 2};
+    return r;
+}
+";
+
+const BIG_AL: &str = r#"
+from io import printf
+
+struct Big { a: int, b: flt, c: int }
+extern sum(int, Big, flt, int): flt
+extern sum8(int, int, int, int, int, int, Big, int): flt
+extern make(int, flt): Big
+
+pub fun main(): int {
+    let p = Big { a: 1, b: 2.5, c: 3 }
+    printf("%.1f\n", sum(10, p, 0.5, 100))
+    let q = make(4, 1.5)
+    printf("%d %.1f %d\n", q.a, q.b, q.c)
+    printf("%.1f\n", sum8(1, 2, 3, 4, 5, 6, q, 7))
+    return 0
+}
+"#;
+
+/// Compiles `src` with the system C compiler, or returns `None` if there is none.
+fn compile_c(dir: &Path, name: &str, src: &str) -> Option<PathBuf> {
+    let (c, object) = (
+        dir.join(format!("{}.c", name)),
+        dir.join(format!("{}.o", name)),
+    );
+    std::fs::write(&c, src).unwrap();
+    let Ok(cc) = Command::new("cc")
+        .arg("-c")
+        .arg(&c)
+        .arg("-o")
+        .arg(&object)
+        .status()
+    else {
+        eprintln!("skipped: cc is not installed");
+        return None;
+    };
+    assert!(cc.success());
+    Some(object)
+}
+
+/// Structs larger than 16 bytes are passed to C on the stack, after any other
+/// stack arguments that precede them, and returned through a hidden buffer.
+#[test]
+fn structs_in_memory() {
+    if !common::has_std() {
+        return;
+    }
+    let dir = common::scratch("ffi_c");
+    let Some(object) = compile_c(&dir, "big", BIG_C) else {
+        return;
+    };
+
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("ffi", BIG_AL, &[opt, object.to_str().unwrap()]);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            "117.0\n4 1.5 8\n41.5\n"
+        );
+    }
+}
+
+const SPLIT_C: &str = "
+struct Q { long a; long b; };
+struct D { double x; double y; };
+
+long f2(long a, long b, long c, long d, long e, struct Q q) {
+    return a + b + c + d + e + 10 * q.a + 100 * q.b;
+}
+
+long f3(long a, long b, long c, long d, long e, struct Q q, long g) {
+    return a + b + c + d + e + 10 * q.a + 100 * q.b + 1000 * g;
+}
+
+double fd(double a, double b, double c, double d, double e, double f, double g,
+          struct D p, double h) {
+    return a + b + c + d + e + f + g + 10 * p.x + 100 * p.y + 1000 * h;
+}
+";
+
+const SPLIT_AL: &str = r#"
+from io import printf
+
+struct Q { a: int, b: int }
+struct D { x: flt, y: flt }
+extern f2(int, int, int, int, int, Q): int
+extern f3(int, int, int, int, int, Q, int): int
+extern fd(flt, flt, flt, flt, flt, flt, flt, D, flt): flt
+
+pub fun main(): int {
+    let q = Q { a: 1, b: 1 }
+    let r = Q { a: 2, b: 3 }
+    printf("%d %d\n", f2(1, 1, 1, 1, 1, q), f3(1, 1, 1, 1, 1, r, 4))
+    let p = D { x: 2.0, y: 3.0 }
+    printf("%.1f\n", fd(1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, p, 4.0))
+    return 0
+}
+"#;
+
+/// A small struct whose eightbytes do not all fit in the registers left goes on
+/// the stack as a whole, and the arguments after it still take those registers.
+#[test]
+fn structs_past_the_registers() {
+    if !common::has_std() {
+        return;
+    }
+    let dir = common::scratch("ffi_split_c");
+    let Some(object) = compile_c(&dir, "split", SPLIT_C) else {
+        return;
+    };
+
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("ffi_split", SPLIT_AL, &[opt, object.to_str().unwrap()]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "115 4325\n4327.0\n");
+    }
+}
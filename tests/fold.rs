@@ -0,0 +1,21 @@
+mod common;
+
+use std::os::unix::process::ExitStatusExt;
+
+/// `i64::MIN / -1` traps at run time, so the optimizer must not fold it away.
+#[test]
+fn overflowing_division_traps() {
+    if !common::has_std() {
+        return;
+    }
+    for op in ["/", "%"] {
+        let src = format!(
+            "pub fun main(): int {{\n    let m = 1 << 63\n    let d = 0 - 1\n    return m {} d\n}}\n",
+            op
+        );
+        for opt in ["-O0", "-O1", "-O2"] {
+            let out = common::run("fold", &src, &[opt]);
+            assert_eq!(out.status.signal(), Some(8), "{} at {}", op, opt);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+mod common;
+
+/// A program that imports nothing still needs `_start` from the standard library.
+#[test]
+fn links_without_imports() {
+    if !common::has_std() {
+        return;
+    }
+    let out = common::run("no_imports", "pub fun main(): int { return 7 }\n", &[]);
+    assert_eq!(out.status.code(), Some(7));
+}
@@ -0,0 +1,25 @@
+mod common;
+
+const ROUND: &str = r#"
+from io import printf
+
+pub fun main(): int {
+    printf("%.0f %.0f %.0f %.0f\n", 0.5, 1.5, 2.5, 3.5)
+    printf("%.2f %.2f %.1f %.2f\n", 0.125, 0.375, 0.15, 2.675)
+    printf("%.0f %.1f %.3f\n", 99.5, 9.95, 0.0625)
+    return 0
+}
+"#;
+
+/// `%f` rounds the exact value of a `flt` with ties going to an even digit, as C does.
+#[test]
+fn float_rounds_half_to_even() {
+    if !common::has_std() {
+        return;
+    }
+    let out = common::run("printf_round", ROUND, &[]);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "0 2 2 4\n0.12 0.38 0.1 2.67\n100 9.9 0.062\n"
+    );
+}
@@ -0,0 +1,35 @@
+mod common;
+
+/// `a ~ b` outside a `for` loop calls `range`, which has to be imported.
+#[test]
+fn range_needs_import() {
+    let dir = common::scratch("range_import");
+    let src = "pub fun main(): int {\n    let r = 0 ~ 3\n    return sizeof r\n}\n";
+    let out = common::al(&dir, src, "main.s", &["-S"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("undefined function 'range'") && stderr.contains("main.al:2:13"),
+        "{}",
+        stderr
+    );
+
+    if common::has_std() {
+        let out = common::al(&dir, &format!("import array\n{}", src), "main.s", &["-S"]);
+        assert!(out.status.success());
+    }
+}
+
+/// A `for` loop counts through a range without calling `range`.
+#[test]
+fn for_range_without_import() {
+    let dir = common::scratch("range_for");
+    let src =
+        "pub fun main(): int {\n    let n = 0\n    for i in 0 ~ 3 { n += i }\n    return n\n}\n";
+    let out = common::al(&dir, src, "main.s", &["-S"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
@@ -0,0 +1,20 @@
+mod common;
+
+/// A slice past the end of a string stops the program and shows its range the way
+/// it is written.
+#[test]
+fn slice_out_of_bounds() {
+    if !common::has_std() {
+        return;
+    }
+    let src =
+        "pub fun main(): int {\n    let s = \"abcdef\"\n    let t = s[2 ~ 9]\n    return 0\n}\n";
+    let out = common::run("string_slice", src, &[]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("main.al:3: slice out of bounds: the range is 2 ~ 9 but the length is 6"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,108 @@
+mod common;
+
+const EVEN_ODD: &str = "
+from io import println
+from convert import itoa
+
+fun even(n: int): int {
+    if n == 0 return 1
+    return odd(n - 1)
+}
+
+fun odd(n: int): int {
+    if n == 0 return 0
+    return even(n - 1)
+}
+
+pub fun main(): int {
+    println(itoa(even(10000000)))
+    return 0
+}
+";
+
+/// `odd` is inlined into `even` at `-O2`, which leaves copies and a jump between
+/// the call back to `even` and the return. It must still not grow the stack.
+#[test]
+fn mutual_recursion_after_inlining() {
+    if !common::has_std() {
+        return;
+    }
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("tail_call", EVEN_ODD, &[opt]);
+        assert!(out.status.success(), "crashed at {}", opt);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "1\n", "at {}", opt);
+    }
+}
+
+const SWAP: &str = "
+from io import printf
+
+struct P { x: int, y: int }
+
+fun f(n: int, p: P): int {
+    if n == 0 return p.x * 10 + p.y
+    return f(n - 1, P { x: p.y, y: p.x })
+}
+
+pub fun main(): int {
+    printf(\"%d %d\\n\", f(2, P { x: 1, y: 2 }), f(3, P { x: 1, y: 2 }))
+    return 0
+}
+";
+
+/// A struct argument built from a parameter is not written over the struct that
+/// parameter still points to.
+#[test]
+fn self_call_with_struct_literal() {
+    if !common::has_std() {
+        return;
+    }
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("tail_call_swap", SWAP, &[opt]);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            "12 21\n",
+            "at {}",
+            opt
+        );
+    }
+}
+
+const STACK_ARGS: &str = "
+from io import printf
+
+fun ping(n: int, a: int, b: int, c: int, d: int, e: int, f: int, g: int): int {
+    if n == 0 return a + 2 * b + 3 * g
+    return pong(n - 1, b, c, d, e, f, g, a)
+}
+
+fun pong(n: int, a: int, b: int, c: int, d: int, e: int, f: int, g: int): int {
+    if n == 0 return a + 2 * b + 3 * g
+    return ping(n - 1, g, a, b, c, d, e, f)
+}
+
+pub fun main(): int {
+    printf(\"%d %d\\n\", ping(1000000, 1, 2, 3, 4, 5, 6, 7), ping(1000001, 1, 2, 3, 4, 5, 6, 7))
+    return 0
+}
+";
+
+/// Arguments past the registers are passed where the caller's own were, so
+/// mutual recursion through functions with more than six parameters still does
+/// not grow the stack.
+#[test]
+fn sibling_call_with_stack_args() {
+    if !common::has_std() {
+        return;
+    }
+    for opt in ["-O0", "-O1", "-O2"] {
+        let out = common::run("tail_call_stack", STACK_ARGS, &[opt]);
+        assert!(out.status.success(), "crashed at {}", opt);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            "26 11\n",
+            "at {}",
+            opt
+        );
+    }
+}